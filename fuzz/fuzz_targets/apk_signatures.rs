@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_dex::apk::signing::ApkSignatures;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = ApkSignatures::build(data);
+});
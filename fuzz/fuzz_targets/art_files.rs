@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_dex::art::oat::OatFile;
+use rusty_dex::art::vdex::VdexFile;
+use rusty_dex::elf::ElfFile;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(elf) = ElfFile::build(data) {
+        for symbol in elf.dynamic_symbols.iter() {
+            elf.virtual_address_to_offset(symbol.value);
+        }
+    }
+    if let Ok(mut oat) = OatFile::build(data) {
+        oat.resolve_from_vdex(data);
+        let _ = oat.dex_readers();
+    }
+    if let Ok(vdex) = VdexFile::build(data) {
+        let _ = vdex.dex_readers();
+    }
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_dex::dex::reader::DexReader;
+use rusty_dex::dex::file::DexFile;
+use rusty_dex::dex::integrity::IntegrityPolicy;
+use rusty_dex::dex::options::ParseOptions;
+use rusty_dex::dex::{ code_verifier, dexdump, verifier };
+
+fuzz_target!(|data: &[u8]| {
+    for recover in [false, true] {
+        let options = ParseOptions {
+            checksum: IntegrityPolicy::Skip,
+            signature: IntegrityPolicy::Skip,
+            recover,
+            ..Default::default()
+        };
+        if let Ok(reader) = DexReader::build(data.to_vec())
+           && let Ok(dex) = DexFile::build_with_options(reader, &options) {
+            code_verifier::verify_code(&dex);
+            dexdump::dump(&DexReader::build(data.to_vec()).unwrap(), &dex);
+        }
+    }
+    verifier::verify(data);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_dex::dex::layout::DexLayout;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(layout) = DexLayout::build(data) {
+        layout.render(data);
+    }
+});
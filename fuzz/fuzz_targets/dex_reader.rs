@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rusty_dex::dex::reader::DexReader;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut reader) = DexReader::build(data.to_vec()) else {
+        return;
+    };
+
+    // Exercise every primitive read until the input runs out
+    while reader.remaining() > 0 {
+        let ok = reader.read_uleb128().is_ok()
+            && reader.read_sleb128().is_ok()
+            && reader.read_uleb128p1().is_ok()
+            && reader.read_u16().is_ok()
+            && reader.read_u32().is_ok()
+            && reader.read_i32().is_ok()
+            && reader.read_u8().is_ok()
+            && reader.align_cursor().is_ok();
+        if !ok {
+            break;
+        }
+    }
+});
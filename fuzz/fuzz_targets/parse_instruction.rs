@@ -0,0 +1,35 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::{ Seek, SeekFrom };
+use rusty_dex::dex::reader::DexReader;
+use rusty_dex::dex::instructions::parse_instruction;
+
+/// Size of the DEX header, used as padding before the bytecode
+const HEADER_SIZE: usize = 0x70;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&flags, code)) = data.split_first() else {
+        return;
+    };
+
+    // The reader only needs a valid endianness tag to accept the input
+    let mut raw = vec![0u8; HEADER_SIZE];
+    raw[40..44].copy_from_slice(&0x12345678u32.to_le_bytes());
+    raw.extend_from_slice(code);
+
+    let Ok(mut reader) = DexReader::build(raw) else {
+        return;
+    };
+    reader.quickened = flags & 1 != 0;
+    if reader.bytes.seek(SeekFrom::Start(HEADER_SIZE as u64)).is_err() {
+        return;
+    }
+
+    let mut instructions = Vec::new();
+    while reader.remaining() > 0 {
+        if parse_instruction(&mut reader, &mut instructions).is_err() {
+            break;
+        }
+    }
+});
@@ -7,13 +7,14 @@
 //! # Example
 //!
 //! ```
-//! use dex_parser::adler32::verify_from_bytes;
+//! use std::io::Cursor;
+//! use rusty_dex::adler32::verify_from_bytes;
 //!
-//! let bytes: [u8; 16] = [0x44, 0x45, 0x58, 0x0a,
-//!                        0x30, 0x33, 0x35, 0x00,
-//!                        0x00, 0x00, 0x00, 0x00,
-//!                        0x00, 0x00, 0x00, 0x00];
-//! assert!(verify_from_bytes(&bytes, 0x14300184).unwrap());
+//! let bytes = Cursor::new(vec![0x44, 0x45, 0x58, 0x0a,
+//!                              0x30, 0x33, 0x35, 0x00,
+//!                              0x00, 0x00, 0x00, 0x00,
+//!                              0x00, 0x00, 0x00, 0x00]);
+//! assert!(verify_from_bytes(&bytes, 0x00040001).unwrap());
 //! ```
 
 use std::io::Cursor;
@@ -23,26 +24,33 @@ use crate::error::DexError;
 /// Constant used in the checksum computation
 const MOD_ADLER: u32 = 65521;
 
-/// Verify the Adler32 checksum of a cursor of bytes
+/// Compute the Adler-32 checksum of a DEX file
 ///
-/// Each DEX header contains an Adler-32 checksum of the file, minus the first
-/// 11 bytes, which correspond to the space taken by the magic and the checksum.
-/// This function computes the checksum of the file, and compares it to the one
-/// found in the header.
-pub fn verify_from_bytes(bytes: &Cursor<Vec<u8>>, checksum: u32) -> Result<bool, DexError> {
+/// The first 12 bytes (magic and checksum field) are not part of the checksum.
+pub fn compute(bytes: &[u8]) -> u32 {
 
     // Define variable for checksum computation
     let mut a: u32 = 1;
     let mut b: u32 = 0;
 
     // Main computation
-    for byte in bytes.get_ref().iter().skip(12) {
+    for byte in bytes.iter().skip(12) {
         a = (a + *byte as u32) % MOD_ADLER;
         b = (b + a) % MOD_ADLER;
     }
 
     // Concatenating A and B
-    let computed_checksum = (b << 16) | a;
+    (b << 16) | a
+}
+
+/// Verify the Adler32 checksum of a cursor of bytes
+///
+/// Each DEX header contains an Adler-32 checksum of the file, minus the first
+/// 11 bytes, which correspond to the space taken by the magic and the checksum.
+/// This function computes the checksum of the file, and compares it to the one
+/// found in the header.
+pub fn verify_from_bytes(bytes: &Cursor<impl AsRef<[u8]>>, checksum: u32) -> Result<bool, DexError> {
+    let computed_checksum = compute(bytes.get_ref().as_ref());
 
     // Verification of the checksum read from the DEX header
     if computed_checksum == checksum {
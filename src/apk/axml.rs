@@ -0,0 +1,327 @@
+//! Binary XML files
+//!
+//! The XML files of an APK (`AndroidManifest.xml`, layouts, etc.) are compiled by `aapt` into a
+//! binary format (AXML): a string pool, a map from attribute names to resource IDs, and a flat
+//! list of namespace and element chunks. This module rebuilds the tree of elements.
+//!
+//! Obfuscators often blank or scramble the attribute names in the string pool, since Android only
+//! relies on the resource IDs. For the attributes of the `android` namespace we therefore prefer
+//! the name derived from the resource ID when there is one.
+
+use log::warn;
+
+use crate::apk::chunks::*;
+use crate::bytes::{ read_u16_at, read_u32_at };
+use crate::error::DexError;
+
+/// URI of the `android` namespace
+pub const ANDROID_NAMESPACE: &str = "http://schemas.android.com/apk/res/android";
+
+/// Names of the framework attributes used in manifests, by resource ID
+const ANDROID_ATTRIBUTES: [(u32, &str); 14] = [
+    (0x01010001, "label"),
+    (0x01010003, "name"),
+    (0x01010006, "permission"),
+    (0x0101000e, "enabled"),
+    (0x01010010, "exported"),
+    (0x01010018, "authorities"),
+    (0x01010027, "scheme"),
+    (0x01010028, "host"),
+    (0x0101002a, "path"),
+    (0x0101020c, "minSdkVersion"),
+    (0x0101021b, "versionCode"),
+    (0x0101021c, "versionName"),
+    (0x01010202, "targetActivity"),
+    (0x01010270, "targetSdkVersion"),
+];
+
+/// Attribute of an XML element
+#[derive(Debug, Clone)]
+pub struct XmlAttribute {
+    /// Namespace URI of the attribute, if any
+    pub namespace: Option<String>,
+    /// Name of the attribute
+    pub name: String,
+    /// Resource ID of the attribute, if it is a framework or app attribute
+    pub resource_id: Option<u32>,
+    /// Original string value, if the value was a string when compiled
+    pub raw_value: Option<String>,
+    /// Typed value
+    pub value: ResValue,
+}
+
+/// An XML element with its attributes and children
+#[derive(Debug, Clone, Default)]
+pub struct XmlElement {
+    /// Namespace URI of the element, if any
+    pub namespace: Option<String>,
+    /// Name of the element
+    pub name: String,
+    /// Line of the element in the original file
+    pub line: u32,
+    /// Attributes of the element
+    pub attributes: Vec<XmlAttribute>,
+    /// Child elements
+    pub children: Vec<XmlElement>,
+    /// Decoded attribute values, in the same order as `attributes`
+    values: Vec<String>,
+}
+
+impl XmlElement {
+    /// Get the attribute with the given name, whatever its namespace
+    pub fn get_attribute(&self, name: &str) -> Option<&XmlAttribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    /// Get the value of the attribute with the given name as a string
+    ///
+    /// String values are returned as-is, other values are formatted (e.g., `true`, `@7f010001`).
+    pub fn get_attribute_value(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+                       .position(|attribute| attribute.name == name)
+                       .map(|idx| self.values[idx].as_str())
+    }
+
+    /// Iterate over the children with the given name
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlElement> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+/// Representation of a binary XML document
+#[derive(Debug)]
+pub struct XmlDocument {
+    /// String pool of the document
+    pub strings: StringPool,
+    /// Resource IDs of the attribute names, indexed like the string pool
+    pub resource_ids: Vec<u32>,
+    /// Root element of the document
+    pub root: XmlElement,
+}
+
+impl XmlDocument {
+    /// Parse a binary XML document from its raw bytes
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        let header = ChunkHeader::build(raw, 0)?;
+        if header.kind != RES_XML_TYPE {
+            return Err(DexError::InvalidResourceChunk);
+        }
+
+        let end = header.size as usize;
+        let mut offset = header.header_size as usize;
+        let mut strings = StringPool::default();
+        let mut resource_ids = Vec::new();
+
+        // Elements being built, the last one being the innermost
+        let mut stack: Vec<XmlElement> = Vec::new();
+        let mut root = None;
+
+        while offset + 8 <= end {
+            let chunk = ChunkHeader::build(raw, offset)?;
+            // Position of the extended header, after the node header (line number and comment)
+            let ext = offset + chunk.header_size as usize;
+
+            match chunk.kind {
+                RES_STRING_POOL_TYPE => strings = StringPool::build(raw, offset)?,
+                RES_XML_RESOURCE_MAP_TYPE => {
+                    resource_ids = (ext..offset + chunk.size as usize).step_by(4)
+                                                                      .map(|pos| read_u32_at(raw, pos))
+                                                                      .collect::<Result<_, _>>()?;
+                },
+                RES_XML_START_ELEMENT_TYPE => {
+                    let line = read_u32_at(raw, offset + 8)?;
+                    let element = XmlDocument::parse_element(raw, ext, line, &strings, &resource_ids)?;
+                    stack.push(element);
+                },
+                RES_XML_END_ELEMENT_TYPE => {
+                    let element = stack.pop().ok_or(DexError::InvalidResourceChunk)?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => {
+                            root = Some(element);
+                            break;
+                        }
+                    }
+                },
+                RES_XML_START_NAMESPACE_TYPE | RES_XML_END_NAMESPACE_TYPE | RES_XML_CDATA_TYPE => {},
+                kind => warn!("skipping unknown XML chunk type {kind:#x}"),
+            }
+
+            offset += chunk.size as usize;
+        }
+
+        Ok(XmlDocument {
+            strings,
+            resource_ids,
+            root: root.ok_or(DexError::InvalidResourceChunk)?,
+        })
+    }
+
+    /// Parse a start element chunk and its attributes
+    fn parse_element(raw: &[u8],
+                     ext: usize,
+                     line: u32,
+                     strings: &StringPool,
+                     resource_ids: &[u32]) -> Result<XmlElement, DexError> {
+        let namespace = XmlDocument::get_optional_string(strings, read_u32_at(raw, ext)?);
+        let name = strings.get(read_u32_at(raw, ext + 4)?).unwrap_or_default().to_string();
+        let attribute_start = read_u16_at(raw, ext + 8)? as usize;
+        let attribute_size  = read_u16_at(raw, ext + 10)? as usize;
+        let attribute_count = read_u16_at(raw, ext + 12)? as usize;
+
+        let mut attributes = Vec::with_capacity(attribute_count);
+        let mut values = Vec::with_capacity(attribute_count);
+        for idx in 0..attribute_count {
+            let pos = ext + attribute_start + idx * attribute_size;
+            let namespace = XmlDocument::get_optional_string(strings, read_u32_at(raw, pos)?);
+            let name_idx = read_u32_at(raw, pos + 4)?;
+            let raw_value = XmlDocument::get_optional_string(strings, read_u32_at(raw, pos + 8)?);
+            let value = ResValue::build(raw, pos + 12)?;
+
+            let resource_id = resource_ids.get(name_idx as usize).copied();
+            let framework_name = resource_id.and_then(|id| {
+                ANDROID_ATTRIBUTES.iter().find(|(attr_id, _)| *attr_id == id).map(|(_, name)| *name)
+            });
+            let name = match framework_name {
+                Some(name) => name.to_string(),
+                None => strings.get(name_idx).unwrap_or_default().to_string(),
+            };
+
+            values.push(raw_value.clone().unwrap_or_else(|| value.format(strings)));
+            attributes.push(XmlAttribute {
+                namespace,
+                name,
+                resource_id,
+                raw_value,
+                value,
+            });
+        }
+
+        Ok(XmlElement {
+            namespace,
+            name,
+            line,
+            attributes,
+            children: Vec::new(),
+            values,
+        })
+    }
+
+    /// Get a string from the pool, `NO_STRING` meaning no string
+    fn get_optional_string(strings: &StringPool, idx: u32) -> Option<String> {
+        match idx {
+            NO_STRING => None,
+            idx => strings.get(idx).map(|string| string.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::apk::chunks::tests::fake_string_pool;
+
+    /// Element of a fake XML document: name, attributes as (name index, string value index or
+    /// typed value), and children
+    pub(crate) enum FakeValue {
+        String(u32),
+        Typed(u8, u32),
+    }
+
+    pub(crate) struct FakeElement {
+        pub(crate) name: u32,
+        pub(crate) attributes: Vec<(u32, FakeValue)>,
+        pub(crate) children: Vec<FakeElement>,
+    }
+
+    fn write_element(element: &FakeElement, out: &mut Vec<u8>) {
+        out.extend_from_slice(&RES_XML_START_ELEMENT_TYPE.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(&(36 + 20 * element.attributes.len() as u32).to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&NO_STRING.to_le_bytes());
+        out.extend_from_slice(&NO_STRING.to_le_bytes());
+        out.extend_from_slice(&element.name.to_le_bytes());
+        out.extend_from_slice(&20u16.to_le_bytes());
+        out.extend_from_slice(&20u16.to_le_bytes());
+        out.extend_from_slice(&(element.attributes.len() as u16).to_le_bytes());
+        out.extend_from_slice(&[0; 6]);
+
+        for (name, value) in element.attributes.iter() {
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&name.to_le_bytes());
+            let (raw_value, kind, data) = match value {
+                FakeValue::String(idx) => (*idx, 0x03, *idx),
+                FakeValue::Typed(kind, data) => (NO_STRING, *kind, *data),
+            };
+            out.extend_from_slice(&raw_value.to_le_bytes());
+            out.extend_from_slice(&[0x08, 0x00, 0x00, kind]);
+            out.extend_from_slice(&data.to_le_bytes());
+        }
+
+        for child in element.children.iter() {
+            write_element(child, out);
+        }
+
+        out.extend_from_slice(&RES_XML_END_ELEMENT_TYPE.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(&24u32.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&NO_STRING.to_le_bytes());
+        out.extend_from_slice(&NO_STRING.to_le_bytes());
+        out.extend_from_slice(&element.name.to_le_bytes());
+    }
+
+    /// Build a binary XML document. All attributes use the first string as namespace URI, and
+    /// `resource_ids` maps the first strings to attribute resource IDs.
+    pub(crate) fn fake_document(strings: &[&str], resource_ids: &[u32], root: &FakeElement) -> Vec<u8> {
+        let mut body = fake_string_pool(strings);
+
+        body.extend_from_slice(&RES_XML_RESOURCE_MAP_TYPE.to_le_bytes());
+        body.extend_from_slice(&8u16.to_le_bytes());
+        body.extend_from_slice(&(8 + 4 * resource_ids.len() as u32).to_le_bytes());
+        for id in resource_ids {
+            body.extend_from_slice(&id.to_le_bytes());
+        }
+
+        write_element(root, &mut body);
+
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&RES_XML_TYPE.to_le_bytes());
+        raw.extend_from_slice(&8u16.to_le_bytes());
+        raw.extend_from_slice(&(8 + body.len() as u32).to_le_bytes());
+        raw.extend_from_slice(&body);
+        raw
+    }
+
+    #[test]
+    fn test_build() {
+        // String 0 is an obfuscated attribute name, resolved through the resource map
+        let strings = ["", "debuggable", "manifest", "application", "com.example"];
+        let root = FakeElement {
+            name: 2,
+            attributes: vec![(0, FakeValue::String(4))],
+            children: vec![FakeElement {
+                name: 3,
+                attributes: vec![(1, FakeValue::Typed(0x12, 0xffffffff))],
+                children: Vec::new(),
+            }],
+        };
+        let raw = fake_document(&strings, &[0x01010003], &root);
+
+        let document = XmlDocument::build(&raw).unwrap();
+        assert_eq!(document.resource_ids, vec![0x01010003]);
+        assert_eq!(document.root.name, "manifest");
+        assert_eq!(document.root.get_attribute_value("name"), Some("com.example"));
+
+        let application = document.root.children_named("application").next().unwrap();
+        assert_eq!(application.get_attribute("debuggable").unwrap().value.as_bool(), Some(true));
+        assert_eq!(application.get_attribute_value("debuggable"), Some("true"));
+    }
+
+    #[test]
+    fn test_build_invalid() {
+        assert!(XmlDocument::build(&[0x03, 0x00, 0x08, 0x00, 0x08, 0x00, 0x00, 0x00]).is_err());
+        assert!(XmlDocument::build(&[0x02, 0x00, 0x08, 0x00, 0x08, 0x00, 0x00, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+//! Resource chunks
+//!
+//! The binary XML files (`AndroidManifest.xml`, layouts) and the resource table
+//! (`resources.arsc`) are both made of chunks, each starting with a common header giving its type
+//! and size. This module contains the elements shared by both formats: the chunk header, the
+//! string pool, and the typed values.
+
+use crate::bytes::{ slice_at, read_u16_at, read_u32_at };
+use crate::error::DexError;
+
+/// Chunk types
+pub const RES_STRING_POOL_TYPE: u16 = 0x0001;
+pub const RES_TABLE_TYPE: u16 = 0x0002;
+pub const RES_XML_TYPE: u16 = 0x0003;
+pub const RES_XML_START_NAMESPACE_TYPE: u16 = 0x0100;
+pub const RES_XML_END_NAMESPACE_TYPE: u16 = 0x0101;
+pub const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+pub const RES_XML_END_ELEMENT_TYPE: u16 = 0x0103;
+pub const RES_XML_CDATA_TYPE: u16 = 0x0104;
+pub const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+pub const RES_TABLE_PACKAGE_TYPE: u16 = 0x0200;
+pub const RES_TABLE_TYPE_TYPE: u16 = 0x0201;
+pub const RES_TABLE_TYPE_SPEC_TYPE: u16 = 0x0202;
+pub const RES_TABLE_LIBRARY_TYPE: u16 = 0x0203;
+
+/// Flag of the string pool indicating UTF-8 strings
+const UTF8_FLAG: u32 = 0x100;
+/// Index used to reference no string
+pub const NO_STRING: u32 = 0xffffffff;
+
+/// Header common to all chunks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkHeader {
+    /// Type of the chunk (`RES_*_TYPE` constant)
+    pub kind: u16,
+    /// Size of the chunk header, including this common header
+    pub header_size: u16,
+    /// Size of the chunk, including its header
+    pub size: u32,
+}
+
+impl ChunkHeader {
+    /// Read the chunk header at the given offset
+    ///
+    /// The header is checked for consistency: the chunk must fit in `raw` and contain its header.
+    pub fn build(raw: &[u8], offset: usize) -> Result<Self, DexError> {
+        let kind = read_u16_at(raw, offset)?;
+        let header_size = read_u16_at(raw, offset + 2)?;
+        let size = read_u32_at(raw, offset + 4)?;
+
+        if header_size < 8 || size < header_size as u32 {
+            return Err(DexError::InvalidResourceChunk);
+        }
+        slice_at(raw, offset, size as usize)?;
+
+        Ok(ChunkHeader {
+            kind,
+            header_size,
+            size,
+        })
+    }
+}
+
+/// A string pool
+#[derive(Debug, Default)]
+pub struct StringPool {
+    /// Decoded strings
+    pub strings: Vec<String>,
+    /// Whether the strings were encoded in UTF-8 (UTF-16 otherwise)
+    pub is_utf8: bool,
+}
+
+impl StringPool {
+    /// Parse the string pool chunk starting at `offset`
+    pub fn build(raw: &[u8], offset: usize) -> Result<Self, DexError> {
+        let header = ChunkHeader::build(raw, offset)?;
+        if header.kind != RES_STRING_POOL_TYPE {
+            return Err(DexError::InvalidResourceChunk);
+        }
+        let chunk = slice_at(raw, offset, header.size as usize)?;
+
+        let string_count  = read_u32_at(chunk, 8)? as usize;
+        let flags         = read_u32_at(chunk, 16)?;
+        let strings_start = read_u32_at(chunk, 20)? as usize;
+        let is_utf8 = flags & UTF8_FLAG != 0;
+
+        // Each string needs at least its offset and its length
+        if string_count > chunk.len() / 4 {
+            return Err(DexError::InvalidStringPool);
+        }
+
+        let mut strings = Vec::with_capacity(string_count);
+        for idx in 0..string_count {
+            let string_offset = read_u32_at(chunk, header.header_size as usize + idx * 4)? as usize;
+            let position = strings_start.checked_add(string_offset)
+                                        .ok_or(DexError::InvalidStringPool)?;
+            let string = if is_utf8 {
+                StringPool::read_utf8(chunk, position)?
+            } else {
+                StringPool::read_utf16(chunk, position)?
+            };
+            strings.push(string);
+        }
+
+        Ok(StringPool {
+            strings,
+            is_utf8,
+        })
+    }
+
+    /// Get the string at the given index
+    pub fn get(&self, idx: u32) -> Option<&str> {
+        self.strings.get(idx as usize).map(|string| string.as_str())
+    }
+
+    /// Read a length encoded on one or two bytes
+    fn read_utf8_length(chunk: &[u8], position: &mut usize) -> Result<usize, DexError> {
+        let first = slice_at(chunk, *position, 1)?[0] as usize;
+        *position += 1;
+        if first & 0x80 == 0 {
+            return Ok(first);
+        }
+
+        let second = slice_at(chunk, *position, 1)?[0] as usize;
+        *position += 1;
+        Ok(((first & 0x7f) << 8) | second)
+    }
+
+    /// Read a UTF-8 string: its length in UTF-16 code units, its length in bytes, and the bytes
+    fn read_utf8(chunk: &[u8], mut position: usize) -> Result<String, DexError> {
+        let _ = StringPool::read_utf8_length(chunk, &mut position)?;
+        let size = StringPool::read_utf8_length(chunk, &mut position)?;
+        let bytes = slice_at(chunk, position, size)?;
+
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// Read a UTF-16 string: its length in code units, on one or two `u16`, then the code units
+    fn read_utf16(chunk: &[u8], mut position: usize) -> Result<String, DexError> {
+        let mut size = read_u16_at(chunk, position)? as usize;
+        position += 2;
+        if size & 0x8000 != 0 {
+            size = ((size & 0x7fff) << 16) | read_u16_at(chunk, position)? as usize;
+            position += 2;
+        }
+
+        let bytes = slice_at(chunk, position, size.checked_mul(2).ok_or(DexError::InvalidStringPool)?)?;
+        let units = bytes.chunks_exact(2)
+                         .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                         .collect::<Vec<u16>>();
+
+        Ok(String::from_utf16_lossy(&units))
+    }
+}
+
+/// Type of a `ResValue`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResValueType {
+    Null,
+    Reference,
+    Attribute,
+    String,
+    Float,
+    Dimension,
+    Fraction,
+    DynamicReference,
+    DynamicAttribute,
+    IntDec,
+    IntHex,
+    IntBoolean,
+    IntColor,
+    Unknown(u8),
+}
+
+impl ResValueType {
+    /// Converts an `u8` into a `ResValueType`
+    pub fn parse(value: u8) -> Self {
+        match value {
+            0x00 => ResValueType::Null,
+            0x01 => ResValueType::Reference,
+            0x02 => ResValueType::Attribute,
+            0x03 => ResValueType::String,
+            0x04 => ResValueType::Float,
+            0x05 => ResValueType::Dimension,
+            0x06 => ResValueType::Fraction,
+            0x07 => ResValueType::DynamicReference,
+            0x08 => ResValueType::DynamicAttribute,
+            0x10 => ResValueType::IntDec,
+            0x11 => ResValueType::IntHex,
+            0x12 => ResValueType::IntBoolean,
+            0x1c..=0x1f => ResValueType::IntColor,
+            _ => ResValueType::Unknown(value),
+        }
+    }
+}
+
+/// A typed value, as stored in XML attributes and resource entries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResValue {
+    /// Type of the value
+    pub kind: ResValueType,
+    /// Raw data, interpreted according to the type
+    pub data: u32,
+}
+
+impl ResValue {
+    /// Size of a value in bytes
+    pub const SIZE: usize = 8;
+
+    /// Read the value at the given offset
+    pub fn build(raw: &[u8], offset: usize) -> Result<Self, DexError> {
+        let bytes = slice_at(raw, offset, ResValue::SIZE)?;
+
+        Ok(ResValue {
+            kind: ResValueType::parse(bytes[3]),
+            data: read_u32_at(bytes, 4)?,
+        })
+    }
+
+    /// Interpret the value as a boolean, if it is one
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.kind {
+            ResValueType::IntBoolean => Some(self.data != 0),
+            _ => None,
+        }
+    }
+
+    /// Interpret the value as an integer, if it is one
+    pub fn as_int(&self) -> Option<u32> {
+        match self.kind {
+            ResValueType::IntDec | ResValueType::IntHex => Some(self.data),
+            _ => None,
+        }
+    }
+
+    /// Get a human-readable representation of the value, using the string pool for strings
+    pub fn format(&self, strings: &StringPool) -> String {
+        match self.kind {
+            ResValueType::Null => String::new(),
+            ResValueType::String => strings.get(self.data).unwrap_or_default().to_string(),
+            ResValueType::Reference | ResValueType::DynamicReference => format!("@{:08x}", self.data),
+            ResValueType::Attribute | ResValueType::DynamicAttribute => format!("?{:08x}", self.data),
+            ResValueType::Float => f32::from_bits(self.data).to_string(),
+            ResValueType::IntDec => (self.data as i32).to_string(),
+            ResValueType::IntBoolean => (self.data != 0).to_string(),
+            ResValueType::IntColor => format!("#{:08x}", self.data),
+            _ => format!("{:#x}", self.data),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build a UTF-8 string pool chunk
+    pub(crate) fn fake_string_pool(strings: &[&str]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut offsets = Vec::new();
+        for string in strings {
+            offsets.push(data.len() as u32);
+            data.push(string.len() as u8);
+            data.push(string.len() as u8);
+            data.extend_from_slice(string.as_bytes());
+            data.push(0);
+        }
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+
+        let strings_start = 28 + 4 * strings.len();
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(&RES_STRING_POOL_TYPE.to_le_bytes());
+        chunk.extend_from_slice(&28u16.to_le_bytes());
+        chunk.extend_from_slice(&((strings_start + data.len()) as u32).to_le_bytes());
+        chunk.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&UTF8_FLAG.to_le_bytes());
+        chunk.extend_from_slice(&(strings_start as u32).to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        for offset in offsets {
+            chunk.extend_from_slice(&offset.to_le_bytes());
+        }
+        chunk.extend_from_slice(&data);
+        chunk
+    }
+
+    #[test]
+    fn test_string_pool_utf8() {
+        let chunk = fake_string_pool(&["manifest", "package", ""]);
+        let pool = StringPool::build(&chunk, 0).unwrap();
+        assert!(pool.is_utf8);
+        assert_eq!(pool.strings, vec!["manifest", "package", ""]);
+        assert_eq!(pool.get(1), Some("package"));
+        assert_eq!(pool.get(3), None);
+    }
+
+    #[test]
+    fn test_string_pool_utf16() {
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(&RES_STRING_POOL_TYPE.to_le_bytes());
+        chunk.extend_from_slice(&28u16.to_le_bytes());
+        chunk.extend_from_slice(&44u32.to_le_bytes());
+        chunk.extend_from_slice(&1u32.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&32u32.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&[0x04, 0x00, b'h', 0, b'\xe9', 0, b'l', 0, b'o', 0, 0, 0]);
+
+        let pool = StringPool::build(&chunk, 0).unwrap();
+        assert!(!pool.is_utf8);
+        assert_eq!(pool.strings, vec!["hélo"]);
+
+        chunk[4] = 0xff;
+        assert!(StringPool::build(&chunk, 0).is_err());
+    }
+
+    #[test]
+    fn test_res_value() {
+        let pool = StringPool { strings: vec!["foo".to_string()], is_utf8: true };
+        let raw = [0x08, 0x00, 0x00, 0x12, 0xff, 0xff, 0xff, 0xff];
+        let value = ResValue::build(&raw, 0).unwrap();
+        assert_eq!(value.as_bool(), Some(true));
+        assert_eq!(value.format(&pool), "true");
+
+        let value = ResValue { kind: ResValueType::String, data: 0 };
+        assert_eq!(value.format(&pool), "foo");
+        let value = ResValue { kind: ResValueType::Reference, data: 0x7f010001 };
+        assert_eq!(value.format(&pool), "@7f010001");
+    }
+}
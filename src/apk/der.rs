@@ -0,0 +1,198 @@
+//! Minimal DER decoder and encoder
+//!
+//! Certificates and PKCS#7 signatures are encoded in DER. We only need to walk the structure to
+//! reach a few fields, or to assemble a few fixed structures, so this module provides a reader
+//! and an encoder for tag-length-value elements rather than a full ASN.1 implementation.
+
+use crate::error::DexError;
+
+/// Tags used in certificates and PKCS#7 structures
+pub(crate) const TAG_INTEGER: u8 = 0x02;
+pub(crate) const TAG_OCTET_STRING: u8 = 0x04;
+pub(crate) const TAG_NULL: u8 = 0x05;
+pub(crate) const TAG_OID: u8 = 0x06;
+pub(crate) const TAG_UTC_TIME: u8 = 0x17;
+pub(crate) const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub(crate) const TAG_SEQUENCE: u8 = 0x30;
+pub(crate) const TAG_SET: u8 = 0x31;
+/// Context-specific, constructed tag `[0]`
+pub(crate) const TAG_CONTEXT_0: u8 = 0xa0;
+
+/// A DER element
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DerElement<'a> {
+    /// Tag of the element (only single-byte tags are supported)
+    pub tag: u8,
+    /// Content of the element
+    pub content: &'a [u8],
+    /// Whole encoding of the element, including tag and length
+    pub raw: &'a [u8],
+}
+
+impl<'a> DerElement<'a> {
+    /// Iterate over the elements contained in a constructed element
+    pub fn children(&self) -> DerReader<'a> {
+        DerReader::new(self.content)
+    }
+
+    /// Decode an object identifier into its dotted representation
+    pub fn oid(&self) -> Result<String, DexError> {
+        if self.tag != TAG_OID || self.content.is_empty() {
+            return Err(DexError::InvalidDerEncoding);
+        }
+
+        let mut arcs = Vec::new();
+        let mut value: u64 = 0;
+        for byte in self.content {
+            if value > u64::MAX >> 7 {
+                return Err(DexError::InvalidDerEncoding);
+            }
+            value = (value << 7) | (byte & 0x7f) as u64;
+            if byte & 0x80 == 0 {
+                if arcs.is_empty() {
+                    let first = (value / 40).min(2);
+                    arcs.push(first);
+                    arcs.push(value - first * 40);
+                } else {
+                    arcs.push(value);
+                }
+                value = 0;
+            }
+        }
+
+        Ok(arcs.iter().map(|arc| arc.to_string()).collect::<Vec<String>>().join("."))
+    }
+
+    /// Decode the content as a string, for the string types used in names
+    pub fn string(&self) -> String {
+        match self.tag {
+            // BMPString
+            0x1e => {
+                let units = self.content.chunks_exact(2)
+                                        .map(|unit| u16::from_be_bytes([unit[0], unit[1]]))
+                                        .collect::<Vec<u16>>();
+                String::from_utf16_lossy(&units)
+            },
+            _ => String::from_utf8_lossy(self.content).into_owned(),
+        }
+    }
+}
+
+/// Reader over a sequence of DER elements
+pub(crate) struct DerReader<'a> {
+    raw: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> DerReader<'a> {
+    /// Create a reader over the given bytes
+    pub fn new(raw: &'a [u8]) -> Self {
+        DerReader { raw, pos: 0 }
+    }
+
+    /// Check if there are elements left to read
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.raw.len()
+    }
+
+    /// Read the next element
+    pub fn read(&mut self) -> Result<DerElement<'a>, DexError> {
+        let start = self.pos;
+        let tag = *self.raw.get(start).ok_or(DexError::InvalidDerEncoding)?;
+        if tag & 0x1f == 0x1f {
+            // Multi-byte tags are not used in the structures we decode
+            return Err(DexError::InvalidDerEncoding);
+        }
+
+        let first = *self.raw.get(start + 1).ok_or(DexError::InvalidDerEncoding)? as usize;
+        let (length, header_size) = if first & 0x80 == 0 {
+            (first, 2)
+        } else {
+            // Long form; indefinite lengths (0x80) are not valid DER
+            let count = first & 0x7f;
+            if count == 0 || count > 4 {
+                return Err(DexError::InvalidDerEncoding);
+            }
+            let bytes = self.raw.get(start + 2..start + 2 + count).ok_or(DexError::InvalidDerEncoding)?;
+            (bytes.iter().fold(0usize, |acc, byte| (acc << 8) | *byte as usize), 2 + count)
+        };
+
+        let end = (start + header_size).checked_add(length).ok_or(DexError::InvalidDerEncoding)?;
+        let raw = self.raw.get(start..end).ok_or(DexError::InvalidDerEncoding)?;
+        self.pos = end;
+
+        Ok(DerElement {
+            tag,
+            content: &raw[header_size..],
+            raw,
+        })
+    }
+
+    /// Read the next element and check its tag
+    pub fn expect(&mut self, tag: u8) -> Result<DerElement<'a>, DexError> {
+        let element = self.read()?;
+        if element.tag != tag {
+            return Err(DexError::InvalidDerEncoding);
+        }
+        Ok(element)
+    }
+
+    /// Read the next element if it has the given tag
+    pub fn optional(&mut self, tag: u8) -> Result<Option<DerElement<'a>>, DexError> {
+        if self.raw.get(self.pos) == Some(&tag) {
+            return Ok(Some(self.read()?));
+        }
+        Ok(None)
+    }
+}
+
+/// Encode an element with the given tag and content
+pub(crate) fn encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        let length = (content.len() as u32).to_be_bytes();
+        let skip = length.iter().take_while(|byte| **byte == 0).count();
+        out.push(0x80 | (4 - skip) as u8);
+        out.extend_from_slice(&length[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        // SEQUENCE { OID 2.5.4.3, UTF8String "ab" }
+        let raw = [0x30, 0x09, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x02, b'a', b'b'];
+        let mut reader = DerReader::new(&raw);
+        let sequence = reader.expect(TAG_SEQUENCE).unwrap();
+        assert!(reader.is_empty());
+
+        let mut children = sequence.children();
+        assert_eq!(children.read().unwrap().oid().unwrap(), "2.5.4.3");
+        assert!(children.optional(TAG_OID).unwrap().is_none());
+        assert_eq!(children.read().unwrap().string(), "ab");
+        assert!(children.read().is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(TAG_NULL, &[]), [0x05, 0x00]);
+
+        let long = encode(TAG_OCTET_STRING, &[0xaa; 0x1234]);
+        assert_eq!(long[..4], [0x04, 0x82, 0x12, 0x34]);
+        assert_eq!(DerReader::new(&long).read().unwrap().content.len(), 0x1234);
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        assert!(DerReader::new(&[0x30, 0x05, 0x00]).read().is_err());
+        assert!(DerReader::new(&[0x30, 0x80, 0x00, 0x00]).read().is_err());
+        assert!(DerReader::new(&[0x30, 0x81]).read().is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! Discovery of code blobs in APKs
+//!
+//! `DexReader::build_from_file` only looks at the entries whose name ends with `.dex`. Packers
+//! and plugin frameworks often store additional DEX files elsewhere: in `assets/` or `res/raw/`,
+//! with a misleading extension, or inside nested JAR/ZIP/APK files. The discovery mode sniffs the
+//! magic bytes of every entry instead, and recurses into nested archives.
+//!
+//! Since the input is untrusted, the recursion depth, the size of each extracted entry and the
+//! total number of extracted bytes are bounded by `DiscoveryOptions`.
+
+use std::io::{ Cursor, Read, Seek };
+use log::warn;
+use zip::ZipArchive;
+
+use crate::dex::header::{ DEX_FILE_MAGIC, CDEX_FILE_MAGIC };
+use crate::dex::odex::{ ODEX_FILE_MAGIC, OdexFile };
+use crate::dex::reader::DexReader;
+use crate::error::DexError;
+
+/// Magic bytes of a ZIP local file header
+pub const ZIP_FILE_MAGIC: [u8; 4] = *b"PK\x03\x04";
+/// Separator between the path of an archive and the path of an entry in that archive
+pub const NESTED_PATH_SEPARATOR: char = '!';
+
+/// Kind of code blob, identified from its magic bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlobKind {
+    /// Standard DEX file
+    Dex,
+    /// Compact DEX file
+    CompactDex,
+    /// Optimized DEX file (Dalvik)
+    Odex,
+}
+
+impl BlobKind {
+    /// Identify the kind of code blob from its first bytes
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        match bytes.get(0..4)? {
+            magic if magic == DEX_FILE_MAGIC => Some(BlobKind::Dex),
+            magic if magic == CDEX_FILE_MAGIC => Some(BlobKind::CompactDex),
+            magic if magic == ODEX_FILE_MAGIC => Some(BlobKind::Odex),
+            _ => None
+        }
+    }
+}
+
+/// Limits applied while exploring an archive
+#[derive(Debug, Clone)]
+pub struct DiscoveryOptions {
+    /// Maximum nesting level of the archives to explore, the top-level archive being at level 0
+    pub max_depth: usize,
+    /// Maximum uncompressed size of an entry; larger entries are skipped
+    pub max_entry_size: u64,
+    /// Maximum number of bytes extracted from the whole archive, nested archives included; the
+    /// entries found once it is reached are skipped
+    pub max_total_size: u64,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        DiscoveryOptions {
+            max_depth: 3,
+            max_entry_size: 256 * 1024 * 1024,
+            max_total_size: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// A code blob found in an archive
+#[derive(Debug)]
+pub struct DiscoveredBlob {
+    /// Path of the blob, with nested archives separated by `!` (e.g., `assets/a.jar!classes.dex`)
+    pub path: String,
+    /// Nesting level of the archive containing the blob
+    pub depth: usize,
+    /// Kind of code blob
+    pub kind: BlobKind,
+    /// Raw bytes of the blob
+    pub bytes: Vec<u8>,
+}
+
+impl DiscoveredBlob {
+    /// Create a reader for the DEX file contained in the blob
+    ///
+    /// Returns `None` for compact DEX files, which the parser cannot decode.
+    pub fn dex_reader(&self) -> Option<Result<DexReader<'static>, DexError>> {
+        match self.kind {
+            BlobKind::Dex => Some(DexReader::build(self.bytes.clone())),
+            BlobKind::Odex => Some(OdexFile::build(&self.bytes).and_then(|odex| odex.dex_reader())),
+            BlobKind::CompactDex => None,
+        }
+    }
+}
+
+/// Open the archive at the given path and find all code blobs in it
+pub fn discover_from_file(filepath: &str,
+                          options: &DiscoveryOptions) -> Result<Vec<DiscoveredBlob>, DexError> {
+    let raw_file = std::fs::File::open(filepath)?;
+    let mut blobs = Vec::new();
+    explore_archive(raw_file, "", 0, options, &mut 0, &mut blobs)?;
+    Ok(blobs)
+}
+
+/// Find all code blobs in an archive from its raw bytes
+pub fn discover(raw: &[u8], options: &DiscoveryOptions) -> Result<Vec<DiscoveredBlob>, DexError> {
+    let mut blobs = Vec::new();
+    explore_archive(Cursor::new(raw), "", 0, options, &mut 0, &mut blobs)?;
+    Ok(blobs)
+}
+
+/// Sniff every entry of an archive, recursing into the nested archives
+///
+/// `extracted` is the number of bytes extracted so far, shared by all the nested archives.
+fn explore_archive<R: Read + Seek>(reader: R,
+                                   prefix: &str,
+                                   depth: usize,
+                                   options: &DiscoveryOptions,
+                                   extracted: &mut u64,
+                                   blobs: &mut Vec<DiscoveredBlob>) -> Result<(), DexError> {
+    let mut archive = ZipArchive::new(reader).map_err(|_| DexError::InvalidZipArchive)?;
+
+    for idx in 0..archive.len() {
+        let mut entry = match archive.by_index(idx) {
+            Ok(entry) => entry,
+            Err(err) => {
+                warn!("cannot read entry {idx} of archive {prefix}: {err}");
+                continue;
+            }
+        };
+        if entry.is_dir() {
+            continue;
+        }
+
+        let path = format!("{prefix}{}", entry.name());
+        if entry.size() > options.max_entry_size {
+            warn!("skipping entry {path}: {} bytes exceeds size limit", entry.size());
+            continue;
+        }
+
+        let remaining = options.max_total_size.saturating_sub(*extracted);
+        if entry.size() > remaining {
+            warn!("skipping entry {path}: total extraction limit reached");
+            continue;
+        }
+
+        // The size in the header can be forged: do not trust it when reading
+        let limit = options.max_entry_size.min(remaining);
+        let mut raw = Vec::new();
+        if let Err(err) = (&mut entry).take(limit + 1).read_to_end(&mut raw) {
+            warn!("cannot extract entry {path}: {err}");
+            continue;
+        }
+        if raw.len() as u64 > limit {
+            warn!("skipping entry {path}: exceeds size limit");
+            continue;
+        }
+        *extracted += raw.len() as u64;
+
+        if let Some(kind) = BlobKind::from_magic(&raw) {
+            blobs.push(DiscoveredBlob {
+                path,
+                depth,
+                kind,
+                bytes: raw,
+            });
+        } else if raw.starts_with(&ZIP_FILE_MAGIC) {
+            if depth >= options.max_depth {
+                warn!("skipping nested archive {path}: maximum depth reached");
+                continue;
+            }
+
+            let nested_prefix = format!("{path}{NESTED_PATH_SEPARATOR}");
+            if let Err(err) = explore_archive(Cursor::new(raw), &nested_prefix, depth + 1, options, extracted, blobs) {
+                warn!("cannot explore nested archive {path}: {err}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    fn fake_archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer.start_file(*name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_discover_nested() {
+        let inner = fake_archive(&[("classes.dex", b"dex\n035\0"),
+                                   ("readme.txt", b"hello")]);
+        let middle = fake_archive(&[("inner.zip", &inner)]);
+        let apk = fake_archive(&[("classes.dex", b"dex\n035\0"),
+                                 ("assets/payload.bin", b"dey\n036\0"),
+                                 ("res/raw/code.png", b"cdex001\0"),
+                                 ("assets/middle.jar", &middle)]);
+
+        let blobs = discover(&apk, &DiscoveryOptions::default()).unwrap();
+        let found = blobs.iter()
+                         .map(|blob| (blob.path.as_str(), blob.depth, blob.kind))
+                         .collect::<Vec<_>>();
+        assert_eq!(found, vec![("classes.dex", 0, BlobKind::Dex),
+                               ("assets/payload.bin", 0, BlobKind::Odex),
+                               ("res/raw/code.png", 0, BlobKind::CompactDex),
+                               ("assets/middle.jar!inner.zip!classes.dex", 2, BlobKind::Dex)]);
+    }
+
+    #[test]
+    fn test_discover_limits() {
+        let inner = fake_archive(&[("classes.dex", b"dex\n035\0")]);
+        let apk = fake_archive(&[("nested.apk", &inner),
+                                 ("big.dex", &[b'd', b'e', b'x', b'\n', 0, 0, 0, 0, 0, 0])]);
+
+        let options = DiscoveryOptions { max_depth: 0, max_entry_size: 8, ..Default::default() };
+        let blobs = discover(&apk, &options).unwrap();
+        assert!(blobs.is_empty());
+
+        // The budget is shared by the nested archives: the second DEX file does not fit anymore
+        let apk = fake_archive(&[("classes.dex", b"dex\n035\0"),
+                                 ("nested.apk", &inner)]);
+        let options = DiscoveryOptions { max_total_size: 16, ..Default::default() };
+        let found = discover(&apk, &options).unwrap()
+                                            .into_iter()
+                                            .map(|blob| blob.path)
+                                            .collect::<Vec<_>>();
+        assert_eq!(found, vec!["classes.dex"]);
+
+        assert!(discover(b"not an archive", &options).is_err());
+    }
+}
@@ -0,0 +1,444 @@
+//! Framework and packer detection
+//!
+//! The DEX files of an APK are not always where the app logic lives. Cross-platform frameworks
+//! (Flutter, React Native, Xamarin, Unity, Cordova) only ship a thin Java layer and keep the app
+//! in native libraries, scripts, or assemblies. Packers go further: the DEX files only contain a
+//! stub which decrypts and loads the real code at runtime.
+//!
+//! The detector matches the archive entries and the classes of the DEX files against known
+//! signatures, and looks for the usual signs of an unknown packer (encrypted assets, dynamic
+//! class loading, application class missing from the DEX files). Each detection comes with the
+//! evidence found.
+
+use std::fs::File;
+use std::io::Read;
+use zip::ZipArchive;
+
+use crate::apk::manifest::Manifest;
+use crate::dex::file::DexFile;
+use crate::error::DexError;
+
+/// Number of bytes of each entry read to compute its entropy
+const ENTROPY_SAMPLE_SIZE: u64 = 64 * 1024;
+/// Minimum number of bytes for the entropy of an entry to be meaningful
+const ENTROPY_MIN_SIZE: usize = 4096;
+/// Entropy (in bits per byte) above which an asset in an unknown format is considered encrypted
+const ENCRYPTED_ENTROPY_THRESHOLD: f64 = 7.9;
+
+/// Magic bytes of formats which naturally have a high entropy
+const COMPRESSED_FORMAT_MAGICS: [&[u8]; 10] = [
+    b"PK\x03\x04",          // ZIP, JAR
+    b"\x1f\x8b",            // gzip
+    b"\x89PNG",             // PNG
+    b"\xff\xd8\xff",        // JPEG
+    b"RIFF",                // WebP, WAV
+    b"OggS",                // Ogg
+    b"ID3",                 // MP3
+    b"\x00\x00\x00\x18ftyp", // MP4
+    b"\x00\x00\x00\x20ftyp", // MP4
+    b"\x28\xb5\x2f\xfd",    // Zstandard
+];
+
+/// Types used to load code at runtime
+const DYNAMIC_LOADERS: [&str; 5] = [
+    "Ldalvik/system/DexClassLoader;",
+    "Ldalvik/system/InMemoryDexClassLoader;",
+    "Ldalvik/system/PathClassLoader;",
+    "Ldalvik/system/DexFile;",
+    "Ldalvik/system/BaseDexClassLoader;",
+];
+
+/// Kinds of detections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DetectionKind {
+    /// Cross-platform framework: the app logic is not (only) in the DEX files
+    Framework,
+    /// Packer or protector: the DEX files contain a stub loading the real code
+    Packer,
+}
+
+/// Evidence supporting a detection
+#[derive(Debug, Clone, PartialEq)]
+pub enum Evidence {
+    /// A native library (e.g., `lib/arm64-v8a/libflutter.so`)
+    Library(String),
+    /// An entry of the archive (e.g., `assets/index.android.bundle`)
+    Entry(String),
+    /// A class defined in the DEX files
+    Class(String),
+    /// A type used to load code at runtime, referenced by the DEX files
+    DynamicLoader(String),
+    /// An asset in an unknown format with a high entropy, in bits per byte
+    EncryptedAsset { path: String, entropy: f64 },
+    /// The application class declared in the manifest is not defined in the DEX files
+    MissingApplicationClass(String),
+}
+
+/// An identified framework or packer
+#[derive(Debug, Clone)]
+pub struct Detection {
+    /// Name of the framework or packer
+    pub name: String,
+    /// Kind of the detection
+    pub kind: DetectionKind,
+    /// Evidence found
+    pub evidence: Vec<Evidence>,
+}
+
+/// Signature of a framework or packer
+struct Signature {
+    name: &'static str,
+    kind: DetectionKind,
+    /// File names of native libraries
+    libraries: &'static [&'static str],
+    /// Prefixes of archive entries
+    entries: &'static [&'static str],
+    /// Prefixes of class descriptors
+    classes: &'static [&'static str],
+}
+
+const SIGNATURES: &[Signature] = &[
+    Signature {
+        name: "Flutter",
+        kind: DetectionKind::Framework,
+        libraries: &["libflutter.so", "libapp.so"],
+        entries: &["assets/flutter_assets/"],
+        classes: &["Lio/flutter/embedding/", "Lio/flutter/app/"],
+    },
+    Signature {
+        name: "React Native",
+        kind: DetectionKind::Framework,
+        libraries: &["libreactnativejni.so", "libhermes.so", "libjsc.so"],
+        entries: &["assets/index.android.bundle"],
+        classes: &["Lcom/facebook/react/"],
+    },
+    Signature {
+        name: "Xamarin",
+        kind: DetectionKind::Framework,
+        libraries: &["libmonodroid.so", "libmonosgen-2.0.so", "libxamarin-app.so"],
+        entries: &["assemblies/"],
+        classes: &["Lmono/android/", "Lmicrosoft/maui/"],
+    },
+    Signature {
+        name: "Unity",
+        kind: DetectionKind::Framework,
+        libraries: &["libunity.so"],
+        entries: &["assets/bin/Data/"],
+        classes: &["Lcom/unity3d/player/"],
+    },
+    Signature {
+        name: "Unity IL2CPP",
+        kind: DetectionKind::Framework,
+        libraries: &["libil2cpp.so"],
+        entries: &["assets/bin/Data/Managed/Metadata/global-metadata.dat"],
+        classes: &[],
+    },
+    Signature {
+        name: "Cordova",
+        kind: DetectionKind::Framework,
+        libraries: &[],
+        entries: &["assets/www/cordova.js", "assets/www/cordova_plugins.js"],
+        classes: &["Lorg/apache/cordova/"],
+    },
+    Signature {
+        name: "Jiagu",
+        kind: DetectionKind::Packer,
+        libraries: &["libjiagu.so", "libjiagu_a64.so", "libjiagu_x86.so", "libjiagu_x64.so"],
+        entries: &["assets/libjiagu"],
+        classes: &["Lcom/stub/StubApp;", "Lcom/qihoo/util/"],
+    },
+    Signature {
+        name: "Bangcle",
+        kind: DetectionKind::Packer,
+        libraries: &["libsecexe.so", "libsecmain.so", "libSecShell.so"],
+        entries: &["assets/bangcle_classes.jar", "assets/secData0.jar"],
+        classes: &["Lcom/secneo/apkwrapper/", "Lcom/secshell/"],
+    },
+    Signature {
+        name: "Tencent Legu",
+        kind: DetectionKind::Packer,
+        libraries: &["libshella.so", "libshellx.so", "libshell-super.2019.so", "libBugly-legu.so"],
+        entries: &["assets/0OO00l111l1l", "assets/t86", "assets/tosversion"],
+        classes: &["Lcom/tencent/StubShell/"],
+    },
+    Signature {
+        name: "Ijiami",
+        kind: DetectionKind::Packer,
+        libraries: &["libexec.so", "libexecmain.so", "libijiami.so"],
+        entries: &["assets/ijiami.dat", "assets/ijm_lib/"],
+        classes: &["Lcom/shell/SuperApplication;"],
+    },
+    Signature {
+        name: "Baidu",
+        kind: DetectionKind::Packer,
+        libraries: &["libbaiduprotect.so"],
+        entries: &["assets/baiduprotect"],
+        classes: &["Lcom/baidu/protect/"],
+    },
+    Signature {
+        name: "Alibaba",
+        kind: DetectionKind::Packer,
+        libraries: &["libmobisec.so", "libsgmain.so"],
+        entries: &["assets/aliprotect.dat"],
+        classes: &["Lcom/ali/mobisecenhance/"],
+    },
+    Signature {
+        name: "DexProtector",
+        kind: DetectionKind::Packer,
+        libraries: &["libdexprotector.so", "libdpboot.so"],
+        entries: &["assets/dp.mp3"],
+        classes: &[],
+    },
+    Signature {
+        name: "AppSealing",
+        kind: DetectionKind::Packer,
+        libraries: &["libcovault-appsec.so"],
+        entries: &["assets/appsealing.dex"],
+        classes: &["Lcom/inka/appsealing/"],
+    },
+];
+
+/// An entry of the archive, as seen by the detector
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    /// Path of the entry
+    pub name: String,
+    /// Entropy of the start of the entry, in bits per byte, if it is large enough
+    pub entropy: Option<f64>,
+    /// Whether the entry starts with the magic of a compressed format
+    pub compressed_format: bool,
+}
+
+impl ArchiveEntry {
+    /// Describe an entry from its path and (the start of) its contents
+    pub fn build(name: &str, data: &[u8]) -> Self {
+        ArchiveEntry {
+            name: name.to_string(),
+            entropy: (data.len() >= ENTROPY_MIN_SIZE).then(|| entropy(data)),
+            compressed_format: COMPRESSED_FORMAT_MAGICS.iter().any(|magic| data.starts_with(magic)),
+        }
+    }
+
+    /// Check if the entry is an asset which looks encrypted
+    fn is_encrypted_asset(&self) -> bool {
+        self.name.starts_with("assets/")
+            && !self.compressed_format
+            && self.entropy.is_some_and(|entropy| entropy > ENCRYPTED_ENTROPY_THRESHOLD)
+    }
+}
+
+/// Frameworks and packers identified in an APK
+#[derive(Debug, Default)]
+pub struct DetectionReport {
+    /// All detections
+    pub detections: Vec<Detection>,
+}
+
+impl DetectionReport {
+    /// Open the APK at the given path and run the detection over its entries and DEX files
+    pub fn build_from_apk(filepath: &str, dex: &DexFile) -> Result<Self, DexError> {
+        let raw_file = File::open(filepath)?;
+        let mut zip_file = ZipArchive::new(raw_file).map_err(|_| DexError::InvalidZipArchive)?;
+
+        let mut entries = Vec::new();
+        for idx in 0..zip_file.len() {
+            let Ok(entry) = zip_file.by_index(idx) else {
+                continue;
+            };
+            let name = entry.name().to_string();
+            let mut data = Vec::new();
+            entry.take(ENTROPY_SAMPLE_SIZE).read_to_end(&mut data)?;
+            entries.push(ArchiveEntry::build(&name, &data));
+        }
+
+        let manifest = Manifest::build_from_apk(filepath).ok();
+        Ok(DetectionReport::build(&entries, dex, manifest.as_ref()))
+    }
+
+    /// Run the detection over the entries of an archive and its DEX files
+    pub fn build(entries: &[ArchiveEntry], dex: &DexFile, manifest: Option<&Manifest>) -> Self {
+        let class_names = dex.get_classes_names();
+        let mut detections = Vec::new();
+
+        for signature in SIGNATURES {
+            let mut evidence = Vec::new();
+
+            for entry in entries {
+                let file_name = entry.name.rsplit('/').next().unwrap_or_default();
+                if entry.name.starts_with("lib/") && signature.libraries.contains(&file_name) {
+                    evidence.push(Evidence::Library(entry.name.clone()));
+                } else if signature.entries.iter().any(|prefix| entry.name.starts_with(prefix)) {
+                    evidence.push(Evidence::Entry(entry.name.clone()));
+                }
+            }
+
+            // One class per prefix is enough
+            for prefix in signature.classes {
+                if let Some(name) = class_names.iter().find(|name| name.starts_with(prefix)) {
+                    evidence.push(Evidence::Class(name.to_string()));
+                }
+            }
+
+            if !evidence.is_empty() {
+                detections.push(Detection {
+                    name: signature.name.to_string(),
+                    kind: signature.kind,
+                    evidence,
+                });
+            }
+        }
+
+        let is_packed = detections.iter().any(|detection| detection.kind == DetectionKind::Packer);
+        if !is_packed && let Some(detection) = DetectionReport::detect_unknown_packer(entries, dex, manifest) {
+            detections.push(detection);
+        }
+
+        DetectionReport { detections }
+    }
+
+    /// Look for the signs of a packer without a known signature
+    ///
+    /// Dynamic class loading is common, so it is only reported along with encrypted assets or an
+    /// application class missing from the DEX files.
+    fn detect_unknown_packer(entries: &[ArchiveEntry],
+                             dex: &DexFile,
+                             manifest: Option<&Manifest>) -> Option<Detection> {
+        let mut evidence = Vec::new();
+
+        for entry in entries.iter().filter(|entry| entry.is_encrypted_asset()) {
+            evidence.push(Evidence::EncryptedAsset {
+                path: entry.name.clone(),
+                entropy: entry.entropy.unwrap_or_default(),
+            });
+        }
+
+        if let Some(application_class) = manifest.and_then(|manifest| manifest.application_class.as_ref()) {
+            let descriptor = format!("L{};", application_class.replace('.', "/"));
+            if !dex.get_classes_names().iter().any(|name| **name == descriptor) {
+                evidence.push(Evidence::MissingApplicationClass(descriptor));
+            }
+        }
+
+        if evidence.is_empty() {
+            return None;
+        }
+
+        let loaders = DYNAMIC_LOADERS.iter()
+                                     .filter(|loader| dex.types.items.iter().any(|name| name == *loader))
+                                     .map(|loader| Evidence::DynamicLoader(loader.to_string()))
+                                     .collect::<Vec<Evidence>>();
+        if loaders.is_empty() {
+            return None;
+        }
+        evidence.extend(loaders);
+
+        Some(Detection {
+            name: "Unknown packer".to_string(),
+            kind: DetectionKind::Packer,
+            evidence,
+        })
+    }
+
+    /// Get the detection with the given name
+    pub fn get(&self, name: &str) -> Option<&Detection> {
+        self.detections.iter().find(|detection| detection.name == name)
+    }
+
+    /// Check if the DEX files are likely a stub loading the real code
+    pub fn is_packed(&self) -> bool {
+        self.detections.iter().any(|detection| detection.kind == DetectionKind::Packer)
+    }
+}
+
+/// Compute the Shannon entropy of the data, in bits per byte
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+
+    let size = data.len() as f64;
+    counts.iter()
+          .filter(|count| **count > 0)
+          .map(|count| {
+              let probability = *count as f64 / size;
+              -probability * probability.log2()
+          })
+          .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apk::axml::tests::{ fake_document, FakeElement, FakeValue };
+    use crate::fixtures::fake_dex_file;
+
+    /// Pseudo-random bytes, with a high entropy
+    fn random_bytes(size: usize) -> Vec<u8> {
+        let mut state = 0x2545f491u32;
+        (0..size).map(|_| {
+                     state ^= state << 13;
+                     state ^= state >> 17;
+                     state ^= state << 5;
+                     (state >> 24) as u8
+                 })
+                 .collect()
+    }
+
+    #[test]
+    fn test_frameworks() {
+        let entries = [ArchiveEntry::build("lib/arm64-v8a/libflutter.so", b"\x7fELF"),
+                       ArchiveEntry::build("lib/arm64-v8a/libapp.so", b"\x7fELF"),
+                       ArchiveEntry::build("assets/flutter_assets/AssetManifest.json", b"{}"),
+                       ArchiveEntry::build("assets/flutter.so", b"\x7fELF")];
+        let dex = fake_dex_file(&["Lio/flutter/embedding/android/FlutterActivity;", "Lcom/example/MainActivity;"], &[]);
+
+        let report = DetectionReport::build(&entries, &dex, None);
+        assert_eq!(report.detections.len(), 1);
+        assert!(!report.is_packed());
+
+        let flutter = report.get("Flutter").unwrap();
+        assert_eq!(flutter.kind, DetectionKind::Framework);
+        assert_eq!(flutter.evidence, vec![Evidence::Library("lib/arm64-v8a/libflutter.so".to_string()),
+                                          Evidence::Library("lib/arm64-v8a/libapp.so".to_string()),
+                                          Evidence::Entry("assets/flutter_assets/AssetManifest.json".to_string()),
+                                          Evidence::Class("Lio/flutter/embedding/android/FlutterActivity;".to_string())]);
+    }
+
+    #[test]
+    fn test_packers() {
+        let entries = [ArchiveEntry::build("lib/armeabi-v7a/libjiagu.so", b"\x7fELF")];
+        let dex = fake_dex_file(&["Lcom/stub/StubApp;"], &[]);
+        let report = DetectionReport::build(&entries, &dex, None);
+        assert!(report.is_packed());
+        assert_eq!(report.get("Jiagu").unwrap().evidence.len(), 2);
+
+        // Unknown packer: encrypted asset, missing application class, and dynamic loading
+        let strings = ["name", "manifest", "package", "com.example", "application", "com.example.App"];
+        let root = FakeElement {
+            name: 1,
+            attributes: vec![(2, FakeValue::String(3))],
+            children: vec![FakeElement { name: 4, attributes: vec![(0, FakeValue::String(5))], children: vec![] }],
+        };
+        let manifest = Manifest::build(&fake_document(&strings, &[0x01010003], &root)).unwrap();
+
+        let encrypted = random_bytes(ENTROPY_MIN_SIZE);
+        let mut compressed = b"PK\x03\x04".to_vec();
+        compressed.extend_from_slice(&encrypted);
+        let entries = [ArchiveEntry::build("assets/payload.bin", &encrypted),
+                       ArchiveEntry::build("assets/archive.zip", &compressed),
+                       ArchiveEntry::build("assets/small.bin", &encrypted[..16])];
+        let dex = fake_dex_file(&["Lcom/stub/Loader;"], &["Ldalvik/system/DexClassLoader;"]);
+
+        let report = DetectionReport::build(&entries, &dex, Some(&manifest));
+        let detection = report.get("Unknown packer").unwrap();
+        assert_eq!(detection.evidence.len(), 3);
+        assert!(matches!(&detection.evidence[0], Evidence::EncryptedAsset { path, .. } if path == "assets/payload.bin"));
+        assert_eq!(detection.evidence[1], Evidence::MissingApplicationClass("Lcom/example/App;".to_string()));
+        assert_eq!(detection.evidence[2], Evidence::DynamicLoader("Ldalvik/system/DexClassLoader;".to_string()));
+
+        // Dynamic loading alone is not enough
+        let report = DetectionReport::build(&[], &dex, None);
+        assert!(!report.is_packed());
+    }
+}
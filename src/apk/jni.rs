@@ -0,0 +1,273 @@
+//! JNI native methods
+//!
+//! Methods declared `native` have no bytecode: their implementation lives in a shared library
+//! shipped in `lib/<abi>/`. The VM binds them either by looking up a symbol whose name is derived
+//! from the method (`Java_<class>_<method>`, with the argument types appended for overloaded
+//! methods), or explicitly through `RegisterNatives`, usually called from `JNI_OnLoad`.
+//!
+//! This module computes the expected symbol names of each native method and matches them against
+//! the dynamic symbols exported by the libraries. Native methods without a matching symbol are
+//! likely registered dynamically; exported `Java_` symbols without a matching method may belong
+//! to code loaded at runtime.
+
+use std::fs::File;
+use std::io::Read;
+use log::warn;
+use zip::ZipArchive;
+
+use crate::dex::access_flags::AccessFlag;
+use crate::dex::file::DexFile;
+use crate::elf::ElfFile;
+use crate::error::DexError;
+
+/// Prefix of the symbols of statically bound native methods
+const JNI_SYMBOL_PREFIX: &str = "Java_";
+/// Function called when a library is loaded, where `RegisterNatives` is usually called
+const JNI_ONLOAD_SYMBOL: &str = "JNI_OnLoad";
+
+/// A native library of an APK
+#[derive(Debug, Clone)]
+pub struct NativeLibrary {
+    /// Path of the library in the APK (e.g., `lib/arm64-v8a/libfoo.so`)
+    pub path: String,
+    /// ABI of the library (e.g., `arm64-v8a`)
+    pub abi: String,
+    /// Exported symbols of native methods (`Java_*`)
+    pub jni_exports: Vec<String>,
+    /// Whether the library exports `JNI_OnLoad`
+    pub has_jni_onload: bool,
+}
+
+impl NativeLibrary {
+    /// Parse a native library from its raw bytes
+    pub fn build(path: &str, raw: &[u8]) -> Result<Self, DexError> {
+        let elf = ElfFile::build(raw)?;
+        let exports = elf.dynamic_symbols
+                         .iter()
+                         .filter(|symbol| symbol.is_defined())
+                         .map(|symbol| symbol.name.as_str())
+                         .collect::<Vec<&str>>();
+
+        let abi = path.strip_prefix("lib/")
+                      .and_then(|rest| rest.split('/').next())
+                      .unwrap_or_default();
+
+        Ok(NativeLibrary {
+            path: path.to_string(),
+            abi: abi.to_string(),
+            jni_exports: exports.iter()
+                                .filter(|name| name.starts_with(JNI_SYMBOL_PREFIX))
+                                .map(|name| name.to_string())
+                                .collect(),
+            has_jni_onload: exports.contains(&JNI_ONLOAD_SYMBOL),
+        })
+    }
+
+    /// Open the APK at the given path and parse all the libraries in `lib/`
+    ///
+    /// Libraries which are not valid ELF files are skipped.
+    pub fn build_from_apk(filepath: &str) -> Result<Vec<Self>, DexError> {
+        let raw_file = File::open(filepath)?;
+        let mut zip_file = ZipArchive::new(raw_file).map_err(|_| DexError::InvalidZipArchive)?;
+
+        let names = zip_file.file_names()
+                            .filter(|name| name.starts_with("lib/") && name.ends_with(".so"))
+                            .map(|name| name.to_string())
+                            .collect::<Vec<String>>();
+
+        let mut libraries = Vec::new();
+        for name in names {
+            let mut raw = Vec::new();
+            match zip_file.by_name(&name) {
+                Ok(mut entry) => entry.read_to_end(&mut raw)?,
+                Err(_) => continue,
+            };
+
+            match NativeLibrary::build(&name, &raw) {
+                Ok(library) => libraries.push(library),
+                Err(err) => warn!("cannot parse native library {name}: {err}"),
+            }
+        }
+
+        Ok(libraries)
+    }
+}
+
+/// A native method and the libraries exporting its implementation
+#[derive(Debug, Clone)]
+pub struct NativeMethodBinding {
+    /// Prototype of the method (e.g., `Lcom/example/Foo;->bar(I)V`)
+    pub method: String,
+    /// Symbol name without the arguments (e.g., `Java_com_example_Foo_bar`)
+    pub short_name: String,
+    /// Symbol name with the arguments (e.g., `Java_com_example_Foo_bar__I`)
+    pub long_name: String,
+    /// Paths of the libraries exporting one of the two symbols
+    pub libraries: Vec<String>,
+}
+
+/// An exported `Java_` symbol
+#[derive(Debug, Clone, PartialEq)]
+pub struct JniExport {
+    /// Path of the library exporting the symbol
+    pub library: String,
+    /// Name of the symbol
+    pub symbol: String,
+}
+
+/// Mapping between native methods and library symbols
+#[derive(Debug)]
+pub struct JniReport {
+    /// All native methods, with the libraries implementing them
+    pub bindings: Vec<NativeMethodBinding>,
+    /// Exported `Java_` symbols which do not match any native method
+    pub orphan_exports: Vec<JniExport>,
+    /// Paths of the libraries exporting `JNI_OnLoad`
+    pub onload_libraries: Vec<String>,
+}
+
+impl JniReport {
+    /// Match the native methods of the DEX file(s) with the symbols of the libraries
+    pub fn build(dex: &DexFile, libraries: &[NativeLibrary]) -> Self {
+        let mut bindings = Vec::new();
+
+        for class in dex.classes.items.iter() {
+            for method in class.get_methods() {
+                if !method.access_flags.contains(&AccessFlag::ACC_NATIVE) {
+                    continue;
+                }
+                let Some((short_name, long_name)) = jni_symbol_names(&method.proto) else {
+                    warn!("cannot compute JNI symbol of {}", method.proto);
+                    continue;
+                };
+
+                let libraries = libraries.iter()
+                                         .filter(|library| library.jni_exports.iter()
+                                                                  .any(|name| *name == short_name || *name == long_name))
+                                         .map(|library| library.path.clone())
+                                         .collect();
+
+                bindings.push(NativeMethodBinding {
+                    method: method.proto.clone(),
+                    short_name,
+                    long_name,
+                    libraries,
+                });
+            }
+        }
+
+        let mut orphan_exports = Vec::new();
+        for library in libraries.iter() {
+            for symbol in library.jni_exports.iter() {
+                let matched = bindings.iter().any(|binding| *symbol == binding.short_name
+                                                            || *symbol == binding.long_name);
+                if !matched {
+                    orphan_exports.push(JniExport {
+                        library: library.path.clone(),
+                        symbol: symbol.clone(),
+                    });
+                }
+            }
+        }
+
+        JniReport {
+            bindings,
+            orphan_exports,
+            onload_libraries: libraries.iter()
+                                       .filter(|library| library.has_jni_onload)
+                                       .map(|library| library.path.clone())
+                                       .collect(),
+        }
+    }
+
+    /// Get the native methods without an exported symbol
+    ///
+    /// If one of the libraries exports `JNI_OnLoad`, these methods are likely bound with
+    /// `RegisterNatives`.
+    pub fn unmatched_methods(&self) -> Vec<&NativeMethodBinding> {
+        self.bindings.iter().filter(|binding| binding.libraries.is_empty()).collect()
+    }
+}
+
+/// Compute the short and long JNI symbol names of a method from its prototype
+pub fn jni_symbol_names(proto: &str) -> Option<(String, String)> {
+    let (class, rest) = proto.split_once("->")?;
+    let (name, signature) = rest.split_once('(')?;
+    let (arguments, _) = signature.split_once(')')?;
+
+    let class = class.strip_prefix('L')?.strip_suffix(';')?;
+    let arguments = arguments.replace(' ', "");
+
+    let short_name = format!("{JNI_SYMBOL_PREFIX}{}_{}", mangle(class), mangle(name));
+    let long_name = format!("{short_name}__{}", mangle(&arguments));
+    Some((short_name, long_name))
+}
+
+/// Mangle a name as specified by JNI
+fn mangle(name: &str) -> String {
+    let mut mangled = String::new();
+
+    for unit in name.encode_utf16() {
+        match char::from_u32(unit as u32) {
+            Some('/') => mangled.push('_'),
+            Some('_') => mangled.push_str("_1"),
+            Some(';') => mangled.push_str("_2"),
+            Some('[') => mangled.push_str("_3"),
+            Some(chr) if chr.is_ascii_alphanumeric() => mangled.push(chr),
+            _ => mangled.push_str(&format!("_0{unit:04x}")),
+        }
+    }
+
+    mangled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::tests::fake_elf;
+    use crate::fixtures::{ fake_dex_file_with_methods, FakeMethod };
+
+    #[test]
+    fn test_jni_symbol_names() {
+        let (short_name, long_name) = jni_symbol_names("Lcom/my_app/Foo$Bar;->run(I [Ljava/lang/String;)V").unwrap();
+        assert_eq!(short_name, "Java_com_my_1app_Foo_00024Bar_run");
+        assert_eq!(long_name, "Java_com_my_1app_Foo_00024Bar_run__I_3Ljava_lang_String_2");
+
+        let (short_name, long_name) = jni_symbol_names("La;->b()V").unwrap();
+        assert_eq!(short_name, "Java_a_b");
+        assert_eq!(long_name, "Java_a_b__");
+
+        assert!(jni_symbol_names("not a prototype").is_none());
+    }
+
+    #[test]
+    fn test_native_library() {
+        let raw = fake_elf(&[("Java_a_b", 0x100, 4), ("JNI_OnLoad", 0x104, 4), ("malloc", 0, 0)], &[]);
+        let library = NativeLibrary::build("lib/arm64-v8a/libfoo.so", &raw).unwrap();
+        assert_eq!(library.abi, "arm64-v8a");
+        assert_eq!(library.jni_exports, vec!["Java_a_b"]);
+        assert!(library.has_jni_onload);
+    }
+
+    #[test]
+    fn test_jni_report() {
+        let native = |name| FakeMethod { class: "La;", name, access_flags: 0x109, code: None };
+        let dex = fake_dex_file_with_methods(&["La;"], &[], &[native("run"), native("stop")]);
+        let raw = fake_elf(&[("Java_a_run__", 0x100, 4), ("Java_a_gone", 0x104, 4), ("JNI_OnLoad", 0x108, 4)], &[]);
+        let library = NativeLibrary::build("lib/arm64-v8a/libfoo.so", &raw).unwrap();
+
+        let report = JniReport::build(&dex, &[library]);
+        let bindings = report.bindings.iter()
+                                      .map(|binding| (binding.method.as_str(), binding.libraries.clone()))
+                                      .collect::<Vec<_>>();
+        assert_eq!(bindings, vec![("La;->run()V", vec!["lib/arm64-v8a/libfoo.so".to_string()]),
+                                  ("La;->stop()V", vec![])]);
+        assert_eq!(report.unmatched_methods().iter().map(|binding| binding.method.as_str()).collect::<Vec<_>>(),
+                   vec!["La;->stop()V"]);
+        assert_eq!(report.orphan_exports, vec![JniExport {
+            library: "lib/arm64-v8a/libfoo.so".to_string(),
+            symbol: "Java_a_gone".to_string(),
+        }]);
+        assert_eq!(report.onload_libraries, vec!["lib/arm64-v8a/libfoo.so"]);
+    }
+}
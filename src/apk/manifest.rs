@@ -0,0 +1,322 @@
+//! Android manifest
+//!
+//! Decode the binary `AndroidManifest.xml` of an APK into the information needed to analyze the
+//! app: its package name, SDK versions, permissions, and components. Components can then be linked
+//! to the classes of the DEX files, e.g., to find the ones which are declared but not implemented
+//! (a common sign of code loaded at runtime).
+
+use std::fs::File;
+use std::io::Read;
+use zip::ZipArchive;
+
+use crate::apk::axml::{ XmlDocument, XmlElement };
+use crate::dex::file::DexFile;
+use crate::error::DexError;
+
+/// Path of the manifest in an APK
+pub const MANIFEST_PATH: &str = "AndroidManifest.xml";
+/// API level from which components with intent filters are no longer exported by default
+const EXPLICIT_EXPORT_SDK: u32 = 31;
+
+/// Kind of app component
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComponentKind {
+    Activity,
+    ActivityAlias,
+    Service,
+    Receiver,
+    Provider,
+}
+
+impl ComponentKind {
+    /// Get the kind of component declared by a manifest tag
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "activity" => Some(ComponentKind::Activity),
+            "activity-alias" => Some(ComponentKind::ActivityAlias),
+            "service" => Some(ComponentKind::Service),
+            "receiver" => Some(ComponentKind::Receiver),
+            "provider" => Some(ComponentKind::Provider),
+            _ => None
+        }
+    }
+}
+
+/// An intent filter of a component
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IntentFilter {
+    /// Actions handled by the filter
+    pub actions: Vec<String>,
+    /// Categories of the filter
+    pub categories: Vec<String>,
+    /// URI schemes of the `data` elements
+    pub schemes: Vec<String>,
+    /// URI hosts of the `data` elements
+    pub hosts: Vec<String>,
+}
+
+/// A component declared in the manifest
+#[derive(Debug, Clone)]
+pub struct Component {
+    /// Kind of component
+    pub kind: ComponentKind,
+    /// Fully qualified class name (e.g., `com.example.MainActivity`)
+    pub name: String,
+    /// Target activity of an activity alias, fully qualified
+    pub target_activity: Option<String>,
+    /// Value of the `exported` attribute, if present
+    pub exported: Option<bool>,
+    /// Value of the `enabled` attribute, if present
+    pub enabled: Option<bool>,
+    /// Permission required to interact with the component
+    pub permission: Option<String>,
+    /// Authorities of a content provider
+    pub authorities: Vec<String>,
+    /// Intent filters of the component
+    pub intent_filters: Vec<IntentFilter>,
+}
+
+impl Component {
+    /// Get the type descriptor of the class implementing the component (e.g.,
+    /// `Lcom/example/MainActivity;`)
+    ///
+    /// For an activity alias, this is the class of the target activity.
+    pub fn class_descriptor(&self) -> String {
+        let name = self.target_activity.as_ref().unwrap_or(&self.name);
+        format!("L{};", name.replace('.', "/"))
+    }
+
+    /// Check if the component can be started by other apps
+    ///
+    /// Without an explicit `exported` attribute, components with intent filters are exported.
+    /// Since Android 12 the attribute is mandatory for such components, so this default only
+    /// matters for apps targeting older versions.
+    pub fn is_exported(&self, target_sdk: Option<u32>) -> bool {
+        match self.exported {
+            Some(exported) => exported,
+            None => !self.intent_filters.is_empty()
+                    && target_sdk.is_none_or(|sdk| sdk < EXPLICIT_EXPORT_SDK),
+        }
+    }
+}
+
+/// Representation of an Android manifest
+#[derive(Debug)]
+pub struct Manifest {
+    /// Package name of the app
+    pub package: String,
+    /// Internal version number
+    pub version_code: Option<u32>,
+    /// Version displayed to users
+    pub version_name: Option<String>,
+    /// Minimum API level required by the app
+    pub min_sdk: Option<u32>,
+    /// API level targeted by the app
+    pub target_sdk: Option<u32>,
+    /// Permissions requested by the app (`uses-permission`)
+    pub uses_permissions: Vec<String>,
+    /// Permissions defined by the app (`permission`)
+    pub permissions: Vec<String>,
+    /// Class name of the `Application` subclass, if any
+    pub application_class: Option<String>,
+    /// Components declared by the app
+    pub components: Vec<Component>,
+    /// Decoded XML document, for the elements not covered above
+    pub document: XmlDocument,
+}
+
+impl Manifest {
+    /// Open the APK at the given path and decode its manifest
+    pub fn build_from_apk(filepath: &str) -> Result<Self, DexError> {
+        let raw_file = File::open(filepath)?;
+        let mut zip_file = ZipArchive::new(raw_file).map_err(|_| DexError::InvalidZipArchive)?;
+        let mut entry = zip_file.by_name(MANIFEST_PATH).map_err(|_| DexError::MissingManifest)?;
+
+        let mut raw = Vec::new();
+        entry.read_to_end(&mut raw)?;
+        Manifest::build(&raw)
+    }
+
+    /// Decode a manifest from the raw bytes of the binary XML file
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        let document = XmlDocument::build(raw)?;
+        let root = &document.root;
+        if root.name != "manifest" {
+            return Err(DexError::MissingManifest);
+        }
+
+        let package = root.get_attribute_value("package").unwrap_or_default().to_string();
+        let version_code = root.get_attribute("versionCode").and_then(|attr| attr.value.as_int());
+        let version_name = root.get_attribute_value("versionName").map(|name| name.to_string());
+
+        let uses_sdk = root.children_named("uses-sdk").next();
+        let min_sdk = uses_sdk.and_then(|element| Manifest::get_sdk(element, "minSdkVersion"));
+        let target_sdk = uses_sdk.and_then(|element| Manifest::get_sdk(element, "targetSdkVersion"));
+
+        let uses_permissions = Manifest::get_names(root, "uses-permission");
+        let permissions = Manifest::get_names(root, "permission");
+
+        let mut application_class = None;
+        let mut components = Vec::new();
+        if let Some(application) = root.children_named("application").next() {
+            application_class = application.get_attribute_value("name")
+                                           .map(|name| Manifest::qualify(&package, name));
+
+            for element in application.children.iter() {
+                if let Some(kind) = ComponentKind::from_tag(&element.name) {
+                    components.push(Manifest::parse_component(element, kind, &package));
+                }
+            }
+        }
+
+        Ok(Manifest {
+            package,
+            version_code,
+            version_name,
+            min_sdk,
+            target_sdk,
+            uses_permissions,
+            permissions,
+            application_class,
+            components,
+            document,
+        })
+    }
+
+    /// Get the components whose class is not defined in the given DEX file(s)
+    ///
+    /// Activity aliases are checked against their target activity.
+    pub fn missing_components(&self, dex: &DexFile) -> Vec<&Component> {
+        let class_names = dex.get_classes_names();
+
+        self.components.iter()
+                       .filter(|component| {
+                           let descriptor = component.class_descriptor();
+                           !class_names.iter().any(|name| **name == descriptor)
+                       })
+                       .collect()
+    }
+
+    /// Get the components which can be started by other apps
+    pub fn exported_components(&self) -> Vec<&Component> {
+        self.components.iter()
+                       .filter(|component| component.is_exported(self.target_sdk))
+                       .collect()
+    }
+
+    /// Get an SDK version, which is usually an integer but can be a codename
+    fn get_sdk(element: &XmlElement, name: &str) -> Option<u32> {
+        let attribute = element.get_attribute(name)?;
+        attribute.value.as_int()
+                 .or_else(|| attribute.raw_value.as_ref()?.parse().ok())
+    }
+
+    /// Get the `name` attribute of all the children with the given tag
+    fn get_names(element: &XmlElement, tag: &str) -> Vec<String> {
+        element.children_named(tag)
+               .filter_map(|child| child.get_attribute_value("name"))
+               .map(|name| name.to_string())
+               .collect()
+    }
+
+    /// Resolve a class name relative to the package (e.g., `.MainActivity`)
+    fn qualify(package: &str, name: &str) -> String {
+        if name.starts_with('.') {
+            format!("{package}{name}")
+        } else if !name.contains('.') {
+            format!("{package}.{name}")
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Decode a component element and its intent filters
+    fn parse_component(element: &XmlElement, kind: ComponentKind, package: &str) -> Component {
+        let intent_filters = element.children_named("intent-filter")
+                                    .map(|filter| IntentFilter {
+                                        actions: Manifest::get_names(filter, "action"),
+                                        categories: Manifest::get_names(filter, "category"),
+                                        schemes: Manifest::get_data_attributes(filter, "scheme"),
+                                        hosts: Manifest::get_data_attributes(filter, "host"),
+                                    })
+                                    .collect();
+
+        Component {
+            kind,
+            name: Manifest::qualify(package, element.get_attribute_value("name").unwrap_or_default()),
+            target_activity: element.get_attribute_value("targetActivity")
+                                    .map(|name| Manifest::qualify(package, name)),
+            exported: element.get_attribute("exported").and_then(|attr| attr.value.as_bool()),
+            enabled: element.get_attribute("enabled").and_then(|attr| attr.value.as_bool()),
+            permission: element.get_attribute_value("permission").map(|perm| perm.to_string()),
+            authorities: element.get_attribute_value("authorities")
+                                .map(|authorities| authorities.split(';')
+                                                              .map(|authority| authority.to_string())
+                                                              .collect())
+                                .unwrap_or_default(),
+            intent_filters,
+        }
+    }
+
+    /// Get an attribute of all the `data` elements of an intent filter
+    fn get_data_attributes(filter: &XmlElement, name: &str) -> Vec<String> {
+        filter.children_named("data")
+              .filter_map(|data| data.get_attribute_value(name))
+              .map(|value| value.to_string())
+              .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apk::axml::tests::{ fake_document, FakeElement, FakeValue };
+
+    #[test]
+    fn test_build() {
+        let strings = ["name", "exported", "targetSdkVersion", "package", "manifest", "uses-sdk",
+                       "uses-permission", "android.permission.INTERNET", "application",
+                       "activity", ".MainActivity", "intent-filter", "action",
+                       "android.intent.action.MAIN", "service", "com.other.Service", "com.example"];
+        let root = FakeElement {
+            name: 4,
+            attributes: vec![(3, FakeValue::String(16))],
+            children: vec![
+                FakeElement { name: 5, attributes: vec![(2, FakeValue::Typed(0x10, 30))], children: vec![] },
+                FakeElement { name: 6, attributes: vec![(0, FakeValue::String(7))], children: vec![] },
+                FakeElement { name: 8, attributes: vec![], children: vec![
+                    FakeElement { name: 9, attributes: vec![(0, FakeValue::String(10))], children: vec![
+                        FakeElement { name: 11, attributes: vec![], children: vec![
+                            FakeElement { name: 12, attributes: vec![(0, FakeValue::String(13))], children: vec![] },
+                        ] },
+                    ] },
+                    FakeElement { name: 14, attributes: vec![(0, FakeValue::String(15)),
+                                                             (1, FakeValue::Typed(0x12, 0))], children: vec![] },
+                ] },
+            ],
+        };
+        let raw = fake_document(&strings, &[0x01010003, 0x01010010, 0x01010270], &root);
+
+        let manifest = Manifest::build(&raw).unwrap();
+        assert_eq!(manifest.package, "com.example");
+        assert_eq!(manifest.target_sdk, Some(30));
+        assert_eq!(manifest.min_sdk, None);
+        assert_eq!(manifest.uses_permissions, vec!["android.permission.INTERNET"]);
+        assert_eq!(manifest.components.len(), 2);
+
+        let activity = &manifest.components[0];
+        assert_eq!(activity.kind, ComponentKind::Activity);
+        assert_eq!(activity.name, "com.example.MainActivity");
+        assert_eq!(activity.class_descriptor(), "Lcom/example/MainActivity;");
+        assert_eq!(activity.intent_filters[0].actions, vec!["android.intent.action.MAIN"]);
+        assert!(activity.is_exported(manifest.target_sdk));
+        assert!(!activity.is_exported(Some(31)));
+
+        let service = &manifest.components[1];
+        assert_eq!(service.kind, ComponentKind::Service);
+        assert_eq!(service.name, "com.other.Service");
+        assert_eq!(service.exported, Some(false));
+
+        assert_eq!(manifest.exported_components().len(), 1);
+    }
+}
@@ -0,0 +1,16 @@
+//! APK files
+//!
+//! An APK is a ZIP archive. Besides the DEX files, it contains the compiled manifest, the
+//! resources, the signatures, and native libraries. The modules in this directory deal with the
+//! parts of the archive which are not bytecode but are needed to analyze an app.
+
+pub mod discovery;
+pub mod chunks;
+pub mod axml;
+pub mod manifest;
+pub mod resources;
+pub mod signing;
+pub mod repackage;
+pub mod jni;
+pub mod frameworks;
+mod der;
@@ -0,0 +1,487 @@
+//! APK repackaging
+//!
+//! Rebuild an APK after modifying its DEX files: the entries of the original archive are copied
+//! in order (compressed entries are copied as-is), `classesN.dex` entries are replaced or added,
+//! and uncompressed entries are aligned the way `zipalign` does it (4 bytes, or 16 KiB for native
+//! libraries so they can be mapped directly from the APK).
+//!
+//! The archive can then be signed with the v1 (JAR signing, with SHA-256 digests) and v2 schemes,
+//! using an RSA key. Signing drops the signature files of the original archive; without a key,
+//! they are kept but no longer match the contents.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{ Cursor, Read, Seek, Write };
+use rsa::{ Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey };
+use rsa::pkcs8::{ DecodePrivateKey, DecodePublicKey };
+use sha2::{ Digest, Sha256 };
+use zip::{ CompressionMethod, ZipArchive, ZipWriter };
+use zip::result::ZipError;
+use zip::write::FileOptions;
+
+use crate::apk::der::*;
+use crate::apk::signing::{ APK_SIGNATURE_SCHEME_V2_BLOCK_ID, ApkSignatures, compute_content_digest,
+                           insert_signing_block };
+use crate::error::DexError;
+
+/// Alignment of uncompressed entries
+const ALIGNMENT: u16 = 4;
+/// Alignment of uncompressed native libraries
+const NATIVE_LIBRARY_ALIGNMENT: u16 = 16384;
+/// Signature algorithm of the v2 scheme: RSASSA-PKCS1-v1_5 with SHA2-256
+const SIGNATURE_RSA_PKCS1_V1_5_WITH_SHA256: u32 = 0x0103;
+/// Name of the signer in `META-INF/`
+const V1_SIGNER_NAME: &str = "CERT";
+
+/// OIDs used in the PKCS#7 signature of the v1 scheme
+const OID_SIGNED_DATA: [u8; 9] = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+const OID_DATA: [u8; 9] = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01];
+const OID_SHA256: [u8; 9] = [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_RSA_ENCRYPTION: [u8; 9] = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+
+/// An RSA key and its certificate, used to sign APKs
+#[derive(Debug)]
+pub struct SigningKey {
+    private_key: RsaPrivateKey,
+    /// DER-encoded X.509 certificate
+    certificate: Vec<u8>,
+}
+
+impl SigningKey {
+    /// Load a PKCS#8 DER-encoded private key and a DER-encoded X.509 certificate
+    ///
+    /// These are the formats of the `.pk8` and `.x509.der` files used by the platform tools.
+    pub fn build(private_key: &[u8], certificate: &[u8]) -> Result<Self, DexError> {
+        let private_key = RsaPrivateKey::from_pkcs8_der(private_key).map_err(|_| DexError::InvalidSigningKey)?;
+
+        let fields = CertificateFields::build(certificate)?;
+        let public_key = RsaPublicKey::from_public_key_der(fields.public_key)
+                                      .map_err(|_| DexError::InvalidSigningKey)?;
+        if public_key != private_key.to_public_key() {
+            return Err(DexError::InvalidSigningKey);
+        }
+
+        Ok(SigningKey {
+            private_key,
+            certificate: certificate.to_vec(),
+        })
+    }
+
+    /// Open the private key and certificate at the given paths and load them
+    pub fn build_from_files(private_key_path: &str, certificate_path: &str) -> Result<Self, DexError> {
+        let private_key = std::fs::read(private_key_path)?;
+        let certificate = std::fs::read(certificate_path)?;
+        SigningKey::build(&private_key, &certificate)
+    }
+
+    /// Sign the SHA-256 digest of the data with RSASSA-PKCS1-v1_5
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>, DexError> {
+        self.private_key.sign(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(data))
+                        .map_err(|_| DexError::InvalidSigningKey)
+    }
+}
+
+/// Fields of a certificate needed to sign
+struct CertificateFields<'a> {
+    issuer: &'a [u8],
+    serial_number: &'a [u8],
+    /// DER-encoded `SubjectPublicKeyInfo`
+    public_key: &'a [u8],
+}
+
+impl<'a> CertificateFields<'a> {
+    fn build(raw: &'a [u8]) -> Result<Self, DexError> {
+        let certificate = DerReader::new(raw).expect(TAG_SEQUENCE).map_err(|_| DexError::InvalidSigningKey)?;
+        let tbs = certificate.children().expect(TAG_SEQUENCE).map_err(|_| DexError::InvalidSigningKey)?;
+
+        let mut fields = tbs.children();
+        let _version = fields.optional(TAG_CONTEXT_0).map_err(|_| DexError::InvalidSigningKey)?;
+        let mut next = |tag| fields.expect(tag).map_err(|_| DexError::InvalidSigningKey);
+        let serial_number = next(TAG_INTEGER)?;
+        let _algorithm = next(TAG_SEQUENCE)?;
+        let issuer = next(TAG_SEQUENCE)?;
+        let _validity = next(TAG_SEQUENCE)?;
+        let _subject = next(TAG_SEQUENCE)?;
+        let public_key = next(TAG_SEQUENCE)?;
+
+        Ok(CertificateFields {
+            issuer: issuer.raw,
+            serial_number: serial_number.raw,
+            public_key: public_key.raw,
+        })
+    }
+}
+
+/// Rebuilds an APK with new DEX files
+#[derive(Debug)]
+pub struct ApkRepackager {
+    /// Raw bytes of the original archive
+    source: Vec<u8>,
+    /// New DEX files, by index (0 for `classes.dex`, 1 for `classes2.dex`, etc.)
+    dex_files: BTreeMap<usize, Vec<u8>>,
+    /// Key used to sign the archive
+    signing_key: Option<SigningKey>,
+}
+
+impl ApkRepackager {
+    /// Open the APK at the given path
+    pub fn build_from_file(filepath: &str) -> Result<Self, DexError> {
+        let mut raw = Vec::new();
+        File::open(filepath)?.read_to_end(&mut raw)?;
+        ApkRepackager::build(raw)
+    }
+
+    /// Create a repackager from the raw bytes of an APK
+    pub fn build(raw: Vec<u8>) -> Result<Self, DexError> {
+        ZipArchive::new(Cursor::new(&raw)).map_err(zip_error)?;
+
+        Ok(ApkRepackager {
+            source: raw,
+            dex_files: BTreeMap::new(),
+            signing_key: None,
+        })
+    }
+
+    /// Replace or add the DEX file at the given index (0 for `classes.dex`, 1 for
+    /// `classes2.dex`, etc.)
+    pub fn set_dex_file(&mut self, index: usize, bytes: Vec<u8>) {
+        self.dex_files.insert(index, bytes);
+    }
+
+    /// Sign the rebuilt archive with the given key
+    pub fn sign_with(&mut self, signing_key: SigningKey) {
+        self.signing_key = Some(signing_key);
+    }
+
+    /// Rebuild the archive and write it to the given path
+    pub fn write_to_file(&self, filepath: &str) -> Result<(), DexError> {
+        let raw = self.write()?;
+        File::create(filepath)?.write_all(&raw)?;
+        Ok(())
+    }
+
+    /// Rebuild the archive
+    pub fn write(&self) -> Result<Vec<u8>, DexError> {
+        let mut source = ZipArchive::new(Cursor::new(&self.source)).map_err(zip_error)?;
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let mut new_dex_files = self.dex_files.iter()
+                                              .map(|(index, bytes)| (dex_entry_name(*index), bytes))
+                                              .collect::<BTreeMap<String, &Vec<u8>>>();
+        // Digests of the entries, for the v1 scheme
+        let mut digests = Vec::new();
+
+        for idx in 0..source.len() {
+            let mut entry = source.by_index(idx).map_err(zip_error)?;
+            let name = entry.name().to_string();
+            if self.signing_key.is_some() && is_signature_file(&name) {
+                continue;
+            }
+
+            let mut options = FileOptions::default().last_modified_time(entry.last_modified());
+            if let Some(mode) = entry.unix_mode() {
+                options = options.unix_permissions(mode);
+            }
+
+            if entry.is_dir() {
+                writer.add_directory(name, options).map_err(zip_error)?;
+                continue;
+            }
+
+            if let Some(bytes) = new_dex_files.remove(&name) {
+                let compression = match entry.compression() {
+                    CompressionMethod::Stored => CompressionMethod::Stored,
+                    _ => CompressionMethod::Deflated,
+                };
+                write_entry(&mut writer, &name, compression, options, bytes)?;
+                digests.push((name, Sha256::digest(bytes).to_vec()));
+                continue;
+            }
+
+            if entry.compression() == CompressionMethod::Stored {
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                write_entry(&mut writer, &name, CompressionMethod::Stored, options, &data)?;
+                digests.push((name, Sha256::digest(&data).to_vec()));
+                continue;
+            }
+
+            if self.signing_key.is_some() {
+                let mut hasher = Sha256::new();
+                std::io::copy(&mut entry, &mut hasher)?;
+                digests.push((name, hasher.finalize().to_vec()));
+            }
+            drop(entry);
+            writer.raw_copy_file(source.by_index(idx).map_err(zip_error)?).map_err(zip_error)?;
+        }
+
+        let options = FileOptions::default();
+        for (name, bytes) in new_dex_files {
+            write_entry(&mut writer, &name, CompressionMethod::Deflated, options, bytes)?;
+            digests.push((name, Sha256::digest(bytes).to_vec()));
+        }
+
+        let Some(signing_key) = &self.signing_key else {
+            return Ok(writer.finish().map_err(zip_error)?.into_inner());
+        };
+
+        // v1 scheme: the manifest lists the digests of the entries, the signature file the
+        // digests of the manifest sections, and the PKCS#7 signature covers the signature file
+        let (manifest, signature_file) = v1_manifests(&digests);
+        let pkcs7 = v1_signature(signing_key, &signature_file)?;
+        for (name, data) in [("META-INF/MANIFEST.MF".to_string(), manifest),
+                             (format!("META-INF/{V1_SIGNER_NAME}.SF"), signature_file),
+                             (format!("META-INF/{V1_SIGNER_NAME}.RSA"), pkcs7)] {
+            write_entry(&mut writer, &name, CompressionMethod::Deflated, options, &data)?;
+        }
+        let zip = writer.finish().map_err(zip_error)?.into_inner();
+
+        // v2 scheme: the signing block goes right before the central directory
+        let layout = ApkSignatures::find_zip_layout(&zip)?;
+        let digest = compute_content_digest(&zip, &layout, layout.cd_offset, SIGNATURE_RSA_PKCS1_V1_5_WITH_SHA256)
+                                           .ok_or(DexError::InvalidSigningKey)?;
+        let block = v2_block(signing_key, &digest)?;
+        insert_signing_block(&zip, &[(APK_SIGNATURE_SCHEME_V2_BLOCK_ID, block)])
+    }
+}
+
+/// Get the name of the DEX file at the given index
+fn dex_entry_name(index: usize) -> String {
+    match index {
+        0 => "classes.dex".to_string(),
+        _ => format!("classes{}.dex", index + 1),
+    }
+}
+
+/// Check if the entry is part of a v1 signature
+fn is_signature_file(name: &str) -> bool {
+    let upper = name.to_ascii_uppercase();
+    let Some(file_name) = upper.strip_prefix("META-INF/") else {
+        return false;
+    };
+    !file_name.contains('/') && (file_name == "MANIFEST.MF"
+                                 || [".SF", ".RSA", ".DSA", ".EC"].iter().any(|ext| file_name.ends_with(ext)))
+}
+
+/// Write an entry, aligning its data if it is not compressed
+fn write_entry<W: Write + Seek>(writer: &mut ZipWriter<W>,
+                                name: &str,
+                                compression: CompressionMethod,
+                                options: FileOptions,
+                                data: &[u8]) -> Result<(), DexError> {
+    let options = options.compression_method(compression);
+    if compression == CompressionMethod::Stored {
+        let alignment = if name.ends_with(".so") { NATIVE_LIBRARY_ALIGNMENT } else { ALIGNMENT };
+        writer.start_file_aligned(name, options, alignment).map_err(zip_error)?;
+    } else {
+        writer.start_file(name, options).map_err(zip_error)?;
+    }
+    writer.write_all(data)?;
+    Ok(())
+}
+
+fn zip_error(_: ZipError) -> DexError {
+    DexError::InvalidZipArchive
+}
+
+/// Build the manifest and the signature file of the v1 scheme from the digests of the entries
+fn v1_manifests(digests: &[(String, Vec<u8>)]) -> (Vec<u8>, Vec<u8>) {
+    let mut manifest = Vec::new();
+    write_manifest_line(&mut manifest, "Manifest-Version: 1.0");
+    write_manifest_line(&mut manifest, "Created-By: rusty-dex");
+    manifest.extend_from_slice(b"\r\n");
+
+    let mut sections = Vec::new();
+    for (name, digest) in digests {
+        let mut section = Vec::new();
+        write_manifest_line(&mut section, &format!("Name: {name}"));
+        write_manifest_line(&mut section, &format!("SHA-256-Digest: {}", base64(digest)));
+        section.extend_from_slice(b"\r\n");
+
+        let mut signed_section = Vec::new();
+        write_manifest_line(&mut signed_section, &format!("Name: {name}"));
+        write_manifest_line(&mut signed_section, &format!("SHA-256-Digest: {}", base64(&Sha256::digest(&section))));
+        signed_section.extend_from_slice(b"\r\n");
+
+        manifest.extend_from_slice(&section);
+        sections.push(signed_section);
+    }
+
+    let mut signature_file = Vec::new();
+    write_manifest_line(&mut signature_file, "Signature-Version: 1.0");
+    write_manifest_line(&mut signature_file, "Created-By: rusty-dex");
+    write_manifest_line(&mut signature_file, &format!("SHA-256-Digest-Manifest: {}", base64(&Sha256::digest(&manifest))));
+    // Tell verifiers the APK is also signed with the v2 scheme, so stripping it is detected
+    write_manifest_line(&mut signature_file, "X-Android-APK-Signed: 2");
+    signature_file.extend_from_slice(b"\r\n");
+    signature_file.extend(sections.concat());
+
+    (manifest, signature_file)
+}
+
+/// Write a manifest line, split in lines of at most 72 bytes
+fn write_manifest_line(out: &mut Vec<u8>, line: &str) {
+    let mut bytes = line.as_bytes();
+    let mut max_size = 72;
+
+    loop {
+        let (head, tail) = bytes.split_at(bytes.len().min(max_size));
+        out.extend_from_slice(head);
+        out.extend_from_slice(b"\r\n");
+        if tail.is_empty() {
+            break;
+        }
+        // Continuation lines start with a space
+        out.push(b' ');
+        bytes = tail;
+        max_size = 71;
+    }
+}
+
+/// Build the PKCS#7 detached signature of the signature file
+fn v1_signature(signing_key: &SigningKey, signature_file: &[u8]) -> Result<Vec<u8>, DexError> {
+    let fields = CertificateFields::build(&signing_key.certificate)?;
+    let signature = signing_key.sign(signature_file)?;
+
+    let sha256 = encode(TAG_SEQUENCE, &[encode(TAG_OID, &OID_SHA256), encode(TAG_NULL, &[])].concat());
+    let rsa = encode(TAG_SEQUENCE, &[encode(TAG_OID, &OID_RSA_ENCRYPTION), encode(TAG_NULL, &[])].concat());
+    let signer_info = encode(TAG_SEQUENCE, &[encode(TAG_INTEGER, &[1]),
+                                             encode(TAG_SEQUENCE, &[fields.issuer, fields.serial_number].concat()),
+                                             sha256.clone(),
+                                             rsa,
+                                             encode(TAG_OCTET_STRING, &signature)].concat());
+    let signed_data = encode(TAG_SEQUENCE, &[encode(TAG_INTEGER, &[1]),
+                                             encode(TAG_SET, &sha256),
+                                             encode(TAG_SEQUENCE, &encode(TAG_OID, &OID_DATA)),
+                                             encode(TAG_CONTEXT_0, &signing_key.certificate),
+                                             encode(TAG_SET, &signer_info)].concat());
+
+    Ok(encode(TAG_SEQUENCE, &[encode(TAG_OID, &OID_SIGNED_DATA), encode(TAG_CONTEXT_0, &signed_data)].concat()))
+}
+
+/// Build the v2 scheme block with one signer
+fn v2_block(signing_key: &SigningKey, digest: &[u8]) -> Result<Vec<u8>, DexError> {
+    let algorithm = SIGNATURE_RSA_PKCS1_V1_5_WITH_SHA256.to_le_bytes();
+    let fields = CertificateFields::build(&signing_key.certificate)?;
+
+    let digests = prefixed(&prefixed(&[&algorithm[..], &prefixed(digest)].concat()));
+    let certificates = prefixed(&prefixed(&signing_key.certificate));
+    let signed_data = [digests, certificates, prefixed(&[])].concat();
+
+    let signature = signing_key.sign(&signed_data)?;
+    let signatures = prefixed(&prefixed(&[&algorithm[..], &prefixed(&signature)].concat()));
+
+    let signer = [prefixed(&signed_data), signatures, prefixed(fields.public_key)].concat();
+    Ok(prefixed(&prefixed(&signer)))
+}
+
+/// Prefix a value with its 32 bits length
+fn prefixed(value: &[u8]) -> Vec<u8> {
+    [&(value.len() as u32).to_le_bytes()[..], value].concat()
+}
+
+/// Encode bytes in base64, as used in JAR manifests
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let value = chunk.iter().enumerate().fold(0u32, |acc, (idx, byte)| acc | (*byte as u32) << (16 - 8 * idx));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * idx) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apk::signing::SignatureScheme;
+
+    const TEST_KEY: &[u8] = include_bytes!("../../tests/testkey.pk8");
+    const TEST_CERTIFICATE: &[u8] = include_bytes!("../../tests/testkey.x509.der");
+
+    fn fake_apk() -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data, compression) in [("AndroidManifest.xml", &b"<manifest/>"[..], CompressionMethod::Deflated),
+                                          ("classes.dex", b"dex\n035\0old", CompressionMethod::Deflated),
+                                          ("res/raw/a.bin", b"abc", CompressionMethod::Stored),
+                                          ("lib/arm64-v8a/libfoo.so", b"\x7fELF", CompressionMethod::Stored),
+                                          ("META-INF/OLD.RSA", b"signature", CompressionMethod::Deflated)] {
+            writer.start_file(name, FileOptions::default().compression_method(compression)).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn read_entry(apk: &[u8], name: &str) -> Option<(Vec<u8>, u64)> {
+        let mut zip_file = ZipArchive::new(Cursor::new(apk)).unwrap();
+        let mut entry = zip_file.by_name(name).ok()?;
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        Some((data, entry.data_start()))
+    }
+
+    #[test]
+    fn test_write() {
+        let mut repackager = ApkRepackager::build(fake_apk()).unwrap();
+        repackager.set_dex_file(0, b"dex\n035\0new".to_vec());
+        repackager.set_dex_file(2, b"dex\n035\0third".to_vec());
+        let apk = repackager.write().unwrap();
+
+        assert_eq!(read_entry(&apk, "classes.dex").unwrap().0, b"dex\n035\0new");
+        assert_eq!(read_entry(&apk, "classes3.dex").unwrap().0, b"dex\n035\0third");
+        assert!(read_entry(&apk, "classes2.dex").is_none());
+        assert_eq!(read_entry(&apk, "AndroidManifest.xml").unwrap().0, b"<manifest/>");
+        assert!(read_entry(&apk, "META-INF/OLD.RSA").is_some());
+
+        let (data, offset) = read_entry(&apk, "res/raw/a.bin").unwrap();
+        assert_eq!(data, b"abc");
+        assert_eq!(offset % ALIGNMENT as u64, 0);
+        let (_, offset) = read_entry(&apk, "lib/arm64-v8a/libfoo.so").unwrap();
+        assert_eq!(offset % NATIVE_LIBRARY_ALIGNMENT as u64, 0);
+    }
+
+    #[test]
+    fn test_write_signed() {
+        let mut repackager = ApkRepackager::build(fake_apk()).unwrap();
+        repackager.set_dex_file(0, b"dex\n035\0new".to_vec());
+        repackager.sign_with(SigningKey::build(TEST_KEY, TEST_CERTIFICATE).unwrap());
+        let apk = repackager.write().unwrap();
+
+        assert!(read_entry(&apk, "META-INF/OLD.RSA").is_none());
+        let (manifest, _) = read_entry(&apk, "META-INF/MANIFEST.MF").unwrap();
+        let manifest = String::from_utf8(manifest).unwrap();
+        let digest = base64(&Sha256::digest(b"dex\n035\0new"));
+        assert!(manifest.contains(&format!("Name: classes.dex\r\nSHA-256-Digest: {digest}\r\n")));
+
+        let signatures = ApkSignatures::build(&apk).unwrap();
+        for scheme in [SignatureScheme::V1, SignatureScheme::V2] {
+            let signers = signatures.signers_for(scheme);
+            assert_eq!(signers.len(), 1);
+            assert_eq!(signers[0].certificates[0].subject, "CN=Test Key, O=rusty-dex, C=US");
+        }
+        assert_eq!(signatures.signers_for(SignatureScheme::V2)[0].digests_verified(), Some(true));
+
+        // The certificate does not match the key
+        let other = crate::apk::signing::tests::fake_certificate("Other");
+        assert!(SigningKey::build(TEST_KEY, &other).is_err());
+    }
+
+    #[test]
+    fn test_manifest_encoding() {
+        let mut out = Vec::new();
+        write_manifest_line(&mut out, &format!("Name: {}", "a".repeat(100)));
+        let lines = out.split(|byte| *byte == b'\n').collect::<Vec<&[u8]>>();
+        assert_eq!(lines[0].len(), 73);
+        assert_eq!(lines[1][0], b' ');
+
+        assert_eq!(base64(b"abcd"), "YWJjZA==");
+        assert_eq!(base64(b"abcde"), "YWJjZGU=");
+        assert_eq!(base64(b"abc"), "YWJj");
+    }
+}
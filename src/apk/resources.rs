@@ -0,0 +1,517 @@
+//! Resource table
+//!
+//! `resources.arsc` maps resource IDs (`0xPPTTEEEE`: package, type, entry) to their names and
+//! values for each configuration (language, screen density, etc.). The bytecode only references
+//! resources by ID, e.g., `const v0, 0x7f0f0001`: the resource table gives these IDs a meaning
+//! such as `R.string.app_name`.
+//!
+//! The table is made of a global string pool, holding the string values, followed by one chunk
+//! per package. Each package has its own pools for the type names and entry names, then the
+//! entries of each type grouped by configuration.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use log::warn;
+use zip::ZipArchive;
+
+use crate::apk::chunks::*;
+use crate::bytes::{ slice_at, read_u16_at, read_u32_at };
+use crate::dex::file::DexFile;
+use crate::dex::instructions::Instructions;
+use crate::dex::opcodes::OpCode;
+use crate::error::DexError;
+
+/// Path of the resource table in an APK
+pub const RESOURCES_PATH: &str = "resources.arsc";
+
+/// Offset value of a missing entry
+const NO_ENTRY: u32 = 0xffffffff;
+/// Offset value of a missing entry, when offsets are stored on 16 bits
+const NO_ENTRY_16: u16 = 0xffff;
+/// Flags of type chunks
+const TYPE_FLAG_SPARSE: u8 = 0x01;
+const TYPE_FLAG_OFFSET16: u8 = 0x02;
+/// Flags of entries
+const ENTRY_FLAG_COMPLEX: u16 = 0x0001;
+const ENTRY_FLAG_COMPACT: u16 = 0x0008;
+/// Package ID of the framework resources
+const FRAMEWORK_PACKAGE_ID: u32 = 0x01;
+
+/// Configuration an entry applies to
+///
+/// Only the most common qualifiers are decoded; the raw bytes contain all of them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResConfig {
+    /// Language code (e.g., `fr`), empty for any
+    pub language: String,
+    /// Region code (e.g., `CA`), empty for any
+    pub region: String,
+    /// Screen density in dpi, zero for any
+    pub density: u16,
+    /// Minimum API level, zero for any
+    pub sdk_version: u16,
+    /// Raw configuration, without its size field
+    pub raw: Vec<u8>,
+}
+
+impl ResConfig {
+    /// Parse the configuration at the given offset
+    fn build(raw: &[u8], offset: usize) -> Result<Self, DexError> {
+        let size = read_u32_at(raw, offset)? as usize;
+        let config = slice_at(raw, offset, size.max(4))?;
+
+        let read_code = |start: usize| {
+            config.get(start..start + 2)
+                  .filter(|code| code[0] != 0 && code[0] & 0x80 == 0)
+                  .map(|code| code.iter()
+                                  .take_while(|byte| **byte != 0)
+                                  .map(|byte| *byte as char)
+                                  .collect())
+                  .unwrap_or_default()
+        };
+
+        Ok(ResConfig {
+            language: read_code(8),
+            region: read_code(10),
+            density: read_u16_at(config, 14).unwrap_or(0),
+            sdk_version: read_u16_at(config, 24).unwrap_or(0),
+            raw: config[4..].to_vec(),
+        })
+    }
+
+    /// Check if this is the default configuration, used when no other one matches
+    pub fn is_default(&self) -> bool {
+        self.raw.iter().all(|byte| *byte == 0)
+    }
+}
+
+/// Value of a resource entry
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResEntryValue {
+    /// A single typed value (string, integer, reference, etc.)
+    Simple(ResValue),
+    /// A bag of values (style, array, plurals, etc.), with its parent and (name, value) pairs
+    Complex {
+        parent: u32,
+        items: Vec<(u32, ResValue)>,
+    },
+}
+
+/// An entry of the resource table, for one configuration
+#[derive(Debug, Clone)]
+pub struct ResEntry {
+    /// Configuration of the entry
+    pub config: ResConfig,
+    /// Value of the entry
+    pub value: ResEntryValue,
+}
+
+/// A resource, with its entries for all configurations
+#[derive(Debug, Clone)]
+pub struct Resource {
+    /// Resource ID
+    pub id: u32,
+    /// Name of the package defining the resource
+    pub package: String,
+    /// Name of the type (e.g., `string`, `drawable`)
+    pub type_name: String,
+    /// Name of the entry (e.g., `app_name`)
+    pub name: String,
+    /// Values of the resource for each configuration
+    pub entries: Vec<ResEntry>,
+}
+
+impl Resource {
+    /// Get the name of the resource as in Java code, e.g., `R.string.app_name`
+    ///
+    /// Framework resources are prefixed with `android.`.
+    pub fn qualified_name(&self) -> String {
+        let prefix = if self.id >> 24 == FRAMEWORK_PACKAGE_ID { "android." } else { "" };
+        format!("{prefix}R.{}.{}", self.type_name, self.name)
+    }
+
+    /// Get the entry for the default configuration, or the first entry if there is none
+    pub fn default_entry(&self) -> Option<&ResEntry> {
+        self.entries.iter()
+                    .find(|entry| entry.config.is_default())
+                    .or(self.entries.first())
+    }
+}
+
+/// A reference to a resource found in the bytecode
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceReference {
+    /// Where the ID was found: a method or a static field
+    pub location: String,
+    /// Resource ID
+    pub id: u32,
+    /// Qualified name of the resource
+    pub name: String,
+    /// Default value of the resource, if it is a simple value
+    pub value: Option<String>,
+}
+
+/// Representation of a resource table
+#[derive(Debug)]
+pub struct ResourceTable {
+    /// Global string pool, holding the string values
+    pub strings: StringPool,
+    /// Names of the packages, by package ID
+    pub packages: BTreeMap<u32, String>,
+    /// Resources, by resource ID
+    pub resources: BTreeMap<u32, Resource>,
+}
+
+impl ResourceTable {
+    /// Open the APK at the given path and parse its resource table
+    pub fn build_from_apk(filepath: &str) -> Result<Self, DexError> {
+        let raw_file = File::open(filepath)?;
+        let mut zip_file = ZipArchive::new(raw_file).map_err(|_| DexError::InvalidZipArchive)?;
+        let mut entry = zip_file.by_name(RESOURCES_PATH).map_err(|_| DexError::MissingResourceTable)?;
+
+        let mut raw = Vec::new();
+        entry.read_to_end(&mut raw)?;
+        ResourceTable::build(&raw)
+    }
+
+    /// Parse a resource table from its raw bytes
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        let header = ChunkHeader::build(raw, 0)?;
+        if header.kind != RES_TABLE_TYPE {
+            return Err(DexError::InvalidResourceChunk);
+        }
+
+        let mut table = ResourceTable {
+            strings: StringPool::default(),
+            packages: BTreeMap::new(),
+            resources: BTreeMap::new(),
+        };
+
+        let end = header.size as usize;
+        let mut offset = header.header_size as usize;
+        while offset + 8 <= end {
+            let chunk = ChunkHeader::build(raw, offset)?;
+            match chunk.kind {
+                RES_STRING_POOL_TYPE => table.strings = StringPool::build(raw, offset)?,
+                RES_TABLE_PACKAGE_TYPE => table.parse_package(raw, offset, &chunk)?,
+                kind => warn!("skipping unknown resource table chunk type {kind:#x}"),
+            }
+            offset += chunk.size as usize;
+        }
+
+        Ok(table)
+    }
+
+    /// Get a resource from its ID
+    pub fn get(&self, id: u32) -> Option<&Resource> {
+        self.resources.get(&id)
+    }
+
+    /// Get the qualified name of a resource from its ID (e.g., `R.string.app_name`)
+    pub fn get_name(&self, id: u32) -> Option<String> {
+        self.get(id).map(|resource| resource.qualified_name())
+    }
+
+    /// Get the default value of a resource, if it is a simple value
+    ///
+    /// References to other resources are followed, up to a few levels.
+    pub fn get_default_value(&self, id: u32) -> Option<String> {
+        let mut id = id;
+        for _ in 0..8 {
+            let entry = self.get(id)?.default_entry()?;
+            match &entry.value {
+                ResEntryValue::Simple(value) if value.kind == ResValueType::Reference
+                                             && self.get(value.data).is_some() => id = value.data,
+                ResEntryValue::Simple(value) => return Some(value.format(&self.strings)),
+                ResEntryValue::Complex { .. } => return None,
+            }
+        }
+
+        None
+    }
+
+    /// Find all the references to known resources in the bytecode and static field values
+    pub fn find_references(&self, dex: &DexFile) -> Vec<ResourceReference> {
+        let mut references = Vec::new();
+        let mut add_reference = |location: &str, id: u32| {
+            if let Some(resource) = self.get(id) {
+                references.push(ResourceReference {
+                    location: location.to_string(),
+                    id,
+                    name: resource.qualified_name(),
+                    value: self.get_default_value(id),
+                });
+            }
+        };
+
+        for class in dex.classes.items.iter() {
+            for (field, value) in class.get_static_values() {
+                if let Some(id) = value.as_int() {
+                    add_reference(field, id as u32);
+                }
+            }
+
+            for method in class.get_methods() {
+                let Some(code_item) = &method.code_item else { continue };
+                for inst in code_item.insns.iter().flatten() {
+                    if let Some(id) = resource_id_from_instruction(inst) {
+                        add_reference(&method.proto, id);
+                    }
+                }
+            }
+        }
+
+        references
+    }
+
+    /// Parse a package chunk and add its resources to the table
+    fn parse_package(&mut self, raw: &[u8], offset: usize, header: &ChunkHeader) -> Result<(), DexError> {
+        let package = slice_at(raw, offset, header.size as usize)?;
+        let package_id = read_u32_at(package, 8)?;
+        let name_units = slice_at(package, 12, 256)?.chunks_exact(2)
+                                                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                                                    .take_while(|unit| *unit != 0)
+                                                    .collect::<Vec<u16>>();
+        let package_name = String::from_utf16_lossy(&name_units);
+
+        let type_strings = StringPool::build(package, read_u32_at(package, 268)? as usize)?;
+        let key_strings = StringPool::build(package, read_u32_at(package, 276)? as usize)?;
+
+        let mut chunk_offset = header.header_size as usize;
+        while chunk_offset + 8 <= package.len() {
+            let chunk = ChunkHeader::build(package, chunk_offset)?;
+            if chunk.kind == RES_TABLE_TYPE_TYPE {
+                let type_chunk = slice_at(package, chunk_offset, chunk.size as usize)?;
+                self.parse_type(type_chunk, &chunk, package_id, &package_name, &type_strings, &key_strings)?;
+            }
+            chunk_offset += chunk.size as usize;
+        }
+
+        self.packages.insert(package_id, package_name);
+        Ok(())
+    }
+
+    /// Parse a type chunk: the entries of one type for one configuration
+    fn parse_type(&mut self,
+                  chunk: &[u8],
+                  header: &ChunkHeader,
+                  package_id: u32,
+                  package_name: &str,
+                  type_strings: &StringPool,
+                  key_strings: &StringPool) -> Result<(), DexError> {
+        let type_id = slice_at(chunk, 8, 1)?[0] as u32;
+        let flags = slice_at(chunk, 9, 1)?[0];
+        let entry_count = read_u32_at(chunk, 12)? as usize;
+        let entries_start = read_u32_at(chunk, 16)? as usize;
+        let config = ResConfig::build(chunk, 20)?;
+        let offsets_start = header.header_size as usize;
+
+        // (entry index, offset of the entry from `entries_start`)
+        let mut offsets = Vec::new();
+        if flags & TYPE_FLAG_SPARSE != 0 {
+            for idx in 0..entry_count {
+                let pos = offsets_start + idx * 4;
+                offsets.push((read_u16_at(chunk, pos)? as u32, read_u16_at(chunk, pos + 2)? as usize * 4));
+            }
+        } else if flags & TYPE_FLAG_OFFSET16 != 0 {
+            for idx in 0..entry_count {
+                let entry_offset = read_u16_at(chunk, offsets_start + idx * 2)?;
+                if entry_offset != NO_ENTRY_16 {
+                    offsets.push((idx as u32, entry_offset as usize * 4));
+                }
+            }
+        } else {
+            for idx in 0..entry_count {
+                let entry_offset = read_u32_at(chunk, offsets_start + idx * 4)?;
+                if entry_offset != NO_ENTRY {
+                    offsets.push((idx as u32, entry_offset as usize));
+                }
+            }
+        }
+
+        // Type IDs start at 1
+        let type_name = type_strings.get(type_id.wrapping_sub(1)).unwrap_or_default().to_string();
+
+        for (entry_idx, entry_offset) in offsets {
+            let pos = entries_start + entry_offset;
+            let (key, value) = ResourceTable::parse_entry(chunk, pos)?;
+            let id = (package_id << 24) | (type_id << 16) | entry_idx;
+
+            let resource = self.resources.entry(id).or_insert_with(|| Resource {
+                id,
+                package: package_name.to_string(),
+                type_name: type_name.clone(),
+                name: key_strings.get(key).unwrap_or_default().to_string(),
+                entries: Vec::new(),
+            });
+            resource.entries.push(ResEntry {
+                config: config.clone(),
+                value,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Parse an entry, returning its key index and its value
+    fn parse_entry(chunk: &[u8], pos: usize) -> Result<(u32, ResEntryValue), DexError> {
+        let size = read_u16_at(chunk, pos)?;
+        let flags = read_u16_at(chunk, pos + 2)?;
+
+        // Compact entries store the key in the size field and the value type in the flags
+        if flags & ENTRY_FLAG_COMPACT != 0 {
+            let value = ResValue {
+                kind: ResValueType::parse((flags >> 8) as u8),
+                data: read_u32_at(chunk, pos + 4)?,
+            };
+            return Ok((size as u32, ResEntryValue::Simple(value)));
+        }
+
+        let key = read_u32_at(chunk, pos + 4)?;
+        if flags & ENTRY_FLAG_COMPLEX == 0 {
+            return Ok((key, ResEntryValue::Simple(ResValue::build(chunk, pos + size as usize)?)));
+        }
+
+        let parent = read_u32_at(chunk, pos + 8)?;
+        let count = read_u32_at(chunk, pos + 12)? as usize;
+        let mut items = Vec::new();
+        for idx in 0..count {
+            let item_pos = pos + size as usize + idx * (4 + ResValue::SIZE);
+            items.push((read_u32_at(chunk, item_pos)?, ResValue::build(chunk, item_pos + 4)?));
+        }
+
+        Ok((key, ResEntryValue::Complex { parent, items }))
+    }
+}
+
+/// Get the resource ID loaded by an instruction, if any
+///
+/// Resource IDs are loaded with `const` (or `const/high16` when the entry index is zero). The
+/// value is only considered an ID if its package and type parts are set.
+pub fn resource_id_from_instruction(inst: &Instructions) -> Option<u32> {
+    let bytes = inst.bytes();
+    let value = match inst.opcode() {
+        OpCode::CONST => (bytes[1] as u32) | ((bytes[2] as u32) << 16),
+        OpCode::CONST_HIGH16 => (bytes[1] as u32) << 16,
+        _ => return None,
+    };
+
+    if value >> 24 != 0 && (value >> 16) & 0xff != 0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apk::chunks::tests::fake_string_pool;
+    use crate::fixtures::{ fake_dex_file_with_methods, FakeMethod };
+
+    /// Build a resource table with one package (0x7f) and one type, with the given entries as
+    /// (key index, value type, value data) for the default configuration
+    fn fake_table(global: &[&str], types: &[&str], keys: &[&str], entries: &[(u32, u8, u32)]) -> Vec<u8> {
+        let mut type_chunk = Vec::new();
+        let header_size = 20 + 64;
+        let entries_start = header_size + 4 * entries.len();
+        type_chunk.extend_from_slice(&RES_TABLE_TYPE_TYPE.to_le_bytes());
+        type_chunk.extend_from_slice(&(header_size as u16).to_le_bytes());
+        type_chunk.extend_from_slice(&((entries_start + 16 * entries.len()) as u32).to_le_bytes());
+        type_chunk.extend_from_slice(&[1, 0, 0, 0]);
+        type_chunk.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        type_chunk.extend_from_slice(&(entries_start as u32).to_le_bytes());
+        type_chunk.extend_from_slice(&64u32.to_le_bytes());
+        type_chunk.extend_from_slice(&[0; 60]);
+        for idx in 0..entries.len() {
+            type_chunk.extend_from_slice(&(16 * idx as u32).to_le_bytes());
+        }
+        for (key, kind, data) in entries {
+            type_chunk.extend_from_slice(&8u16.to_le_bytes());
+            type_chunk.extend_from_slice(&0u16.to_le_bytes());
+            type_chunk.extend_from_slice(&key.to_le_bytes());
+            type_chunk.extend_from_slice(&[0x08, 0x00, 0x00, *kind]);
+            type_chunk.extend_from_slice(&data.to_le_bytes());
+        }
+
+        let type_strings = fake_string_pool(types);
+        let key_strings = fake_string_pool(keys);
+        let mut package = Vec::new();
+        package.extend_from_slice(&RES_TABLE_PACKAGE_TYPE.to_le_bytes());
+        package.extend_from_slice(&288u16.to_le_bytes());
+        let package_size = 288 + type_strings.len() + key_strings.len() + type_chunk.len();
+        package.extend_from_slice(&(package_size as u32).to_le_bytes());
+        package.extend_from_slice(&0x7fu32.to_le_bytes());
+        let mut name = [0u8; 256];
+        for (idx, byte) in b"com.example".iter().enumerate() {
+            name[idx * 2] = *byte;
+        }
+        package.extend_from_slice(&name);
+        package.extend_from_slice(&288u32.to_le_bytes());
+        package.extend_from_slice(&0u32.to_le_bytes());
+        package.extend_from_slice(&((288 + type_strings.len()) as u32).to_le_bytes());
+        package.extend_from_slice(&[0; 8]);
+        package.extend_from_slice(&type_strings);
+        package.extend_from_slice(&key_strings);
+        package.extend_from_slice(&type_chunk);
+
+        let global_strings = fake_string_pool(global);
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&RES_TABLE_TYPE.to_le_bytes());
+        raw.extend_from_slice(&12u16.to_le_bytes());
+        raw.extend_from_slice(&((12 + global_strings.len() + package.len()) as u32).to_le_bytes());
+        raw.extend_from_slice(&1u32.to_le_bytes());
+        raw.extend_from_slice(&global_strings);
+        raw.extend_from_slice(&package);
+        raw
+    }
+
+    #[test]
+    fn test_build() {
+        let raw = fake_table(&["Example"], &["string"], &["app_name", "alias"],
+                             &[(0, 0x03, 0), (1, 0x01, 0x7f010000)]);
+        let table = ResourceTable::build(&raw).unwrap();
+
+        assert_eq!(table.packages.get(&0x7f).unwrap(), "com.example");
+        assert_eq!(table.resources.len(), 2);
+        assert_eq!(table.get_name(0x7f010000).unwrap(), "R.string.app_name");
+        assert_eq!(table.get_default_value(0x7f010000).unwrap(), "Example");
+        assert_eq!(table.get_name(0x7f010001).unwrap(), "R.string.alias");
+        assert_eq!(table.get_default_value(0x7f010001).unwrap(), "Example");
+        assert!(table.get(0x7f010002).is_none());
+        assert!(table.get(0x7f010000).unwrap().default_entry().unwrap().config.is_default());
+    }
+
+    #[test]
+    fn test_find_references() {
+        let raw = fake_table(&["Example"], &["string"], &["app_name", "alias"],
+                             &[(0, 0x03, 0), (1, 0x01, 0x7f010000)]);
+        let table = ResourceTable::build(&raw).unwrap();
+
+        // const v0, #0x7f010001; const/high16 v0, #0x7f010000; const v0, #0x7f010005 (unknown);
+        // const v0, #0x00010001 (not an ID); return-void
+        let dex = fake_dex_file_with_methods(&["La;"], &[], &[FakeMethod {
+            class: "La;",
+            name: "load",
+            access_flags: 0x9,
+            code: Some((1, 0, 0, vec![0x0014, 0x0001, 0x7f01, 0x0015, 0x7f01, 0x0014, 0x0005, 0x7f01,
+                                      0x0014, 0x0001, 0x0001, 0x000e])),
+        }]);
+
+        let references = table.find_references(&dex);
+        let found = references.iter()
+                              .map(|reference| (reference.id, reference.name.as_str(), reference.value.as_deref()))
+                              .collect::<Vec<_>>();
+        assert_eq!(found, vec![(0x7f010001, "R.string.alias", Some("Example")),
+                               (0x7f010000, "R.string.app_name", Some("Example"))]);
+        assert!(references.iter().all(|reference| reference.location == "La;->load()V"));
+    }
+
+    #[test]
+    fn test_build_invalid() {
+        let mut raw = fake_table(&[], &["string"], &["app_name"], &[(0, 0x03, 0)]);
+        raw.truncate(raw.len() - 4);
+        assert!(ResourceTable::build(&raw).is_err());
+    }
+}
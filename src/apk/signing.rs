@@ -0,0 +1,647 @@
+//! APK signatures
+//!
+//! APKs are signed with one or more schemes:
+//!   * v1 (JAR signing): a PKCS#7 signature in `META-INF/*.RSA`, `*.DSA`, or `*.EC`;
+//!   * v2, v3, and v3.1: blocks of the APK Signing Block, stored between the ZIP entries and the
+//!     central directory.
+//!
+//! This module extracts the signer certificates of every scheme. For v2 and v3 signers, it also
+//! recomputes the digest of the archive contents and compares it to the signed one. The
+//! signatures themselves are not checked: a matching digest means the archive was not modified
+//! after signing, provided the signature over the digest is valid.
+
+use std::fs::File;
+use std::io::{ Cursor, Read };
+use log::warn;
+use sha2::{ Digest, Sha256, Sha512 };
+use zip::ZipArchive;
+
+use crate::apk::der::*;
+use crate::bytes::{ slice_at, read_u16_at, read_u32_at, read_u64_at };
+use crate::error::DexError;
+
+/// Magic at the end of the APK Signing Block
+pub(crate) const APK_SIG_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+/// Magic of the ZIP end of central directory record
+const EOCD_MAGIC: u32 = 0x06054b50;
+/// Size of the end of central directory record, without its comment
+pub(crate) const EOCD_SIZE: usize = 22;
+/// Size of the chunks used to compute the digest of the archive contents
+const DIGEST_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// IDs of the signature scheme blocks in the APK Signing Block
+pub const APK_SIGNATURE_SCHEME_V2_BLOCK_ID: u32 = 0x7109871a;
+pub const APK_SIGNATURE_SCHEME_V3_BLOCK_ID: u32 = 0xf05368c0;
+pub const APK_SIGNATURE_SCHEME_V31_BLOCK_ID: u32 = 0x1b93ad61;
+
+/// Names of the attributes commonly found in certificate names, by OID
+const NAME_ATTRIBUTES: [(&str, &str); 7] = [
+    ("2.5.4.3", "CN"),
+    ("2.5.4.6", "C"),
+    ("2.5.4.7", "L"),
+    ("2.5.4.8", "ST"),
+    ("2.5.4.10", "O"),
+    ("2.5.4.11", "OU"),
+    ("1.2.840.113549.1.9.1", "E"),
+];
+
+/// APK signature schemes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureScheme {
+    V1,
+    V2,
+    V3,
+    V31,
+}
+
+/// Information about a signer certificate
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    /// Distinguished name of the subject (e.g., `CN=Android Debug, O=Android, C=US`)
+    pub subject: String,
+    /// Distinguished name of the issuer
+    pub issuer: String,
+    /// Serial number, in hexadecimal
+    pub serial_number: String,
+    /// Start of the validity period (e.g., `2023-01-31T12:00:00Z`)
+    pub not_before: String,
+    /// End of the validity period
+    pub not_after: String,
+    /// SHA-256 digest of the DER-encoded certificate, in hexadecimal
+    pub sha256_fingerprint: String,
+    /// DER-encoded certificate
+    pub raw: Vec<u8>,
+}
+
+impl Certificate {
+    /// Decode a DER-encoded X.509 certificate
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        let certificate = DerReader::new(raw).expect(TAG_SEQUENCE)?;
+        let tbs_certificate = certificate.children().expect(TAG_SEQUENCE)?;
+
+        let mut fields = tbs_certificate.children();
+        let _version = fields.optional(TAG_CONTEXT_0)?;
+        let serial = fields.expect(TAG_INTEGER)?;
+        let _signature = fields.expect(TAG_SEQUENCE)?;
+        let issuer = fields.expect(TAG_SEQUENCE)?;
+        let validity = fields.expect(TAG_SEQUENCE)?;
+        let subject = fields.expect(TAG_SEQUENCE)?;
+
+        let mut validity = validity.children();
+        let not_before = Certificate::format_time(&validity.read()?)?;
+        let not_after = Certificate::format_time(&validity.read()?)?;
+
+        Ok(Certificate {
+            subject: Certificate::format_name(&subject)?,
+            issuer: Certificate::format_name(&issuer)?,
+            serial_number: to_hex(serial.content),
+            not_before,
+            not_after,
+            sha256_fingerprint: to_hex(&Sha256::digest(certificate.raw)),
+            raw: certificate.raw.to_vec(),
+        })
+    }
+
+    /// Format a distinguished name, most specific attribute first (as `keytool` does)
+    fn format_name(name: &DerElement) -> Result<String, DexError> {
+        let mut attributes = Vec::new();
+
+        let mut sets = name.children();
+        while !sets.is_empty() {
+            let mut set = sets.expect(TAG_SET)?.children();
+            while !set.is_empty() {
+                let mut attribute = set.expect(TAG_SEQUENCE)?.children();
+                let oid = attribute.expect(TAG_OID)?.oid()?;
+                let value = attribute.read()?.string();
+
+                let key = NAME_ATTRIBUTES.iter()
+                                         .find(|(attr_oid, _)| *attr_oid == oid)
+                                         .map(|(_, key)| key.to_string())
+                                         .unwrap_or(oid);
+                attributes.push(format!("{key}={value}"));
+            }
+        }
+
+        attributes.reverse();
+        Ok(attributes.join(", "))
+    }
+
+    /// Format a UTC or generalized time as an ISO 8601 string
+    fn format_time(time: &DerElement) -> Result<String, DexError> {
+        let value = String::from_utf8_lossy(time.content);
+        let digits = value.trim_end_matches('Z');
+        if !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(DexError::InvalidDerEncoding);
+        }
+
+        let full = match (time.tag, digits.len()) {
+            (TAG_UTC_TIME, 12) => {
+                let century = if &digits[0..2] < "50" { "20" } else { "19" };
+                format!("{century}{digits}")
+            },
+            (TAG_GENERALIZED_TIME, 14) => digits.to_string(),
+            _ => return Err(DexError::InvalidDerEncoding),
+        };
+
+        Ok(format!("{}-{}-{}T{}:{}:{}Z",
+                   &full[0..4], &full[4..6], &full[6..8], &full[8..10], &full[10..12], &full[12..14]))
+    }
+}
+
+/// Digest of the archive contents, as signed by a v2 or v3 signer
+#[derive(Debug, Clone)]
+pub struct ContentDigest {
+    /// Signature algorithm ID (e.g., `0x0103` for RSASSA-PKCS1-v1_5 with SHA-256)
+    pub algorithm: u32,
+    /// Signed digest
+    pub digest: Vec<u8>,
+    /// Whether the digest matches the archive, `None` if the algorithm is not supported
+    pub verified: Option<bool>,
+}
+
+/// A signer of the APK
+#[derive(Debug, Clone)]
+pub struct Signer {
+    /// Scheme of the signature
+    pub scheme: SignatureScheme,
+    /// Certificate chain of the signer, the signer's certificate first
+    pub certificates: Vec<Certificate>,
+    /// Signed digests of the archive contents (v2 and v3 only)
+    pub digests: Vec<ContentDigest>,
+    /// Minimum API level the signer applies to (v3 only)
+    pub min_sdk: Option<u32>,
+    /// Maximum API level the signer applies to (v3 only)
+    pub max_sdk: Option<u32>,
+}
+
+impl Signer {
+    /// Check the digests of the signer
+    ///
+    /// Returns `None` if no digest could be checked (v1 signers, unsupported algorithms).
+    pub fn digests_verified(&self) -> Option<bool> {
+        self.digests.iter()
+                    .filter_map(|digest| digest.verified)
+                    .reduce(|acc, verified| acc && verified)
+    }
+}
+
+/// Location of the ZIP structures needed to find and verify the APK Signing Block
+pub(crate) struct ZipLayout {
+    /// Offset of the central directory
+    pub cd_offset: usize,
+    /// Offset of the end of central directory record
+    pub eocd_offset: usize,
+}
+
+/// APK Signing Block
+struct SigningBlock<'a> {
+    /// Offset of the block in the archive
+    offset: usize,
+    /// (ID, value) pairs of the block
+    pairs: Vec<(u32, &'a [u8])>,
+}
+
+/// Signatures of an APK
+#[derive(Debug)]
+pub struct ApkSignatures {
+    /// All signers, whatever their scheme
+    pub signers: Vec<Signer>,
+}
+
+impl ApkSignatures {
+    /// Open the APK at the given path and extract its signatures
+    pub fn build_from_file(filepath: &str) -> Result<Self, DexError> {
+        let mut raw = Vec::new();
+        File::open(filepath)?.read_to_end(&mut raw)?;
+        ApkSignatures::build(&raw)
+    }
+
+    /// Extract the signatures of an APK from its raw bytes
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        let mut signers = ApkSignatures::v1_signers(raw)?;
+
+        let layout = ApkSignatures::find_zip_layout(raw)?;
+        if let Some(block) = ApkSignatures::find_signing_block(raw, &layout)? {
+            for (id, value) in block.pairs {
+                let scheme = match id {
+                    APK_SIGNATURE_SCHEME_V2_BLOCK_ID => SignatureScheme::V2,
+                    APK_SIGNATURE_SCHEME_V3_BLOCK_ID => SignatureScheme::V3,
+                    APK_SIGNATURE_SCHEME_V31_BLOCK_ID => SignatureScheme::V31,
+                    _ => continue,
+                };
+                let mut block_signers = ApkSignatures::parse_scheme_block(value, scheme)?;
+                for signer in block_signers.iter_mut() {
+                    for digest in signer.digests.iter_mut() {
+                        digest.verified = compute_content_digest(raw, &layout, block.offset, digest.algorithm)
+                                              .map(|computed| computed == digest.digest);
+                    }
+                }
+                signers.extend(block_signers);
+            }
+        }
+
+        Ok(ApkSignatures { signers })
+    }
+
+    /// Get the signers using the given scheme
+    pub fn signers_for(&self, scheme: SignatureScheme) -> Vec<&Signer> {
+        self.signers.iter().filter(|signer| signer.scheme == scheme).collect()
+    }
+
+    /// Extract the certificates of the PKCS#7 signatures in `META-INF/`
+    fn v1_signers(raw: &[u8]) -> Result<Vec<Signer>, DexError> {
+        let mut zip_file = ZipArchive::new(Cursor::new(raw)).map_err(|_| DexError::InvalidZipArchive)?;
+        let names = zip_file.file_names()
+                            .filter(|name| {
+                                let upper = name.to_ascii_uppercase();
+                                upper.starts_with("META-INF/") && [".RSA", ".DSA", ".EC"].iter()
+                                                                                         .any(|ext| upper.ends_with(ext))
+                            })
+                            .map(|name| name.to_string())
+                            .collect::<Vec<String>>();
+
+        let mut signers = Vec::new();
+        for name in names {
+            let mut pkcs7 = Vec::new();
+            match zip_file.by_name(&name) {
+                Ok(mut entry) => entry.read_to_end(&mut pkcs7)?,
+                Err(_) => continue,
+            };
+
+            match ApkSignatures::parse_pkcs7_certificates(&pkcs7) {
+                Ok(certificates) => signers.push(Signer {
+                    scheme: SignatureScheme::V1,
+                    certificates,
+                    digests: Vec::new(),
+                    min_sdk: None,
+                    max_sdk: None,
+                }),
+                Err(err) => warn!("cannot decode v1 signature {name}: {err}"),
+            }
+        }
+
+        Ok(signers)
+    }
+
+    /// Get the certificates of a PKCS#7 `SignedData` structure
+    fn parse_pkcs7_certificates(raw: &[u8]) -> Result<Vec<Certificate>, DexError> {
+        let content_info = DerReader::new(raw).expect(TAG_SEQUENCE)?;
+        let mut content_info = content_info.children();
+        let _content_type = content_info.expect(TAG_OID)?;
+        let signed_data = content_info.expect(TAG_CONTEXT_0)?.children().expect(TAG_SEQUENCE)?;
+
+        let mut fields = signed_data.children();
+        let _version = fields.expect(TAG_INTEGER)?;
+        let _digest_algorithms = fields.expect(TAG_SET)?;
+        let _content = fields.expect(TAG_SEQUENCE)?;
+
+        let mut certificates = Vec::new();
+        if let Some(set) = fields.optional(TAG_CONTEXT_0)? {
+            let mut reader = set.children();
+            while !reader.is_empty() {
+                certificates.push(Certificate::build(reader.read()?.raw)?);
+            }
+        }
+
+        Ok(certificates)
+    }
+
+    /// Locate the central directory and the end of central directory record
+    pub(crate) fn find_zip_layout(raw: &[u8]) -> Result<ZipLayout, DexError> {
+        // The record is followed by a comment of up to 65535 bytes
+        let last = raw.len().checked_sub(EOCD_SIZE).ok_or(DexError::InvalidZipArchive)?;
+        let first = last.saturating_sub(u16::MAX as usize);
+
+        for eocd_offset in (first..=last).rev() {
+            if read_u32_at(raw, eocd_offset)? == EOCD_MAGIC
+                && read_u16_at(raw, eocd_offset + 20)? as usize == raw.len() - eocd_offset - EOCD_SIZE {
+                let cd_offset = read_u32_at(raw, eocd_offset + 16)? as usize;
+                if cd_offset > eocd_offset {
+                    return Err(DexError::InvalidZipArchive);
+                }
+                return Ok(ZipLayout { cd_offset, eocd_offset });
+            }
+        }
+
+        Err(DexError::InvalidZipArchive)
+    }
+
+    /// Find the APK Signing Block, right before the central directory
+    fn find_signing_block<'a>(raw: &'a [u8], layout: &ZipLayout) -> Result<Option<SigningBlock<'a>>, DexError> {
+        let Some(footer_offset) = layout.cd_offset.checked_sub(24) else {
+            return Ok(None);
+        };
+        if slice_at(raw, footer_offset + 8, 16)? != APK_SIG_BLOCK_MAGIC {
+            return Ok(None);
+        }
+
+        let size = read_u64_at(raw, footer_offset)? as usize;
+        let block_start = layout.cd_offset.checked_sub(size)
+                                          .and_then(|offset| offset.checked_sub(8))
+                                          .ok_or(DexError::InvalidApkSigningBlock)?;
+        if read_u64_at(raw, block_start)? as usize != size {
+            return Err(DexError::InvalidApkSigningBlock);
+        }
+
+        let mut pairs = Vec::new();
+        let mut pos = block_start + 8;
+        while pos < footer_offset {
+            let pair_size = read_u64_at(raw, pos)? as usize;
+            let max_size = footer_offset.checked_sub(pos + 8).ok_or(DexError::InvalidApkSigningBlock)?;
+            if pair_size < 4 || pair_size > max_size {
+                return Err(DexError::InvalidApkSigningBlock);
+            }
+            let id = read_u32_at(raw, pos + 8)?;
+            pairs.push((id, slice_at(raw, pos + 12, pair_size - 4)?));
+            pos += 8 + pair_size;
+        }
+
+        Ok(Some(SigningBlock {
+            offset: block_start,
+            pairs,
+        }))
+    }
+
+    /// Parse a v2 or v3 block: a sequence of signers
+    fn parse_scheme_block(value: &[u8], scheme: SignatureScheme) -> Result<Vec<Signer>, DexError> {
+        let mut signers = Vec::new();
+        let mut signers_reader = LengthPrefixed::new(LengthPrefixed::new(value).read()?);
+
+        while !signers_reader.is_empty() {
+            let mut signer = LengthPrefixed::new(signers_reader.read()?);
+            let mut signed_data = LengthPrefixed::new(signer.read()?);
+
+            let mut digests = Vec::new();
+            let mut digests_reader = LengthPrefixed::new(signed_data.read()?);
+            while !digests_reader.is_empty() {
+                let mut digest = LengthPrefixed::new(digests_reader.read()?);
+                let algorithm = digest.read_u32()?;
+                digests.push(ContentDigest {
+                    algorithm,
+                    digest: digest.read()?.to_vec(),
+                    verified: None,
+                });
+            }
+
+            let mut certificates = Vec::new();
+            let mut certificates_reader = LengthPrefixed::new(signed_data.read()?);
+            while !certificates_reader.is_empty() {
+                certificates.push(Certificate::build(certificates_reader.read()?)?);
+            }
+
+            let (min_sdk, max_sdk) = match scheme {
+                SignatureScheme::V3 | SignatureScheme::V31 => (Some(signed_data.read_u32()?),
+                                                               Some(signed_data.read_u32()?)),
+                _ => (None, None),
+            };
+
+            signers.push(Signer {
+                scheme,
+                certificates,
+                digests,
+                min_sdk,
+                max_sdk,
+            });
+        }
+
+        Ok(signers)
+    }
+}
+
+/// Reader over a sequence of values prefixed by their 32 bits length, as used by the v2 and v3
+/// signature schemes
+struct LengthPrefixed<'a> {
+    raw: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> LengthPrefixed<'a> {
+    fn new(raw: &'a [u8]) -> Self {
+        LengthPrefixed { raw, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.raw.len()
+    }
+
+    fn read_u32(&mut self) -> Result<u32, DexError> {
+        let value = read_u32_at(self.raw, self.pos).map_err(|_| DexError::InvalidApkSigningBlock)?;
+        self.pos += 4;
+        Ok(value)
+    }
+
+    fn read(&mut self) -> Result<&'a [u8], DexError> {
+        let size = self.read_u32()? as usize;
+        let value = slice_at(self.raw, self.pos, size).map_err(|_| DexError::InvalidApkSigningBlock)?;
+        self.pos += size;
+        Ok(value)
+    }
+}
+
+/// Compute the digest of the archive contents for the given signature algorithm
+///
+/// The contents are the ZIP entries, the central directory, and the end of central directory
+/// record (pointing to the signing block instead of the central directory), split in chunks of
+/// 1 MiB. Returns `None` for unsupported algorithms (e.g., verity-based digests).
+pub(crate) fn compute_content_digest(raw: &[u8], layout: &ZipLayout, block_start: usize, algorithm: u32) -> Option<Vec<u8>> {
+    let mut eocd = raw[layout.eocd_offset..].to_vec();
+    eocd[16..20].copy_from_slice(&(block_start as u32).to_le_bytes());
+
+    let sections = [&raw[..block_start], &raw[layout.cd_offset..layout.eocd_offset], &eocd[..]];
+    match algorithm {
+        0x0101 | 0x0103 | 0x0201 | 0x0301 => Some(chunked_digest::<Sha256>(&sections)),
+        0x0102 | 0x0104 | 0x0202 => Some(chunked_digest::<Sha512>(&sections)),
+        _ => None,
+    }
+}
+
+/// Compute the digest of each 1 MiB chunk of the sections, then the digest of the chunk digests
+fn chunked_digest<D: Digest>(sections: &[&[u8]]) -> Vec<u8> {
+    let mut chunk_count = 0u32;
+    let mut chunk_digests = Vec::new();
+
+    for section in sections {
+        for chunk in section.chunks(DIGEST_CHUNK_SIZE) {
+            let mut hasher = D::new();
+            hasher.update([0xa5]);
+            hasher.update((chunk.len() as u32).to_le_bytes());
+            hasher.update(chunk);
+            chunk_digests.extend_from_slice(&hasher.finalize());
+            chunk_count += 1;
+        }
+    }
+
+    let mut hasher = D::new();
+    hasher.update([0x5a]);
+    hasher.update(chunk_count.to_le_bytes());
+    hasher.update(&chunk_digests);
+    hasher.finalize().to_vec()
+}
+
+/// Insert an APK Signing Block with the given (ID, value) pairs before the central directory
+pub(crate) fn insert_signing_block(zip: &[u8], pairs: &[(u32, Vec<u8>)]) -> Result<Vec<u8>, DexError> {
+    let layout = ApkSignatures::find_zip_layout(zip)?;
+
+    let mut content = Vec::new();
+    for (id, value) in pairs {
+        content.extend_from_slice(&((value.len() + 4) as u64).to_le_bytes());
+        content.extend_from_slice(&id.to_le_bytes());
+        content.extend_from_slice(value);
+    }
+    let size = (content.len() + 24) as u64;
+    let mut block = size.to_le_bytes().to_vec();
+    block.extend_from_slice(&content);
+    block.extend_from_slice(&size.to_le_bytes());
+    block.extend_from_slice(APK_SIG_BLOCK_MAGIC);
+
+    let mut apk = zip[..layout.cd_offset].to_vec();
+    apk.extend_from_slice(&block);
+    apk.extend_from_slice(&zip[layout.cd_offset..]);
+    let eocd_offset = layout.eocd_offset + block.len();
+    let cd_offset = u32::try_from(layout.cd_offset + block.len()).map_err(|_| DexError::InvalidZipArchive)?;
+    apk[eocd_offset + 16..eocd_offset + 20].copy_from_slice(&cd_offset.to_le_bytes());
+    Ok(apk)
+}
+
+/// Format bytes as a lowercase hexadecimal string
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if content.len() < 0x80 {
+            out.push(content.len() as u8);
+        } else {
+            out.push(0x82);
+            out.extend_from_slice(&(content.len() as u16).to_be_bytes());
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    fn name(common_name: &str, country: &str) -> Vec<u8> {
+        let cn = der(TAG_SET, &der(TAG_SEQUENCE, &[der(TAG_OID, &[0x55, 0x04, 0x03]),
+                                                   der(0x0c, common_name.as_bytes())].concat()));
+        let c = der(TAG_SET, &der(TAG_SEQUENCE, &[der(TAG_OID, &[0x55, 0x04, 0x06]),
+                                                  der(0x13, country.as_bytes())].concat()));
+        der(TAG_SEQUENCE, &[c, cn].concat())
+    }
+
+    /// Build a certificate with the fields we decode (the keys and signature are not valid)
+    pub(crate) fn fake_certificate(common_name: &str) -> Vec<u8> {
+        let algorithm = der(TAG_SEQUENCE, &der(TAG_OID, &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b]));
+        let validity = der(TAG_SEQUENCE, &[der(TAG_UTC_TIME, b"230131120000Z"),
+                                           der(TAG_GENERALIZED_TIME, b"20530131120000Z")].concat());
+        let tbs = der(TAG_SEQUENCE, &[der(TAG_CONTEXT_0, &der(TAG_INTEGER, &[0x02])),
+                                      der(TAG_INTEGER, &[0x01, 0x23]),
+                                      algorithm.clone(),
+                                      name("Issuer", "US"),
+                                      validity,
+                                      name(common_name, "FR"),
+                                      der(TAG_SEQUENCE, &[])].concat());
+        der(TAG_SEQUENCE, &[tbs, algorithm, der(0x03, &[0x00])].concat())
+    }
+
+    /// Build a v2 block with one signer
+    fn fake_v2_block(algorithm: u32, digest: &[u8], certificate: &[u8]) -> Vec<u8> {
+        fn prefixed(value: &[u8]) -> Vec<u8> {
+            [&(value.len() as u32).to_le_bytes()[..], value].concat()
+        }
+
+        let digests = prefixed(&prefixed(&[&algorithm.to_le_bytes()[..], &prefixed(digest)].concat()));
+        let certificates = prefixed(&prefixed(certificate));
+        let signed_data = prefixed(&[digests, certificates, prefixed(&[])].concat());
+        let signer = prefixed(&[signed_data, prefixed(&[]), prefixed(&[])].concat());
+        prefixed(&signer)
+    }
+
+    fn fake_zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_certificate() {
+        let raw = fake_certificate("Test");
+        let certificate = Certificate::build(&raw).unwrap();
+        assert_eq!(certificate.subject, "CN=Test, C=FR");
+        assert_eq!(certificate.issuer, "CN=Issuer, C=US");
+        assert_eq!(certificate.serial_number, "0123");
+        assert_eq!(certificate.not_before, "2023-01-31T12:00:00Z");
+        assert_eq!(certificate.not_after, "2053-01-31T12:00:00Z");
+        assert_eq!(certificate.sha256_fingerprint, to_hex(&Sha256::digest(&raw)));
+    }
+
+    #[test]
+    fn test_v1_signature() {
+        let certificate = fake_certificate("V1");
+        let signed_data = der(TAG_SEQUENCE, &[der(TAG_INTEGER, &[0x01]),
+                                              der(TAG_SET, &[]),
+                                              der(TAG_SEQUENCE, &[]),
+                                              der(TAG_CONTEXT_0, &certificate)].concat());
+        let pkcs7 = der(TAG_SEQUENCE, &[der(TAG_OID, &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02]),
+                                        der(TAG_CONTEXT_0, &signed_data)].concat());
+        let apk = fake_zip(&[("classes.dex", b"dex\n035\0"), ("META-INF/CERT.RSA", &pkcs7)]);
+
+        let signatures = ApkSignatures::build(&apk).unwrap();
+        let signers = signatures.signers_for(SignatureScheme::V1);
+        assert_eq!(signers.len(), 1);
+        assert_eq!(signers[0].certificates[0].subject, "CN=V1, C=FR");
+        assert_eq!(signers[0].digests_verified(), None);
+    }
+
+    #[test]
+    fn test_v2_signature() {
+        let zip = fake_zip(&[("classes.dex", b"dex\n035\0")]);
+        let certificate = fake_certificate("V2");
+
+        // The digest does not cover the block contents, so it can be computed with a placeholder
+        let placeholder = insert_signing_block(&zip, &[(APK_SIGNATURE_SCHEME_V2_BLOCK_ID,
+                                                        fake_v2_block(0x0103, &[0; 32], &certificate))]).unwrap();
+        let layout = ApkSignatures::find_zip_layout(&placeholder).unwrap();
+        let block_start = ApkSignatures::find_signing_block(&placeholder, &layout).unwrap().unwrap().offset;
+        let digest = compute_content_digest(&placeholder, &layout, block_start, 0x0103).unwrap();
+
+        let apk = insert_signing_block(&zip, &[(APK_SIGNATURE_SCHEME_V2_BLOCK_ID,
+                                                fake_v2_block(0x0103, &digest, &certificate))]).unwrap();
+        let signatures = ApkSignatures::build(&apk).unwrap();
+        let signers = signatures.signers_for(SignatureScheme::V2);
+        assert_eq!(signers.len(), 1);
+        assert_eq!(signers[0].certificates[0].subject, "CN=V2, C=FR");
+        assert_eq!(signers[0].digests_verified(), Some(true));
+
+        // Tamper with the contents of the archive
+        let mut tampered = apk.clone();
+        let dex_offset = tampered.windows(4).position(|window| window == b"dex\n").unwrap();
+        tampered[dex_offset + 4] = b'9';
+        let signatures = ApkSignatures::build(&tampered).unwrap();
+        assert_eq!(signatures.signers[0].digests_verified(), Some(false));
+    }
+
+    #[test]
+    fn test_truncated_signing_block() {
+        let zip = fake_zip(&[("classes.dex", b"dex\n035\0")]);
+        let mut apk = insert_signing_block(&zip, &[(APK_SIGNATURE_SCHEME_V2_BLOCK_ID, Vec::new())]).unwrap();
+        let layout = ApkSignatures::find_zip_layout(&apk).unwrap();
+        let block_start = ApkSignatures::find_signing_block(&apk, &layout).unwrap().unwrap().offset;
+
+        // Declare a 28-byte block, too short to hold a pair before the footer
+        apk[block_start + 8..block_start + 16].copy_from_slice(&28u64.to_le_bytes());
+        apk[block_start + 20..block_start + 28].copy_from_slice(&28u64.to_le_bytes());
+        assert!(matches!(ApkSignatures::find_signing_block(&apk, &layout), Err(DexError::InvalidApkSigningBlock)));
+        assert!(matches!(ApkSignatures::build(&apk), Err(DexError::InvalidApkSigningBlock)));
+    }
+}
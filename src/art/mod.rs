@@ -0,0 +1,9 @@
+//! Android Runtime artifacts
+//!
+//! When an app is installed, ART verifies and compiles its DEX files and stores the results next
+//! to the APK. These files all embed the original bytecode in some form: the parsers in this
+//! module extract it so it can go through the usual `DexReader`/`DexFile` pipeline.
+
+pub mod vdex;
+pub mod oat;
+pub mod profile;
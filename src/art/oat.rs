@@ -0,0 +1,397 @@
+//! OAT files
+//!
+//! OAT files (`.oat`, or `.odex` when stored next to an APK) are ELF shared objects produced by
+//! `dex2oat`. The `oatdata` dynamic symbol points to the OAT header, which is followed by a
+//! key-value store and by a table describing each DEX file the code was compiled from.
+//!
+//! Up to Android 7, the DEX files are copied in the `oatdata` region. Since Android 8 they live in
+//! the companion VDEX file and the offsets in the table refer to that file instead:
+//! `OatFile::resolve_from_vdex` extracts them from there.
+//!
+//! The layout of the header and of the DEX files table changes with almost every version. Rather
+//! than hard-coding each layout, the parser uses the fields that never moved and validates the
+//! others (e.g., locations must be printable strings) to find its way through the file.
+
+use std::path::Path;
+use log::warn;
+
+use crate::bytes::{ slice_at, read_u32_at };
+use crate::dex::header::{ DexKind, dex_slice_at };
+use crate::dex::reader::DexReader;
+use crate::elf::ElfFile;
+use crate::error::DexError;
+
+/// Magic bytes at the start of the OAT header
+const OAT_MAGIC: [u8; 4] = *b"oat\n";
+/// First version with a separate table of DEX files (Android 8.1)
+const SEPARATE_DEX_FILES_TABLE_VERSION: u32 = 127;
+/// Maximum length of a DEX file location we accept as plausible
+const MAX_LOCATION_SIZE: usize = 4096;
+/// Offset of the `class_defs_size` field in a DEX header
+const DEX_CLASS_DEFS_SIZE_OFFSET: usize = 96;
+
+/// Representation of the OAT header
+#[derive(Debug)]
+pub struct OatHeader {
+    /// Version of the OAT format
+    pub version: u32,
+    /// Checksum of the OAT file
+    pub checksum: u32,
+    /// Instruction set the code was compiled for (ART `InstructionSet` value)
+    pub instruction_set: u32,
+    /// Bitmap of the instruction set features
+    pub instruction_set_features: u32,
+    /// Number of DEX files the code was compiled from
+    pub dex_file_count: u32,
+    /// Key-value store (compiler filter, class path, command line, etc.)
+    pub key_value_store: Vec<(String, String)>,
+}
+
+/// A DEX file referenced by an OAT file
+#[derive(Debug)]
+pub struct OatDexFile {
+    /// Original location of the DEX file (e.g., `/data/app/.../base.apk!classes2.dex`)
+    pub location: String,
+    /// Checksum of the original DEX file
+    pub location_checksum: u32,
+    /// Offset of the DEX file, in the `oatdata` region or in the companion VDEX file
+    pub dex_file_offset: u32,
+    /// Raw bytes of the DEX file, `None` until they are found
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl OatDexFile {
+    /// Get the kind of the DEX file, if its bytes were found
+    pub fn kind(&self) -> Option<DexKind> {
+        DexKind::from_magic(self.bytes.as_ref()?)
+    }
+}
+
+/// Representation of an OAT file
+#[derive(Debug)]
+pub struct OatFile {
+    /// Offset of the `oatdata` region in the ELF file
+    pub oatdata_offset: u64,
+    /// OAT header
+    pub header: OatHeader,
+    /// DEX files the code was compiled from
+    pub dex_files: Vec<OatDexFile>,
+}
+
+impl OatFile {
+    /// Open the OAT file at the given path and parse it
+    ///
+    /// If some DEX files are not embedded in the OAT file, they are looked up in the VDEX file
+    /// with the same name, if there is one.
+    pub fn build_from_file(filepath: &str) -> Result<Self, DexError> {
+        let raw = std::fs::read(filepath)?;
+        let mut oat = OatFile::build(&raw)?;
+
+        let vdex_path = Path::new(filepath).with_extension("vdex");
+        if oat.dex_files.iter().any(|dex_file| dex_file.bytes.is_none()) && vdex_path.exists() {
+            let raw_vdex = std::fs::read(vdex_path)?;
+            oat.resolve_from_vdex(&raw_vdex);
+        }
+
+        Ok(oat)
+    }
+
+    /// Parse an OAT file from its raw bytes
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        let elf = ElfFile::build(raw)?;
+        let oatdata = elf.get_dynamic_symbol("oatdata").ok_or(DexError::MissingElfSymbol)?;
+        let oatdata_offset = elf.virtual_address_to_offset(oatdata.value)
+                                .ok_or(DexError::InvalidSectionBounds)?;
+
+        let oat = match oatdata.size {
+            0 => raw.get(oatdata_offset as usize..).ok_or(DexError::InvalidSectionBounds)?,
+            size => slice_at(raw, oatdata_offset as usize, size as usize)?,
+        };
+
+        if slice_at(oat, 0, 4)? != OAT_MAGIC {
+            return Err(DexError::InvalidOatMagic);
+        }
+
+        let version = slice_at(oat, 4, 4)?.iter()
+                                          .take_while(|byte| byte.is_ascii_digit())
+                                          .fold(0, |acc, byte| acc * 10 + (byte - b'0') as u32);
+        let checksum                 = read_u32_at(oat, 8)?;
+        let instruction_set          = read_u32_at(oat, 12)?;
+        let instruction_set_features = read_u32_at(oat, 16)?;
+        let dex_file_count           = read_u32_at(oat, 20)?;
+
+        let key_value_store = OatFile::find_key_value_store(oat);
+
+        // Find the DEX files table, either at the offset stored in the header, or right after the
+        // key-value store for older versions
+        let mut candidates = Vec::new();
+        if version >= SEPARATE_DEX_FILES_TABLE_VERSION {
+            candidates.push(read_u32_at(oat, 24)? as usize);
+        }
+        if let Some((kv_end, _)) = &key_value_store {
+            candidates.push(*kv_end);
+        }
+
+        let mut dex_files = Vec::new();
+        if dex_file_count != 0 {
+            let mut offset = candidates.into_iter()
+                                       .find(|&offset| OatFile::read_location(oat, offset).is_some())
+                                       .ok_or(DexError::InvalidOatDexFilesTable)?;
+
+            for idx in 0..dex_file_count {
+                let (location, pos) = OatFile::read_location(oat, offset)
+                                              .ok_or(DexError::InvalidOatDexFilesTable)?;
+                let location_checksum = read_u32_at(oat, pos)?;
+                let dex_file_offset = read_u32_at(oat, pos + 4)?;
+                let bytes = dex_slice_at(oat, dex_file_offset as usize).map(|(_, bytes)| bytes.to_vec());
+
+                if idx + 1 < dex_file_count {
+                    offset = OatFile::find_next_entry(oat, pos + 8, version, bytes.as_deref())
+                                     .ok_or(DexError::InvalidOatDexFilesTable)?;
+                }
+
+                dex_files.push(OatDexFile {
+                    location,
+                    location_checksum,
+                    dex_file_offset,
+                    bytes,
+                });
+            }
+        }
+
+        Ok(OatFile {
+            oatdata_offset,
+            header: OatHeader {
+                version,
+                checksum,
+                instruction_set,
+                instruction_set_features,
+                dex_file_count,
+                key_value_store: key_value_store.map(|(_, pairs)| pairs).unwrap_or_default(),
+            },
+            dex_files,
+        })
+    }
+
+    /// Locate and decode the key-value store
+    ///
+    /// The store is preceded by its size, which is the last field of the header. The header size
+    /// depends on the version so we try all plausible sizes and keep the first one which leads to
+    /// a valid store: a list of NUL-terminated, printable keys and values. Returns the offset of
+    /// the end of the store along with the decoded pairs.
+    fn find_key_value_store(oat: &[u8]) -> Option<(usize, Vec<(String, String)>)> {
+        for header_size in (56..=100).step_by(4) {
+            let Ok(store_size) = read_u32_at(oat, header_size - 4) else { continue };
+            let Ok(store) = slice_at(oat, header_size, store_size as usize) else { continue };
+
+            if store.last() != Some(&0) {
+                continue;
+            }
+
+            let tokens: Vec<&[u8]> = store[..store.len() - 1].split(|&byte| byte == 0).collect();
+            if !tokens.len().is_multiple_of(2) || tokens[0].is_empty()
+                || tokens[0].iter().any(|byte| !byte.is_ascii_graphic()) {
+                continue;
+            }
+
+            let pairs = tokens.chunks_exact(2)
+                              .map(|pair| (String::from_utf8_lossy(pair[0]).to_string(),
+                                           String::from_utf8_lossy(pair[1]).to_string()))
+                              .collect();
+            return Some((header_size + store_size as usize, pairs));
+        }
+
+        None
+    }
+
+    /// Read a DEX file location at `offset` if it looks valid
+    ///
+    /// Returns the location and the offset right after it.
+    fn read_location(oat: &[u8], offset: usize) -> Option<(String, usize)> {
+        let size = read_u32_at(oat, offset).ok()? as usize;
+        if size == 0 || size > MAX_LOCATION_SIZE {
+            return None;
+        }
+
+        let location = slice_at(oat, offset + 4, size).ok()?;
+        if location.iter().any(|byte| !(0x20..0x7f).contains(byte)) {
+            return None;
+        }
+
+        Some((String::from_utf8_lossy(location).to_string(), offset + 4 + size))
+    }
+
+    /// Find the start of the next entry of the DEX files table
+    ///
+    /// `offset` points after the DEX file offset of the current entry. What follows depends on
+    /// the version: offsets to various tables in recent versions, or the class offsets inline in
+    /// older ones. We start with the expected number of fields and then try the others until we
+    /// find something that looks like the next entry. The number of fields is bounded by the size
+    /// of the OAT file.
+    fn find_next_entry(oat: &[u8], offset: usize, version: u32, dex: Option<&[u8]>) -> Option<usize> {
+        let max_fields = oat.len().saturating_sub(offset) / 4;
+        let class_defs_size = dex.and_then(|dex| read_u32_at(dex, DEX_CLASS_DEFS_SIZE_OFFSET).ok())
+                                 .map_or(0, |size| (size as usize).min(max_fields));
+
+        let expected = match version {
+            195.. => 8,
+            138.. => 6,
+            131.. => 4,
+            SEPARATE_DEX_FILES_TABLE_VERSION.. => 3,
+            _ => class_defs_size + 1,
+        };
+
+        std::iter::once(expected)
+            .chain(0..=(class_defs_size + 16).min(max_fields))
+            .filter_map(|fields| offset.checked_add(fields * 4))
+            .find(|&next| OatFile::read_location(oat, next).is_some())
+    }
+
+    /// Extract the DEX files which are not embedded in the OAT file from the companion VDEX file
+    ///
+    /// Returns the number of DEX files found.
+    pub fn resolve_from_vdex(&mut self, raw_vdex: &[u8]) -> usize {
+        let mut resolved = 0;
+
+        for dex_file in self.dex_files.iter_mut().filter(|dex_file| dex_file.bytes.is_none()) {
+            if let Some((_, bytes)) = dex_slice_at(raw_vdex, dex_file.dex_file_offset as usize) {
+                dex_file.bytes = Some(bytes.to_vec());
+                resolved += 1;
+            }
+        }
+
+        resolved
+    }
+
+    /// Create a `DexReader` for each standard DEX file found, along with its location
+    ///
+    /// Compact DEX files and DEX files which could not be found are skipped.
+    pub fn dex_readers(&self) -> Result<Vec<(String, DexReader<'static>)>, DexError> {
+        let mut readers = Vec::new();
+
+        for dex_file in self.dex_files.iter() {
+            match (dex_file.kind(), &dex_file.bytes) {
+                (Some(DexKind::Dex), Some(bytes)) => {
+                    readers.push((dex_file.location.clone(), DexReader::build(bytes.clone())?));
+                },
+                (Some(DexKind::CompactDex), _) => warn!("skipping compact DEX file {}",
+                                                        dex_file.location),
+                _ => warn!("DEX file {} not found", dex_file.location),
+            }
+        }
+
+        Ok(readers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fake_dex_header;
+    use crate::elf::tests::fake_elf;
+
+    /// Build the `oatdata` region of a version 183 OAT file with two DEX files: the first one is
+    /// embedded, the second one is at offset 0x40 of the VDEX file
+    fn fake_oatdata(embedded_dex: &[u8]) -> Vec<u8> {
+        let key_value_store = b"classpath\0&\0compiler-filter\0speed\0";
+        let header_size = 60;
+        let table_off = header_size + key_value_store.len();
+
+        let mut table = Vec::new();
+        let mut entries_size = 0;
+        for location in ["/system/app/Foo.apk", "/system/app/Foo.apk!classes2.dex"] {
+            entries_size += 4 + location.len() + 8 + 6 * 4;
+        }
+        let dex_off = (table_off + entries_size).div_ceil(4) * 4;
+
+        for (location, checksum, offset) in [("/system/app/Foo.apk", 0x1111u32, dex_off as u32),
+                                             ("/system/app/Foo.apk!classes2.dex", 0x2222, 0x40)] {
+            table.extend_from_slice(&(location.len() as u32).to_le_bytes());
+            table.extend_from_slice(location.as_bytes());
+            table.extend_from_slice(&checksum.to_le_bytes());
+            table.extend_from_slice(&offset.to_le_bytes());
+            table.extend_from_slice(&[0u8; 6 * 4]);
+        }
+
+        let mut oat = Vec::new();
+        oat.extend_from_slice(b"oat\n183\0");
+        oat.extend_from_slice(&0xdeadbeefu32.to_le_bytes());         // checksum
+        oat.extend_from_slice(&2u32.to_le_bytes());                  // instruction set
+        oat.extend_from_slice(&0u32.to_le_bytes());
+        oat.extend_from_slice(&2u32.to_le_bytes());                  // DEX files count
+        oat.extend_from_slice(&(table_off as u32).to_le_bytes());
+        oat.resize(header_size - 4, 0);
+        oat.extend_from_slice(&(key_value_store.len() as u32).to_le_bytes());
+        oat.extend_from_slice(key_value_store);
+        oat.extend_from_slice(&table);
+        oat.resize(dex_off, 0);
+        oat.extend_from_slice(embedded_dex);
+        oat
+    }
+
+    #[test]
+    fn test_build() {
+        let dex = fake_dex_header(0x70);
+        let oatdata = fake_oatdata(&dex);
+        let raw = fake_elf(&[("oatdata", 0, oatdata.len() as u64)], &oatdata);
+
+        let mut oat = OatFile::build(&raw).unwrap();
+        assert_eq!(oat.header.version, 183);
+        assert_eq!(oat.header.checksum, 0xdeadbeef);
+        assert_eq!(oat.header.instruction_set, 2);
+        assert_eq!(oat.header.key_value_store,
+                   vec![("classpath".to_string(), "&".to_string()),
+                        ("compiler-filter".to_string(), "speed".to_string())]);
+
+        assert_eq!(oat.dex_files.len(), 2);
+        assert_eq!(oat.dex_files[0].location, "/system/app/Foo.apk");
+        assert_eq!(oat.dex_files[0].location_checksum, 0x1111);
+        assert_eq!(oat.dex_files[0].bytes, Some(dex.clone()));
+        assert_eq!(oat.dex_files[1].location, "/system/app/Foo.apk!classes2.dex");
+        assert!(oat.dex_files[1].bytes.is_none());
+        assert_eq!(oat.dex_readers().unwrap().len(), 1);
+
+        let mut vdex = vec![0u8; 0x40];
+        vdex.extend_from_slice(&fake_dex_header(0x78));
+        assert_eq!(oat.resolve_from_vdex(&vdex), 1);
+        assert_eq!(oat.dex_files[1].kind(), Some(DexKind::Dex));
+
+        let readers = oat.dex_readers().unwrap();
+        assert_eq!(readers.len(), 2);
+        assert_eq!(readers[1].0, "/system/app/Foo.apk!classes2.dex");
+        assert_eq!(readers[1].1.bytes_len, 0x78);
+    }
+
+    #[test]
+    fn test_build_unexpected_fields() {
+        // Same table but the parser expects a different number of fields per entry
+        let dex = fake_dex_header(0x70);
+        let mut oatdata = fake_oatdata(&dex);
+        oatdata[4..8].copy_from_slice(b"225\0");
+        let raw = fake_elf(&[("oatdata", 0, oatdata.len() as u64)], &oatdata);
+
+        let oat = OatFile::build(&raw).unwrap();
+        assert_eq!(oat.dex_files.len(), 2);
+        assert_eq!(oat.dex_files[1].location_checksum, 0x2222);
+    }
+
+    #[test]
+    fn test_build_invalid() {
+        let raw = fake_elf(&[("oatexec", 0, 4)], b"oat\n");
+        assert_eq!(OatFile::build(&raw).unwrap_err().to_string(),
+                   "cannot find symbol in ELF file");
+
+        let raw = fake_elf(&[("oatdata", 0, 8)], b"dex\n035\0");
+        assert_eq!(OatFile::build(&raw).unwrap_err().to_string(), "invalid OAT magic");
+    }
+
+    #[test]
+    fn test_find_next_entry_bounded() {
+        // The scan stops at the end of the OAT file whatever the number of classes
+        let mut dex = fake_dex_header(0x70);
+        dex[DEX_CLASS_DEFS_SIZE_OFFSET..DEX_CLASS_DEFS_SIZE_OFFSET + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let oat = vec![0u8; 0x100];
+        assert_eq!(OatFile::find_next_entry(&oat, 0x10, 64, Some(&dex)), None);
+        assert_eq!(OatFile::find_next_entry(&oat, usize::MAX - 8, 195, Some(&dex)), None);
+    }
+}
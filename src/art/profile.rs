@@ -0,0 +1,515 @@
+//! ART profiles and DEX metadata files
+//!
+//! ART compiles the methods and classes listed in a profile ahead of time. Apps can ship a
+//! baseline profile in `assets/dexopt/baseline.prof`, along with a metadata file
+//! (`assets/dexopt/baseline.profm`) which completes the class lists for the profile formats that
+//! do not store them. Profiles installed through a DEX metadata (`.dm`) archive are stored in its
+//! `primary.prof` entry.
+//!
+//! Profiles identify classes and methods by their index in a DEX file, along with the checksum
+//! of this DEX file. They can only be resolved against the `DexFile` built from the matching DEX
+//! file alone, as merging DEX files changes the indices.
+//!
+//! Version 010 of the profile format (Android 9 to 11, and baseline profiles) and versions 001
+//! and 002 of the metadata format are supported.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use flate2::read::ZlibDecoder;
+use zip::ZipArchive;
+
+use crate::bytes::{ slice_at, read_u16_at, read_u32_at };
+use crate::dex::file::DexFile;
+use crate::dex::options::ResourceLimits;
+use crate::error::DexError;
+
+/// Magic bytes at the start of a profile
+const PROFILE_MAGIC: [u8; 4] = *b"pro\0";
+/// Magic bytes at the start of a profile metadata file
+const PROFILE_METADATA_MAGIC: [u8; 4] = *b"prm\0";
+/// Inline cache markers which are not followed by classes
+const INLINE_CACHE_MISSING_TYPES: u8 = 6;
+const INLINE_CACHE_MEGAMORPHIC: u8 = 7;
+/// Maximum compression ratio of zlib
+const MAX_ZLIB_RATIO: usize = 1032;
+
+/// Path of the baseline profile in an APK
+pub const BASELINE_PROFILE_PATH: &str = "assets/dexopt/baseline.prof";
+/// Path of the baseline profile metadata in an APK
+pub const BASELINE_PROFILE_METADATA_PATH: &str = "assets/dexopt/baseline.profm";
+/// Path of the profile in a DEX metadata archive
+pub const DEX_METADATA_PROFILE_PATH: &str = "primary.prof";
+
+/// Supported profile versions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProfileVersion {
+    /// Android 9 to 11, and baseline profiles
+    V010,
+}
+
+impl ProfileVersion {
+    /// Parse the version bytes following the magic
+    fn parse(raw: &[u8]) -> Result<Self, DexError> {
+        match raw {
+            b"010\0" => Ok(ProfileVersion::V010),
+            _ => Err(DexError::UnsupportedProfileVersion)
+        }
+    }
+}
+
+/// How a method is used, according to the profile
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MethodHotness {
+    /// The method is executed often and should be compiled
+    pub hot: bool,
+    /// The method is executed during the startup of the app
+    pub startup: bool,
+    /// The method is executed after the startup of the app
+    pub post_startup: bool,
+}
+
+/// Profile data of one DEX file
+#[derive(Debug)]
+pub struct DexProfile {
+    /// Key identifying the DEX file (e.g., `base.apk!classes2.dex`)
+    pub profile_key: String,
+    /// Checksum of the DEX file
+    pub checksum: u32,
+    /// Number of types of the DEX file
+    pub num_type_ids: u32,
+    /// Number of methods of the DEX file
+    pub num_method_ids: u32,
+    /// Indices of the types of the profiled classes
+    pub classes: Vec<u32>,
+    /// Profiled methods, by method index
+    pub methods: BTreeMap<u32, MethodHotness>,
+}
+
+/// A profiled method resolved against a DEX file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfiledMethod {
+    /// Prototype of the method (e.g., `Lcom/example/Foo;->bar(I)V`)
+    pub method: String,
+    /// How the method is used
+    pub hotness: MethodHotness,
+}
+
+/// Profile data of one DEX file resolved against this DEX file
+#[derive(Debug, Default)]
+pub struct ResolvedProfile {
+    /// Descriptors of the profiled classes
+    pub classes: Vec<String>,
+    /// Profiled methods
+    pub methods: Vec<ProfiledMethod>,
+}
+
+impl DexProfile {
+    /// Check if the profile data was recorded for the given DEX file
+    pub fn matches(&self, dex: &DexFile) -> bool {
+        self.checksum == dex.header.checksum
+    }
+
+    /// Resolve the class and method indices to their names
+    ///
+    /// The DEX file must be the one the profile data was recorded for, built on its own.
+    pub fn resolve(&self, dex: &DexFile) -> Result<ResolvedProfile, DexError> {
+        if !self.matches(dex) {
+            return Err(DexError::ProfileChecksumMismatch);
+        }
+        self.resolve_indices(&dex.types.items, &dex.methods.items)
+    }
+
+    fn resolve_indices(&self, types: &[String], methods: &[String]) -> Result<ResolvedProfile, DexError> {
+        let mut resolved = ResolvedProfile::default();
+
+        for type_idx in self.classes.iter() {
+            let class = types.get(*type_idx as usize).ok_or(DexError::InvalidTypeIdx)?;
+            resolved.classes.push(class.to_string());
+        }
+
+        for (method_idx, hotness) in self.methods.iter() {
+            let method = methods.get(*method_idx as usize).ok_or(DexError::InvalidMethodIdx)?;
+            resolved.methods.push(ProfiledMethod {
+                method: method.to_string(),
+                hotness: *hotness,
+            });
+        }
+
+        Ok(resolved)
+    }
+}
+
+/// Representation of an ART profile
+#[derive(Debug)]
+pub struct ArtProfile {
+    /// Version of the profile
+    pub version: ProfileVersion,
+    /// Profile data, one per DEX file
+    pub dex_files: Vec<DexProfile>,
+}
+
+impl ArtProfile {
+    /// Open the profile at the given path and parse it
+    pub fn build_from_file(filepath: &str) -> Result<Self, DexError> {
+        let raw = std::fs::read(filepath)?;
+        ArtProfile::build(&raw)
+    }
+
+    /// Open the APK at the given path and parse its baseline profile
+    ///
+    /// The metadata file is applied to the profile if the APK contains one.
+    pub fn build_from_apk(filepath: &str) -> Result<Self, DexError> {
+        let mut zip_file = open_archive(filepath)?;
+        let raw = read_entry(&mut zip_file, BASELINE_PROFILE_PATH)?.ok_or(DexError::MissingProfile)?;
+        let mut profile = ArtProfile::build(&raw)?;
+
+        if let Some(metadata) = read_entry(&mut zip_file, BASELINE_PROFILE_METADATA_PATH)? {
+            profile.apply_metadata(&metadata)?;
+        }
+
+        Ok(profile)
+    }
+
+    /// Open the DEX metadata (`.dm`) archive at the given path and parse its profile
+    pub fn build_from_dex_metadata(filepath: &str) -> Result<Self, DexError> {
+        let mut zip_file = open_archive(filepath)?;
+        let raw = read_entry(&mut zip_file, DEX_METADATA_PROFILE_PATH)?.ok_or(DexError::MissingProfile)?;
+        ArtProfile::build(&raw)
+    }
+
+    /// Parse a profile from its raw bytes
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        if slice_at(raw, 0, 4)? != PROFILE_MAGIC {
+            return Err(DexError::InvalidProfileMagic);
+        }
+        let version = ProfileVersion::parse(slice_at(raw, 4, 4)?)?;
+
+        let dex_count = *raw.get(8).ok_or(DexError::InvalidProfileData)? as usize;
+        let body = inflate(raw, 9)?;
+
+        // Headers of all DEX files come first, followed by their data
+        let mut pos = 0;
+        let mut headers = Vec::with_capacity(dex_count);
+        for _ in 0..dex_count {
+            let key_size = read_u16_at(&body, pos)? as usize;
+            let class_count = read_u16_at(&body, pos + 2)? as usize;
+            let hot_methods_size = read_u32_at(&body, pos + 4)? as usize;
+            let checksum = read_u32_at(&body, pos + 8)?;
+            let num_method_ids = read_u32_at(&body, pos + 12)?;
+            let key = slice_at(&body, pos + 16, key_size)?;
+            pos += 16 + key_size;
+
+            headers.push((class_count, hot_methods_size, DexProfile {
+                profile_key: String::from_utf8_lossy(key).into_owned(),
+                checksum,
+                num_type_ids: 0,
+                num_method_ids,
+                classes: Vec::new(),
+                methods: BTreeMap::new(),
+            }));
+        }
+
+        let mut dex_files = Vec::with_capacity(dex_count);
+        for (class_count, hot_methods_size, mut dex_profile) in headers {
+            let hot_methods = slice_at(&body, pos, hot_methods_size)?;
+            for method_idx in read_hot_methods(hot_methods)? {
+                dex_profile.methods.entry(method_idx).or_default().hot = true;
+            }
+            pos += hot_methods_size;
+
+            dex_profile.classes = read_deltas(&body, &mut pos, class_count)?;
+
+            let num_method_ids = dex_profile.num_method_ids as usize;
+            let bitmap = slice_at(&body, pos, (num_method_ids * 2).div_ceil(8))?;
+            for method_idx in 0..num_method_ids {
+                let startup = bit_at(bitmap, method_idx);
+                let post_startup = bit_at(bitmap, method_idx + num_method_ids);
+                if startup || post_startup {
+                    let hotness = dex_profile.methods.entry(method_idx as u32).or_default();
+                    hotness.startup = startup;
+                    hotness.post_startup = post_startup;
+                }
+            }
+            pos += bitmap.len();
+
+            dex_files.push(dex_profile);
+        }
+
+        Ok(ArtProfile {
+            version,
+            dex_files,
+        })
+    }
+
+    /// Complete the profile with the content of a metadata (`.profm`) file
+    ///
+    /// The metadata sets the number of types and the profiled classes of the DEX files it
+    /// describes, matched by profile index (version 002) or profile key (version 001).
+    pub fn apply_metadata(&mut self, raw: &[u8]) -> Result<(), DexError> {
+        if slice_at(raw, 0, 4)? != PROFILE_METADATA_MAGIC {
+            return Err(DexError::InvalidProfileMagic);
+        }
+
+        match slice_at(raw, 4, 4)? {
+            b"001\0" => {
+                let dex_count = *raw.get(8).ok_or(DexError::InvalidProfileData)? as usize;
+                let body = inflate(raw, 9)?;
+
+                let mut pos = 0;
+                let mut sizes = Vec::with_capacity(dex_count);
+                for _ in 0..dex_count {
+                    sizes.push((read_u16_at(&body, pos)? as usize, read_u16_at(&body, pos + 2)? as usize));
+                    pos += 4;
+                }
+
+                for (key_size, class_count) in sizes {
+                    let key = String::from_utf8_lossy(slice_at(&body, pos, key_size)?).into_owned();
+                    pos += key_size;
+                    let classes = read_deltas(&body, &mut pos, class_count)?;
+
+                    if let Some(dex_profile) = self.dex_files.iter_mut().find(|dex| dex.profile_key == key) {
+                        dex_profile.classes = classes;
+                    }
+                }
+            },
+            b"002\0" => {
+                let dex_count = read_u16_at(raw, 8)? as usize;
+                let body = inflate(raw, 10)?;
+
+                let mut pos = 0;
+                for _ in 0..dex_count {
+                    let profile_idx = read_u16_at(&body, pos)? as usize;
+                    let key_size = read_u16_at(&body, pos + 2)? as usize;
+                    pos += 4 + key_size;
+                    let num_type_ids = read_u32_at(&body, pos)?;
+                    let class_count = read_u16_at(&body, pos + 4)? as usize;
+                    pos += 6;
+                    let classes = read_deltas(&body, &mut pos, class_count)?;
+
+                    let dex_profile = self.dex_files.get_mut(profile_idx).ok_or(DexError::InvalidProfileData)?;
+                    dex_profile.num_type_ids = num_type_ids;
+                    dex_profile.classes = classes;
+                }
+            },
+            _ => return Err(DexError::UnsupportedProfileVersion),
+        }
+
+        Ok(())
+    }
+
+    /// Get the profile data of the given DEX file
+    pub fn get_dex_profile(&self, dex: &DexFile) -> Option<&DexProfile> {
+        self.dex_files.iter().find(|dex_profile| dex_profile.matches(dex))
+    }
+}
+
+/// Inflate the zlib-compressed body starting at `offset` with its uncompressed and compressed sizes
+fn inflate(raw: &[u8], offset: usize) -> Result<Vec<u8>, DexError> {
+    let uncompressed_size = read_u32_at(raw, offset)? as usize;
+    let compressed_size = read_u32_at(raw, offset + 4)? as usize;
+    let compressed = slice_at(raw, offset + 8, compressed_size)?;
+
+    // Reject sizes which cannot be reached from the compressed data, or which are too large to
+    // be decoded, before inflating anything
+    if uncompressed_size > compressed_size.saturating_mul(MAX_ZLIB_RATIO) {
+        return Err(DexError::InvalidProfileData);
+    }
+    if uncompressed_size as u64 > ResourceLimits::default().max_decoded_size {
+        return Err(DexError::ResourceLimitExceeded);
+    }
+
+    // Do not trust the uncompressed size for the allocation, but stop reading after it
+    let mut body = Vec::new();
+    ZlibDecoder::new(compressed).take(uncompressed_size as u64)
+                                .read_to_end(&mut body)
+                                .map_err(|_| DexError::InvalidProfileData)?;
+    if body.len() != uncompressed_size {
+        return Err(DexError::InvalidProfileData);
+    }
+
+    Ok(body)
+}
+
+/// Read `count` delta-encoded 16 bits indices at `pos` and move `pos` after them
+fn read_deltas(raw: &[u8], pos: &mut usize, count: usize) -> Result<Vec<u32>, DexError> {
+    let mut indices = Vec::with_capacity(count.min(raw.len() / 2));
+    let mut last = 0u32;
+    for _ in 0..count {
+        last = last.checked_add(read_u16_at(raw, *pos)? as u32).ok_or(DexError::InvalidProfileData)?;
+        indices.push(last);
+        *pos += 2;
+    }
+    Ok(indices)
+}
+
+/// Read the indices of the hot methods, skipping their inline caches
+fn read_hot_methods(raw: &[u8]) -> Result<Vec<u32>, DexError> {
+    let mut methods = Vec::new();
+    let mut last = 0u32;
+    let mut pos = 0;
+
+    while pos < raw.len() {
+        last = last.checked_add(read_u16_at(raw, pos)? as u32).ok_or(DexError::InvalidProfileData)?;
+        methods.push(last);
+        let inline_cache_size = read_u16_at(raw, pos + 2)?;
+        pos += 4;
+
+        for _ in 0..inline_cache_size {
+            // Dex PC, then the classes seen at this PC grouped by DEX file
+            let map_size = *raw.get(pos + 2).ok_or(DexError::InvalidProfileData)?;
+            pos += 3;
+            if map_size == INLINE_CACHE_MISSING_TYPES || map_size == INLINE_CACHE_MEGAMORPHIC {
+                continue;
+            }
+            for _ in 0..map_size {
+                let class_count = *raw.get(pos + 1).ok_or(DexError::InvalidProfileData)? as usize;
+                pos += 2 + class_count * 2;
+            }
+        }
+    }
+
+    if pos != raw.len() {
+        return Err(DexError::InvalidProfileData);
+    }
+    Ok(methods)
+}
+
+/// Check if the bit at the given index is set
+fn bit_at(bitmap: &[u8], idx: usize) -> bool {
+    bitmap[idx / 8] & (1 << (idx % 8)) != 0
+}
+
+fn open_archive(filepath: &str) -> Result<ZipArchive<File>, DexError> {
+    let raw_file = File::open(filepath)?;
+    ZipArchive::new(raw_file).map_err(|_| DexError::InvalidZipArchive)
+}
+
+fn read_entry(zip_file: &mut ZipArchive<File>, name: &str) -> Result<Option<Vec<u8>>, DexError> {
+    let mut raw = Vec::new();
+    match zip_file.by_name(name) {
+        Ok(mut entry) => entry.read_to_end(&mut raw)?,
+        Err(_) => return Ok(None),
+    };
+    Ok(Some(raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+
+    fn compress(header: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut raw = header.to_vec();
+        raw.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        raw.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        raw.extend_from_slice(&compressed);
+        raw
+    }
+
+    /// Profile of one DEX file with 4 methods: 1 and 3 are hot, 0 and 1 are startup methods,
+    /// 3 is a post-startup method, and the class 2 is profiled
+    fn fake_profile() -> Vec<u8> {
+        let key = b"base.apk";
+        let mut body = Vec::new();
+        body.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&11u32.to_le_bytes());
+        body.extend_from_slice(&0xcafebabeu32.to_le_bytes());
+        body.extend_from_slice(&4u32.to_le_bytes());
+        body.extend_from_slice(key);
+
+        // Method 1 without inline cache, method 3 with a megamorphic call site
+        body.extend_from_slice(&[0x01, 0x00, 0x00, 0x00]);
+        body.extend_from_slice(&[0x02, 0x00, 0x01, 0x00, 0x10, 0x00, INLINE_CACHE_MEGAMORPHIC]);
+        body.extend_from_slice(&[0x02, 0x00]);
+        body.push(0b1000_0011);
+
+        let mut header = PROFILE_MAGIC.to_vec();
+        header.extend_from_slice(b"010\0");
+        header.push(1);
+        compress(&header, &body)
+    }
+
+    #[test]
+    fn test_build() {
+        let profile = ArtProfile::build(&fake_profile()).unwrap();
+        assert_eq!(profile.version, ProfileVersion::V010);
+        assert_eq!(profile.dex_files.len(), 1);
+
+        let dex_profile = &profile.dex_files[0];
+        assert_eq!(dex_profile.profile_key, "base.apk");
+        assert_eq!(dex_profile.checksum, 0xcafebabe);
+        assert_eq!(dex_profile.classes, vec![2]);
+        assert_eq!(dex_profile.methods.len(), 3);
+        assert_eq!(dex_profile.methods[&0], MethodHotness { hot: false, startup: true, post_startup: false });
+        assert_eq!(dex_profile.methods[&1], MethodHotness { hot: true, startup: true, post_startup: false });
+        assert_eq!(dex_profile.methods[&3], MethodHotness { hot: true, startup: false, post_startup: true });
+
+        let types = ["La;", "Lb;", "Lc;"].map(String::from);
+        let methods = ["La;->a()V", "La;->b()V", "Lb;->c()V", "Lc;->d()V"].map(String::from);
+        let resolved = dex_profile.resolve_indices(&types, &methods).unwrap();
+        assert_eq!(resolved.classes, vec!["Lc;"]);
+        assert_eq!(resolved.methods[2].method, "Lc;->d()V");
+        assert!(dex_profile.resolve_indices(&types, &methods[..2]).is_err());
+
+        let mut raw = fake_profile();
+        raw[4..8].copy_from_slice(b"015\0");
+        assert!(matches!(ArtProfile::build(&raw), Err(DexError::UnsupportedProfileVersion)));
+        raw[0..4].copy_from_slice(&PROFILE_METADATA_MAGIC);
+        assert!(matches!(ArtProfile::build(&raw), Err(DexError::InvalidProfileMagic)));
+    }
+
+    #[test]
+    fn test_inflate_bomb() {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&u32::MAX.to_le_bytes());
+        raw.extend_from_slice(&4u32.to_le_bytes());
+        raw.extend_from_slice(&[0x78, 0x9c, 0x03, 0x00]);
+        assert!(matches!(inflate(&raw, 0), Err(DexError::InvalidProfileData)));
+
+        // Within the compression ratio but above the resource limits
+        let compressed_size = 0x200000u32;
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&0x5000_0000u32.to_le_bytes());
+        raw.extend_from_slice(&compressed_size.to_le_bytes());
+        raw.resize(8 + compressed_size as usize, 0);
+        assert!(matches!(inflate(&raw, 0), Err(DexError::ResourceLimitExceeded)));
+    }
+
+    #[test]
+    fn test_read_hot_methods_overflow() {
+        // Each delta is 0xffff without inline caches: the indices overflow after 0x10001 methods
+        let raw = [0xff, 0xff, 0x00, 0x00].repeat(0x10002);
+        assert!(matches!(read_hot_methods(&raw), Err(DexError::InvalidProfileData)));
+        assert_eq!(read_hot_methods(&raw[4..]).unwrap().last(), Some(&u32::MAX));
+    }
+
+    #[test]
+    fn test_apply_metadata() {
+        let mut profile = ArtProfile::build(&fake_profile()).unwrap();
+
+        let key = b"base.apk";
+        let mut body = Vec::new();
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        body.extend_from_slice(key);
+        body.extend_from_slice(&3u32.to_le_bytes());
+        body.extend_from_slice(&2u16.to_le_bytes());
+        body.extend_from_slice(&[0x00, 0x00, 0x01, 0x00]);
+
+        let mut header = PROFILE_METADATA_MAGIC.to_vec();
+        header.extend_from_slice(b"002\0");
+        header.extend_from_slice(&1u16.to_le_bytes());
+        profile.apply_metadata(&compress(&header, &body)).unwrap();
+
+        assert_eq!(profile.dex_files[0].num_type_ids, 3);
+        assert_eq!(profile.dex_files[0].classes, vec![0, 1]);
+    }
+}
@@ -0,0 +1,459 @@
+//! VDEX container files
+//!
+//! ART stores the DEX files it verified at install time in `.vdex` files. Along with the DEX (or
+//! compact DEX) files, a VDEX file contains the dependencies recorded by the verifier and, in
+//! recent versions, the type lookup tables used to speed up class resolution.
+//!
+//! Two layouts are supported:
+//!   * versions 019 and 021 (Android 10 and 11): a fixed header followed by the DEX checksums,
+//!     the DEX section, the verifier dependencies, and the quickening information;
+//!   * version 027 (Android 12 and later): a header followed by a table of sections.
+//!
+//! Compact DEX files are extracted as-is but cannot be decoded by `DexFile::build`.
+
+use log::warn;
+
+use crate::bytes::{ slice_at, read_u32_at, read_uleb128_at, read_c_string_at, align_up };
+use crate::dex::header::{ DexKind, dex_slice_at };
+use crate::dex::reader::DexReader;
+use crate::error::DexError;
+
+/// Magic bytes at the start of a VDEX file
+const VDEX_MAGIC: [u8; 4] = *b"vdex";
+/// Version of the DEX section in legacy VDEX files without DEX files
+const DEX_SECTION_VERSION_EMPTY: [u8; 4] = *b"000\0";
+/// Marker for classes which were not verified at compile time
+const NOT_VERIFIED_MARKER: u32 = 0xffffffff;
+
+/// Section kinds of a VDEX file (version 027 and later)
+const CHECKSUM_SECTION: u32 = 0;
+const DEX_FILE_SECTION: u32 = 1;
+const VERIFIER_DEPS_SECTION: u32 = 2;
+const TYPE_LOOKUP_TABLE_SECTION: u32 = 3;
+
+/// Supported VDEX versions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VdexVersion {
+    /// Android 10
+    V019,
+    /// Android 11
+    V021,
+    /// Android 12 and later
+    V027,
+}
+
+impl VdexVersion {
+    /// Parse the version bytes following the magic
+    fn parse(raw: &[u8]) -> Result<Self, DexError> {
+        match raw {
+            b"019\0" => Ok(VdexVersion::V019),
+            b"021\0" => Ok(VdexVersion::V021),
+            b"027\0" => Ok(VdexVersion::V027),
+            _ => Err(DexError::UnsupportedVdexVersion)
+        }
+    }
+}
+
+/// A DEX file embedded in a VDEX file
+#[derive(Debug)]
+pub struct VdexDexFile {
+    /// Offset of the DEX file in the VDEX file
+    pub offset: u32,
+    /// Checksum of the original DEX file, as recorded by ART
+    pub location_checksum: u32,
+    /// Standard or compact DEX file
+    pub kind: DexKind,
+    /// Raw bytes of the DEX file
+    pub bytes: Vec<u8>,
+}
+
+/// Dependencies recorded by the verifier for one DEX file (version 027 and later)
+#[derive(Debug, Default)]
+pub struct VdexVerifierDeps {
+    /// For each class definition, whether the class was verified at compile time
+    pub verified_classes: Vec<bool>,
+    /// For each class definition, the assignability checks the verifier relied on, as pairs of
+    /// (destination, source) string indices
+    pub assignable_types: Vec<Vec<(u32, u32)>>,
+    /// Strings needed by the verifier which are not in the DEX file
+    pub extra_strings: Vec<String>,
+}
+
+/// Entry of a type lookup table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TypeLookupEntry {
+    /// Offset of the class descriptor in the DEX file, zero for empty entries
+    pub string_offset: u32,
+    /// Packed class definition index, hash bits, and offset to the next entry
+    pub data: u32,
+}
+
+/// Type lookup table of one DEX file (version 027 and later)
+#[derive(Debug, Default)]
+pub struct TypeLookupTable {
+    pub entries: Vec<TypeLookupEntry>,
+}
+
+/// Representation of a VDEX file
+#[derive(Debug)]
+pub struct VdexFile {
+    /// Version of the VDEX file
+    pub version: VdexVersion,
+    /// Checksums of the DEX files this VDEX file was created for
+    pub checksums: Vec<u32>,
+    /// Embedded DEX files, empty if the DEX files were left in the APK
+    pub dex_files: Vec<VdexDexFile>,
+    /// Decoded verifier dependencies, one per DEX file (version 027 and later)
+    pub verifier_deps: Vec<VdexVerifierDeps>,
+    /// Raw bytes of the verifier dependencies section
+    pub raw_verifier_deps: Vec<u8>,
+    /// Type lookup tables, one per DEX file (version 027 and later)
+    pub type_lookup_tables: Vec<TypeLookupTable>,
+}
+
+impl VdexFile {
+    /// Open the VDEX file at the given path and parse it
+    pub fn build_from_file(filepath: &str) -> Result<Self, DexError> {
+        let raw = std::fs::read(filepath)?;
+        VdexFile::build(&raw)
+    }
+
+    /// Parse a VDEX file from its raw bytes
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        if slice_at(raw, 0, 4)? != VDEX_MAGIC {
+            return Err(DexError::InvalidVdexMagic);
+        }
+
+        match VdexVersion::parse(slice_at(raw, 4, 4)?)? {
+            VdexVersion::V027 => VdexFile::build_sections(raw),
+            version => VdexFile::build_legacy(raw, version),
+        }
+    }
+
+    /// Parse a VDEX file using the section-based layout (version 027 and later)
+    fn build_sections(raw: &[u8]) -> Result<Self, DexError> {
+        let number_of_sections = read_u32_at(raw, 8)?;
+
+        let mut sections = [(0usize, 0usize); 4];
+        for idx in 0..number_of_sections as usize {
+            let header_off = 12 + idx * 12;
+            let kind = read_u32_at(raw, header_off)?;
+            let offset = read_u32_at(raw, header_off + 4)? as usize;
+            let size = read_u32_at(raw, header_off + 8)? as usize;
+
+            // Make sure the section is within the file
+            slice_at(raw, offset, size)?;
+
+            match sections.get_mut(kind as usize) {
+                Some(section) => *section = (offset, size),
+                None => warn!("ignoring unknown VDEX section kind {kind}"),
+            }
+        }
+
+        let (checksums_off, checksums_size) = sections[CHECKSUM_SECTION as usize];
+        let mut checksums = Vec::new();
+        for idx in 0..checksums_size / 4 {
+            checksums.push(read_u32_at(raw, checksums_off + idx * 4)?);
+        }
+
+        let (dex_off, dex_size) = sections[DEX_FILE_SECTION as usize];
+        let mut dex_files = Vec::new();
+        if dex_size != 0 {
+            let mut offset = dex_off;
+            for checksum in checksums.iter() {
+                let dex_file = VdexFile::read_dex_file(raw, offset, *checksum)?;
+                offset = align_up(offset + dex_file.bytes.len(), 4);
+                dex_files.push(dex_file);
+            }
+        }
+
+        let (deps_off, deps_size) = sections[VERIFIER_DEPS_SECTION as usize];
+        let raw_verifier_deps = slice_at(raw, deps_off, deps_size)?;
+        let mut verifier_deps = Vec::new();
+        if deps_size != 0 {
+            for idx in 0..checksums.len() {
+                verifier_deps.push(VdexFile::decode_verifier_deps(raw_verifier_deps, idx)?);
+            }
+        }
+
+        let (tables_off, tables_size) = sections[TYPE_LOOKUP_TABLE_SECTION as usize];
+        let mut type_lookup_tables = Vec::new();
+        let mut offset = tables_off;
+        while offset < tables_off + tables_size {
+            let table_size = read_u32_at(raw, offset)? as usize;
+            let table = slice_at(raw, offset + 4, table_size)?;
+
+            let entries = table.chunks_exact(8)
+                               .map(|entry| TypeLookupEntry {
+                                   string_offset: u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]),
+                                   data: u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]),
+                               })
+                               .collect();
+            type_lookup_tables.push(TypeLookupTable { entries });
+
+            offset += 4 + table_size;
+        }
+
+        Ok(VdexFile {
+            version: VdexVersion::V027,
+            checksums,
+            dex_files,
+            verifier_deps,
+            raw_verifier_deps: raw_verifier_deps.to_vec(),
+            type_lookup_tables,
+        })
+    }
+
+    /// Parse a VDEX file using the legacy layout (versions 019 and 021)
+    fn build_legacy(raw: &[u8], version: VdexVersion) -> Result<Self, DexError> {
+        let has_dex_section = slice_at(raw, 8, 4)? != DEX_SECTION_VERSION_EMPTY;
+        let number_of_dex_files = read_u32_at(raw, 12)? as usize;
+        let verifier_deps_size = read_u32_at(raw, 16)? as usize;
+
+        // Checksums immediately follow the 28 bytes header
+        let mut checksums = Vec::new();
+        for idx in 0..number_of_dex_files {
+            checksums.push(read_u32_at(raw, 28 + idx * 4)?);
+        }
+
+        let mut offset = 28 + number_of_dex_files * 4;
+        let mut dex_files = Vec::new();
+        if has_dex_section {
+            let dex_size = read_u32_at(raw, offset)? as usize;
+            let dex_shared_data_size = read_u32_at(raw, offset + 4)? as usize;
+            let dex_begin = offset + 12;
+
+            // Each DEX file is preceded by the offset of its quickening table
+            let mut dex_offset = dex_begin;
+            for checksum in checksums.iter() {
+                dex_offset = align_up(dex_offset, 4) + 4;
+                let dex_file = VdexFile::read_dex_file(raw, dex_offset, *checksum)?;
+                dex_offset += dex_file.bytes.len();
+                dex_files.push(dex_file);
+            }
+
+            offset = dex_begin + dex_size + dex_shared_data_size;
+        }
+
+        let raw_verifier_deps = slice_at(raw, offset, verifier_deps_size)?.to_vec();
+
+        Ok(VdexFile {
+            version,
+            checksums,
+            dex_files,
+            verifier_deps: Vec::new(),
+            raw_verifier_deps,
+            type_lookup_tables: Vec::new(),
+        })
+    }
+
+    /// Extract the DEX file starting at `offset`, using the file size from its header
+    fn read_dex_file(raw: &[u8], offset: usize, location_checksum: u32) -> Result<VdexDexFile, DexError> {
+        let (kind, bytes) = dex_slice_at(raw, offset).ok_or(DexError::InvalidSectionBounds)?;
+
+        Ok(VdexDexFile {
+            offset: offset as u32,
+            location_checksum,
+            kind,
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    /// Decode the verifier dependencies of the DEX file at index `dex_idx`
+    ///
+    /// Offsets in the section are relative to the start of the section. For each DEX file, the
+    /// data starts with a table with one offset per class definition (or a marker for classes
+    /// that were not verified) followed by an end offset. The number of classes is not stored:
+    /// it is deduced from the first actual offset, which points right after the table.
+    fn decode_verifier_deps(section: &[u8], dex_idx: usize) -> Result<VdexVerifierDeps, DexError> {
+        let table_off = read_u32_at(section, dex_idx * 4)? as usize;
+
+        let mut idx = 0;
+        let table_end = loop {
+            let value = read_u32_at(section, table_off + idx * 4)?;
+            if value != NOT_VERIFIED_MARKER {
+                break value as usize;
+            }
+            idx += 1;
+        };
+
+        if table_end < table_off + 4 || !(table_end - table_off).is_multiple_of(4) {
+            return Err(DexError::InvalidSectionBounds);
+        }
+        let classes_count = (table_end - table_off) / 4 - 1;
+
+        let mut offsets = Vec::with_capacity(classes_count + 1);
+        for idx in 0..=classes_count {
+            offsets.push(read_u32_at(section, table_off + idx * 4)?);
+        }
+
+        let mut deps = VdexVerifierDeps::default();
+        for idx in 0..classes_count {
+            if offsets[idx] == NOT_VERIFIED_MARKER {
+                deps.verified_classes.push(false);
+                deps.assignable_types.push(Vec::new());
+                continue;
+            }
+
+            // The set ends where the next verified class (or the end marker) starts
+            let end = offsets[idx + 1..].iter()
+                                        .find(|&&offset| offset != NOT_VERIFIED_MARKER)
+                                        .copied()
+                                        .unwrap_or(offsets[classes_count]) as usize;
+
+            let mut pos = offsets[idx] as usize;
+            let mut pairs = Vec::new();
+            while pos < end {
+                let destination = read_uleb128_at(section, &mut pos)?;
+                let source = read_uleb128_at(section, &mut pos)?;
+                pairs.push((destination, source));
+            }
+
+            deps.verified_classes.push(true);
+            deps.assignable_types.push(pairs);
+        }
+
+        // Extra strings: count, then one offset per string
+        let strings_off = align_up(offsets[classes_count] as usize, 4);
+        let strings_count = read_u32_at(section, strings_off)? as usize;
+        for idx in 0..strings_count {
+            let string_off = read_u32_at(section, strings_off + 4 + idx * 4)? as usize;
+            deps.extra_strings.push(read_c_string_at(section, string_off)?);
+        }
+
+        Ok(deps)
+    }
+
+    /// Create a `DexReader` for each embedded standard DEX file
+    ///
+    /// Compact DEX files cannot be parsed by `DexFile::build` and are skipped.
+    pub fn dex_readers(&self) -> Result<Vec<DexReader<'static>>, DexError> {
+        let mut readers = Vec::new();
+
+        for dex_file in self.dex_files.iter() {
+            match dex_file.kind {
+                DexKind::Dex => readers.push(DexReader::build(dex_file.bytes.clone())?),
+                DexKind::CompactDex => warn!("skipping compact DEX file at offset {:#x}",
+                                             dex_file.offset),
+            }
+        }
+
+        Ok(readers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fake_dex_header;
+
+    #[test]
+    fn test_build_invalid_magic() {
+        let raw = b"vdez027\0\0\0\0\0";
+        assert_eq!(VdexFile::build(raw).unwrap_err().to_string(), "invalid VDEX magic");
+
+        let raw = b"vdex099\0\0\0\0\0";
+        assert_eq!(VdexFile::build(raw).unwrap_err().to_string(), "unsupported VDEX version");
+    }
+
+    #[test]
+    fn test_build_v027() {
+        let dex = fake_dex_header(0x72);
+
+        // Verifier deps for one DEX file with two classes, only the second one is verified
+        let mut deps = Vec::new();
+        deps.extend_from_slice(&4u32.to_le_bytes());            // offset of the DEX data
+        deps.extend_from_slice(&NOT_VERIFIED_MARKER.to_le_bytes());
+        deps.extend_from_slice(&16u32.to_le_bytes());           // class 1
+        deps.extend_from_slice(&18u32.to_le_bytes());           // end
+        deps.extend_from_slice(&[0x05, 0x06, 0x00, 0x00]);      // (5, 6) + alignment
+        deps.extend_from_slice(&1u32.to_le_bytes());            // one extra string
+        deps.extend_from_slice(&28u32.to_le_bytes());
+        deps.extend_from_slice(b"LFoo;\0");
+
+        let table = [0x10u8, 0, 0, 0, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+        let sections_off = 12 + 4 * 12;
+        let dex_off = sections_off + 4;
+        let deps_off = align_up(dex_off + dex.len(), 4);
+        let tables_off = deps_off + deps.len();
+
+        let mut raw = Vec::new();
+        raw.extend_from_slice(b"vdex027\0");
+        raw.extend_from_slice(&4u32.to_le_bytes());
+        for (kind, offset, size) in [(CHECKSUM_SECTION, sections_off, 4),
+                                     (DEX_FILE_SECTION, dex_off, dex.len()),
+                                     (VERIFIER_DEPS_SECTION, deps_off, deps.len()),
+                                     (TYPE_LOOKUP_TABLE_SECTION, tables_off, 4 + table.len())] {
+            raw.extend_from_slice(&kind.to_le_bytes());
+            raw.extend_from_slice(&(offset as u32).to_le_bytes());
+            raw.extend_from_slice(&(size as u32).to_le_bytes());
+        }
+        raw.extend_from_slice(&0xcafebabeu32.to_le_bytes());
+        raw.extend_from_slice(&dex);
+        raw.resize(deps_off, 0);
+        raw.extend_from_slice(&deps);
+        raw.extend_from_slice(&(table.len() as u32).to_le_bytes());
+        raw.extend_from_slice(&table);
+
+        let vdex = VdexFile::build(&raw).unwrap();
+        assert_eq!(vdex.version, VdexVersion::V027);
+        assert_eq!(vdex.checksums, vec![0xcafebabe]);
+        assert_eq!(vdex.dex_files.len(), 1);
+        assert_eq!(vdex.dex_files[0].kind, DexKind::Dex);
+        assert_eq!(vdex.dex_files[0].offset, dex_off as u32);
+        assert_eq!(vdex.dex_files[0].bytes, dex);
+
+        assert_eq!(vdex.verifier_deps.len(), 1);
+        assert_eq!(vdex.verifier_deps[0].verified_classes, vec![false, true]);
+        assert_eq!(vdex.verifier_deps[0].assignable_types, vec![vec![], vec![(5, 6)]]);
+        assert_eq!(vdex.verifier_deps[0].extra_strings, vec!["LFoo;".to_string()]);
+
+        assert_eq!(vdex.type_lookup_tables.len(), 1);
+        assert_eq!(vdex.type_lookup_tables[0].entries,
+                   vec![TypeLookupEntry { string_offset: 0x10, data: 1 },
+                        TypeLookupEntry { string_offset: 0, data: 0 }]);
+
+        let readers = vdex.dex_readers().unwrap();
+        assert_eq!(readers.len(), 1);
+        assert_eq!(readers[0].bytes_len, 0x72);
+    }
+
+    #[test]
+    fn test_build_v021() {
+        let first = fake_dex_header(0x71);
+        let second = fake_dex_header(0x70);
+
+        // Quickening offset, first DEX, padding, quickening offset, second DEX
+        let mut dex_section = Vec::new();
+        dex_section.extend_from_slice(&0u32.to_le_bytes());
+        dex_section.extend_from_slice(&first);
+        dex_section.resize(align_up(dex_section.len(), 4), 0);
+        dex_section.extend_from_slice(&0u32.to_le_bytes());
+        dex_section.extend_from_slice(&second);
+
+        let mut raw = Vec::new();
+        raw.extend_from_slice(b"vdex021\x00002\x00");
+        raw.extend_from_slice(&2u32.to_le_bytes());                  // number of DEX files
+        raw.extend_from_slice(&3u32.to_le_bytes());                  // verifier deps size
+        raw.extend_from_slice(&0u32.to_le_bytes());
+        raw.extend_from_slice(&0u32.to_le_bytes());
+        raw.extend_from_slice(&1u32.to_le_bytes());                  // checksums
+        raw.extend_from_slice(&2u32.to_le_bytes());
+        raw.extend_from_slice(&(dex_section.len() as u32).to_le_bytes());
+        raw.extend_from_slice(&0u32.to_le_bytes());
+        raw.extend_from_slice(&0u32.to_le_bytes());
+        raw.extend_from_slice(&dex_section);
+        raw.extend_from_slice(&[0xaa, 0xbb, 0xcc]);
+
+        let vdex = VdexFile::build(&raw).unwrap();
+        assert_eq!(vdex.version, VdexVersion::V021);
+        assert_eq!(vdex.checksums, vec![1, 2]);
+        assert_eq!(vdex.dex_files.len(), 2);
+        assert_eq!(vdex.dex_files[0].bytes, first);
+        assert_eq!(vdex.dex_files[1].bytes, second);
+        assert_eq!(vdex.dex_files[1].location_checksum, 2);
+        assert_eq!(vdex.raw_verifier_deps, vec![0xaa, 0xbb, 0xcc]);
+        assert!(vdex.verifier_deps.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+//! Helpers to read little-endian values from raw byte slices
+//!
+//! `DexReader` is tied to DEX files (it needs the endianness tag of the DEX header before reading
+//! anything). The container formats wrapping DEX files (VDEX, OAT, APK resources, etc.) are always
+//! little-endian and are accessed at arbitrary offsets, so we use these bounds-checked helpers
+//! instead.
+
+use crate::error::DexError;
+
+/// Get `size` bytes starting at `offset`, checking the bounds of the slice
+pub(crate) fn slice_at(raw: &[u8], offset: usize, size: usize) -> Result<&[u8], DexError> {
+    let end = offset.checked_add(size).ok_or(DexError::InvalidSectionBounds)?;
+    raw.get(offset..end).ok_or(DexError::InvalidSectionBounds)
+}
+
+/// Read an unsigned 16 bits little-endian integer at the given offset
+pub(crate) fn read_u16_at(raw: &[u8], offset: usize) -> Result<u16, DexError> {
+    let bytes = slice_at(raw, offset, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+/// Read an unsigned 32 bits little-endian integer at the given offset
+pub(crate) fn read_u32_at(raw: &[u8], offset: usize) -> Result<u32, DexError> {
+    let bytes = slice_at(raw, offset, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read an unsigned 64 bits little-endian integer at the given offset
+pub(crate) fn read_u64_at(raw: &[u8], offset: usize) -> Result<u64, DexError> {
+    let bytes = slice_at(raw, offset, 8)?;
+    let mut value = [0u8; 8];
+    value.copy_from_slice(bytes);
+    Ok(u64::from_le_bytes(value))
+}
+
+/// Read an unsigned LEB128 value at `pos` and move `pos` after it
+pub(crate) fn read_uleb128_at(raw: &[u8], pos: &mut usize) -> Result<u32, DexError> {
+    let mut result: u32 = 0;
+
+    for bytes_read in 0..5 {
+        let byte = *raw.get(*pos).ok_or(DexError::NoDataLeftError)?;
+        *pos += 1;
+        result |= ((byte & 0b0111_1111) as u32) << (7 * bytes_read);
+
+        if (byte & 0b1000_0000) == 0 {
+            return Ok(result);
+        }
+    }
+
+    Err(DexError::InvalidUleb128Value)
+}
+
+/// Read a signed LEB128 value at `pos` and move `pos` after it
+pub(crate) fn read_sleb128_at(raw: &[u8], pos: &mut usize) -> Result<i32, DexError> {
+    let mut result: u32 = 0;
+
+    for bytes_read in 0..5 {
+        let byte = *raw.get(*pos).ok_or(DexError::NoDataLeftError)?;
+        *pos += 1;
+        result |= ((byte & 0b0111_1111) as u32) << (7 * bytes_read);
+
+        if (byte & 0b1000_0000) == 0 {
+            let shift = 7 * (bytes_read + 1);
+            if shift < 32 && (byte & 0b0100_0000) != 0 {
+                // Sign extend
+                result |= u32::MAX << shift;
+            }
+            return Ok(result as i32);
+        }
+    }
+
+    Err(DexError::InvalidSleb128Value)
+}
+
+/// Read a NUL-terminated string at the given offset
+pub(crate) fn read_c_string_at(raw: &[u8], offset: usize) -> Result<String, DexError> {
+    let tail = raw.get(offset..).ok_or(DexError::InvalidSectionBounds)?;
+    let len = tail.iter().position(|&b| b == 0).ok_or(DexError::NoDataLeftError)?;
+    Ok(String::from_utf8_lossy(&tail[..len]).to_string())
+}
+
+/// Round `value` up to the next multiple of `alignment`
+pub(crate) fn align_up(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_at() {
+        let raw = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08];
+        assert_eq!(read_u16_at(&raw, 0).unwrap(), 0x0201);
+        assert_eq!(read_u32_at(&raw, 4).unwrap(), 0x08070605);
+        assert_eq!(read_u64_at(&raw, 0).unwrap(), 0x0807060504030201);
+        assert_eq!(read_u32_at(&raw, 6).unwrap_err().to_string(),
+                   "section offset or size out of bounds");
+        assert!(slice_at(&raw, usize::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn test_read_uleb128_at() {
+        let raw = [0x7f, 0x80, 0x7f, 0xff, 0xff, 0xff, 0xff, 0x0f, 0x80];
+        let mut pos = 0;
+        assert_eq!(read_uleb128_at(&raw, &mut pos).unwrap(), 0x7f);
+        assert_eq!(read_uleb128_at(&raw, &mut pos).unwrap(), 16256);
+        assert_eq!(read_uleb128_at(&raw, &mut pos).unwrap(), 0xffffffff);
+        assert_eq!(pos, 8);
+        assert!(read_uleb128_at(&raw, &mut pos).is_err());
+    }
+
+    #[test]
+    fn test_read_sleb128_at() {
+        let raw = [0x7f, 0x80, 0x7f, 0x3f, 0xff, 0xff, 0xff, 0xff, 0x07, 0x80];
+        let mut pos = 0;
+        assert_eq!(read_sleb128_at(&raw, &mut pos).unwrap(), -1);
+        assert_eq!(read_sleb128_at(&raw, &mut pos).unwrap(), -128);
+        assert_eq!(read_sleb128_at(&raw, &mut pos).unwrap(), 63);
+        assert_eq!(read_sleb128_at(&raw, &mut pos).unwrap(), i32::MAX);
+        assert_eq!(pos, 9);
+        assert!(read_sleb128_at(&raw, &mut pos).is_err());
+    }
+
+    #[test]
+    fn test_read_c_string_at() {
+        let raw = b"abc\0def";
+        assert_eq!(read_c_string_at(raw, 0).unwrap(), "abc");
+        assert!(read_c_string_at(raw, 4).is_err());
+        assert_eq!(align_up(5, 4), 8);
+        assert_eq!(align_up(8, 4), 8);
+    }
+}
@@ -9,7 +9,7 @@
 //! # Example
 //!
 //! ```
-//! use dex_parser::dex::access_flags::{ AccessFlag, AccessFlagType };
+//! use rusty_dex::dex::access_flags::{ AccessFlag, AccessFlagType };
 //!
 //! let flags = AccessFlag::parse(0x0001_0009, AccessFlagType::Method);
 //!
@@ -22,7 +22,7 @@ use std::fmt;
 use log::warn;
 
 /// Representation of the different access flag types: for classes, fields, or methods
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum AccessFlagType {
     /// Flag for a class
     Class,
@@ -114,7 +114,19 @@ impl AccessFlag {
     /// The result values will be different depending on where the type is used (for a class, a
     /// method, or a field)
     pub fn parse(raw: u32, for_type: AccessFlagType) -> Vec<Self> {
+        let (flags, invalid) = AccessFlag::parse_with_invalid(raw, for_type);
+        if invalid != 0 {
+            warn!("Ignoring invalid flags for {for_type}: 0x{invalid:x}");
+        }
+
+        flags
+    }
+
+    /// Converts a raw flag into a vector of access flags, and also returns the bits which are not
+    /// valid for the type and were ignored
+    pub fn parse_with_invalid(raw: u32, for_type: AccessFlagType) -> (Vec<Self>, u32) {
         let mut flags = Vec::new();
+        let mut invalid = 0;
 
         if raw & 0x01 != 0 { flags.push(AccessFlag::ACC_PUBLIC); }
         if raw & 0x02 != 0 { flags.push(AccessFlag::ACC_PRIVATE); }
@@ -127,14 +139,14 @@ impl AccessFlag {
                     flags.push(AccessFlag::ACC_SYNCHRONIZED);
                 },
                 _ => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x20");
+                    invalid |= 0x20;
                 }
             }
         }
         if raw & 0x40 != 0 {
             match for_type {
                 AccessFlagType::Class => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x40");
+                    invalid |= 0x40;
                 },
                 AccessFlagType::Field => {
                     flags.push(AccessFlag::ACC_VOLATILE);
@@ -147,7 +159,7 @@ impl AccessFlag {
         if raw & 0x80 != 0 {
             match for_type {
                 AccessFlagType::Class => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x80");
+                    invalid |= 0x80;
                 },
                 AccessFlagType::Field => {
                     flags.push(AccessFlag::ACC_TRANSIENT);
@@ -163,7 +175,7 @@ impl AccessFlag {
                     flags.push(AccessFlag::ACC_NATIVE);
                 },
                 _ => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x100");
+                    invalid |= 0x100;
                 }
             }
         }
@@ -173,14 +185,14 @@ impl AccessFlag {
                     flags.push(AccessFlag::ACC_INTERFACE);
                 },
                 _ => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x200");
+                    invalid |= 0x200;
                 }
             }
         }
         if raw & 0x400 != 0 {
             match for_type {
                 AccessFlagType::Field => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x400");
+                    invalid |= 0x400;
                 },
                 _ => {
                     flags.push(AccessFlag::ACC_ABSTRACT);
@@ -193,7 +205,7 @@ impl AccessFlag {
                     flags.push(AccessFlag::ACC_STRICT);
                 },
                 _ => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x800");
+                    invalid |= 0x800;
                 }
             }
         }
@@ -204,28 +216,28 @@ impl AccessFlag {
                     flags.push(AccessFlag::ACC_ANNOTATION);
                 },
                 _ => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x2000");
+                    invalid |= 0x2000;
                 }
             }
         }
         if raw & 0x4000 != 0 {
             match for_type {
                 AccessFlagType::Method => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x4000");
+                    invalid |= 0x4000;
                 },
                 _ => {
                     flags.push(AccessFlag::ACC_ENUM);
                 }
             }
         }
-        if raw & 0x8000 != 0  { warn!("Ignoring invalid (unused) flag: 0x8000"); }
+        if raw & 0x8000 != 0  { invalid |= 0x8000; }
         if raw & 0x10000 != 0 {
             match for_type {
                 AccessFlagType::Method => {
                     flags.push(AccessFlag::ACC_CONSTRUCTOR);
                 },
                 _ => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x10000");
+                    invalid |= 0x10000;
                 }
             }
         }
@@ -235,12 +247,12 @@ impl AccessFlag {
                     flags.push(AccessFlag::ACC_DECLARED_SYNCHRONIZED);
                 },
                 _ => {
-                    warn!("Ignoring invalid flag for {for_type}: 0x20000");
+                    invalid |= 0x20000;
                 }
             }
         }
 
-        flags
+        (flags, invalid)
     }
 
     /// Pretty print a vector of access flags
@@ -354,4 +366,12 @@ mod tests {
                                AccessFlag::ACC_CONSTRUCTOR,
                                AccessFlag::ACC_DECLARED_SYNCHRONIZED]);
     }
+
+    #[test]
+    fn test_access_flag_parse_with_invalid() {
+        assert_eq!(AccessFlag::parse_with_invalid(0x3ffff, AccessFlagType::Class).1, 0x389e0);
+        assert_eq!(AccessFlag::parse_with_invalid(0x3ffff, AccessFlagType::Field).1, 0x3af20);
+        assert_eq!(AccessFlag::parse_with_invalid(0x3ffff, AccessFlagType::Method).1, 0xe200);
+        assert_eq!(AccessFlag::parse_with_invalid(0x0001, AccessFlagType::Method).1, 0);
+    }
 }
@@ -0,0 +1,591 @@
+//! Detection of anti-analysis tricks
+//!
+//! Some samples are deliberately malformed so that analysis tools crash or show the wrong code,
+//! while the runtime still loads them. The scanner reads the raw structures again with a
+//! `DexReader` and compares them with the parsed `DexFile` to find the usual tricks:
+//!
+//!   * code items shared by several methods, or overlapping each other
+//!   * LEB128 values encoded with more bytes than needed
+//!   * class data pointing into the header
+//!   * classes defined more than once
+//!   * access flags which cannot be combined (e.g. `abstract final`)
+//!   * bytes after the map list and the data section
+//!   * payloads which are never referenced, or which the execution flow falls into, used to hide
+//!     code from linear disassemblers
+//!
+//! Every trick is reported as a `Diagnostic` with the `Warning` severity, at the offset of the
+//! offending bytes. Structures which cannot be read are left to the verifier (see `verifier`).
+
+use std::collections::{ BTreeMap, HashMap };
+use std::collections::hash_map::Entry;
+use std::io::{ Seek, SeekFrom };
+
+use crate::dex::access_flags::AccessFlagType;
+use crate::dex::classes::CLASS_DEF_ITEM_SIZE;
+use crate::dex::code_item::CODE_ITEM_HEADER_SIZE;
+use crate::dex::diagnostics::{ Diagnostic, DiagnosticCode, Severity };
+use crate::dex::file::DexFile;
+use crate::dex::instructions::Instructions;
+use crate::dex::integrity::IntegrityPolicy;
+use crate::dex::header::HEADER_SIZE;
+use crate::dex::map::{ MapItemType, MAP_ITEM_SIZE };
+use crate::dex::opcodes::OpCode;
+use crate::dex::options::ParseOptions;
+use crate::dex::reader::DexReader;
+use crate::error::DexError;
+
+const ACC_PUBLIC: u32 = 0x1;
+const ACC_PRIVATE: u32 = 0x2;
+const ACC_PROTECTED: u32 = 0x4;
+const ACC_STATIC: u32 = 0x8;
+const ACC_FINAL: u32 = 0x10;
+const ACC_SYNCHRONIZED: u32 = 0x20;
+const ACC_VOLATILE: u32 = 0x40;
+const ACC_NATIVE: u32 = 0x100;
+const ACC_INTERFACE: u32 = 0x200;
+const ACC_ABSTRACT: u32 = 0x400;
+const ACC_STRICT: u32 = 0x800;
+const ACC_ANNOTATION: u32 = 0x2000;
+const ACC_ENUM: u32 = 0x4000;
+const ACC_CONSTRUCTOR: u32 = 0x10000;
+
+/// Pairs of flags which cannot be set together on any item
+const VISIBILITY_CONFLICTS: [(u32, u32, &str); 3] = [
+    (ACC_PUBLIC, ACC_PRIVATE, "public and private"),
+    (ACC_PUBLIC, ACC_PROTECTED, "public and protected"),
+    (ACC_PRIVATE, ACC_PROTECTED, "private and protected"),
+];
+/// Pairs of flags which cannot be set together on a class
+const CLASS_CONFLICTS: [(u32, u32, &str); 3] = [
+    (ACC_ABSTRACT, ACC_FINAL, "abstract and final"),
+    (ACC_INTERFACE, ACC_FINAL, "interface and final"),
+    (ACC_INTERFACE, ACC_ENUM, "interface and enum"),
+];
+/// Pairs of flags which cannot be set together on a field
+const FIELD_CONFLICTS: [(u32, u32, &str); 1] = [
+    (ACC_FINAL, ACC_VOLATILE, "final and volatile"),
+];
+/// Pairs of flags which cannot be set together on a method
+const METHOD_CONFLICTS: [(u32, u32, &str); 7] = [
+    (ACC_ABSTRACT, ACC_PRIVATE, "abstract and private"),
+    (ACC_ABSTRACT, ACC_STATIC, "abstract and static"),
+    (ACC_ABSTRACT, ACC_FINAL, "abstract and final"),
+    (ACC_ABSTRACT, ACC_NATIVE, "abstract and native"),
+    (ACC_ABSTRACT, ACC_SYNCHRONIZED, "abstract and synchronized"),
+    (ACC_ABSTRACT, ACC_STRICT, "abstract and strict"),
+    (ACC_ABSTRACT, ACC_CONSTRUCTOR, "abstract and constructor"),
+];
+
+/// Scan the DEX file at the given path
+///
+/// The file is parsed in recovery mode, only warning about a stale checksum, so that broken
+/// classes do not prevent the scan.
+pub fn scan_from_file(filepath: &str) -> Result<Vec<Diagnostic>, DexError> {
+    let raw = std::fs::read(filepath)?;
+    let options = ParseOptions {
+        checksum: IntegrityPolicy::Warn,
+        signature: IntegrityPolicy::Skip,
+        recover: true,
+        ..Default::default()
+    };
+    let dex = DexFile::build_with_options(DexReader::build(raw.clone())?, &options)?;
+
+    Ok(scan(&mut DexReader::build(raw)?, &dex))
+}
+
+/// Scan a DEX file for anti-analysis tricks and return the list of anomalies
+///
+/// The reader must be over the bytes the `DexFile` was parsed from, which must therefore come
+/// from a single DEX file.
+pub fn scan(dex_reader: &mut DexReader, dex: &DexFile) -> Vec<Diagnostic> {
+    let mut scanner = Scanner {
+        reader: dex_reader,
+        dex,
+        anomalies: Vec::new(),
+        code_items: BTreeMap::new(),
+    };
+
+    // A check stops at the first bytes it cannot read
+    let _ = scanner.scan_strings();
+    let _ = scanner.scan_class_defs();
+    scanner.scan_code_items();
+    let _ = scanner.scan_trailing_data();
+    scanner.scan_payloads();
+
+    scanner.anomalies
+}
+
+/// State of the scan of a file
+struct Scanner<'a, 'b> {
+    reader: &'a mut DexReader<'b>,
+    dex: &'a DexFile,
+    anomalies: Vec<Diagnostic>,
+    /// Offsets of the code items, with the methods using them
+    code_items: BTreeMap<u64, Vec<String>>,
+}
+
+impl Scanner<'_, '_> {
+    /// Record an anomaly
+    fn report(&mut self, code: DiagnosticCode, offset: u64, section: MapItemType, message: String) {
+        self.anomalies.push(Diagnostic::new(Severity::Warning, code, offset, Some(section), message));
+    }
+
+    /// Read an unsigned LEB128 value and check that its encoding is as short as possible
+    fn uleb128(&mut self, section: MapItemType) -> Result<u32, DexError> {
+        let offset = self.reader.bytes.position();
+        let (value, size) = self.reader.read_uleb128()?;
+        let expected = uleb128_size(value);
+        if size > expected {
+            self.report(DiagnosticCode::OverlongLeb128, offset, section,
+                        format!("unsigned value {value} encoded on {size} bytes instead of {expected}"));
+        }
+        Ok(value)
+    }
+
+    /// Read a signed LEB128 value and check that its encoding is as short as possible
+    fn sleb128(&mut self, section: MapItemType) -> Result<i32, DexError> {
+        let offset = self.reader.bytes.position();
+        let (value, size) = self.reader.read_sleb128()?;
+        let expected = sleb128_size(value);
+        if size > expected {
+            self.report(DiagnosticCode::OverlongLeb128, offset, section,
+                        format!("signed value {value} encoded on {size} bytes instead of {expected}"));
+        }
+        Ok(value)
+    }
+
+    /// Check the combination of the access flags of an item
+    fn check_flags(&mut self, flags: u32, for_type: AccessFlagType, offset: u64, section: MapItemType) {
+        let conflicts = conflicting_flags(flags, for_type);
+        if !conflicts.is_empty() {
+            self.report(DiagnosticCode::ConflictingAccessFlags, offset, section,
+                        format!("{for_type} flags 0x{flags:x} are {}", conflicts.join(", ")));
+        }
+    }
+
+    /// Check the encoding of the lengths of the strings
+    fn scan_strings(&mut self) -> Result<(), DexError> {
+        let header = &self.dex.header;
+        let (offset, size) = (u64::from(header.string_ids_off), header.string_ids_size);
+
+        for idx in 0..size {
+            self.reader.bytes.seek(SeekFrom::Start(offset + 4 * u64::from(idx)))?;
+            let string_data_off = self.reader.read_u32()?;
+            self.reader.bytes.seek(SeekFrom::Start(string_data_off.into()))?;
+            self.uleb128(MapItemType::StringDataItem)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the class definitions and their class data, and collect the code items
+    fn scan_class_defs(&mut self) -> Result<(), DexError> {
+        let offset = u64::from(self.dex.header.class_defs_off);
+        let size = self.dex.header.class_defs_size;
+        let header_size = self.dex.header.header_size.max(HEADER_SIZE as u32);
+        let mut defined = HashMap::new();
+
+        for idx in 0..size {
+            let def_offset = offset + u64::from(idx) * CLASS_DEF_ITEM_SIZE;
+            self.reader.bytes.seek(SeekFrom::Start(def_offset))?;
+            let class_idx = self.reader.read_u32()?;
+            let access_flags = self.reader.read_u32()?;
+            self.reader.bytes.seek(SeekFrom::Start(def_offset + 24))?;
+            let class_data_off = self.reader.read_u32()?;
+            let first_anomaly = self.anomalies.len();
+
+            match defined.entry(class_idx) {
+                Entry::Occupied(first) => {
+                    let first_offset = *first.get();
+                    self.report(DiagnosticCode::DuplicateClass, def_offset, MapItemType::ClassDefItem,
+                                format!("class already defined at 0x{first_offset:x}"));
+                },
+                Entry::Vacant(entry) => {
+                    entry.insert(def_offset);
+                },
+            }
+            self.check_flags(access_flags, AccessFlagType::Class, def_offset + 4, MapItemType::ClassDefItem);
+            if class_data_off != 0 {
+                if class_data_off < header_size {
+                    self.report(DiagnosticCode::ClassDataInHeader, def_offset + 24, MapItemType::ClassDefItem,
+                                format!("class data at 0x{class_data_off:x} is in the header"));
+                }
+                // Broken class data only stops the scan of this class
+                let _ = self.scan_class_data(class_data_off.into());
+            }
+
+            let class = self.dex.types.items.get(class_idx as usize);
+            for anomaly in self.anomalies[first_anomaly..].iter_mut() {
+                anomaly.class = class.cloned();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the class data at the given offset
+    fn scan_class_data(&mut self, offset: u64) -> Result<(), DexError> {
+        self.reader.bytes.seek(SeekFrom::Start(offset))?;
+        let mut sizes = [0; 4];
+        for size in sizes.iter_mut() {
+            *size = self.uleb128(MapItemType::ClassDataItem)?;
+        }
+
+        for (list, size) in sizes.into_iter().enumerate() {
+            let is_method = list >= 2;
+            let mut member_idx = 0u32;
+
+            for _ in 0..size {
+                let member_offset = self.reader.bytes.position();
+                member_idx = member_idx.wrapping_add(self.uleb128(MapItemType::ClassDataItem)?);
+                let access_flags = self.uleb128(MapItemType::ClassDataItem)?;
+
+                if !is_method {
+                    self.check_flags(access_flags, AccessFlagType::Field, member_offset, MapItemType::ClassDataItem);
+                    continue;
+                }
+                let code_off = self.uleb128(MapItemType::ClassDataItem)?;
+                self.check_flags(access_flags, AccessFlagType::Method, member_offset, MapItemType::ClassDataItem);
+                if code_off != 0 {
+                    let method = self.dex.methods.items.get(member_idx as usize)
+                                                       .cloned()
+                                                       .unwrap_or_else(|| format!("method@{member_idx}"));
+                    self.code_items.entry(code_off.into()).or_default().push(method);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that code items are neither shared nor overlapping
+    fn scan_code_items(&mut self) {
+        let code_items = std::mem::take(&mut self.code_items);
+        // Start and end of the code item ending the furthest so far
+        let mut furthest: Option<(u64, u64)> = None;
+
+        for (&offset, methods) in code_items.iter() {
+            let first_anomaly = self.anomalies.len();
+
+            if methods.len() > 1 {
+                self.report(DiagnosticCode::SharedCodeItem, offset, MapItemType::CodeItem,
+                            format!("code item used by {} methods: {}", methods.len(), methods.join(", ")));
+            }
+            if let Ok(end) = self.code_item_end(offset) {
+                match furthest {
+                    Some((start, furthest_end)) if offset < furthest_end => {
+                        self.report(DiagnosticCode::OverlappingCodeItems, offset, MapItemType::CodeItem,
+                                    format!("code item overlaps the code item at 0x{start:x}"));
+                        if end > furthest_end {
+                            furthest = Some((offset, end));
+                        }
+                    },
+                    _ => furthest = Some((offset, end)),
+                }
+            }
+
+            for anomaly in self.anomalies[first_anomaly..].iter_mut() {
+                anomaly.method = methods.first().cloned();
+            }
+        }
+
+        self.code_items = code_items;
+    }
+
+    /// Find the end of the code item at the given offset, checking the encoding of its handlers
+    fn code_item_end(&mut self, offset: u64) -> Result<u64, DexError> {
+        self.reader.bytes.seek(SeekFrom::Start(offset + 6))?;
+        let tries_size = self.reader.read_u16()?;
+        self.reader.bytes.seek(SeekFrom::Start(offset + 12))?;
+        let insns_size = self.reader.read_u32()?;
+
+        let mut end = offset + CODE_ITEM_HEADER_SIZE as u64 + 2 * u64::from(insns_size);
+        if tries_size == 0 {
+            return Ok(end);
+        }
+
+        // Padding to align the tries, then the tries and the handlers
+        if !insns_size.is_multiple_of(2) {
+            end += 2;
+        }
+        end += 8 * u64::from(tries_size);
+        self.reader.bytes.seek(SeekFrom::Start(end))?;
+
+        let handlers_size = self.uleb128(MapItemType::CodeItem)?;
+        for _ in 0..handlers_size {
+            let size = self.sleb128(MapItemType::CodeItem)?;
+            for _ in 0..size.unsigned_abs() {
+                self.uleb128(MapItemType::CodeItem)?;
+                self.uleb128(MapItemType::CodeItem)?;
+            }
+            if size <= 0 {
+                self.uleb128(MapItemType::CodeItem)?;
+            }
+        }
+
+        Ok(self.reader.bytes.position())
+    }
+
+    /// Check that nothing follows the map list and the data section
+    fn scan_trailing_data(&mut self) -> Result<(), DexError> {
+        let header = &self.dex.header;
+        let map_off = u64::from(header.map_off);
+        let data_end = u64::from(header.data_off) + u64::from(header.data_size);
+
+        self.reader.bytes.seek(SeekFrom::Start(map_off))?;
+        let map_end = map_off + 4 + MAP_ITEM_SIZE as u64 * u64::from(self.reader.read_u32()?);
+
+        let end = map_end.max(data_end);
+        if end < self.reader.bytes_len {
+            self.report(DiagnosticCode::TrailingData, end, MapItemType::MapList,
+                        format!("{} bytes after the map list and the data section", self.reader.bytes_len - end));
+        }
+
+        Ok(())
+    }
+
+    /// Check that the payloads are referenced and cannot be executed
+    fn scan_payloads(&mut self) {
+        let dex = self.dex;
+
+        for class in dex.classes.items.iter() {
+            for method in class.get_methods() {
+                let Some(code) = &method.code_item else {
+                    continue;
+                };
+                let Some(insns) = &code.insns else {
+                    continue;
+                };
+
+                // Payloads referenced by `packed-switch`, `sparse-switch`, and `fill-array-data`
+                let mut referenced = Vec::new();
+                let mut addr = 0;
+                for inst in insns.iter() {
+                    if let Instructions::Instruction31t(_) = inst {
+                        let units = inst.bytes();
+                        let offset = (u32::from(units[1]) | (u32::from(units[2]) << 16)) as i32;
+                        referenced.push(addr as i64 + i64::from(offset));
+                    }
+                    addr += inst.length();
+                }
+
+                let mut addr = 0;
+                // Last instruction which is not a `nop`, as `nop`s pad payloads
+                let mut previous: Option<&Instructions> = None;
+                for inst in insns.iter() {
+                    if is_payload(inst) {
+                        let mut reasons = Vec::new();
+                        if !referenced.contains(&(addr as i64)) {
+                            reasons.push("never referenced");
+                        }
+                        if previous.is_none_or(|previous| continues(previous.opcode())) {
+                            reasons.push("reached by the execution flow");
+                        }
+                        if !reasons.is_empty() {
+                            let mut anomaly = Diagnostic::new(Severity::Warning,
+                                                              DiagnosticCode::FakePayload,
+                                                              u64::from(code.offset()) + CODE_ITEM_HEADER_SIZE as u64 + 2 * addr as u64,
+                                                              Some(MapItemType::CodeItem),
+                                                              format!("0x{addr:04x}: {:?} {}", inst.opcode(), reasons.join(" and ")));
+                            anomaly.class = Some(class.get_class_name().clone());
+                            anomaly.method = Some(method.proto.clone());
+                            self.anomalies.push(anomaly);
+                        }
+                    }
+
+                    if inst.opcode() != OpCode::NOP || is_payload(inst) {
+                        previous = Some(inst);
+                    }
+                    addr += inst.length();
+                }
+            }
+        }
+    }
+}
+
+/// Smallest number of bytes needed to encode an unsigned LEB128 value
+fn uleb128_size(value: u32) -> usize {
+    ((32 - value.leading_zeros() as usize).div_ceil(7)).max(1)
+}
+
+/// Smallest number of bytes needed to encode a signed LEB128 value
+fn sleb128_size(value: i32) -> usize {
+    let magnitude = if value < 0 { !value } else { value };
+    // Significant bits and the sign bit
+    (33 - magnitude.leading_zeros() as usize).div_ceil(7)
+}
+
+/// Descriptions of the flags which are set together but cannot be combined
+fn conflicting_flags(flags: u32, for_type: AccessFlagType) -> Vec<&'static str> {
+    let specific: &[(u32, u32, &str)] = match for_type {
+        AccessFlagType::Class => &CLASS_CONFLICTS,
+        AccessFlagType::Field => &FIELD_CONFLICTS,
+        AccessFlagType::Method => &METHOD_CONFLICTS,
+    };
+    let mut conflicts = VISIBILITY_CONFLICTS.iter()
+                                            .chain(specific.iter())
+                                            .filter(|(first, second, _)| flags & first != 0 && flags & second != 0)
+                                            .map(|(_, _, description)| *description)
+                                            .collect::<Vec<&str>>();
+
+    if let AccessFlagType::Class = for_type {
+        if flags & ACC_INTERFACE != 0 && flags & ACC_ABSTRACT == 0 {
+            conflicts.push("interface but not abstract");
+        }
+        if flags & ACC_ANNOTATION != 0 && flags & ACC_INTERFACE == 0 {
+            conflicts.push("annotation but not interface");
+        }
+    }
+
+    conflicts
+}
+
+/// Whether the instruction is a payload
+fn is_payload(inst: &Instructions) -> bool {
+    matches!(inst, Instructions::PackedSwitchPayload(_)
+                 | Instructions::SparseSwitchPayload(_)
+                 | Instructions::FillArrayDataPayload(_))
+}
+
+/// Whether the execution can continue to the next instruction
+fn continues(opcode: OpCode) -> bool {
+    !matches!(opcode, OpCode::GOTO | OpCode::GOTO_16 | OpCode::GOTO_32
+                    | OpCode::RETURN_VOID | OpCode::RETURN | OpCode::RETURN_WIDE | OpCode::RETURN_OBJECT
+                    | OpCode::RETURN_VOID_BARRIER | OpCode::THROW
+                    | OpCode::PACKED_SWITCH_PAYLOAD | OpCode::SPARSE_SWITCH_PAYLOAD
+                    | OpCode::FILL_ARRAY_DATA_PAYLOAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes::{ read_u32_at, read_uleb128_at };
+    use crate::fixtures::{ fake_dex, fake_dex_with_methods, FakeMethod };
+
+    fn codes(raw: &[u8]) -> Vec<DiagnosticCode> {
+        let options = ParseOptions {
+            checksum: IntegrityPolicy::Skip,
+            signature: IntegrityPolicy::Skip,
+            recover: true,
+            ..Default::default()
+        };
+        let dex = DexFile::build_with_options(DexReader::build(raw.to_vec()).unwrap(), &options).unwrap();
+        scan(&mut DexReader::build(raw.to_vec()).unwrap(), &dex).into_iter()
+                                                                .map(|anomaly| anomaly.code)
+                                                                .collect()
+    }
+
+    /// Offsets of the `code_off` fields of the methods of the first class
+    fn code_off_positions(raw: &[u8]) -> Vec<usize> {
+        let class_defs_off = read_u32_at(raw, 0x64).unwrap() as usize;
+        let mut pos = read_u32_at(raw, class_defs_off + 24).unwrap() as usize;
+        let sizes = (0..4).map(|_| read_uleb128_at(raw, &mut pos).unwrap()).collect::<Vec<u32>>();
+
+        let mut positions = Vec::new();
+        for _ in 0..sizes[2] {
+            read_uleb128_at(raw, &mut pos).unwrap();
+            read_uleb128_at(raw, &mut pos).unwrap();
+            positions.push(pos);
+            read_uleb128_at(raw, &mut pos).unwrap();
+        }
+        positions
+    }
+
+    #[test]
+    fn test_scan_clean() {
+        // const/4 v0, #0; packed-switch v0, +5; return-void; nop; packed-switch-payload
+        let switch = vec![0x0012, 0x002b, 0x0005, 0x0000, 0x000e, 0x0000,
+                          0x0100, 0x0001, 0x0000, 0x0000, 0x0003, 0x0000];
+        let methods = [FakeMethod { class: "La;", name: "run", access_flags: 0x9, code: Some((1, 0, 0, switch)) }];
+        assert!(codes(&fake_dex_with_methods(&["La;"], &[], &methods)).is_empty());
+    }
+
+    #[test]
+    fn test_scan_class_defs() {
+        let mut raw = fake_dex(&["La;", "Lb;"], &[]);
+        let class_defs_off = read_u32_at(&raw, 0x64).unwrap() as usize;
+
+        // abstract final class La;, defined twice, with its class data in the header
+        raw[class_defs_off + 4..class_defs_off + 8].copy_from_slice(&0x411u32.to_le_bytes());
+        raw.copy_within(class_defs_off..class_defs_off + 4, class_defs_off + 32);
+        raw[class_defs_off + 56..class_defs_off + 60].copy_from_slice(&0x20u32.to_le_bytes());
+
+        let codes = codes(&raw);
+        assert!(codes.contains(&DiagnosticCode::ConflictingAccessFlags));
+        assert!(codes.contains(&DiagnosticCode::DuplicateClass));
+        assert!(codes.contains(&DiagnosticCode::ClassDataInHeader));
+    }
+
+    #[test]
+    fn test_scan_code_items() {
+        // The code of `b` starts within the code of `a`, and `c` reuses the code of `a`
+        let methods = [
+            FakeMethod { class: "La;", name: "a", access_flags: 0x9, code: Some((1, 0, 0, vec![0, 0, 1, 0, 0x0e, 0x0e])) },
+            FakeMethod { class: "La;", name: "b", access_flags: 0x9, code: Some((1, 0, 0, vec![0x0e])) },
+            FakeMethod { class: "La;", name: "c", access_flags: 0x9, code: Some((1, 0, 0, vec![0x0e])) },
+        ];
+        let mut raw = fake_dex_with_methods(&["La;"], &[], &methods);
+        assert!(codes(&raw).is_empty());
+
+        let positions = code_off_positions(&raw);
+        let mut pos = positions[0];
+        let code_off = read_uleb128_at(&raw, &mut pos).unwrap();
+        let overlapping = [((code_off + 8) & 0x7f) as u8 | 0x80, ((code_off + 8) >> 7) as u8];
+        raw[positions[1]..positions[1] + 2].copy_from_slice(&overlapping);
+        raw.copy_within(positions[0]..positions[0] + 2, positions[2]);
+
+        assert_eq!(codes(&raw), vec![DiagnosticCode::SharedCodeItem, DiagnosticCode::OverlappingCodeItems]);
+    }
+
+    #[test]
+    fn test_scan_leb128_and_trailing_data() {
+        let mut raw = fake_dex(&["La;"], &[]);
+        let class_defs_off = read_u32_at(&raw, 0x64).unwrap() as usize;
+
+        // Empty class data, appended after the map list, with a two-byte encoding of 0
+        let class_data_off = raw.len() as u32;
+        raw.extend_from_slice(&[0x80, 0x00, 0x00, 0x00, 0x00]);
+        raw[class_defs_off + 24..class_defs_off + 28].copy_from_slice(&class_data_off.to_le_bytes());
+        let file_size = raw.len() as u32;
+        raw[0x20..0x24].copy_from_slice(&file_size.to_le_bytes());
+
+        let codes = codes(&raw);
+        assert_eq!(codes, vec![DiagnosticCode::OverlongLeb128, DiagnosticCode::TrailingData]);
+    }
+
+    #[test]
+    fn test_scan_payloads() {
+        // return-void; nop; fill-array-data-payload, never referenced
+        let hidden = vec![0x000e, 0x0000, 0x0300, 0x0001, 0x0000, 0x0000];
+        // fill-array-data v0, +4; const/4 v0, #0; fill-array-data-payload, reached after const/4
+        let reached = vec![0x0026, 0x0004, 0x0000, 0x0012, 0x0300, 0x0001, 0x0000, 0x0000, 0x000e];
+        let methods = [
+            FakeMethod { class: "La;", name: "hidden", access_flags: 0x9, code: Some((1, 0, 0, hidden)) },
+            FakeMethod { class: "La;", name: "reached", access_flags: 0x9, code: Some((1, 0, 0, reached)) },
+        ];
+        let raw = fake_dex_with_methods(&["La;"], &[], &methods);
+
+        let options = ParseOptions { recover: true, ..Default::default() };
+        let dex = DexFile::build_with_options(DexReader::build(raw.clone()).unwrap(), &options).unwrap();
+        let anomalies = scan(&mut DexReader::build(raw).unwrap(), &dex);
+        assert_eq!(anomalies.len(), 2);
+        assert!(anomalies.iter().all(|anomaly| anomaly.code == DiagnosticCode::FakePayload));
+        assert!(anomalies[0].message.ends_with("never referenced"));
+        assert_eq!(anomalies[0].method.as_deref(), Some("La;->hidden()V"));
+        assert!(anomalies[1].message.starts_with("0x0004: FILL_ARRAY_DATA_PAYLOAD reached by the execution flow"));
+    }
+
+    #[test]
+    fn test_leb128_size() {
+        assert_eq!(uleb128_size(0), 1);
+        assert_eq!(uleb128_size(0x7f), 1);
+        assert_eq!(uleb128_size(0x80), 2);
+        assert_eq!(uleb128_size(u32::MAX), 5);
+        assert_eq!(sleb128_size(0), 1);
+        assert_eq!(sleb128_size(63), 1);
+        assert_eq!(sleb128_size(64), 2);
+        assert_eq!(sleb128_size(-64), 1);
+        assert_eq!(sleb128_size(-65), 2);
+        assert_eq!(sleb128_size(i32::MIN), 5);
+    }
+}
@@ -0,0 +1,185 @@
+//! Carving DEX files out of arbitrary data
+//!
+//! Memory dumps and unknown containers can hold DEX images at any offset. The carver looks for
+//! the DEX magic, then checks that the header describes a plausible file: expected header size
+//! and endianness tag, a file size which fits in the buffer, ID sections within the file, and a
+//! valid map list.
+//!
+//! The checksum is not part of the validation: dumped images often have it zeroed or stale. The
+//! carved bytes get a recomputed checksum so they can go through `DexFile::build`, and the
+//! validity of the original one is reported.
+
+use crate::adler32;
+use crate::bytes::{ slice_at, read_u32_at };
+use crate::dex::header::{ HEADER_SIZE, ENDIAN_CONSTANT };
+use crate::dex::map::{ MapItemType, MapList };
+use crate::dex::reader::DexReader;
+use crate::error::DexError;
+
+/// A DEX file found in a buffer
+#[derive(Debug)]
+pub struct CarvedDex {
+    /// Offset of the DEX file in the buffer
+    pub offset: usize,
+    /// Version of the DEX file (e.g., `035`)
+    pub version: [u8; 3],
+    /// Checksum found in the header
+    pub checksum: u32,
+    /// Whether the checksum found in the header matches the contents
+    pub checksum_valid: bool,
+    /// Raw bytes of the DEX file, with a valid checksum
+    pub bytes: Vec<u8>,
+}
+
+impl CarvedDex {
+    /// Create a `DexReader` over the carved bytes
+    pub fn dex_reader(&self) -> Result<DexReader<'static>, DexError> {
+        DexReader::build(self.bytes.clone())
+    }
+}
+
+/// Open the file at the given path and carve the DEX files it contains
+pub fn carve_from_file(filepath: &str) -> Result<Vec<CarvedDex>, DexError> {
+    let raw = std::fs::read(filepath)?;
+    Ok(carve(&raw))
+}
+
+/// Find the DEX files contained in a buffer
+///
+/// The search resumes after the end of each DEX file found, so DEX files are never nested or
+/// overlapping.
+pub fn carve(raw: &[u8]) -> Vec<CarvedDex> {
+    let mut carved = Vec::new();
+    let mut offset = 0;
+
+    while let Some(position) = find_magic(raw, offset) {
+        match check_candidate(raw, position) {
+            Some(file_size) => {
+                let mut bytes = raw[position..position + file_size].to_vec();
+                let checksum = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+                let computed = adler32::compute(&bytes);
+                bytes[8..12].copy_from_slice(&computed.to_le_bytes());
+
+                carved.push(CarvedDex {
+                    offset: position,
+                    version: [bytes[4], bytes[5], bytes[6]],
+                    checksum,
+                    checksum_valid: checksum == computed,
+                    bytes,
+                });
+                offset = position + file_size;
+            },
+            None => offset = position + 1,
+        }
+    }
+
+    carved
+}
+
+/// Find the next `dex\n0XX\0` magic at or after `offset`
+fn find_magic(raw: &[u8], offset: usize) -> Option<usize> {
+    raw.get(offset..)?
+       .windows(8)
+       .position(|window| window.starts_with(b"dex\n0")
+                          && window[5].is_ascii_digit()
+                          && window[6].is_ascii_digit()
+                          && window[7] == 0)
+       .map(|position| offset + position)
+}
+
+/// Validate the header of a candidate DEX file and return its size
+fn check_candidate(raw: &[u8], offset: usize) -> Option<usize> {
+    let header = slice_at(raw, offset, HEADER_SIZE).ok()?;
+    let field = |field_offset: usize| read_u32_at(header, field_offset).ok().map(|value| value as usize);
+
+    let file_size = field(0x20)?;
+    if field(0x24)? != HEADER_SIZE || field(0x28)? != ENDIAN_CONSTANT as usize {
+        return None;
+    }
+    if file_size < HEADER_SIZE || file_size > raw.len() - offset {
+        return None;
+    }
+    let dex = &raw[offset..offset + file_size];
+
+    // (size, offset, item size) of the ID sections
+    let sections = [(field(0x38)?, field(0x3c)?, 4),
+                    (field(0x40)?, field(0x44)?, 4),
+                    (field(0x48)?, field(0x4c)?, 12),
+                    (field(0x50)?, field(0x54)?, 8),
+                    (field(0x58)?, field(0x5c)?, 8),
+                    (field(0x60)?, field(0x64)?, 32)];
+    for (size, section_offset, item_size) in sections {
+        if size > 0 && slice_at(dex, section_offset, size.checked_mul(item_size)?).is_err() {
+            return None;
+        }
+    }
+
+    let map_off = field(0x34)?;
+    let map = MapList::build(dex, map_off).ok()?;
+    let header_item = map.items.first()?;
+    if header_item.kind != MapItemType::HeaderItem || header_item.offset != 0 {
+        return None;
+    }
+    if map.get(MapItemType::MapList).is_none_or(|item| item.offset as usize != map_off) {
+        return None;
+    }
+    if map.items.iter().any(|item| item.offset as usize >= file_size) {
+        return None;
+    }
+
+    Some(file_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::file::DexFile;
+    use crate::fixtures::fake_dex;
+
+    #[test]
+    fn test_carve() {
+        let first = fake_dex(&["La;"], &[]);
+        let mut second = fake_dex(&["Lb;", "Lc;"], &[]);
+        second[8..12].copy_from_slice(&[0; 4]);
+
+        // Noise, a truncated DEX magic, a false positive, and two DEX files
+        let mut raw = b"noise dex\n03".to_vec();
+        raw.extend_from_slice(b"dex\n035\0 not a header");
+        let first_offset = raw.len();
+        raw.extend_from_slice(&first);
+        raw.extend_from_slice(&[0xaa; 7]);
+        let second_offset = raw.len();
+        raw.extend_from_slice(&second);
+
+        let carved = carve(&raw);
+        assert_eq!(carved.len(), 2);
+        assert_eq!(carved[0].offset, first_offset);
+        assert_eq!(carved[0].version, *b"035");
+        assert!(carved[0].checksum_valid);
+        assert_eq!(carved[0].bytes, first);
+
+        assert_eq!(carved[1].offset, second_offset);
+        assert_eq!(carved[1].checksum, 0);
+        assert!(!carved[1].checksum_valid);
+        let dex = DexFile::build(carved[1].dex_reader().unwrap()).unwrap();
+        assert_eq!(dex.get_classes_names().len(), 2);
+    }
+
+    #[test]
+    fn test_carve_invalid() {
+        let valid = fake_dex(&["La;"], &[]);
+
+        // Truncated
+        assert!(carve(&valid[..valid.len() - 1]).is_empty());
+
+        // Map list pointing out of the file
+        let mut invalid = valid.clone();
+        invalid[0x34..0x38].copy_from_slice(&0x10000u32.to_le_bytes());
+        assert!(carve(&invalid).is_empty());
+
+        // ID section out of the file
+        let mut invalid = valid.clone();
+        invalid[0x64..0x68].copy_from_slice(&(valid.len() as u32).to_le_bytes());
+        assert!(carve(&invalid).is_empty());
+    }
+}
@@ -8,15 +8,20 @@ use log::{ warn, debug };
 use crate::dex::reader::DexReader;
 use crate::dex::access_flags::{ AccessFlag, AccessFlagType };
 use crate::dex::code_item::CodeItem;
+use crate::dex::encoded_values::{ EncodedValue, EncodedValueContext };
+use crate::dex::diagnostics::{ Diagnostic, DiagnosticCode, Severity };
+use crate::dex::map::MapItemType;
 
 use crate::dex::strings::DexStrings;
 use crate::dex::types::DexTypes;
 use crate::dex::fields::DexFields;
 use crate::dex::methods::DexMethods;
-use crate::error::DexError;
+use crate::error::{ DexError, ErrorContext, ErrorFrame };
 
 /// Constant to represent the absence of index
-const NO_INDEX: u32 = 0xffffffff;
+pub const NO_INDEX: u32 = 0xffffffff;
+/// Size of a class definition item
+pub const CLASS_DEF_ITEM_SIZE: u64 = 32;
 
 lazy_static!{
     /// Regex for method prototypes
@@ -35,29 +40,40 @@ lazy_static!{
 /// class contains not fields or methods, in which case `class_data` will be `None`.
 #[derive(Debug)]
 pub struct ClassDefItem {
+    class_idx: u32,
     class_str: String,
+    raw_access_flags: u32,
     access_flags: Vec<AccessFlag>,
+    superclass_idx: u32,
     superclass_str: Option<String>,
     interfaces_off: u32,
+    interfaces: Vec<String>,
+    source_file_idx: u32,
     source_file_str: Option<String>,
     annotations_off: u32,
     class_data_off: u32,
     static_value_off: u32,
+    static_values: Vec<EncodedValue>,
     class_data: Option<ClassDataItem>
 }
 
 /// Representation of an encoded field
 #[derive(Debug)]
 pub struct EncodedField {
+    field_idx: u32,
     field: String,
+    raw_access_flags: u32,
     access_flags: Vec<AccessFlag>,
 }
 
 /// Representation of an encoded method
 #[derive(Debug)]
 pub struct EncodedMethod {
+    method_idx: u32,
     pub proto: String,
+    raw_access_flags: u32,
     pub access_flags: Vec<AccessFlag>,
+    code_off: u32,
     pub code_item: Option<CodeItem>,
 }
 
@@ -78,6 +94,9 @@ pub struct DexClasses {
 
 impl DexClasses {
     /// Parse the DEX file to extract the classes and their content
+    ///
+    /// If the reader is in recovery mode, classes and methods which cannot be parsed are skipped
+    /// and reported as diagnostics instead of failing the whole parse.
     pub fn build(dex_reader: &mut DexReader,
                  offset: u32,
                  size: u32,
@@ -85,201 +104,373 @@ impl DexClasses {
                  types_list: &DexTypes,
                  strings_list: &DexStrings,
                  methods_list: &DexMethods) -> Result<Self, DexError> {
-        dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
+        let context = EncodedValueContext {
+            strings: strings_list,
+            types: types_list,
+            fields: fields_list,
+            methods: methods_list,
+        };
 
-        let mut methods = Vec::new();
+        DexClasses::build_with_context(dex_reader, offset, size, &context, true)
+    }
 
-        for _ in 0..size {
-            let class_idx = dex_reader.read_u32()?;
-            let access_flags = dex_reader.read_u32()?;
-            let access_flags_decoded = AccessFlag::parse(access_flags,
-                                                         AccessFlagType::Class);
-
-            let superclass_idx   = dex_reader.read_u32()?;
-            let interfaces_off   = dex_reader.read_u32()?;
-            let source_file_idx  = dex_reader.read_u32()?;
-            let annotations_off  = dex_reader.read_u32()?;
-            let class_data_off   = dex_reader.read_u32()?;
-            let static_value_off = dex_reader.read_u32()?;
-
-            // Convert indexs into human-readable strings
-            let class_str = types_list.items
-                                      .get(class_idx as usize)
-                                      .ok_or(DexError::InvalidTypeIdx)?;
-
-            let mut superclass_str = None;
-            if superclass_idx != NO_INDEX {
-                superclass_str = Some(types_list.items
-                                                .get(superclass_idx as usize)
-                                                .ok_or(DexError::InvalidTypeIdx)?);
+    /// Parse the class definitions, decoding their class data and code items only if
+    /// `decode_class_data` is set
+    pub(crate) fn build_with_context(dex_reader: &mut DexReader,
+                                     offset: u32,
+                                     size: u32,
+                                     context: &EncodedValueContext,
+                                     decode_class_data: bool) -> Result<Self, DexError> {
+        dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
+        let mut classes = Vec::with_capacity(dex_reader.check_count(size, CLASS_DEF_ITEM_SIZE)?);
+
+        for idx in 0..size {
+            let class_offset = u64::from(offset) + u64::from(idx) * CLASS_DEF_ITEM_SIZE;
+            dex_reader.bytes.seek(SeekFrom::Start(class_offset))?;
+            let first_diagnostic = dex_reader.diagnostics.len();
+
+            let class = DexClasses::build_class(dex_reader, context, decode_class_data)
+                                   .in_frame(ErrorFrame::item("class_defs", idx as usize, class_offset));
+            match class {
+                Ok(class) => {
+                    attach_context(&mut dex_reader.diagnostics[first_diagnostic..], Some(&class.class_str), None);
+                    classes.push(class);
+                },
+                Err(err) if dex_reader.recover && !matches!(err.root(), DexError::ResourceLimitExceeded) => {
+                    // Best effort to name the class in the report
+                    dex_reader.bytes.seek(SeekFrom::Start(class_offset))?;
+                    let class_str = dex_reader.read_u32()
+                                              .ok()
+                                              .and_then(|class_idx| context.types.items.get(class_idx as usize));
+
+                    dex_reader.diagnostics.push(Diagnostic::new(Severity::Error,
+                                                                DiagnosticCode::SkippedClass,
+                                                                class_offset,
+                                                                Some(MapItemType::ClassDefItem),
+                                                                format!("skipping class: {err}")));
+                    attach_context(&mut dex_reader.diagnostics[first_diagnostic..], class_str.map(String::as_str), None);
+                },
+                Err(err) => return Err(err),
             }
+        }
 
-            let mut source_file_str = None;
-            if source_file_idx != NO_INDEX {
-                source_file_str = Some(strings_list.strings
-                                                   .get(source_file_idx as usize)
-                                                   .ok_or(DexError::InvalidStringIdx)?);
-            }
+        Ok(DexClasses { items: classes })
+    }
 
-            // If class_data_off == 0 then we have no class data
-            let mut class_data = None;
-            if class_data_off != 0 {
-                // Start parse class data
+    /// Parse the class definition at the current position of the reader
+    fn build_class(dex_reader: &mut DexReader,
+                   context: &EncodedValueContext,
+                   decode_class_data: bool) -> Result<ClassDefItem, DexError> {
+        let class_idx = dex_reader.read_u32()?;
+        let flags_offset = dex_reader.bytes.position();
+        let access_flags = dex_reader.read_u32()?;
+        let access_flags_decoded = decode_access_flags(dex_reader,
+                                                       access_flags,
+                                                       AccessFlagType::Class,
+                                                       flags_offset,
+                                                       MapItemType::ClassDefItem);
+
+        let superclass_idx   = dex_reader.read_u32()?;
+        let interfaces_off   = dex_reader.read_u32()?;
+        let source_file_idx  = dex_reader.read_u32()?;
+        let annotations_off  = dex_reader.read_u32()?;
+        let class_data_off   = dex_reader.read_u32()?;
+        let static_value_off = dex_reader.read_u32()?;
+
+        // Convert indexs into human-readable strings
+        let class_str = context.types.items
+                                     .get(class_idx as usize)
+                                     .ok_or(DexError::InvalidTypeIdx)
+                                     .at_index(class_idx.into())?;
+
+        let mut superclass_str = None;
+        if superclass_idx != NO_INDEX {
+            superclass_str = Some(context.types.items
+                                               .get(superclass_idx as usize)
+                                               .ok_or(DexError::InvalidTypeIdx)
+                                               .at_index(superclass_idx.into())
+                                               .in_class(class_str)?);
+        }
 
-                // Keep track of current stream position
-                let current_offset = dex_reader.bytes.position();
+        let mut interfaces = Vec::new();
+        if interfaces_off != 0 {
+            interfaces = read_type_list(dex_reader, interfaces_off, context.types)
+                             .in_frame(ErrorFrame::at("interfaces", interfaces_off.into()))
+                             .in_class(class_str)?;
+        }
 
-                // Go to class data offset
-                dex_reader.bytes.seek(SeekFrom::Start(class_data_off.into()))?;
+        let mut source_file_str = None;
+        if source_file_idx != NO_INDEX {
+            source_file_str = Some(context.strings.strings
+                                                  .get(source_file_idx as usize)
+                                                  .ok_or(DexError::InvalidStringIdx)
+                                                  .at_index(source_file_idx.into())
+                                                  .in_class(class_str)?);
+        }
 
-                let (static_fields_size, _)   = dex_reader.read_uleb128()?;
-                let (instance_fields_size, _) = dex_reader.read_uleb128()?;
-                let (direct_methods_size, _)  = dex_reader.read_uleb128()?;
-                let (virtual_methods_size, _) = dex_reader.read_uleb128()?;
+        // Initial values of the static fields, in the same order as the fields. Fields
+        // without a value in the array are initialized to zero or null. Malformed values are
+        // ignored, unless the resource limits are exceeded.
+        let mut static_values = Vec::new();
+        if static_value_off != 0 {
+            dex_reader.bytes.seek(SeekFrom::Start(static_value_off.into()))?;
+            match EncodedValue::read_array(dex_reader, context) {
+                Ok(values) => static_values = values,
+                Err(err) if err.is(&DexError::ResourceLimitExceeded) => {
+                    return Err(err).in_frame(ErrorFrame::at("static_values", static_value_off.into()))
+                                   .in_class(class_str);
+                },
+                Err(err) => dex_reader.diagnostics.push(Diagnostic::new(Severity::Warning,
+                                                                        DiagnosticCode::InvalidStaticValues,
+                                                                        static_value_off.into(),
+                                                                        Some(MapItemType::EncodedArrayItem),
+                                                                        format!("ignoring static values: {err}"))),
+            }
+        }
 
-                let mut static_fields   = Vec::<EncodedField>::with_capacity(static_fields_size as usize);
-                let mut instance_fields = Vec::<EncodedField>::with_capacity(instance_fields_size as usize);
-                let mut direct_methods  = Vec::<EncodedMethod>::with_capacity(direct_methods_size as usize);
-                let mut virtual_methods = Vec::<EncodedMethod>::with_capacity(virtual_methods_size as usize);
+        // If class_data_off == 0 then we have no class data
+        let mut class_data = None;
+        if class_data_off != 0 && decode_class_data {
+            class_data = Some(read_class_data(dex_reader, class_data_off, context, true)
+                                  .in_frame(ErrorFrame::at("class_data", class_data_off.into()))
+                                  .in_class(class_str)?);
+        }
 
-                // Encoded fields
-                let mut field_idx = 0;
-                for _ in 0..static_fields_size {
-                    let (idx, _) = dex_reader.read_uleb128()?;
-                    let (access_flags, _) = dex_reader.read_uleb128()?;
+        Ok(ClassDefItem {
+            class_idx,
+            class_str: class_str.to_string(),
+            raw_access_flags: access_flags,
+            access_flags: access_flags_decoded,
+            superclass_idx,
+            superclass_str: superclass_str.cloned(),
+            interfaces_off,
+            interfaces,
+            source_file_idx,
+            source_file_str: source_file_str.cloned(),
+            annotations_off,
+            class_data_off,
+            static_value_off,
+            static_values,
+            class_data
+        })
+    }
 
-                    field_idx += idx;
+    /// Get a class definition from the class name, if it exists
+    pub fn get_class_def(&self, class_name: &String) -> Option<&ClassDefItem> {
+        self.items.iter().find(|&item| &item.class_str == class_name)
+    }
 
-                    let decoded_field = fields_list.items.get(field_idx as usize)
-                                                         .ok_or(DexError::InvalidFieldIdx)?;
-                    let decoded_flags = AccessFlag::parse(access_flags,
-                                                          AccessFlagType::Field);
+    /// Decode the class data of a class definition parsed without it
+    ///
+    /// The code items of the methods are not decoded: their offset is kept in the methods.
+    pub(crate) fn build_class_data(dex_reader: &mut DexReader,
+                                   class: &ClassDefItem,
+                                   context: &EncodedValueContext) -> Result<Option<ClassDataItem>, DexError> {
+        if class.class_data_off == 0 {
+            return Ok(None);
+        }
 
-                    static_fields.push(EncodedField {
-                        field: decoded_field.to_string(),
-                        access_flags: decoded_flags
-                    });
-                }
+        let first_diagnostic = dex_reader.diagnostics.len();
+        let class_data = read_class_data(dex_reader, class.class_data_off, context, false)
+                             .in_frame(ErrorFrame::at("class_data", class.class_data_off.into()))
+                             .in_class(&class.class_str);
+        attach_context(&mut dex_reader.diagnostics[first_diagnostic..], Some(&class.class_str), None);
 
-                let mut field_idx = 0;
-                for _ in 0..instance_fields_size {
-                    let (idx, _) = dex_reader.read_uleb128()?;
-                    let (access_flags, _) = dex_reader.read_uleb128()?;
+        class_data.map(Some)
+    }
+}
 
-                    field_idx += idx;
+/// Read the list of types at the given offset
+fn read_type_list(dex_reader: &mut DexReader,
+                  offset: u32,
+                  types_list: &DexTypes) -> Result<Vec<String>, DexError> {
+    dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
+
+    let size = dex_reader.read_u32()?;
+    let mut types = Vec::with_capacity(dex_reader.check_count(size, 2)?);
+    for _ in 0..size {
+        let type_idx = dex_reader.read_u16()?;
+        let decoded_type = types_list.items.get(type_idx as usize)
+                                           .ok_or(DexError::InvalidTypeIdx)
+                                           .at_index(type_idx.into())?;
+        dex_reader.charge(decoded_type.len())?;
+        types.push(decoded_type.to_string());
+    }
 
-                    let decoded_field = fields_list.items.get(field_idx as usize)
-                                                         .ok_or(DexError::InvalidFieldIdx)?;
-                    let decoded_flags = AccessFlag::parse(access_flags,
-                                                          AccessFlagType::Field);
+    Ok(types)
+}
 
-                    instance_fields.push(EncodedField {
-                        field: decoded_field.to_string(),
-                        access_flags: decoded_flags
-                    });
-                }
+/// Read the class data item at the given offset, and the code of its methods if `with_code` is set
+fn read_class_data(dex_reader: &mut DexReader,
+                   offset: u32,
+                   context: &EncodedValueContext,
+                   with_code: bool) -> Result<ClassDataItem, DexError> {
+    dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
+
+    let (static_fields_size, _)   = dex_reader.read_uleb128()?;
+    let (instance_fields_size, _) = dex_reader.read_uleb128()?;
+    let (direct_methods_size, _)  = dex_reader.read_uleb128()?;
+    let (virtual_methods_size, _) = dex_reader.read_uleb128()?;
+
+    let static_fields   = read_encoded_fields(dex_reader, "static_fields", static_fields_size, context)?;
+    let instance_fields = read_encoded_fields(dex_reader, "instance_fields", instance_fields_size, context)?;
+    let direct_methods  = read_encoded_methods(dex_reader, "direct_methods", direct_methods_size, context, with_code)?;
+    let virtual_methods = read_encoded_methods(dex_reader, "virtual_methods", virtual_methods_size, context, with_code)?;
+
+    Ok(ClassDataItem {
+        static_fields,
+        instance_fields,
+        direct_methods,
+        virtual_methods,
+    })
+}
 
-                // Encoded methods
-                let mut method_idx = 0;
-                for _ in 0..direct_methods_size {
-                    let (idx, _) = dex_reader.read_uleb128()?;
-                    let (access_flags, _) = dex_reader.read_uleb128()?;
-                    let (code_offset, _) = dex_reader.read_uleb128()?;
-
-                    method_idx += idx;
-
-                    let proto = methods_list.items.get(method_idx as usize)
-                                                         .ok_or(DexError::InvalidMethodIdx)?;
-                    let decoded_flags = AccessFlag::parse(access_flags,
-                                                          AccessFlagType::Method);
-
-                    if code_offset == 0 {
-                        // Abstract or native methods have no code
-                        direct_methods.push(EncodedMethod {
-                            proto: proto.to_string(),
-                            access_flags: decoded_flags,
-                            code_item: None
-                        });
-                    } else {
-                        let current_offset = dex_reader.bytes.position();
-                        let code_item = CodeItem::build(dex_reader,
-                                                        code_offset,
-                                                        types_list)?;
-                        dex_reader.bytes.seek(SeekFrom::Start(current_offset))?;
-
-                        direct_methods.push(EncodedMethod {
-                            proto: proto.to_string(),
-                            access_flags: decoded_flags,
-                            code_item: Some(code_item),
-                        });
-                    }
-                }
+/// Read a list of encoded fields from class data
+fn read_encoded_fields(dex_reader: &mut DexReader,
+                       list: &'static str,
+                       count: u32,
+                       context: &EncodedValueContext) -> Result<Vec<EncodedField>, DexError> {
+    // Each field takes at least two bytes
+    let mut fields = Vec::with_capacity(dex_reader.check_count(count, 2).in_frame(ErrorFrame::at(list, dex_reader.bytes.position()))?);
+
+    let mut field_idx: u32 = 0;
+    for position in 0..count as usize {
+        let field_offset = dex_reader.bytes.position();
+        let frame = || ErrorFrame::item(list, position, field_offset);
+        let (idx, _) = dex_reader.read_uleb128().in_frame(frame())?;
+        let flags_offset = dex_reader.bytes.position();
+        let (access_flags, _) = dex_reader.read_uleb128().in_frame(frame())?;
+
+        field_idx = field_idx.checked_add(idx).ok_or(DexError::InvalidFieldIdx).in_frame(frame())?;
+
+        let decoded_field = context.fields.items.get(field_idx as usize)
+                                                .ok_or(DexError::InvalidFieldIdx)
+                                                .at_index(field_idx.into())
+                                                .in_frame(frame())?;
+        dex_reader.charge(decoded_field.len())?;
+        let decoded_flags = decode_access_flags(dex_reader,
+                                                access_flags,
+                                                AccessFlagType::Field,
+                                                flags_offset,
+                                                MapItemType::ClassDataItem);
+
+        fields.push(EncodedField {
+            field_idx,
+            field: decoded_field.to_string(),
+            raw_access_flags: access_flags,
+            access_flags: decoded_flags
+        });
+    }
 
-                let mut method_idx = 0;
-                for _ in 0..virtual_methods_size {
-                    let (idx, _) = dex_reader.read_uleb128()?;
-                    let (access_flags, _) = dex_reader.read_uleb128()?;
-                    let (code_offset, _) = dex_reader.read_uleb128()?;
-
-                    method_idx += idx;
-
-                    let proto = methods_list.items.get(method_idx as usize)
-                                                  .ok_or(DexError::InvalidMethodIdx)?;
-                    let decoded_flags = AccessFlag::parse(access_flags,
-                                                          AccessFlagType::Method);
-
-                    if code_offset == 0 {
-                        // Abstract or native methods have no code
-                        virtual_methods.push(EncodedMethod {
-                            proto: proto.to_string(),
-                            access_flags: decoded_flags,
-                            code_item: None
-                        });
-                    } else {
-                        let current_offset = dex_reader.bytes.position();
-                        let code_item = CodeItem::build(dex_reader,
-                                                        code_offset,
-                                                        types_list)?;
-                        dex_reader.bytes.seek(SeekFrom::Start(current_offset))?;
-
-                        virtual_methods.push(EncodedMethod {
-                            proto: proto.to_string(),
-                            access_flags: decoded_flags,
-                            code_item: Some(code_item),
-                        });
-                    }
-                }
+    Ok(fields)
+}
 
-                // Go back to the previous offset
-                dex_reader.bytes.seek(SeekFrom::Start(current_offset))?;
+/// Read a list of encoded methods, and their code if `with_code` is set, from class data
+///
+/// In recovery mode, methods whose prototype or code cannot be parsed are skipped.
+fn read_encoded_methods(dex_reader: &mut DexReader,
+                        list: &'static str,
+                        count: u32,
+                        context: &EncodedValueContext,
+                        with_code: bool) -> Result<Vec<EncodedMethod>, DexError> {
+    // Each method takes at least three bytes
+    let mut methods = Vec::with_capacity(dex_reader.check_count(count, 3).in_frame(ErrorFrame::at(list, dex_reader.bytes.position()))?);
+
+    let mut method_idx: u32 = 0;
+    for position in 0..count as usize {
+        let first_diagnostic = dex_reader.diagnostics.len();
+        let method_offset = dex_reader.bytes.position();
+        let frame = || ErrorFrame::item(list, position, method_offset);
+        let (idx, _) = dex_reader.read_uleb128().in_frame(frame())?;
+        let flags_offset = dex_reader.bytes.position();
+        let (access_flags, _) = dex_reader.read_uleb128().in_frame(frame())?;
+        let (code_offset, _) = dex_reader.read_uleb128().in_frame(frame())?;
+        let next_method = dex_reader.bytes.position();
+
+        method_idx = method_idx.checked_add(idx).ok_or(DexError::InvalidMethodIdx).in_frame(frame())?;
+
+        let proto = context.methods.items.get(method_idx as usize);
+        let decoded_flags = decode_access_flags(dex_reader,
+                                                access_flags,
+                                                AccessFlagType::Method,
+                                                flags_offset,
+                                                MapItemType::ClassDataItem);
+
+        let method = proto.ok_or(DexError::InvalidMethodIdx).at_index(method_idx.into()).and_then(|proto| {
+            dex_reader.charge(proto.len())?;
+            // Abstract or native methods have no code
+            let code_item = match code_offset {
+                0 => None,
+                _ if !with_code => None,
+                _ => Some(CodeItem::build(dex_reader, code_offset, context.types)
+                              .in_frame(ErrorFrame::at("code_item", code_offset.into()))
+                              .in_method(proto)?),
+            };
+            Ok((proto, code_item))
+        }).in_frame(frame());
+        dex_reader.bytes.seek(SeekFrom::Start(next_method))?;
+
+        match method {
+            Ok((proto, code_item)) => {
+                if !METHOD_REGEX.is_match(proto) {
+                    dex_reader.diagnostics.push(Diagnostic::new(Severity::Warning,
+                                                                DiagnosticCode::InvalidMethodName,
+                                                                method_offset,
+                                                                Some(MapItemType::ClassDataItem),
+                                                                format!("cannot retrieve method name from prototype {proto}")));
+                }
 
-                class_data = Some(ClassDataItem {
-                    static_fields,
-                    instance_fields,
-                    direct_methods,
-                    virtual_methods,
+                methods.push(EncodedMethod {
+                    method_idx,
+                    proto: proto.to_string(),
+                    raw_access_flags: access_flags,
+                    access_flags: decoded_flags,
+                    code_off: code_offset,
+                    code_item,
                 });
-            }
-
-            methods.push(ClassDefItem {
-                class_str: class_str.to_string(),
-                access_flags: access_flags_decoded,
-                superclass_str: superclass_str.cloned(),
-                interfaces_off,
-                source_file_str: source_file_str.cloned(),
-                annotations_off,
-                class_data_off,
-                static_value_off,
-                class_data
-            });
+            },
+            Err(err) if dex_reader.recover && !matches!(err.root(), DexError::ResourceLimitExceeded) => {
+                dex_reader.diagnostics.push(Diagnostic::new(Severity::Error,
+                                                            DiagnosticCode::SkippedMethod,
+                                                            method_offset,
+                                                            Some(MapItemType::ClassDataItem),
+                                                            format!("skipping method: {err}")));
+            },
+            Err(err) => return Err(err),
         }
+        attach_context(&mut dex_reader.diagnostics[first_diagnostic..], None, proto.map(String::as_str));
+    }
+
+    Ok(methods)
+}
 
-        Ok(DexClasses { items: methods })
+/// Decode access flags, recording a diagnostic if some of them are not valid for the type
+fn decode_access_flags(dex_reader: &mut DexReader,
+                       raw: u32,
+                       for_type: AccessFlagType,
+                       offset: u64,
+                       section: MapItemType) -> Vec<AccessFlag> {
+    let (flags, invalid) = AccessFlag::parse_with_invalid(raw, for_type);
+    if invalid != 0 {
+        dex_reader.diagnostics.push(Diagnostic::new(Severity::Warning,
+                                                    DiagnosticCode::InvalidAccessFlags,
+                                                    offset,
+                                                    Some(section),
+                                                    format!("ignoring invalid flags for {for_type}: 0x{invalid:x}")));
     }
 
-    /// Get a class definition from the class name, if it exists
-    pub fn get_class_def(&self, class_name: &String) -> Option<&ClassDefItem> {
-        self.items.iter().find(|&item| &item.class_str == class_name)
+    flags
+}
+
+/// Attach the class or method being parsed to the diagnostics which do not have one yet
+fn attach_context(diagnostics: &mut [Diagnostic], class: Option<&str>, method: Option<&str>) {
+    for diagnostic in diagnostics.iter_mut() {
+        if diagnostic.class.is_none() {
+            diagnostic.class = class.map(str::to_string);
+        }
+        if diagnostic.method.is_none() {
+            diagnostic.method = method.map(str::to_string);
+        }
     }
 }
 
@@ -289,11 +480,81 @@ impl ClassDefItem {
         &self.class_str
     }
 
+    /// Get the index of the class in the types list
+    pub fn get_class_idx(&self) -> u32 {
+        self.class_idx
+    }
+
     /// Get the access flags of a class definition
     pub fn get_access_flags(&self) -> String {
         AccessFlag::vec_to_string(&self.access_flags)
     }
 
+    /// Get the access flags of a class definition as found in the file, including invalid ones
+    pub fn get_raw_access_flags(&self) -> u32 {
+        self.raw_access_flags
+    }
+
+    /// Get the index of the superclass in the types list (`NO_INDEX` if there is none)
+    pub fn get_superclass_idx(&self) -> u32 {
+        self.superclass_idx
+    }
+
+    /// Get the name of the superclass, if any
+    pub fn get_superclass_name(&self) -> Option<&String> {
+        self.superclass_str.as_ref()
+    }
+
+    /// Get the offset of the list of interfaces (0 if there is none)
+    pub fn get_interfaces_off(&self) -> u32 {
+        self.interfaces_off
+    }
+
+    /// Get the interfaces implemented by the class
+    pub fn get_interfaces(&self) -> &[String] {
+        &self.interfaces
+    }
+
+    /// Get the index of the source file name in the strings list (`NO_INDEX` if there is none)
+    pub fn get_source_file_idx(&self) -> u32 {
+        self.source_file_idx
+    }
+
+    /// Get the name of the source file, if known
+    pub fn get_source_file(&self) -> Option<&String> {
+        self.source_file_str.as_ref()
+    }
+
+    /// Get the offset of the annotations directory (0 if there is none)
+    pub fn get_annotations_off(&self) -> u32 {
+        self.annotations_off
+    }
+
+    /// Get the offset of the class data (0 if there is none)
+    pub fn get_class_data_off(&self) -> u32 {
+        self.class_data_off
+    }
+
+    /// Get the static fields of a class definition
+    pub fn get_static_fields(&self) -> &[EncodedField] {
+        self.class_data.as_ref().map_or(&[], |class_data| &class_data.static_fields)
+    }
+
+    /// Get the instance fields of a class definition
+    pub fn get_instance_fields(&self) -> &[EncodedField] {
+        self.class_data.as_ref().map_or(&[], |class_data| &class_data.instance_fields)
+    }
+
+    /// Get the direct (static, private, or constructor) methods of a class definition
+    pub fn get_direct_methods(&self) -> &[EncodedMethod] {
+        self.class_data.as_ref().map_or(&[], |class_data| &class_data.direct_methods)
+    }
+
+    /// Get the virtual methods of a class definition
+    pub fn get_virtual_methods(&self) -> &[EncodedMethod] {
+        self.class_data.as_ref().map_or(&[], |class_data| &class_data.virtual_methods)
+    }
+
     /// Get the methods of a class definition
     pub fn get_methods(&self) -> Vec<&EncodedMethod> {
         let mut methods = Vec::new();
@@ -306,6 +567,20 @@ impl ClassDefItem {
         methods
     }
 
+    /// Get the static fields of a class definition along with their initial value
+    ///
+    /// Only the fields with an explicit initial value are returned.
+    pub fn get_static_values(&self) -> Vec<(&String, &EncodedValue)> {
+        match &self.class_data {
+            Some(class_data) => class_data.static_fields
+                                          .iter()
+                                          .map(|field| &field.field)
+                                          .zip(self.static_values.iter())
+                                          .collect(),
+            None => Vec::new(),
+        }
+    }
+
     /// Get a method from a class definition using the method name
     pub fn get_encoded_method(&self, method_name: &String) -> Option<&EncodedMethod> {
         if let Some(class_data) = &self.class_data {
@@ -324,7 +599,61 @@ impl ClassDefItem {
     }
 }
 
+impl ClassDataItem {
+    /// Get the static fields of the class
+    pub fn get_static_fields(&self) -> &[EncodedField] {
+        &self.static_fields
+    }
+
+    /// Get the instance fields of the class
+    pub fn get_instance_fields(&self) -> &[EncodedField] {
+        &self.instance_fields
+    }
+
+    /// Get the direct (static, private, or constructor) methods of the class
+    pub fn get_direct_methods(&self) -> &[EncodedMethod] {
+        &self.direct_methods
+    }
+
+    /// Get the virtual methods of the class
+    pub fn get_virtual_methods(&self) -> &[EncodedMethod] {
+        &self.virtual_methods
+    }
+
+    /// Get the methods of the class
+    pub fn get_methods(&self) -> Vec<&EncodedMethod> {
+        self.direct_methods.iter().chain(self.virtual_methods.iter()).collect()
+    }
+}
+
+impl EncodedField {
+    /// Get the index of the field in the fields list
+    pub fn get_field_idx(&self) -> u32 {
+        self.field_idx
+    }
+
+    /// Get the field, as `class->name:type`
+    pub fn get_field(&self) -> &str {
+        &self.field
+    }
+
+    /// Get the access flags of a field
+    pub fn get_access_flags(&self) -> String {
+        AccessFlag::vec_to_string(&self.access_flags)
+    }
+
+    /// Get the access flags of a field as found in the file, including invalid ones
+    pub fn get_raw_access_flags(&self) -> u32 {
+        self.raw_access_flags
+    }
+}
+
 impl EncodedMethod {
+    /// Get the index of the method in the methods list
+    pub fn get_method_idx(&self) -> u32 {
+        self.method_idx
+    }
+
     /// Get the prototype of a method
     pub fn get_proto(&self) -> &str {
         &self.proto
@@ -355,4 +684,109 @@ impl EncodedMethod {
     pub fn get_access_flags(&self) -> String {
         AccessFlag::vec_to_string(&self.access_flags)
     }
+
+    /// Get the access flags of a method as found in the file, including invalid ones
+    pub fn get_raw_access_flags(&self) -> u32 {
+        self.raw_access_flags
+    }
+
+    /// Get the offset of the code item of a method (0 for abstract and native methods)
+    pub fn get_code_off(&self) -> u32 {
+        self.code_off
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes::read_u32_at;
+    use crate::dex::file::DexFile;
+    use crate::fixtures::{ fake_dex_with_methods, FakeMethod };
+    use crate::dex::integrity::fix_integrity;
+    use crate::dex::options::ParseOptions;
+
+    #[test]
+    fn test_build_recover() {
+        let methods = [
+            FakeMethod { class: "La;", name: "good", access_flags: 0x9, code: Some((0, 0, 0, vec![0x000e])) },
+            FakeMethod { class: "La;", name: "bad", access_flags: 0x9, code: Some((1, 0, 0, vec![0x0012, 0x003e, 0x000e])) },
+            FakeMethod { class: "Lb;", name: "run", access_flags: 0x9, code: None },
+        ];
+        let mut raw = fake_dex_with_methods(&["La;", "Lb;", "Lc;"], &[], &methods);
+
+        // Class data of the last class out of the file
+        let class_defs_off = read_u32_at(&raw, 0x64).unwrap() as usize;
+        let class_data_off = class_defs_off + 2 * 32 + 24;
+        raw[class_data_off..class_data_off + 4].copy_from_slice(&0x10000u32.to_le_bytes());
+        fix_integrity(&mut raw).unwrap();
+
+        assert!(DexFile::build(DexReader::build(raw.clone()).unwrap()).is_err());
+
+        let options = ParseOptions { recover: true, ..Default::default() };
+        let dex = DexFile::build_with_options(DexReader::build(raw).unwrap(), &options).unwrap();
+        assert_eq!(dex.get_classes_names(), vec!["La;", "Lb;"]);
+
+        let methods = dex.get_methods_for_class(&"La;".to_string());
+        assert_eq!(methods.len(), 2);
+        let bad = methods.iter().find(|method| method.get_method_name() == "bad").unwrap();
+        let code_item = bad.code_item.as_ref().unwrap();
+        assert!(code_item.insns.is_none());
+        let undecoded = code_item.undecoded.as_ref().unwrap();
+        assert_eq!(undecoded.address, 1);
+        assert_eq!(undecoded.code_units, vec![0x0012, 0x003e, 0x000e]);
+        let good = methods.iter().find(|method| method.get_method_name() == "good").unwrap();
+        assert_eq!(good.code_item.as_ref().unwrap().insns.as_ref().unwrap().len(), 1);
+
+        let codes = dex.diagnostics.iter().map(|diagnostic| diagnostic.code).collect::<Vec<DiagnosticCode>>();
+        assert_eq!(codes, vec![DiagnosticCode::UnusedOpcode, DiagnosticCode::UndecodedCode, DiagnosticCode::SkippedClass]);
+        assert_eq!(dex.diagnostics[1].method.as_deref(), Some("La;->bad()V"));
+        assert_eq!(dex.diagnostics[1].class.as_deref(), Some("La;"));
+        assert_eq!(dex.diagnostics[2].class.as_deref(), Some("Lc;"));
+    }
+
+    #[test]
+    fn test_build_invalid_static_values() {
+        let mut raw = fake_dex_with_methods(&["La;", "Lb;"], &[], &[]);
+
+        // One value, of an unknown type
+        let static_values_off = raw.len() as u32;
+        raw.extend_from_slice(&[0x01, 0x01]);
+        let class_defs_off = read_u32_at(&raw, 0x64).unwrap() as usize;
+        raw[class_defs_off + 28..class_defs_off + 32].copy_from_slice(&static_values_off.to_le_bytes());
+        fix_integrity(&mut raw).unwrap();
+
+        let dex = DexFile::build(DexReader::build(raw).unwrap()).unwrap();
+        assert_eq!(dex.get_classes_names(), vec!["La;", "Lb;"]);
+        assert_eq!(dex.diagnostics.len(), 1);
+        assert_eq!(dex.diagnostics[0].code, DiagnosticCode::InvalidStaticValues);
+        assert_eq!(dex.diagnostics[0].offset, u64::from(static_values_off));
+        assert_eq!(dex.diagnostics[0].class.as_deref(), Some("La;"));
+    }
+
+    #[test]
+    fn test_build_error_location() {
+        let methods = [
+            FakeMethod { class: "La;", name: "good", access_flags: 0x9, code: Some((0, 0, 0, vec![0x000e])) },
+            FakeMethod { class: "La;", name: "bad", access_flags: 0x9, code: Some((1, 0, 0, vec![0x0013, 0x0000, 0x003e, 0x000e])) },
+        ];
+        let raw = fake_dex_with_methods(&["La;"], &[], &methods);
+
+        let err = DexFile::build(DexReader::build(raw).unwrap()).unwrap_err();
+        assert!(matches!(err.root(), DexError::InvalidOpCode));
+
+        let location = err.location().unwrap();
+        let frames = location.frames.iter().map(|frame| frame.name).collect::<Vec<&str>>();
+        assert_eq!(frames, vec!["class_defs", "class_data", "direct_methods", "code_item", "insns"]);
+        assert_eq!(location.frames[0].index, Some(0));
+        // Methods are sorted by name, and instructions are indexed by position, not address
+        assert_eq!(location.frames[2].index, Some(0));
+        assert_eq!(location.frames[4].index, Some(1));
+        assert_eq!(location.method.as_deref(), Some("La;->bad()V"));
+        assert_eq!(location.class.as_deref(), Some("La;"));
+
+        let code_off = location.frames[3].offset.unwrap();
+        assert_eq!(location.offset(), Some(code_off + 16 + 4));
+        assert!(err.to_string().ends_with(&format!("code_item@0x{code_off:x} -> insns[1]@0x{:x} in La;->bad()V: cannot parse instruction opcode",
+                                                   code_off + 20)));
+    }
 }
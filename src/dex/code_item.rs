@@ -7,14 +7,20 @@
 
 use std::io::{Seek, SeekFrom};
 
-use crate::error::DexError;
+use crate::error::{ DexError, ErrorContext, ErrorFrame };
 use crate::dex::{
     reader::DexReader,
     types::DexTypes,
+    debug_info::DebugInfo,
     instructions,
-    instructions::Instructions
+    instructions::Instructions,
+    diagnostics::{ Diagnostic, DiagnosticCode, Severity },
+    map::MapItemType
 };
 
+/// Size of the fields of a code item preceding the bytecode
+pub const CODE_ITEM_HEADER_SIZE: usize = 16;
+
 /// A `try` statement with offset to the `catch` part
 #[derive(Clone, Debug)]
 pub struct TryItem {
@@ -26,6 +32,7 @@ pub struct TryItem {
 /// A `catch` statement
 #[derive(Clone, Debug)]
 pub struct EncodedCatchHandler {
+    offset        : u16,
     size          : i32,
     handlers      : Vec<EncodedTypeAddrPair>,
     catch_all_addr: Option<u32>,
@@ -38,22 +45,40 @@ pub struct EncodedTypeAddrPair {
     addr        : u32,
 }
 
+/// Bytecode which could not be decoded, kept as raw code units
+#[derive(Clone, Debug)]
+pub struct UndecodedCode {
+    /// Description of the decoding error
+    pub error: String,
+    /// Address of the instruction which could not be decoded, in code units
+    pub address: u32,
+    /// Raw code units of the method
+    pub code_units: Vec<u16>,
+}
+
 /// Code structure for a method
 #[derive(Debug)]
 pub struct CodeItem {
+    offset        : u32,
     registers_size: u16,
     ins_size      : u16,
     outs_size     : u16,
     debug_info_off: u32,
+    insns_size    : u32,
     pub insns         : Option<Vec<Instructions>>,
     tries         : Option<Vec<TryItem>>,
-    handlers      : Option<Vec<EncodedCatchHandler>>
+    handlers      : Option<Vec<EncodedCatchHandler>>,
+    /// Bytecode which could not be decoded (in recovery mode only), in which case `insns` is
+    /// `None`
+    pub undecoded : Option<UndecodedCode>,
+    debug_info    : Option<DebugInfo>,
 }
 
 impl CodeItem {
     /// Build a `CodeItem` struct from the reader
     ///
-    /// The `offset` argument corresponds to the offset of the code item in the cursor
+    /// The `offset` argument corresponds to the offset of the code item in the cursor. If the
+    /// reader is in recovery mode, bytecode which cannot be decoded is kept as raw code units.
     pub fn build(dex_reader: &mut DexReader,
                  offset: u32,
                  types_list: &DexTypes) -> Result<Self, DexError> {
@@ -69,15 +94,48 @@ impl CodeItem {
         let debug_info_off = dex_reader.read_u32()?;
         let insns_size     = dex_reader.read_u32()?;
 
+        if insns_size > dex_reader.limits.max_code_units {
+            return Err(DexError::ResourceLimitExceeded);
+        }
+        dex_reader.check_count(insns_size, 2)?;
+        dex_reader.charge(insns_size as usize * 2)?;
+
         // Get the actual bytecode
         let mut insns = Vec::with_capacity(insns_size as usize);
-        let end_offset = dex_reader.bytes.position() + (insns_size * 2) as u64;
+        let mut undecoded = None;
+        let start_offset = dex_reader.bytes.position();
+        let end_offset = start_offset + u64::from(insns_size) * 2;
 
         // No need to update the stream's position manually: it is updated in
         // `parse_instruction` when reading bytes from it
         while dex_reader.bytes.position() < end_offset {
-            let _ = instructions::parse_instruction(dex_reader, &mut insns)?;
+            let insn_offset = dex_reader.bytes.position();
+            let address = ((insn_offset - start_offset) / 2) as usize;
+            let parsed = instructions::parse_instruction(dex_reader, &mut insns)
+                                      .in_frame(ErrorFrame::item("insns", insns.len(), insn_offset));
+            if let Err(err) = parsed {
+                if !dex_reader.recover {
+                    return Err(err);
+                }
+
+                dex_reader.bytes.seek(SeekFrom::Start(start_offset))?;
+                let code_units = (0..insns_size).map(|_| dex_reader.read_u16())
+                                                .collect::<Result<Vec<u16>, DexError>>()?;
+
+                dex_reader.diagnostics.push(Diagnostic::new(Severity::Error,
+                                                            DiagnosticCode::UndecodedCode,
+                                                            insn_offset,
+                                                            Some(MapItemType::CodeItem),
+                                                            format!("keeping raw code units: {err}")));
+                undecoded = Some(UndecodedCode {
+                    error: err.to_string(),
+                    address: address as u32,
+                    code_units,
+                });
+                break;
+            }
         }
+        let insns = if undecoded.is_none() { Some(insns) } else { None };
 
         // Check if there is some padding
         if tries_size != 0 && insns_size % 2 == 1 {
@@ -88,7 +146,7 @@ impl CodeItem {
         let mut handlers = Vec::<EncodedCatchHandler>::new();
 
         if tries_size != 0 {
-            tries = Vec::with_capacity(tries_size as usize);
+            tries = Vec::with_capacity(dex_reader.check_count(tries_size.into(), 8)?);
             for _ in 0..tries_size {
                 let start_addr = dex_reader.read_u32()?;
                 let insn_count = dex_reader.read_u16()?;
@@ -101,17 +159,22 @@ impl CodeItem {
                 });
             }
 
+            let handlers_offset = dex_reader.bytes.position();
             let (handlers_list_size, _) = dex_reader.read_uleb128()?;
-            handlers = Vec::with_capacity(handlers_list_size as usize);
+            handlers = Vec::with_capacity(dex_reader.check_count(handlers_list_size, 1)?);
 
             for _ in 0..handlers_list_size {
+                let handler_offset = (dex_reader.bytes.position() - handlers_offset) as u16;
                 let (handler_size, _) = dex_reader.read_sleb128()?;
-                let mut type_add_pairs = Vec::with_capacity(handler_size.unsigned_abs() as usize);
+                // Each handler takes at least two bytes
+                let mut type_add_pairs = Vec::with_capacity(dex_reader.check_count(handler_size.unsigned_abs(), 2)?);
 
-                for _ in 0..handler_size.abs() {
+                for _ in 0..handler_size.unsigned_abs() {
                     let (type_idx, _) = dex_reader.read_uleb128()?;
                     let decoded_type = types_list.items.get(type_idx as usize)
-                                                       .ok_or(DexError::InvalidTypeIdx)?;
+                                                       .ok_or(DexError::InvalidTypeIdx)
+                                                       .at_index(type_idx.into())
+                                                       .in_frame(ErrorFrame::at("handlers", handlers_offset))?;
                     let (addr, _) = dex_reader.read_uleb128()?;
 
                     type_add_pairs.push(EncodedTypeAddrPair {
@@ -124,12 +187,14 @@ impl CodeItem {
                 if handler_size <= 0 {
                     let (catch_all_addr, _) = dex_reader.read_uleb128()?;
                     handlers.push(EncodedCatchHandler {
+                        offset: handler_offset,
                         size: handler_size,
                         handlers: type_add_pairs,
                         catch_all_addr: Some(catch_all_addr)
                     });
                 } else {
                     handlers.push(EncodedCatchHandler {
+                        offset: handler_offset,
                         size: handler_size,
                         handlers: type_add_pairs,
                         catch_all_addr: None
@@ -138,32 +203,131 @@ impl CodeItem {
             }
         }
 
-        if tries_size != 0 {
-            Ok(
-                CodeItem {
-                    registers_size,
-                    ins_size,
-                    outs_size,
-                    debug_info_off,
-                    // insns: parsed_ins,
-                    insns: Some(insns),
-                    tries: Some(tries),
-                    handlers: Some(handlers)
-                }
-            )
-        } else {
-            Ok(
-                CodeItem {
-                    registers_size,
-                    ins_size,
-                    outs_size,
-                    debug_info_off,
-                    // insns: parsed_ins,
-                    insns: Some(insns),
-                    tries: None,
-                    handlers: None
-                }
-            )
+        // Debug information is not needed to run the code, so malformed debug information is
+        // reported and ignored rather than failing the whole method
+        let mut debug_info = None;
+        if debug_info_off != 0 {
+            match DebugInfo::build(dex_reader, debug_info_off) {
+                Ok(decoded) => debug_info = Some(decoded),
+                Err(err) if err.is(&DexError::ResourceLimitExceeded) => return Err(err),
+                Err(err) => dex_reader.diagnostics.push(Diagnostic::new(Severity::Warning,
+                                                                        DiagnosticCode::InvalidDebugInfo,
+                                                                        debug_info_off.into(),
+                                                                        Some(MapItemType::DebugInfoItem),
+                                                                        format!("ignoring debug information: {err}"))),
+            }
         }
+
+        let (tries, handlers) = match tries_size {
+            0 => (None, None),
+            _ => (Some(tries), Some(handlers)),
+        };
+
+        Ok(
+            CodeItem {
+                offset,
+                registers_size,
+                ins_size,
+                outs_size,
+                debug_info_off,
+                insns_size,
+                insns,
+                tries,
+                handlers,
+                undecoded,
+                debug_info
+            }
+        )
+    }
+
+    /// Offset of the code item in the DEX file
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Number of registers used by the method
+    pub fn registers_size(&self) -> u16 {
+        self.registers_size
+    }
+
+    /// Number of words of incoming arguments
+    pub fn ins_size(&self) -> u16 {
+        self.ins_size
+    }
+
+    /// Number of words of outgoing argument space required for method invocation
+    pub fn outs_size(&self) -> u16 {
+        self.outs_size
+    }
+
+    /// Try blocks of the method, if any
+    pub fn tries(&self) -> &[TryItem] {
+        self.tries.as_deref().unwrap_or_default()
+    }
+
+    /// Catch handlers of the method, if any
+    pub fn handlers(&self) -> &[EncodedCatchHandler] {
+        self.handlers.as_deref().unwrap_or_default()
+    }
+
+    /// Size of the bytecode, in code units
+    pub fn insns_size(&self) -> u32 {
+        self.insns_size
+    }
+
+    /// Offset of the debug information in the DEX file, or 0 if there is none
+    pub fn debug_info_off(&self) -> u32 {
+        self.debug_info_off
+    }
+
+    /// Debug information of the method, if there is some and it could be decoded
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug_info.as_ref()
+    }
+}
+
+impl TryItem {
+    /// Address of the first code unit covered by the block
+    pub fn start_addr(&self) -> u32 {
+        self.start_addr
+    }
+
+    /// Number of code units covered by the block
+    pub fn insn_count(&self) -> u16 {
+        self.insn_count
+    }
+
+    /// Offset of the handler from the start of the handlers list
+    pub fn handler_off(&self) -> u16 {
+        self.handler_off
+    }
+}
+
+impl EncodedCatchHandler {
+    /// Offset of the handler from the start of the handlers list
+    pub fn offset(&self) -> u16 {
+        self.offset
+    }
+
+    /// Typed handlers, in the order they are tested
+    pub fn handlers(&self) -> &[EncodedTypeAddrPair] {
+        &self.handlers
+    }
+
+    /// Address of the catch-all handler, if any
+    pub fn catch_all_addr(&self) -> Option<u32> {
+        self.catch_all_addr
+    }
+}
+
+impl EncodedTypeAddrPair {
+    /// Type of the exception caught by the handler
+    pub fn decoded_type(&self) -> &str {
+        &self.decoded_type
+    }
+
+    /// Address of the handler
+    pub fn addr(&self) -> u32 {
+        self.addr
     }
 }
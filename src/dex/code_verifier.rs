@@ -0,0 +1,1445 @@
+//! Bytecode verification
+//!
+//! The structural verifier (see `verifier`) checks the layout of a DEX file. This module checks the
+//! bytecode of its methods, following the rules of the method verifier of ART:
+//!
+//!   * branch and switch targets land on instruction boundaries
+//!   * registers are within the frame of the method, and wide values use consistent pairs
+//!   * payloads are aligned and only referenced by the matching instruction
+//!   * try blocks and catch handlers cover valid ranges of instructions
+//!   * registers hold values of the type expected by each instruction (int, float, long, double,
+//!     reference), and new instances are not used before their constructor is called
+//!
+//! Register types are inferred with a dataflow analysis over the control flow graph of the method,
+//! merging types where paths join. Classes are not resolved, so references are only checked to be
+//! references. Indices are resolved against the lists of the `DexFile`, which must therefore come
+//! from a single DEX file. Methods using ODEX-only instructions only go through the structural
+//! checks.
+//!
+//! Every violation is reported as a `Diagnostic` with the `Error` severity.
+
+use std::fmt;
+
+use crate::dex::access_flags::AccessFlag;
+use crate::dex::classes::EncodedMethod;
+use crate::dex::code_item::{ CodeItem, CODE_ITEM_HEADER_SIZE };
+use crate::dex::diagnostics::{ Diagnostic, DiagnosticCode, Severity };
+use crate::dex::file::DexFile;
+use crate::dex::instructions::Instructions;
+use crate::dex::map::MapItemType;
+use crate::dex::opcodes::OpCode;
+
+/// Root of the class hierarchy
+const OBJECT: &str = "Ljava/lang/Object;";
+/// Type of the values returned by `move-exception`
+const THROWABLE: &str = "Ljava/lang/Throwable;";
+
+/// Verify the bytecode of every method of the file
+pub fn verify_code(dex: &DexFile) -> Vec<Diagnostic> {
+    dex.classes.items.iter()
+                     .flat_map(|class| class.get_methods())
+                     .flat_map(|method| verify_method(dex, method))
+                     .collect()
+}
+
+/// Verify the bytecode of a method and return the list of violations
+///
+/// Methods without code, or with bytecode which could not be decoded, are not verified.
+pub fn verify_method(dex: &DexFile, method: &EncodedMethod) -> Vec<Diagnostic> {
+    let Some(code) = &method.code_item else {
+        return Vec::new();
+    };
+    let Some(insns) = &code.insns else {
+        return Vec::new();
+    };
+    let Some(signature) = Signature::parse(&method.proto) else {
+        return Vec::new();
+    };
+
+    let mut addresses = Vec::with_capacity(insns.len());
+    let mut insns_size = 0;
+    for inst in insns {
+        addresses.push(insns_size);
+        insns_size += inst.length();
+    }
+    let mut index_at = vec![None; insns_size + 1];
+    for (idx, addr) in addresses.iter().enumerate() {
+        index_at[*addr] = Some(idx);
+    }
+
+    let mut verifier = MethodVerifier {
+        dex,
+        code,
+        insns,
+        addresses,
+        index_at,
+        insns_size,
+        is_handler: vec![false; insns.len()],
+        is_static: method.access_flags.contains(&AccessFlag::ACC_STATIC),
+        proto: &method.proto,
+        signature,
+        reporting: true,
+        violations: Vec::new(),
+    };
+
+    verifier.check_branches();
+    verifier.check_tries();
+    if !insns.is_empty() && !insns.iter().any(is_odex) {
+        verifier.check_types();
+    }
+
+    verifier.violations
+}
+
+/// Type of the value held by a register
+#[derive(Debug, Clone, PartialEq)]
+enum RegType {
+    /// Not written on every path, or written with incompatible types
+    Conflict,
+    /// Zero constant: int, float, or null reference
+    Zero,
+    /// Non-zero 32-bit constant: int or float
+    Const,
+    Integer,
+    Float,
+    LongLo,
+    LongHi,
+    DoubleLo,
+    DoubleHi,
+    /// Low half of a 64-bit constant: long or double
+    WideConstLo,
+    /// High half of a 64-bit constant: long or double
+    WideConstHi,
+    /// Initialized reference of the given type
+    Reference(String),
+    /// `this` in a constructor, before the superclass constructor is called
+    UninitThis(String),
+    /// Instance created at the given address, before its constructor is called
+    Uninit(String, usize),
+    /// Value which cannot be typed statically (e.g. the result of `invoke-custom`)
+    Unknown,
+}
+
+impl RegType {
+    /// Type of the values of a type descriptor
+    fn from_descriptor(descriptor: &str) -> Self {
+        match descriptor.chars().next() {
+            Some('Z' | 'B' | 'S' | 'C' | 'I') => RegType::Integer,
+            Some('F') => RegType::Float,
+            Some('J') => RegType::LongLo,
+            Some('D') => RegType::DoubleLo,
+            Some('L' | '[') => RegType::Reference(descriptor.to_string()),
+            _ => RegType::Unknown,
+        }
+    }
+
+    /// Whether the value is the low half of a wide value
+    fn is_wide_lo(&self) -> bool {
+        matches!(self, RegType::LongLo | RegType::DoubleLo | RegType::WideConstLo)
+    }
+
+    /// Whether the value is the high half of a wide value
+    fn is_wide_hi(&self) -> bool {
+        matches!(self, RegType::LongHi | RegType::DoubleHi | RegType::WideConstHi)
+    }
+
+    /// Whether the value is an instance which is not initialized yet
+    fn is_uninit(&self) -> bool {
+        matches!(self, RegType::UninitThis(_) | RegType::Uninit(..))
+    }
+
+    /// High half of a wide value
+    fn high(&self) -> Self {
+        match self {
+            RegType::LongLo => RegType::LongHi,
+            RegType::DoubleLo => RegType::DoubleHi,
+            RegType::WideConstLo => RegType::WideConstHi,
+            _ => RegType::Unknown,
+        }
+    }
+
+    /// Whether the value can be used as a value of the given kind
+    fn matches(&self, kind: Kind) -> bool {
+        use RegType::*;
+        match kind {
+            Kind::Int => matches!(self, Zero | Const | Integer | Unknown),
+            Kind::Float => matches!(self, Zero | Const | Float | Unknown),
+            Kind::Cat1 => matches!(self, Zero | Const | Integer | Float | Unknown),
+            Kind::Long => matches!(self, LongLo | WideConstLo | Unknown),
+            Kind::Double => matches!(self, DoubleLo | WideConstLo | Unknown),
+            Kind::Wide => matches!(self, LongLo | DoubleLo | WideConstLo | Unknown),
+            Kind::Ref => matches!(self, Zero | Reference(_) | Unknown),
+            Kind::Object => matches!(self, Zero | Reference(_) | UninitThis(_) | Uninit(..) | Unknown),
+        }
+    }
+
+    /// Whether `high` is a valid high half for this low half
+    fn pairs_with(&self, high: &RegType) -> bool {
+        use RegType::*;
+        matches!((self, high), (LongLo, LongHi | WideConstHi)
+                             | (DoubleLo, DoubleHi | WideConstHi)
+                             | (WideConstLo, LongHi | DoubleHi | WideConstHi)
+                             | (Unknown, LongHi | DoubleHi | WideConstHi)
+                             | (_, Unknown))
+    }
+
+    /// Merge the types of a register coming from two paths
+    fn merge(&self, other: &RegType) -> RegType {
+        use RegType::*;
+        if self == other {
+            return self.clone();
+        }
+        match (self, other) {
+            (Zero, Const) | (Const, Zero) => Const,
+            (Zero | Const, Integer) | (Integer, Zero | Const) => Integer,
+            (Zero | Const, Float) | (Float, Zero | Const) => Float,
+            (Zero, Reference(_)) | (WideConstLo, LongLo | DoubleLo) | (WideConstHi, LongHi | DoubleHi) => other.clone(),
+            (Reference(_), Zero) | (LongLo | DoubleLo, WideConstLo) | (LongHi | DoubleHi, WideConstHi) => self.clone(),
+            (Reference(a), Reference(b)) => {
+                // Arrays of the same dimension are still arrays, of a common element type
+                let dimension = a.bytes().take_while(|&byte| byte == b'[').count();
+                if dimension > 0 && dimension == b.bytes().take_while(|&byte| byte == b'[').count() {
+                    Reference(format!("{}{OBJECT}", "[".repeat(dimension)))
+                } else {
+                    Reference(OBJECT.to_string())
+                }
+            },
+            (Conflict, _) | (_, Conflict) => Conflict,
+            (Unknown, _) | (_, Unknown) => Unknown,
+            _ => Conflict,
+        }
+    }
+}
+
+impl fmt::Display for RegType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegType::Conflict => write!(f, "an undefined value"),
+            RegType::Zero => write!(f, "zero"),
+            RegType::Const => write!(f, "a constant"),
+            RegType::Integer => write!(f, "an int"),
+            RegType::Float => write!(f, "a float"),
+            RegType::LongLo => write!(f, "the low half of a long"),
+            RegType::LongHi => write!(f, "the high half of a long"),
+            RegType::DoubleLo => write!(f, "the low half of a double"),
+            RegType::DoubleHi => write!(f, "the high half of a double"),
+            RegType::WideConstLo => write!(f, "the low half of a wide constant"),
+            RegType::WideConstHi => write!(f, "the high half of a wide constant"),
+            RegType::Reference(class) => write!(f, "a reference to {class}"),
+            RegType::UninitThis(class) => write!(f, "an uninitialized this ({class})"),
+            RegType::Uninit(class, addr) => write!(f, "an uninitialized {class} created at 0x{addr:04x}"),
+            RegType::Unknown => write!(f, "an unknown value"),
+        }
+    }
+}
+
+/// Kind of value expected by an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Int,
+    Float,
+    /// Int or float
+    Cat1,
+    Long,
+    Double,
+    /// Long or double
+    Wide,
+    /// Initialized reference
+    Ref,
+    /// Reference, initialized or not
+    Object,
+}
+
+impl Kind {
+    /// Kind of the values of a type descriptor
+    fn from_descriptor(descriptor: &str) -> Option<Self> {
+        match descriptor.chars().next()? {
+            'Z' | 'B' | 'S' | 'C' | 'I' => Some(Kind::Int),
+            'F' => Some(Kind::Float),
+            'J' => Some(Kind::Long),
+            'D' => Some(Kind::Double),
+            'L' | '[' => Some(Kind::Ref),
+            _ => None,
+        }
+    }
+
+    /// Whether the values use a pair of registers
+    fn is_wide(self) -> bool {
+        matches!(self, Kind::Long | Kind::Double | Kind::Wide)
+    }
+
+    /// Whether an instruction expecting this kind accepts values of the given kind
+    fn covers(self, other: Kind) -> bool {
+        self == other || matches!((self, other), (Kind::Cat1, Kind::Int | Kind::Float)
+                                               | (Kind::Wide, Kind::Long | Kind::Double)
+                                               | (Kind::Object, Kind::Ref))
+    }
+
+    /// Type of a value of this kind produced by an instruction
+    fn default_type(self) -> RegType {
+        match self {
+            Kind::Int => RegType::Integer,
+            Kind::Float => RegType::Float,
+            Kind::Long => RegType::LongLo,
+            Kind::Double => RegType::DoubleLo,
+            Kind::Ref | Kind::Object => RegType::Reference(OBJECT.to_string()),
+            Kind::Cat1 | Kind::Wide => RegType::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Int => write!(f, "an int"),
+            Kind::Float => write!(f, "a float"),
+            Kind::Cat1 => write!(f, "a 32-bit value"),
+            Kind::Long => write!(f, "a long"),
+            Kind::Double => write!(f, "a double"),
+            Kind::Wide => write!(f, "a 64-bit value"),
+            Kind::Ref => write!(f, "a reference"),
+            Kind::Object => write!(f, "an object"),
+        }
+    }
+}
+
+/// Decoded prototype of a method (e.g. `La;->name(I Ljava/lang/String;)V`)
+#[derive(Debug)]
+struct Signature {
+    class: String,
+    name: String,
+    params: Vec<String>,
+    return_type: String,
+}
+
+impl Signature {
+    fn parse(proto: &str) -> Option<Self> {
+        let (class, rest) = proto.split_once("->")?;
+        let (name, rest) = rest.split_once('(')?;
+        let (params, return_type) = rest.rsplit_once(')')?;
+
+        Some(Signature {
+            class: class.to_string(),
+            name: name.to_string(),
+            params: params.split(' ').filter(|param| !param.is_empty()).map(str::to_string).collect(),
+            return_type: return_type.to_string(),
+        })
+    }
+
+    /// Number of registers used by the parameters
+    fn params_size(&self) -> usize {
+        self.params.iter()
+                   .map(|param| if Kind::from_descriptor(param).is_some_and(Kind::is_wide) { 2 } else { 1 })
+                   .sum()
+    }
+}
+
+/// Types of the registers before an instruction
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    regs: Vec<RegType>,
+    /// Type of the result of the previous instruction, for `move-result*`
+    result: Option<RegType>,
+}
+
+impl State {
+    /// Merge the state coming from another path and return whether the state changed
+    fn merge(&mut self, other: &State) -> bool {
+        let mut changed = false;
+        for (reg, other_reg) in self.regs.iter_mut().zip(other.regs.iter()) {
+            let merged = reg.merge(other_reg);
+            if merged != *reg {
+                *reg = merged;
+                changed = true;
+            }
+        }
+        if self.result.is_some() && self.result != other.result {
+            self.result = None;
+            changed = true;
+        }
+        changed
+    }
+}
+
+/// Operands of an instruction, decoded from its format
+#[derive(Debug, Default)]
+struct Operands {
+    a: u32,
+    b: u32,
+    c: u32,
+    /// Argument registers of invoke and `filled-new-array` instructions
+    args: Vec<u32>,
+    /// Index of the referenced item
+    index: u32,
+    /// Literal value
+    literal: i64,
+    /// Branch or payload offset, in code units
+    offset: i32,
+}
+
+impl Operands {
+    fn decode(inst: &Instructions) -> Self {
+        let units = inst.bytes();
+        let unit = |idx: usize| u32::from(units.get(idx).copied().unwrap_or(0));
+        let wide = |idx: usize| unit(idx) | (unit(idx + 1) << 16);
+        let nibbles = || Operands { a: (unit(0) >> 8) & 0xf, b: unit(0) >> 12, ..Default::default() };
+        let list = || {
+            let count = (unit(0) >> 12) as usize;
+            let regs = [unit(2) & 0xf, (unit(2) >> 4) & 0xf, (unit(2) >> 8) & 0xf, unit(2) >> 12, (unit(0) >> 8) & 0xf];
+            Operands { args: regs.into_iter().take(count).collect(), index: unit(1), ..Default::default() }
+        };
+        let range = || Operands { args: (unit(2)..unit(2) + (unit(0) >> 8)).collect(), index: unit(1), ..Default::default() };
+
+        match inst {
+            Instructions::Instruction10t(_) => Operands { offset: (unit(0) >> 8) as u8 as i8 as i32, ..Default::default() },
+            Instructions::Instruction11n(_) => Operands { literal: ((unit(0) as i16) >> 12) as i64, ..nibbles() },
+            Instructions::Instruction11x(_) => Operands { a: unit(0) >> 8, ..Default::default() },
+            Instructions::Instruction12x(_) | Instructions::Instruction22cs(_) => nibbles(),
+            Instructions::Instruction20t(_) => Operands { offset: unit(1) as i16 as i32, ..Default::default() },
+            Instructions::Instruction21c(_) => Operands { a: unit(0) >> 8, index: unit(1), ..Default::default() },
+            Instructions::Instruction21h(_) | Instructions::Instruction21s(_)
+                => Operands { a: unit(0) >> 8, literal: unit(1) as i16 as i64, ..Default::default() },
+            Instructions::Instruction21t(_) => Operands { a: unit(0) >> 8, offset: unit(1) as i16 as i32, ..Default::default() },
+            Instructions::Instruction22b(_)
+                => Operands { a: unit(0) >> 8, b: unit(1) & 0xff, literal: (unit(1) >> 8) as u8 as i8 as i64, ..Default::default() },
+            Instructions::Instruction22c(_) => Operands { index: unit(1), ..nibbles() },
+            Instructions::Instruction22s(_) => Operands { literal: unit(1) as i16 as i64, ..nibbles() },
+            Instructions::Instruction22t(_) => Operands { offset: unit(1) as i16 as i32, ..nibbles() },
+            Instructions::Instruction22x(_) => Operands { a: unit(0) >> 8, b: unit(1), ..Default::default() },
+            Instructions::Instruction23x(_) => Operands { a: unit(0) >> 8, b: unit(1) & 0xff, c: unit(1) >> 8, ..Default::default() },
+            Instructions::Instruction30t(_) => Operands { offset: wide(1) as i32, ..Default::default() },
+            Instructions::Instruction31c(_) => Operands { a: unit(0) >> 8, index: wide(1), ..Default::default() },
+            Instructions::Instruction31i(_) => Operands { a: unit(0) >> 8, literal: wide(1) as i32 as i64, ..Default::default() },
+            Instructions::Instruction31t(_) => Operands { a: unit(0) >> 8, offset: wide(1) as i32, ..Default::default() },
+            Instructions::Instruction32x(_) => Operands { a: unit(1), b: unit(2), ..Default::default() },
+            Instructions::Instruction35c(_) | Instructions::Instruction45cc(_)
+            | Instructions::Instruction35mi(_) | Instructions::Instruction35ms(_) => list(),
+            Instructions::Instruction3rc(_) | Instructions::Instruction4rcc(_)
+            | Instructions::Instruction3rmi(_) | Instructions::Instruction3rms(_) => range(),
+            Instructions::Instruction51l(_)
+                => Operands { a: unit(0) >> 8, literal: (u64::from(wide(1)) | (u64::from(wide(3)) << 32)) as i64, ..Default::default() },
+            _ => Operands::default(),
+        }
+    }
+}
+
+/// State of the verification of a method
+struct MethodVerifier<'a> {
+    dex: &'a DexFile,
+    code: &'a CodeItem,
+    insns: &'a [Instructions],
+    /// Address of each instruction, in code units
+    addresses: Vec<usize>,
+    /// Index of the instruction starting at each address, if any
+    index_at: Vec<Option<usize>>,
+    /// Size of the bytecode, in code units
+    insns_size: usize,
+    /// Whether each instruction starts a catch handler
+    is_handler: Vec<bool>,
+    is_static: bool,
+    proto: &'a str,
+    signature: Signature,
+    /// Whether violations are reported (they are not while the register types are being inferred)
+    reporting: bool,
+    violations: Vec<Diagnostic>,
+}
+
+impl MethodVerifier<'_> {
+    fn report(&mut self, addr: usize, code: DiagnosticCode, message: String) {
+        if !self.reporting {
+            return;
+        }
+        let offset = u64::from(self.code.offset()) + CODE_ITEM_HEADER_SIZE as u64 + 2 * addr as u64;
+        let mut diagnostic = Diagnostic::new(Severity::Error,
+                                             code,
+                                             offset,
+                                             Some(MapItemType::CodeItem),
+                                             format!("0x{addr:04x}: {message}"));
+        diagnostic.class = Some(self.signature.class.clone());
+        diagnostic.method = Some(self.proto.to_string());
+        self.violations.push(diagnostic);
+    }
+
+    /// Index of the instruction at the target of a branch, if the target is an instruction
+    fn target(&self, addr: usize, offset: i32) -> Option<usize> {
+        let target = usize::try_from(addr as i64 + i64::from(offset)).ok()?;
+        *self.index_at.get(target)?
+    }
+
+    /// Check that a branch lands on an instruction
+    fn check_target(&mut self, addr: usize, offset: i32) {
+        match self.target(addr, offset) {
+            None => self.report(addr, DiagnosticCode::InvalidBranchTarget,
+                                format!("target {offset:+} is not the start of an instruction")),
+            Some(idx) if is_payload(&self.insns[idx]) => self.report(addr, DiagnosticCode::InvalidBranchTarget,
+                                                                     format!("target {offset:+} is a payload")),
+            Some(_) => {}
+        }
+    }
+
+    /// Check the branches and the references to payloads
+    fn check_branches(&mut self) {
+        for (idx, inst) in self.insns.iter().enumerate() {
+            let addr = self.addresses[idx];
+            let opcode = inst.opcode();
+            let ops = Operands::decode(inst);
+
+            match inst {
+                Instructions::Instruction10t(_) | Instructions::Instruction20t(_) | Instructions::Instruction30t(_)
+                | Instructions::Instruction21t(_) | Instructions::Instruction22t(_) => {
+                    if ops.offset == 0 && opcode != OpCode::GOTO_32 {
+                        self.report(addr, DiagnosticCode::InvalidBranchTarget,
+                                    format!("{opcode:?} branches to itself"));
+                    } else {
+                        self.check_target(addr, ops.offset);
+                    }
+                },
+                Instructions::Instruction31t(_) => {
+                    let payload = self.target(addr, ops.offset).map(|target| &self.insns[target]);
+                    let expected = match opcode {
+                        OpCode::PACKED_SWITCH => OpCode::PACKED_SWITCH_PAYLOAD,
+                        OpCode::SPARSE_SWITCH => OpCode::SPARSE_SWITCH_PAYLOAD,
+                        _ => OpCode::FILL_ARRAY_DATA_PAYLOAD,
+                    };
+
+                    match payload {
+                        Some(payload) if payload.opcode() == expected => {
+                            if (addr as i64 + i64::from(ops.offset)).rem_euclid(2) != 0 {
+                                self.report(addr, DiagnosticCode::InvalidPayload,
+                                            format!("{expected:?} at {:+} is not aligned", ops.offset));
+                            }
+                            for target in switch_targets(payload) {
+                                self.check_target(addr, target);
+                            }
+                        },
+                        _ => self.report(addr, DiagnosticCode::InvalidPayload,
+                                         format!("{opcode:?} does not reference a {expected:?} at {:+}", ops.offset)),
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    /// Check the ranges of the try blocks and the addresses of the handlers
+    fn check_tries(&mut self) {
+        let code = self.code;
+        let mut previous_end = 0;
+
+        for try_item in code.tries() {
+            let start = try_item.start_addr() as usize;
+            let end = start + try_item.insn_count() as usize;
+
+            if try_item.insn_count() == 0 || end > self.insns_size || start < previous_end {
+                self.report(start.min(self.insns_size), DiagnosticCode::InvalidCodeItem,
+                            format!("invalid try block range 0x{start:04x}-0x{end:04x}"));
+            } else if self.index_at[start].is_none() || (end < self.insns_size && self.index_at[end].is_none()) {
+                self.report(start, DiagnosticCode::InvalidBranchTarget,
+                            format!("try block 0x{start:04x}-0x{end:04x} does not cover whole instructions"));
+            }
+            previous_end = end;
+
+            if !code.handlers().iter().any(|handler| handler.offset() == try_item.handler_off()) {
+                self.report(start.min(self.insns_size), DiagnosticCode::InvalidCodeItem,
+                            format!("no catch handler at offset 0x{:x}", try_item.handler_off()));
+            }
+        }
+
+        for handler in code.handlers() {
+            let addrs = handler.handlers().iter().map(|pair| pair.addr()).chain(handler.catch_all_addr());
+            for addr in addrs {
+                match self.index_at.get(addr as usize).copied().flatten() {
+                    Some(idx) if !is_payload(&self.insns[idx]) => self.is_handler[idx] = true,
+                    _ => self.report((addr as usize).min(self.insns_size), DiagnosticCode::InvalidBranchTarget,
+                                     format!("catch handler at 0x{addr:04x} is not the start of an instruction")),
+                }
+            }
+        }
+    }
+
+    /// Indices of the handlers of the exceptions thrown at an address
+    fn handlers_at(&self, addr: usize) -> Vec<usize> {
+        let Some(try_item) = self.code.tries().iter().find(|try_item| {
+            let start = try_item.start_addr() as usize;
+            (start..start + try_item.insn_count() as usize).contains(&addr)
+        }) else {
+            return Vec::new();
+        };
+        let Some(handler) = self.code.handlers().iter().find(|handler| handler.offset() == try_item.handler_off()) else {
+            return Vec::new();
+        };
+
+        handler.handlers().iter()
+                          .map(|pair| pair.addr())
+                          .chain(handler.catch_all_addr())
+                          .filter_map(|addr| self.index_at.get(addr as usize).copied().flatten())
+                          .collect()
+    }
+
+    /// Types of the registers when entering the method
+    fn initial_state(&mut self) -> State {
+        let registers_size = self.code.registers_size() as usize;
+        let ins_size = self.code.ins_size() as usize;
+        let mut state = State { regs: vec![RegType::Conflict; registers_size], result: None };
+
+        let expected = self.signature.params_size() + usize::from(!self.is_static);
+        if expected != ins_size || ins_size > registers_size {
+            self.report(0, DiagnosticCode::InvalidRegister,
+                        format!("{ins_size} incoming registers out of {registers_size}, but the prototype takes {expected}"));
+            return State { regs: vec![RegType::Unknown; registers_size], result: None };
+        }
+
+        let mut reg = registers_size - ins_size;
+        if !self.is_static {
+            let class = self.signature.class.clone();
+            state.regs[reg] = if self.signature.name == "<init>" && class != OBJECT {
+                RegType::UninitThis(class)
+            } else {
+                RegType::Reference(class)
+            };
+            reg += 1;
+        }
+        for param in &self.signature.params {
+            let ty = RegType::from_descriptor(param);
+            if ty.is_wide_lo() {
+                state.regs[reg + 1] = ty.high();
+                state.regs[reg] = ty;
+                reg += 2;
+            } else {
+                state.regs[reg] = ty;
+                reg += 1;
+            }
+        }
+
+        state
+    }
+
+    /// Infer the types of the registers, then check every reachable instruction
+    fn check_types(&mut self) {
+        let mut entries: Vec<Option<State>> = vec![None; self.insns.len()];
+        let mut queued = vec![false; self.insns.len()];
+        let mut worklist = vec![0];
+        entries[0] = Some(self.initial_state());
+        queued[0] = true;
+
+        self.reporting = false;
+        while let Some(idx) = worklist.pop() {
+            queued[idx] = false;
+            let Some(state) = entries[idx].clone() else {
+                continue;
+            };
+
+            for (successor, successor_state) in self.step(idx, state) {
+                let changed = match &mut entries[successor] {
+                    Some(entry) => entry.merge(&successor_state),
+                    entry => {
+                        *entry = Some(successor_state);
+                        true
+                    },
+                };
+                if changed && !queued[successor] {
+                    queued[successor] = true;
+                    worklist.push(successor);
+                }
+            }
+        }
+
+        self.reporting = true;
+        for (idx, entry) in entries.into_iter().enumerate() {
+            if let Some(state) = entry {
+                self.step(idx, state);
+            }
+        }
+    }
+
+    /// Apply an instruction to the register types and return the states of its successors
+    fn step(&mut self, idx: usize, mut state: State) -> Vec<(usize, State)> {
+        let inst = &self.insns[idx];
+        let addr = self.addresses[idx];
+        let opcode = inst.opcode();
+
+        if is_payload(inst) {
+            self.report(addr, DiagnosticCode::InvalidControlFlow, format!("execution reaches {opcode:?}"));
+            return Vec::new();
+        }
+
+        let pending = state.result.take();
+        let before = state.clone();
+        let ops = Operands::decode(inst);
+        self.execute(idx, inst, &ops, &mut state, pending);
+
+        let mut successors = Vec::new();
+        if can_throw(opcode) {
+            for handler in self.handlers_at(addr) {
+                successors.push((handler, before.clone()));
+            }
+        }
+
+        let mut targets = Vec::new();
+        match inst {
+            Instructions::Instruction10t(_) | Instructions::Instruction20t(_) | Instructions::Instruction30t(_)
+            | Instructions::Instruction21t(_) | Instructions::Instruction22t(_) => targets.push(ops.offset),
+            Instructions::Instruction31t(_) if opcode != OpCode::FILL_ARRAY_DATA => {
+                if let Some(payload) = self.target(addr, ops.offset) {
+                    targets.extend(switch_targets(&self.insns[payload]));
+                }
+            },
+            _ => {}
+        }
+        for target in targets {
+            if let Some(target) = self.target(addr, target) {
+                successors.push((target, state.clone()));
+            }
+        }
+
+        let ends = matches!(opcode, OpCode::GOTO | OpCode::GOTO_16 | OpCode::GOTO_32 | OpCode::THROW
+                                  | OpCode::RETURN_VOID | OpCode::RETURN | OpCode::RETURN_WIDE
+                                  | OpCode::RETURN_OBJECT | OpCode::RETURN_VOID_BARRIER);
+        if !ends {
+            if idx + 1 < self.insns.len() {
+                successors.push((idx + 1, state));
+            } else {
+                self.report(addr, DiagnosticCode::InvalidControlFlow,
+                            "execution falls off the end of the method".to_string());
+            }
+        }
+
+        successors
+    }
+
+    /// Check that a register is within the frame of the method
+    fn check_reg(&mut self, addr: usize, state: &State, reg: u32, wide: bool) -> bool {
+        let last = reg as usize + usize::from(wide);
+        if last >= state.regs.len() {
+            self.report(addr, DiagnosticCode::InvalidRegister,
+                        format!("register v{last} is out of the frame of {} registers", state.regs.len()));
+            return false;
+        }
+        true
+    }
+
+    /// Read a register, checking its type, and return its type if it is valid
+    fn use_reg(&mut self, addr: usize, state: &State, reg: u32, kind: Kind) -> RegType {
+        if !self.check_reg(addr, state, reg, kind.is_wide()) {
+            return RegType::Unknown;
+        }
+
+        let ty = &state.regs[reg as usize];
+        if kind == Kind::Ref && ty.is_uninit() {
+            self.report(addr, DiagnosticCode::UninitializedRegister,
+                        format!("v{reg} holds {ty}, whose constructor was not called"));
+            return RegType::Unknown;
+        }
+        if !ty.matches(kind) {
+            self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                        format!("v{reg} holds {ty}, expected {kind}"));
+            return RegType::Unknown;
+        }
+        if kind.is_wide() && !ty.pairs_with(&state.regs[reg as usize + 1]) {
+            self.report(addr, DiagnosticCode::InvalidRegister,
+                        format!("v{reg} and v{} do not hold the halves of the same wide value", reg + 1));
+            return RegType::Unknown;
+        }
+        ty.clone()
+    }
+
+    /// Write a register, breaking the wide values it overlaps
+    fn set_reg(&mut self, addr: usize, state: &mut State, reg: u32, ty: RegType, wide: bool) {
+        if !self.check_reg(addr, state, reg, wide) {
+            return;
+        }
+
+        let reg = reg as usize;
+        let last = reg + usize::from(wide);
+        if reg > 0 && state.regs[reg - 1].is_wide_lo() {
+            state.regs[reg - 1] = RegType::Conflict;
+        }
+        if last + 1 < state.regs.len() && state.regs[last + 1].is_wide_hi() {
+            state.regs[last + 1] = RegType::Conflict;
+        }
+
+        if wide {
+            state.regs[reg + 1] = ty.high();
+        }
+        state.regs[reg] = ty;
+    }
+
+    /// Write the value produced by an instruction for the given kind of values
+    fn define(&mut self, addr: usize, state: &mut State, reg: u32, ty: RegType, kind: Kind) {
+        self.set_reg(addr, state, reg, ty, kind.is_wide());
+    }
+
+    /// Name of the type at the given index
+    fn type_at(&mut self, addr: usize, index: u32) -> Option<String> {
+        let descriptor = self.dex.types.items.get(index as usize).cloned();
+        if descriptor.is_none() {
+            self.report(addr, DiagnosticCode::InvalidIndex, format!("invalid type index {index}"));
+        }
+        descriptor
+    }
+
+    fn execute(&mut self, idx: usize, inst: &Instructions, ops: &Operands, state: &mut State, pending: Option<RegType>) {
+        use OpCode::*;
+
+        let addr = self.addresses[idx];
+        let opcode = inst.opcode();
+
+        if let Some((dst, src)) = unary_op(opcode) {
+            self.use_reg(addr, state, ops.b, src);
+            self.define(addr, state, ops.a, dst.default_type(), dst);
+            return;
+        }
+        if let Some((dst, first, second)) = binary_op(opcode) {
+            let (first_reg, second_reg) = match inst {
+                Instructions::Instruction12x(_) => (ops.a, ops.b),
+                _ => (ops.b, ops.c),
+            };
+            self.use_reg(addr, state, first_reg, first);
+            self.use_reg(addr, state, second_reg, second);
+            self.define(addr, state, ops.a, dst.default_type(), dst);
+            return;
+        }
+        if let Some((is_put, is_static, kind)) = field_op(opcode) {
+            self.field_access(addr, ops, state, is_put, is_static, kind);
+            return;
+        }
+
+        match opcode {
+            MOVE | MOVE_FROM16 | MOVE_16 => {
+                let ty = self.use_reg(addr, state, ops.b, Kind::Cat1);
+                self.define(addr, state, ops.a, ty, Kind::Cat1);
+            },
+            MOVE_WIDE | MOVE_WIDE_FROM16 | MOVE_WIDE_16 => {
+                let ty = self.use_reg(addr, state, ops.b, Kind::Wide);
+                self.define(addr, state, ops.a, ty, Kind::Wide);
+            },
+            MOVE_OBJECT | MOVE_OBJECT_FROM16 | MOVE_OBJECT_16 => {
+                let ty = self.use_reg(addr, state, ops.b, Kind::Object);
+                self.define(addr, state, ops.a, ty, Kind::Object);
+            },
+            MOVE_RESULT | MOVE_RESULT_WIDE | MOVE_RESULT_OBJECT => {
+                let kind = match opcode {
+                    MOVE_RESULT => Kind::Cat1,
+                    MOVE_RESULT_WIDE => Kind::Wide,
+                    _ => Kind::Ref,
+                };
+                let ty = match pending {
+                    None => {
+                        self.report(addr, DiagnosticCode::InvalidControlFlow,
+                                    format!("{opcode:?} does not follow an invoke or filled-new-array"));
+                        RegType::Unknown
+                    },
+                    Some(ty) if !ty.matches(kind) => {
+                        self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                                    format!("{opcode:?} moves {ty}, expected {kind}"));
+                        RegType::Unknown
+                    },
+                    Some(ty) => ty,
+                };
+                self.define(addr, state, ops.a, ty, kind);
+            },
+            MOVE_EXCEPTION => {
+                if !self.is_handler[idx] {
+                    self.report(addr, DiagnosticCode::InvalidControlFlow,
+                                "move-exception is not at the start of a catch handler".to_string());
+                }
+                self.define(addr, state, ops.a, RegType::Reference(THROWABLE.to_string()), Kind::Ref);
+            },
+            RETURN_VOID | RETURN_VOID_BARRIER => {
+                if self.signature.return_type != "V" {
+                    self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                                format!("return-void in a method returning {}", self.signature.return_type));
+                }
+                if self.signature.name == "<init>" && state.regs.iter().any(|reg| matches!(reg, RegType::UninitThis(_))) {
+                    self.report(addr, DiagnosticCode::UninitializedRegister,
+                                "constructor returns without calling the superclass constructor".to_string());
+                }
+            },
+            RETURN | RETURN_WIDE | RETURN_OBJECT => {
+                let kind = match opcode {
+                    RETURN => Kind::Cat1,
+                    RETURN_WIDE => Kind::Wide,
+                    _ => Kind::Ref,
+                };
+                match Kind::from_descriptor(&self.signature.return_type) {
+                    Some(expected) if kind.covers(expected) => {
+                        self.use_reg(addr, state, ops.a, expected);
+                    },
+                    _ => self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                                     format!("{opcode:?} in a method returning {}", self.signature.return_type)),
+                }
+            },
+            CONST_4 | CONST_16 | CONST | CONST_HIGH16 => {
+                let ty = if ops.literal == 0 { RegType::Zero } else { RegType::Const };
+                self.define(addr, state, ops.a, ty, Kind::Cat1);
+            },
+            CONST_WIDE_16 | CONST_WIDE_32 | CONST_WIDE | CONST_WIDE_HIGH16 => {
+                self.define(addr, state, ops.a, RegType::WideConstLo, Kind::Wide);
+            },
+            CONST_STRING | CONST_STRING_JUMBO => {
+                if ops.index as usize >= self.dex.strings.strings.len() {
+                    self.report(addr, DiagnosticCode::InvalidIndex, format!("invalid string index {}", ops.index));
+                }
+                self.define(addr, state, ops.a, RegType::from_descriptor("Ljava/lang/String;"), Kind::Ref);
+            },
+            CONST_CLASS => {
+                self.type_at(addr, ops.index);
+                self.define(addr, state, ops.a, RegType::from_descriptor("Ljava/lang/Class;"), Kind::Ref);
+            },
+            CONST_METHOD_HANDLE => {
+                self.define(addr, state, ops.a, RegType::from_descriptor("Ljava/lang/invoke/MethodHandle;"), Kind::Ref);
+            },
+            CONST_METHOD_TYPE => {
+                self.define(addr, state, ops.a, RegType::from_descriptor("Ljava/lang/invoke/MethodType;"), Kind::Ref);
+            },
+            MONITOR_ENTER | MONITOR_EXIT | THROW | FILL_ARRAY_DATA => {
+                self.use_reg(addr, state, ops.a, Kind::Ref);
+            },
+            CHECK_CAST => {
+                self.use_reg(addr, state, ops.a, Kind::Ref);
+                let ty = self.type_at(addr, ops.index).map_or(RegType::Unknown, RegType::Reference);
+                self.define(addr, state, ops.a, ty, Kind::Ref);
+            },
+            INSTANCE_OF => {
+                self.use_reg(addr, state, ops.b, Kind::Ref);
+                self.type_at(addr, ops.index);
+                self.define(addr, state, ops.a, RegType::Integer, Kind::Int);
+            },
+            ARRAY_LENGTH => {
+                let array = self.use_reg(addr, state, ops.b, Kind::Ref);
+                self.check_array(addr, ops.b, &array);
+                self.define(addr, state, ops.a, RegType::Integer, Kind::Int);
+            },
+            NEW_INSTANCE => {
+                let ty = match self.type_at(addr, ops.index) {
+                    Some(class) if class.starts_with('[') => {
+                        self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                                    format!("new-instance of the array type {class}"));
+                        RegType::Unknown
+                    },
+                    Some(class) => RegType::Uninit(class, addr),
+                    None => RegType::Unknown,
+                };
+                // A previous instance created by the same instruction is no longer usable
+                for reg in state.regs.iter_mut().filter(|reg| **reg == ty) {
+                    *reg = RegType::Conflict;
+                }
+                self.define(addr, state, ops.a, ty, Kind::Object);
+            },
+            NEW_ARRAY => {
+                self.use_reg(addr, state, ops.b, Kind::Int);
+                let ty = self.array_type(addr, ops.index);
+                self.define(addr, state, ops.a, ty, Kind::Ref);
+            },
+            FILLED_NEW_ARRAY | FILLED_NEW_ARRAY_RANGE => {
+                let ty = self.array_type(addr, ops.index);
+                let kind = match &ty {
+                    RegType::Reference(class) if class == "[I" => Some(Kind::Int),
+                    RegType::Reference(class) if class.starts_with("[L") || class.starts_with("[[") => Some(Kind::Ref),
+                    RegType::Reference(class) => {
+                        self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                                    format!("{opcode:?} cannot create arrays of type {class}"));
+                        None
+                    },
+                    _ => None,
+                };
+                if let Some(kind) = kind {
+                    for reg in &ops.args {
+                        self.use_reg(addr, state, *reg, kind);
+                    }
+                }
+                state.result = Some(ty);
+            },
+            PACKED_SWITCH | SPARSE_SWITCH | IF_LTZ | IF_GEZ | IF_GTZ | IF_LEZ => {
+                self.use_reg(addr, state, ops.a, Kind::Int);
+            },
+            IF_EQZ | IF_NEZ => {
+                let kind = comparison_kind(&state.regs, ops.a);
+                self.use_reg(addr, state, ops.a, kind);
+            },
+            IF_EQ | IF_NE => {
+                let kind = comparison_kind(&state.regs, ops.a);
+                self.use_reg(addr, state, ops.a, kind);
+                self.use_reg(addr, state, ops.b, kind);
+            },
+            IF_LT | IF_GE | IF_GT | IF_LE => {
+                self.use_reg(addr, state, ops.a, Kind::Int);
+                self.use_reg(addr, state, ops.b, Kind::Int);
+            },
+            AGET | AGET_WIDE | AGET_OBJECT | AGET_BOOLEAN | AGET_BYTE | AGET_CHAR | AGET_SHORT
+            | APUT | APUT_WIDE | APUT_OBJECT | APUT_BOOLEAN | APUT_BYTE | APUT_CHAR | APUT_SHORT => {
+                self.array_access(addr, opcode, ops, state);
+            },
+            INVOKE_VIRTUAL | INVOKE_SUPER | INVOKE_DIRECT | INVOKE_STATIC | INVOKE_INTERFACE
+            | INVOKE_VIRTUAL_RANGE | INVOKE_SUPER_RANGE | INVOKE_DIRECT_RANGE | INVOKE_STATIC_RANGE
+            | INVOKE_INTERFACE_RANGE | INVOKE_OBJECT_INIT_RANGE => {
+                self.invoke(addr, opcode, ops, state);
+            },
+            INVOKE_POLYMORPHIC | INVOKE_POLYMORPHIC_RANGE | INVOKE_CUSTOM | INVOKE_CUSTOM_RANGE => {
+                for reg in &ops.args {
+                    self.check_reg(addr, state, *reg, false);
+                }
+                if matches!(opcode, INVOKE_POLYMORPHIC | INVOKE_POLYMORPHIC_RANGE) && let Some(receiver) = ops.args.first() {
+                    self.use_reg(addr, state, *receiver, Kind::Ref);
+                }
+                state.result = Some(RegType::Unknown);
+            },
+            _ => {}
+        }
+    }
+
+    /// Report a register which does not hold an array
+    fn check_array(&mut self, addr: usize, reg: u32, ty: &RegType) {
+        if let RegType::Reference(class) = ty && !class.starts_with('[') {
+            self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                        format!("v{reg} holds {ty}, expected an array"));
+        }
+    }
+
+    /// Type of an array created from the type at the given index
+    fn array_type(&mut self, addr: usize, index: u32) -> RegType {
+        match self.type_at(addr, index) {
+            Some(class) if class.starts_with('[') => RegType::Reference(class),
+            Some(class) => {
+                self.report(addr, DiagnosticCode::RegisterTypeMismatch, format!("{class} is not an array type"));
+                RegType::Unknown
+            },
+            None => RegType::Unknown,
+        }
+    }
+
+    fn array_access(&mut self, addr: usize, opcode: OpCode, ops: &Operands, state: &mut State) {
+        use OpCode::*;
+
+        let is_put = matches!(opcode, APUT | APUT_WIDE | APUT_OBJECT | APUT_BOOLEAN | APUT_BYTE | APUT_CHAR | APUT_SHORT);
+        let kind = match opcode {
+            AGET | APUT => Kind::Cat1,
+            AGET_WIDE | APUT_WIDE => Kind::Wide,
+            AGET_OBJECT | APUT_OBJECT => Kind::Ref,
+            _ => Kind::Int,
+        };
+
+        let array = self.use_reg(addr, state, ops.b, Kind::Ref);
+        self.check_array(addr, ops.b, &array);
+        self.use_reg(addr, state, ops.c, Kind::Int);
+
+        // Type of the elements, when the array type is known
+        let element = match &array {
+            RegType::Reference(class) if class.starts_with('[') => Some(class[1..].to_string()),
+            _ => None,
+        };
+        let element = element.and_then(|element| match Kind::from_descriptor(&element) {
+            Some(element_kind) if kind.covers(element_kind) => Some((element, element_kind)),
+            _ => {
+                self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                            format!("{opcode:?} on an array of {element}"));
+                None
+            },
+        });
+
+        match (is_put, element) {
+            (true, Some((_, element_kind))) => {
+                self.use_reg(addr, state, ops.a, element_kind);
+            },
+            (true, None) => {
+                self.use_reg(addr, state, ops.a, kind);
+            },
+            (false, Some((element, element_kind))) => {
+                self.define(addr, state, ops.a, RegType::from_descriptor(&element), element_kind);
+            },
+            (false, None) => {
+                self.define(addr, state, ops.a, kind.default_type(), kind);
+            },
+        }
+    }
+
+    fn field_access(&mut self, addr: usize, ops: &Operands, state: &mut State, is_put: bool, is_static: bool, kind: Kind) {
+        let Some(field) = self.dex.fields.items.get(ops.index as usize) else {
+            self.report(addr, DiagnosticCode::InvalidIndex, format!("invalid field index {}", ops.index));
+            return;
+        };
+        let descriptor = field.rsplit_once(':').map_or("", |(_, descriptor)| descriptor).to_string();
+
+        let (field_kind, ty) = match Kind::from_descriptor(&descriptor) {
+            Some(field_kind) if kind.covers(field_kind) => (field_kind, RegType::from_descriptor(&descriptor)),
+            _ => {
+                self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                            format!("{kind} access to the field {field}"));
+                (kind, kind.default_type())
+            },
+        };
+
+        if !is_static {
+            // Fields of `this` can be accessed before the superclass constructor is called
+            let object = self.use_reg(addr, state, ops.b, Kind::Object);
+            if let RegType::Uninit(..) = object {
+                self.report(addr, DiagnosticCode::UninitializedRegister,
+                            format!("v{} holds {object}, whose constructor was not called", ops.b));
+            }
+        }
+
+        if is_put {
+            self.use_reg(addr, state, ops.a, field_kind);
+        } else {
+            self.define(addr, state, ops.a, ty, field_kind);
+        }
+    }
+
+    fn invoke(&mut self, addr: usize, opcode: OpCode, ops: &Operands, state: &mut State) {
+        let Some(signature) = self.dex.methods.items.get(ops.index as usize).and_then(|method| Signature::parse(method)) else {
+            self.report(addr, DiagnosticCode::InvalidIndex, format!("invalid method index {}", ops.index));
+            state.result = Some(RegType::Unknown);
+            return;
+        };
+        let return_type = (signature.return_type != "V").then(|| RegType::from_descriptor(&signature.return_type));
+
+        let is_static = matches!(opcode, OpCode::INVOKE_STATIC | OpCode::INVOKE_STATIC_RANGE);
+        let expected = signature.params_size() + usize::from(!is_static);
+        if ops.args.len() != expected {
+            self.report(addr, DiagnosticCode::InvalidRegister,
+                        format!("{}->{} takes {expected} registers, got {}", signature.class, signature.name, ops.args.len()));
+            state.result = return_type;
+            return;
+        }
+
+        let mut args = ops.args.iter().copied();
+        let mut initialized = None;
+        if !is_static && let Some(receiver) = args.next() {
+            if signature.name == "<init>" {
+                match self.use_reg(addr, state, receiver, Kind::Object) {
+                    ty @ RegType::UninitThis(_) => {
+                        initialized = Some((ty, RegType::Reference(self.signature.class.clone())));
+                    },
+                    ty @ RegType::Uninit(..) => {
+                        initialized = Some((ty, RegType::Reference(signature.class.clone())));
+                    },
+                    RegType::Unknown => {},
+                    ty => self.report(addr, DiagnosticCode::RegisterTypeMismatch,
+                                      format!("constructor called on v{receiver}, which holds {ty}")),
+                }
+            } else {
+                self.use_reg(addr, state, receiver, Kind::Ref);
+            }
+        }
+
+        for param in &signature.params {
+            let Some(kind) = Kind::from_descriptor(param) else {
+                continue;
+            };
+            let Some(reg) = args.next() else {
+                break;
+            };
+            if kind.is_wide() && args.next() != Some(reg + 1) {
+                self.report(addr, DiagnosticCode::InvalidRegister,
+                            format!("{param} argument in v{reg} is not passed in consecutive registers"));
+                continue;
+            }
+            self.use_reg(addr, state, reg, kind);
+        }
+
+        if let Some((uninit, ty)) = initialized {
+            for reg in state.regs.iter_mut().filter(|reg| **reg == uninit) {
+                *reg = ty.clone();
+            }
+        }
+        state.result = return_type;
+    }
+}
+
+/// Whether the instruction is a payload, which cannot be executed
+fn is_payload(inst: &Instructions) -> bool {
+    matches!(inst, Instructions::PackedSwitchPayload(_)
+                 | Instructions::SparseSwitchPayload(_)
+                 | Instructions::FillArrayDataPayload(_))
+}
+
+/// Whether the instruction only exists in optimized DEX files, whose semantics depend on the VM
+fn is_odex(inst: &Instructions) -> bool {
+    matches!(inst, Instructions::Instruction20bc(_)
+                 | Instructions::Instruction22cs(_)
+                 | Instructions::Instruction35mi(_)
+                 | Instructions::Instruction3rmi(_)
+                 | Instructions::Instruction35ms(_)
+                 | Instructions::Instruction3rms(_))
+        || inst.opcode() == OpCode::BREAKPOINT
+}
+
+/// Branch offsets of a switch payload, relative to the switch instruction
+fn switch_targets(payload: &Instructions) -> Vec<i32> {
+    match payload {
+        Instructions::PackedSwitchPayload(payload) => payload.get_targets().to_vec(),
+        Instructions::SparseSwitchPayload(payload) => payload.get_targets().to_vec(),
+        _ => Vec::new(),
+    }
+}
+
+/// Kind of the operands of a comparison, which can either be integers or references
+fn comparison_kind(regs: &[RegType], reg: u32) -> Kind {
+    match regs.get(reg as usize) {
+        Some(RegType::Reference(_) | RegType::UninitThis(_) | RegType::Uninit(..)) => Kind::Ref,
+        _ => Kind::Int,
+    }
+}
+
+/// Whether the instruction can throw an exception
+fn can_throw(opcode: OpCode) -> bool {
+    use OpCode::*;
+    field_op(opcode).is_some() || matches!(opcode,
+        CONST_STRING | CONST_STRING_JUMBO | CONST_CLASS | CONST_METHOD_HANDLE | CONST_METHOD_TYPE
+        | MONITOR_ENTER | MONITOR_EXIT | CHECK_CAST | INSTANCE_OF | ARRAY_LENGTH | NEW_INSTANCE
+        | NEW_ARRAY | FILLED_NEW_ARRAY | FILLED_NEW_ARRAY_RANGE | FILL_ARRAY_DATA | THROW
+        | AGET | AGET_WIDE | AGET_OBJECT | AGET_BOOLEAN | AGET_BYTE | AGET_CHAR | AGET_SHORT
+        | APUT | APUT_WIDE | APUT_OBJECT | APUT_BOOLEAN | APUT_BYTE | APUT_CHAR | APUT_SHORT
+        | INVOKE_VIRTUAL | INVOKE_SUPER | INVOKE_DIRECT | INVOKE_STATIC | INVOKE_INTERFACE
+        | INVOKE_VIRTUAL_RANGE | INVOKE_SUPER_RANGE | INVOKE_DIRECT_RANGE | INVOKE_STATIC_RANGE
+        | INVOKE_INTERFACE_RANGE | INVOKE_POLYMORPHIC | INVOKE_POLYMORPHIC_RANGE | INVOKE_CUSTOM
+        | INVOKE_CUSTOM_RANGE | INVOKE_OBJECT_INIT_RANGE
+        | DIV_INT | REM_INT | DIV_LONG | REM_LONG | DIV_INT_2ADDR | REM_INT_2ADDR | DIV_LONG_2ADDR
+        | REM_LONG_2ADDR | DIV_INT_LIT16 | REM_INT_LIT16 | DIV_INT_LIT8 | REM_INT_LIT8)
+}
+
+/// Kinds of the result and operand of unary operations
+fn unary_op(opcode: OpCode) -> Option<(Kind, Kind)> {
+    use OpCode::*;
+    let kinds = match opcode {
+        NEG_INT | NOT_INT | INT_TO_BYTE | INT_TO_CHAR | INT_TO_SHORT => (Kind::Int, Kind::Int),
+        NEG_LONG | NOT_LONG => (Kind::Long, Kind::Long),
+        NEG_FLOAT => (Kind::Float, Kind::Float),
+        NEG_DOUBLE => (Kind::Double, Kind::Double),
+        INT_TO_LONG => (Kind::Long, Kind::Int),
+        INT_TO_FLOAT => (Kind::Float, Kind::Int),
+        INT_TO_DOUBLE => (Kind::Double, Kind::Int),
+        LONG_TO_INT => (Kind::Int, Kind::Long),
+        LONG_TO_FLOAT => (Kind::Float, Kind::Long),
+        LONG_TO_DOUBLE => (Kind::Double, Kind::Long),
+        FLOAT_TO_INT => (Kind::Int, Kind::Float),
+        FLOAT_TO_LONG => (Kind::Long, Kind::Float),
+        FLOAT_TO_DOUBLE => (Kind::Double, Kind::Float),
+        DOUBLE_TO_INT => (Kind::Int, Kind::Double),
+        DOUBLE_TO_LONG => (Kind::Long, Kind::Double),
+        DOUBLE_TO_FLOAT => (Kind::Float, Kind::Double),
+        ADD_INT_LIT16 | RSUB_INT | MUL_INT_LIT16 | DIV_INT_LIT16 | REM_INT_LIT16 | AND_INT_LIT16
+        | OR_INT_LIT16 | XOR_INT_LIT16 | ADD_INT_LIT8 | RSUB_INT_LIT8 | MUL_INT_LIT8 | DIV_INT_LIT8
+        | REM_INT_LIT8 | AND_INT_LIT8 | OR_INT_LIT8 | XOR_INT_LIT8 | SHL_INT_LIT8 | SHR_INT_LIT8
+        | USHR_INT_LIT8 => (Kind::Int, Kind::Int),
+        _ => return None,
+    };
+    Some(kinds)
+}
+
+/// Kinds of the result and operands of binary operations, including the `2addr` forms
+fn binary_op(opcode: OpCode) -> Option<(Kind, Kind, Kind)> {
+    use OpCode::*;
+    let kinds = match opcode {
+        ADD_INT | SUB_INT | MUL_INT | DIV_INT | REM_INT | AND_INT | OR_INT | XOR_INT | SHL_INT
+        | SHR_INT | USHR_INT | ADD_INT_2ADDR | SUB_INT_2ADDR | MUL_INT_2ADDR | DIV_INT_2ADDR
+        | REM_INT_2ADDR | AND_INT_2ADDR | OR_INT_2ADDR | XOR_INT_2ADDR | SHL_INT_2ADDR
+        | SHR_INT_2ADDR | USHR_INT_2ADDR => (Kind::Int, Kind::Int, Kind::Int),
+        ADD_LONG | SUB_LONG | MUL_LONG | DIV_LONG | REM_LONG | AND_LONG | OR_LONG | XOR_LONG
+        | ADD_LONG_2ADDR | SUB_LONG_2ADDR | MUL_LONG_2ADDR | DIV_LONG_2ADDR | REM_LONG_2ADDR
+        | AND_LONG_2ADDR | OR_LONG_2ADDR | XOR_LONG_2ADDR => (Kind::Long, Kind::Long, Kind::Long),
+        SHL_LONG | SHR_LONG | USHR_LONG | SHL_LONG_2ADDR | SHR_LONG_2ADDR
+        | USHR_LONG_2ADDR => (Kind::Long, Kind::Long, Kind::Int),
+        ADD_FLOAT | SUB_FLOAT | MUL_FLOAT | DIV_FLOAT | REM_FLOAT | ADD_FLOAT_2ADDR | SUB_FLOAT_2ADDR
+        | MUL_FLOAT_2ADDR | DIV_FLOAT_2ADDR | REM_FLOAT_2ADDR => (Kind::Float, Kind::Float, Kind::Float),
+        ADD_DOUBLE | SUB_DOUBLE | MUL_DOUBLE | DIV_DOUBLE | REM_DOUBLE | ADD_DOUBLE_2ADDR
+        | SUB_DOUBLE_2ADDR | MUL_DOUBLE_2ADDR | DIV_DOUBLE_2ADDR
+        | REM_DOUBLE_2ADDR => (Kind::Double, Kind::Double, Kind::Double),
+        CMPL_FLOAT | CMPG_FLOAT => (Kind::Int, Kind::Float, Kind::Float),
+        CMPL_DOUBLE | CMPG_DOUBLE => (Kind::Int, Kind::Double, Kind::Double),
+        CMP_LONG => (Kind::Int, Kind::Long, Kind::Long),
+        _ => return None,
+    };
+    Some(kinds)
+}
+
+/// Direction (`true` for writes), staticness, and kind of value of field accesses
+fn field_op(opcode: OpCode) -> Option<(bool, bool, Kind)> {
+    use OpCode::*;
+    let op = match opcode {
+        IGET | IGET_VOLATILE => (false, false, Kind::Cat1),
+        IGET_WIDE | IGET_WIDE_VOLATILE => (false, false, Kind::Wide),
+        IGET_OBJECT | IGET_OBJECT_VOLATILE => (false, false, Kind::Ref),
+        IGET_BOOLEAN | IGET_BYTE | IGET_CHAR | IGET_SHORT => (false, false, Kind::Int),
+        IPUT | IPUT_VOLATILE => (true, false, Kind::Cat1),
+        IPUT_WIDE | IPUT_WIDE_VOLATILE => (true, false, Kind::Wide),
+        IPUT_OBJECT | IPUT_OBJECT_VOLATILE => (true, false, Kind::Ref),
+        IPUT_BOOLEAN | IPUT_BYTE | IPUT_CHAR | IPUT_SHORT => (true, false, Kind::Int),
+        SGET | SGET_VOLATILE => (false, true, Kind::Cat1),
+        SGET_WIDE | SGET_WIDE_VOLATILE => (false, true, Kind::Wide),
+        SGET_OBJECT | SGET_OBJECT_VOLATILE => (false, true, Kind::Ref),
+        SGET_BOOLEAN | SGET_BYTE | SGET_CHAR | SGET_SHORT => (false, true, Kind::Int),
+        SPUT | SPUT_VOLATILE => (true, true, Kind::Cat1),
+        SPUT_WIDE | SPUT_WIDE_VOLATILE => (true, true, Kind::Wide),
+        SPUT_OBJECT | SPUT_OBJECT_VOLATILE => (true, true, Kind::Ref),
+        SPUT_BOOLEAN | SPUT_BYTE | SPUT_CHAR | SPUT_SHORT => (true, true, Kind::Int),
+        _ => return None,
+    };
+    Some(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::integrity::fix_integrity;
+    use crate::dex::map::{ MapItemType, MapList };
+    use crate::dex::reader::DexReader;
+    use crate::bytes::read_u32_at;
+    use crate::fixtures::{ fake_dex_file_with_methods, fake_dex_with_methods, FakeMethod };
+
+    fn codes(registers: u16, insns: Vec<u16>) -> Vec<DiagnosticCode> {
+        let methods = [FakeMethod { class: "La;", name: "run", access_flags: 0x9, code: Some((registers, 0, 0, insns)) }];
+        let dex = fake_dex_file_with_methods(&["La;"], &[], &methods);
+        verify_code(&dex).iter().map(|diagnostic| diagnostic.code).collect()
+    }
+
+    /// Codes of the violations of a method with one try block, handled by the given encoded catch
+    /// handler
+    fn codes_with_try(registers: u16, insns: Vec<u16>, start_addr: u16, insn_count: u16, handler: &[u8]) -> Vec<DiagnosticCode> {
+        // The try block and the handlers follow the bytecode: they are added to it, and the size
+        // of the bytecode is fixed afterwards
+        let insns_size = insns.len() as u32;
+        let mut units = insns;
+        if units.len() % 2 == 1 {
+            units.push(0);
+        }
+        // Handler at offset 1 of the list, after its size
+        units.extend([start_addr, 0, insn_count, 1]);
+        let mut handlers = vec![0x01];
+        handlers.extend_from_slice(handler);
+        handlers.resize(handlers.len().next_multiple_of(2), 0);
+        units.extend(handlers.chunks(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])));
+
+        let methods = [FakeMethod { class: "La;", name: "run", access_flags: 0x9, code: Some((registers, 0, 0, units)) }];
+        let mut raw = fake_dex_with_methods(&["La;"], &[], &methods);
+        let map = MapList::build(&raw, read_u32_at(&raw, 0x34).unwrap() as usize).unwrap();
+        let code_off = map.get(MapItemType::CodeItem).unwrap().offset as usize;
+        raw[code_off + 6..code_off + 8].copy_from_slice(&1u16.to_le_bytes());
+        raw[code_off + 12..code_off + 16].copy_from_slice(&insns_size.to_le_bytes());
+        fix_integrity(&mut raw).unwrap();
+
+        let dex = DexFile::build(DexReader::build(raw).unwrap()).unwrap();
+        verify_code(&dex).iter().map(|diagnostic| diagnostic.code).collect()
+    }
+
+    #[test]
+    fn test_verify_valid() {
+        // const/4 v0, #1; add-int/lit8 v0, v0, #2; if-eqz v0, +6; const-wide/16 v1, #5;
+        // add-long v1, v1, v1; return-void
+        let insns = vec![0x1012, 0x00d8, 0x0200, 0x0038, 0x0006, 0x0116, 0x0005, 0x019b, 0x0101, 0x000e];
+        assert!(codes(3, insns).is_empty());
+
+        // const/4 v0, #0; packed-switch v0, +5; return-void; nop; packed-switch-payload
+        let insns = vec![0x0012, 0x002b, 0x0005, 0x0000, 0x000e, 0x0000,
+                         0x0100, 0x0001, 0x0000, 0x0000, 0x0003, 0x0000];
+        assert!(codes(1, insns).is_empty());
+    }
+
+    #[test]
+    fn test_verify_structure() {
+        // const/4 v0, #0; const/4 v7, #0; if-eqz v0, +3 (inside const/16); const/16 v0, #1;
+        // return-void
+        let insns = vec![0x0012, 0x0712, 0x0038, 0x0003, 0x0013, 0x0001, 0x000e];
+        assert_eq!(codes(1, insns), vec![DiagnosticCode::InvalidBranchTarget, DiagnosticCode::InvalidRegister]);
+
+        // Switch target inside the switch, then fill-array-data referencing the switch payload
+        let insns = vec![0x0012, 0x002b, 0x0005, 0x0000, 0x000e, 0x0000,
+                         0x0100, 0x0001, 0x0000, 0x0000, 0x0002, 0x0000];
+        assert_eq!(codes(1, insns), vec![DiagnosticCode::InvalidBranchTarget]);
+        let insns = vec![0x0012, 0x0026, 0x0005, 0x0000, 0x000e, 0x0000,
+                         0x0100, 0x0001, 0x0000, 0x0000, 0x0003, 0x0000];
+        assert_eq!(codes(1, insns), vec![DiagnosticCode::InvalidPayload]);
+
+        // goto to itself
+        assert_eq!(codes(0, vec![0x0028]), vec![DiagnosticCode::InvalidBranchTarget]);
+    }
+
+    #[test]
+    fn test_verify_tries() {
+        // const/4 v0, #1; div-int/2addr v0, v0; return-void; move-exception v0; return-void, with
+        // a try block running to the end of the bytecode and a catch-all handler
+        let insns = vec![0x1012, 0x00b3, 0x000e, 0x000d, 0x000e];
+        assert!(codes_with_try(1, insns.clone(), 1, 4, &[0x00, 0x03]).is_empty());
+        assert!(codes_with_try(1, insns.clone(), 1, 1, &[0x00, 0x03]).is_empty());
+
+        // Handler of type 0 (`La;`), then try blocks out of the bytecode or without handler
+        assert!(codes_with_try(1, insns.clone(), 1, 1, &[0x01, 0x00, 0x03]).is_empty());
+        assert_eq!(codes_with_try(1, insns.clone(), 1, 5, &[0x00, 0x03]), vec![DiagnosticCode::InvalidCodeItem]);
+        assert_eq!(codes_with_try(1, insns.clone(), 1, 0, &[0x00, 0x03]), vec![DiagnosticCode::InvalidCodeItem]);
+
+        // Catch-all handler inside an instruction: const/16 v0, #1; div-int/2addr v0, v0;
+        // return-void
+        let insns = vec![0x0013, 0x0001, 0x00b3, 0x000e];
+        assert_eq!(codes_with_try(1, insns, 2, 1, &[0x00, 0x01]), vec![DiagnosticCode::InvalidBranchTarget]);
+
+        // The handler is entered with the registers before the throwing instruction:
+        // const/4 v0, #1; div-int/2addr v0, v0; const/4 v1, #1; add-int/lit8 v1, v1, #1;
+        // return-void; move-exception v0; add-int/lit8 v1, v1, #1; return-void
+        let insns = vec![0x1012, 0x00b3, 0x1112, 0x01d8, 0x0101, 0x000e, 0x000d, 0x01d8, 0x0101, 0x000e];
+        assert_eq!(codes_with_try(2, insns, 1, 1, &[0x00, 0x06]), vec![DiagnosticCode::RegisterTypeMismatch]);
+
+        // move-exception v0 out of a catch handler
+        assert_eq!(codes(1, vec![0x000d, 0x000e]), vec![DiagnosticCode::InvalidControlFlow]);
+    }
+
+    #[test]
+    fn test_verify_types() {
+        // const-wide/16 v0, #1; add-int/lit8 v1, v0, #1; move-result v0; const/4 v0, #0
+        let insns = vec![0x0016, 0x0001, 0x01d8, 0x0100, 0x000a, 0x0012];
+        assert_eq!(codes(2, insns), vec![DiagnosticCode::RegisterTypeMismatch,
+                                         DiagnosticCode::InvalidControlFlow,
+                                         DiagnosticCode::InvalidControlFlow]);
+
+        // Paths with different types merged: const/4 v0, #0; if-eqz v0, +5; const-string v1, #0;
+        // goto +2; const/4 v1, #1; add-int/lit8 v1, v1, #1; return-void
+        let insns = vec![0x0012, 0x0038, 0x0005, 0x011a, 0x0000, 0x0228, 0x1112, 0x01d8, 0x0101, 0x000e];
+        assert_eq!(codes(2, insns), vec![DiagnosticCode::RegisterTypeMismatch]);
+    }
+
+    #[test]
+    fn test_verify_constructor() {
+        let constructor = FakeMethod { class: "La;", name: "<init>", access_flags: 0x10001, code: None };
+        let dex = fake_dex_file_with_methods(&["La;"], &[], &[constructor]);
+        let init = dex.methods.items.iter().position(|method| method == "La;-><init>()V").unwrap() as u16;
+        let class = dex.types.items.iter().position(|class| class == "La;").unwrap() as u16;
+
+        let verify = |name: &str, access_flags: u32, registers: u16, ins: u16, insns: Vec<u16>| {
+            let methods = [FakeMethod { class: "La;", name, access_flags, code: Some((registers, ins, 1, insns)) }];
+            let dex = fake_dex_file_with_methods(&["La;"], &[], &methods);
+            verify_code(&dex).into_iter().map(|diagnostic| (diagnostic.code, diagnostic.offset)).collect::<Vec<_>>()
+        };
+
+        // invoke-direct {v0}, La;-><init>()V; return-void
+        assert!(verify("<init>", 0x10001, 1, 1, vec![0x1070, init, 0x0000, 0x000e]).is_empty());
+        let diagnostics = verify("<init>", 0x10001, 1, 1, vec![0x000e]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].0, DiagnosticCode::UninitializedRegister);
+
+        // new-instance v0, La;; monitor-enter v0; invoke-direct {v0}, La;-><init>()V;
+        // monitor-enter v0; return-void
+        let insns = vec![0x0022, class, 0x001d, 0x1070, init, 0x0000, 0x001d, 0x000e];
+        let diagnostics = verify("<init>", 0x10001, 2, 1, insns);
+        assert_eq!(diagnostics.iter().map(|(code, _)| *code).collect::<Vec<_>>(),
+                   vec![DiagnosticCode::UninitializedRegister, DiagnosticCode::UninitializedRegister]);
+
+        // invoke-static with a wrong number of registers, then move-result after a void method
+        let insns = vec![0x1071, init, 0x0000, 0x000a, 0x000e];
+        let diagnostics = verify("run", 0x9, 1, 0, insns);
+        assert_eq!(diagnostics.iter().map(|(code, _)| *code).collect::<Vec<_>>(),
+                   vec![DiagnosticCode::InvalidRegister, DiagnosticCode::InvalidControlFlow]);
+    }
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(RegType::Zero.merge(&RegType::Integer), RegType::Integer);
+        assert_eq!(RegType::Zero.merge(&RegType::Reference("La;".to_string())), RegType::Reference("La;".to_string()));
+        assert_eq!(RegType::Reference("La;".to_string()).merge(&RegType::Reference("Lb;".to_string())),
+                   RegType::Reference(OBJECT.to_string()));
+        assert_eq!(RegType::Reference("[I".to_string()).merge(&RegType::Reference("[Ljava/lang/String;".to_string())),
+                   RegType::Reference("[Ljava/lang/Object;".to_string()));
+        assert_eq!(RegType::Reference("[[I".to_string()).merge(&RegType::Reference("[I".to_string())),
+                   RegType::Reference(OBJECT.to_string()));
+        assert_eq!(RegType::WideConstLo.merge(&RegType::DoubleLo), RegType::DoubleLo);
+        assert_eq!(RegType::Integer.merge(&RegType::Float), RegType::Conflict);
+    }
+}
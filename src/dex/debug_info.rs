@@ -0,0 +1,425 @@
+//! Debug information of a method
+//!
+//! The debug information of a code item is a small state machine program which maps the
+//! addresses of the bytecode to source lines, and the registers to local variables. The program
+//! is decoded once when parsing the code item. The positions and locals tables are computed from
+//! it on demand, following the same rules as the Android runtime.
+
+use std::io::{ Seek, SeekFrom };
+
+use crate::dex::code_item::CodeItem;
+use crate::dex::reader::DexReader;
+use crate::dex::strings::DexStrings;
+use crate::dex::types::DexTypes;
+use crate::error::DexError;
+
+const DBG_END_SEQUENCE: u8 = 0x00;
+const DBG_ADVANCE_PC: u8 = 0x01;
+const DBG_ADVANCE_LINE: u8 = 0x02;
+const DBG_START_LOCAL: u8 = 0x03;
+const DBG_START_LOCAL_EXTENDED: u8 = 0x04;
+const DBG_END_LOCAL: u8 = 0x05;
+const DBG_RESTART_LOCAL: u8 = 0x06;
+const DBG_SET_PROLOGUE_END: u8 = 0x07;
+const DBG_SET_EPILOGUE_BEGIN: u8 = 0x08;
+const DBG_SET_FILE: u8 = 0x09;
+const DBG_FIRST_SPECIAL: u8 = 0x0a;
+const DBG_LINE_BASE: i32 = -4;
+const DBG_LINE_RANGE: u8 = 15;
+
+/// An instruction of the debug information state machine
+///
+/// Indices are `None` when they are encoded as `NO_INDEX`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugOpCode {
+    AdvancePc(u32),
+    AdvanceLine(i32),
+    StartLocal {
+        register: u32,
+        name_idx: Option<u32>,
+        type_idx: Option<u32>,
+        signature_idx: Option<u32>,
+    },
+    EndLocal(u32),
+    RestartLocal(u32),
+    SetPrologueEnd,
+    SetEpilogueBegin,
+    SetFile(Option<u32>),
+    /// Special opcode, advancing both the address and the line and emitting a position
+    Special(u8),
+}
+
+/// Debug information of a code item
+#[derive(Debug, Clone)]
+pub struct DebugInfo {
+    line_start: u32,
+    /// Indices of the names of the parameters (excluding `this`)
+    parameter_names: Vec<Option<u32>>,
+    /// State machine program, without the final `DBG_END_SEQUENCE`
+    opcodes: Vec<DebugOpCode>,
+}
+
+/// Entry of the positions table, mapping an address to a source line
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionEntry {
+    pub address: u32,
+    pub line: u32,
+}
+
+/// Entry of the locals table: a local variable and the range of addresses where it is live
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalVariable {
+    pub start_address: u32,
+    pub end_address: u32,
+    pub register: u16,
+    pub name: Option<String>,
+    pub descriptor: Option<String>,
+    pub signature: Option<String>,
+}
+
+/// State of a register while computing the locals table
+#[derive(Clone, Default)]
+struct LocalState {
+    local: Option<LocalVariable>,
+    live: bool,
+}
+
+/// Read an unsigned LEB128p1 value, `NO_INDEX` being decoded as `None`
+fn read_index(dex_reader: &mut DexReader) -> Result<Option<u32>, DexError> {
+    let (value, _) = dex_reader.read_uleb128()?;
+    Ok(value.checked_sub(1))
+}
+
+impl DebugInfo {
+    /// Parse the debug information at the given offset
+    pub fn build(dex_reader: &mut DexReader, offset: u32) -> Result<Self, DexError> {
+        dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
+
+        let (line_start, _) = dex_reader.read_uleb128()?;
+        let (parameters_size, _) = dex_reader.read_uleb128()?;
+        let mut parameter_names = Vec::with_capacity(dex_reader.check_count(parameters_size, 1)?);
+        for _ in 0..parameters_size {
+            parameter_names.push(read_index(dex_reader)?);
+        }
+
+        let mut opcodes = Vec::new();
+        loop {
+            let opcode = match dex_reader.read_u8()? {
+                DBG_END_SEQUENCE => break,
+                DBG_ADVANCE_PC => DebugOpCode::AdvancePc(dex_reader.read_uleb128()?.0),
+                DBG_ADVANCE_LINE => DebugOpCode::AdvanceLine(dex_reader.read_sleb128()?.0),
+                opcode @ (DBG_START_LOCAL | DBG_START_LOCAL_EXTENDED) => {
+                    let (register, _) = dex_reader.read_uleb128()?;
+                    let name_idx = read_index(dex_reader)?;
+                    let type_idx = read_index(dex_reader)?;
+                    let signature_idx = match opcode {
+                        DBG_START_LOCAL_EXTENDED => read_index(dex_reader)?,
+                        _ => None,
+                    };
+                    DebugOpCode::StartLocal { register, name_idx, type_idx, signature_idx }
+                },
+                DBG_END_LOCAL => DebugOpCode::EndLocal(dex_reader.read_uleb128()?.0),
+                DBG_RESTART_LOCAL => DebugOpCode::RestartLocal(dex_reader.read_uleb128()?.0),
+                DBG_SET_PROLOGUE_END => DebugOpCode::SetPrologueEnd,
+                DBG_SET_EPILOGUE_BEGIN => DebugOpCode::SetEpilogueBegin,
+                DBG_SET_FILE => DebugOpCode::SetFile(read_index(dex_reader)?),
+                special => DebugOpCode::Special(special),
+            };
+            dex_reader.charge(std::mem::size_of::<DebugOpCode>())?;
+            opcodes.push(opcode);
+        }
+
+        Ok(DebugInfo {
+            line_start,
+            parameter_names,
+            opcodes,
+        })
+    }
+
+    /// Initial value of the line register
+    pub fn line_start(&self) -> u32 {
+        self.line_start
+    }
+
+    /// Indices of the names of the parameters, excluding `this`
+    pub fn parameter_names(&self) -> &[Option<u32>] {
+        &self.parameter_names
+    }
+
+    /// State machine program
+    pub fn opcodes(&self) -> &[DebugOpCode] {
+        &self.opcodes
+    }
+
+    /// Compute the positions table: one entry per special opcode
+    pub fn positions(&self) -> Vec<PositionEntry> {
+        let mut positions = Vec::new();
+        let mut address: u32 = 0;
+        let mut line = self.line_start;
+
+        for opcode in self.opcodes.iter() {
+            match opcode {
+                DebugOpCode::AdvancePc(delta) => address = address.wrapping_add(*delta),
+                DebugOpCode::AdvanceLine(delta) => line = line.wrapping_add_signed(*delta),
+                DebugOpCode::Special(opcode) => {
+                    let adjusted = opcode - DBG_FIRST_SPECIAL;
+                    address = address.wrapping_add(u32::from(adjusted / DBG_LINE_RANGE));
+                    line = line.wrapping_add_signed(DBG_LINE_BASE + i32::from(adjusted % DBG_LINE_RANGE));
+                    positions.push(PositionEntry { address, line });
+                },
+                _ => (),
+            }
+        }
+
+        positions
+    }
+
+    /// Compute the locals table of the method owning the code item
+    ///
+    /// `this_type` is the class of the method if it is not static, and `parameters` the
+    /// descriptors of its parameters. Locals are listed in the order they end, and those still
+    /// live at the end of the method in the order of their registers. If the program is
+    /// inconsistent with the method (e.g. a register out of its frame) the table stops at the
+    /// offending instruction, and is empty if the parameters do not match the prototype.
+    pub fn locals(&self,
+                  code: &CodeItem,
+                  this_type: Option<&str>,
+                  parameters: &[&str],
+                  strings: &DexStrings,
+                  types: &DexTypes) -> Vec<LocalVariable> {
+        let string = |idx: Option<u32>| idx.and_then(|idx| strings.strings.get(idx as usize)).cloned();
+        let type_name = |idx: Option<u32>| idx.and_then(|idx| types.items.get(idx as usize)).cloned();
+
+        let registers_size = code.registers_size();
+        let mut registers = vec![LocalState::default(); registers_size.into()];
+        let mut locals = Vec::new();
+
+        // Parameters are in the last registers of the frame
+        let mut arg_reg = registers_size.wrapping_sub(code.ins_size());
+        let mut start = |register: u16, name: Option<String>, descriptor: &str| {
+            let state = registers.get_mut(usize::from(register))?;
+            state.local = Some(LocalVariable {
+                start_address: 0,
+                end_address: 0,
+                register,
+                name,
+                descriptor: Some(descriptor.to_string()),
+                signature: None,
+            });
+            state.live = true;
+            Some(())
+        };
+        if let Some(this_type) = this_type {
+            if start(arg_reg, Some("this".to_string()), this_type).is_none() {
+                return locals;
+            }
+            arg_reg = arg_reg.wrapping_add(1);
+        }
+        if self.parameter_names.len() != parameters.len() {
+            return locals;
+        }
+        for (name_idx, descriptor) in self.parameter_names.iter().zip(parameters) {
+            if start(arg_reg, string(*name_idx), descriptor).is_none() {
+                return locals;
+            }
+            arg_reg = match descriptor.as_bytes().first() {
+                Some(b'J' | b'D') => arg_reg.wrapping_add(2),
+                _ => arg_reg.wrapping_add(1),
+            };
+        }
+
+        let mut address: u32 = 0;
+        for opcode in self.opcodes.iter() {
+            match opcode {
+                DebugOpCode::AdvancePc(delta) => address = address.wrapping_add(*delta),
+                DebugOpCode::StartLocal { register, name_idx, type_idx, signature_idx } => {
+                    // Registers are 16-bit values in the runtime
+                    let Some(state) = registers.get_mut(*register as u16 as usize) else {
+                        return locals;
+                    };
+
+                    // Parameters with a generic type are declared twice, the first time with
+                    // an empty range which is not reported
+                    if state.live && let Some(mut local) = state.local.take() && address != 0 {
+                        local.end_address = address;
+                        locals.push(local);
+                    }
+                    state.local = Some(LocalVariable {
+                        start_address: address,
+                        end_address: 0,
+                        register: *register as u16,
+                        name: string(*name_idx),
+                        descriptor: type_name(*type_idx),
+                        signature: string(*signature_idx),
+                    });
+                    state.live = true;
+                },
+                DebugOpCode::EndLocal(register) => {
+                    let Some(state) = registers.get_mut(*register as u16 as usize) else {
+                        return locals;
+                    };
+                    if !state.live {
+                        return locals;
+                    }
+                    if let Some(local) = &state.local {
+                        locals.push(LocalVariable { end_address: address, ..local.clone() });
+                    }
+                    state.live = false;
+                },
+                DebugOpCode::RestartLocal(register) => {
+                    let Some(state) = registers.get_mut(*register as u16 as usize) else {
+                        return locals;
+                    };
+                    // Restarting a live local does not change its start address
+                    if !state.live {
+                        if let Some(local) = state.local.as_mut() {
+                            local.start_address = address;
+                        }
+                        state.live = true;
+                    }
+                },
+                DebugOpCode::Special(opcode) => {
+                    address = address.wrapping_add(u32::from((opcode - DBG_FIRST_SPECIAL) / DBG_LINE_RANGE));
+                },
+                _ => (),
+            }
+        }
+
+        // Locals still live at the end of the method
+        let insns_size = code.insns_size();
+        for state in registers.into_iter().filter(|state| state.live) {
+            if let Some(local) = state.local {
+                locals.push(LocalVariable { end_address: insns_size, ..local });
+            }
+        }
+
+        locals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a reader over a fake header followed by the given bytes, at offset 0x70
+    fn reader(bytes: &[u8]) -> DexReader<'static> {
+        let mut raw = vec![0u8; 0x70];
+        raw[40..44].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        raw.extend_from_slice(bytes);
+
+        DexReader::build(raw).unwrap()
+    }
+
+    #[test]
+    fn test_build() {
+        let bytes = vec![
+            0x05,                       // line_start
+            0x02, 0x00, 0x03,           // parameters: NO_INDEX, string 2
+            0x07,                       // DBG_SET_PROLOGUE_END
+            0x03, 0x01, 0x02, 0x00,     // DBG_START_LOCAL v1, string 1, NO_INDEX
+            0x04, 0x02, 0x01, 0x01, 0x01, // DBG_START_LOCAL_EXTENDED v2, string 0, type 0, string 0
+            0x02, 0x7d,                 // DBG_ADVANCE_LINE -3
+            0x01, 0x04,                 // DBG_ADVANCE_PC 4
+            0x05, 0x01,                 // DBG_END_LOCAL v1
+            0x06, 0x01,                 // DBG_RESTART_LOCAL v1
+            0x09, 0x00,                 // DBG_SET_FILE NO_INDEX
+            0x1c,                       // special
+            0x00,                       // DBG_END_SEQUENCE
+        ];
+        let debug_info = DebugInfo::build(&mut reader(&bytes), 0x70).unwrap();
+
+        assert_eq!(debug_info.line_start(), 5);
+        assert_eq!(debug_info.parameter_names(), &[None, Some(2)]);
+        assert_eq!(debug_info.opcodes(), &[
+            DebugOpCode::SetPrologueEnd,
+            DebugOpCode::StartLocal { register: 1, name_idx: Some(1), type_idx: None, signature_idx: None },
+            DebugOpCode::StartLocal { register: 2, name_idx: Some(0), type_idx: Some(0), signature_idx: Some(0) },
+            DebugOpCode::AdvanceLine(-3),
+            DebugOpCode::AdvancePc(4),
+            DebugOpCode::EndLocal(1),
+            DebugOpCode::RestartLocal(1),
+            DebugOpCode::SetFile(None),
+            DebugOpCode::Special(0x1c),
+        ]);
+
+        // Truncated program
+        assert!(DebugInfo::build(&mut reader(&[0x05, 0x00, 0x01]), 0x70).is_err());
+    }
+
+    #[test]
+    fn test_positions() {
+        let debug_info = DebugInfo {
+            line_start: 10,
+            parameter_names: Vec::new(),
+            opcodes: vec![
+                DebugOpCode::Special(0x0e),  // address +0, line +0
+                DebugOpCode::AdvancePc(3),
+                DebugOpCode::Special(0x1e),  // address +1, line +1
+                DebugOpCode::AdvanceLine(-5),
+                DebugOpCode::Special(0x0a),  // address +0, line -4
+            ],
+        };
+
+        assert_eq!(debug_info.positions(), vec![
+            PositionEntry { address: 0, line: 10 },
+            PositionEntry { address: 4, line: 11 },
+            PositionEntry { address: 4, line: 2 },
+        ]);
+    }
+
+    #[test]
+    fn test_locals() {
+        let mut bytes = Vec::new();
+        for value in [3u16, 2, 0, 0] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0x84u32.to_le_bytes());   // debug_info_off
+        bytes.extend_from_slice(&2u32.to_le_bytes());      // insns_size
+        bytes.extend_from_slice(&[0x00, 0x00, 0x0e, 0x00]); // nop; return-void
+        bytes.extend_from_slice(&[
+            0x01, 0x01, 0x02,           // line 1, parameter "x"
+            0x03, 0x00, 0x01, 0x01,     // DBG_START_LOCAL v0, "i", I
+            0x1d,                       // special: address +1, line +0
+            0x05, 0x00,                 // DBG_END_LOCAL v0
+            0x00,
+        ]);
+        let strings = DexStrings { strings: vec!["i".to_string(), "x".to_string()] };
+        let types = DexTypes { items: vec!["I".to_string(), "La;".to_string()] };
+
+        let code = CodeItem::build(&mut reader(&bytes), 0x70, &types).unwrap();
+        let debug_info = code.debug_info().unwrap();
+        let local = |start_address, end_address, register, name: &str, descriptor: &str| LocalVariable {
+            start_address,
+            end_address,
+            register,
+            name: Some(name.to_string()),
+            descriptor: Some(descriptor.to_string()),
+            signature: None,
+        };
+
+        assert_eq!(debug_info.positions(), vec![PositionEntry { address: 1, line: 1 }]);
+        assert_eq!(debug_info.locals(&code, Some("La;"), &["I"], &strings, &types), vec![
+            local(0, 1, 0, "i", "I"),
+            local(0, 2, 1, "this", "La;"),
+            local(0, 2, 2, "x", "I"),
+        ]);
+
+        // Static method: the parameter is in the last register
+        assert_eq!(debug_info.locals(&code, None, &["I"], &strings, &types)[1], local(0, 2, 1, "x", "I"));
+        // Parameters inconsistent with the prototype
+        assert!(debug_info.locals(&code, Some("La;"), &[], &strings, &types).is_empty());
+
+        // Exceeding the limits is not recoverable: only the bytecode fits in the budget
+        let mut limited = reader(&bytes);
+        limited.limits.max_decoded_size = 4;
+        assert!(CodeItem::build(&mut limited, 0x70, &types).unwrap_err().is(&DexError::ResourceLimitExceeded));
+        assert!(limited.diagnostics.is_empty());
+
+        // Malformed debug information is ignored
+        bytes.truncate(bytes.len() - 1);
+        let mut truncated = reader(&bytes);
+        let code = CodeItem::build(&mut truncated, 0x70, &types).unwrap();
+        assert!(code.debug_info().is_none());
+        assert_eq!(truncated.diagnostics[0].code, crate::dex::diagnostics::DiagnosticCode::InvalidDebugInfo);
+    }
+}
@@ -0,0 +1,723 @@
+//! Structural dump in the format of `dexdump`
+//!
+//! The dump reproduces the plain text output of the AOSP `dexdump -f -h -d` command, so that the
+//! parser can be compared with the platform tools on the same files: the file header, then for
+//! each class its `class_def_item` followed by its members, with the access flags in hex and in
+//! text, and for each method the sizes of its frame, its disassembled bytecode, its try blocks,
+//! and the positions and locals tables of its debug information.
+//!
+//! Indices found in the bytecode are resolved with the lists of the `DexFile`, so the dump only
+//! matches `dexdump` for files which were not merged with others.
+
+use std::fmt::Write;
+
+use crate::bytes::read_u16_at;
+use crate::dex::access_flags::{ AccessFlag, AccessFlagType };
+use crate::dex::classes::{ ClassDefItem, EncodedField, EncodedMethod };
+use crate::dex::code_item::{ CodeItem, CODE_ITEM_HEADER_SIZE };
+use crate::dex::encoded_values::EncodedValue;
+use crate::dex::file::DexFile;
+use crate::dex::header::DexHeader;
+use crate::dex::instructions::Instructions;
+use crate::dex::integrity::IntegrityPolicy;
+use crate::dex::opcodes::OpCode;
+use crate::dex::options::ParseOptions;
+use crate::dex::reader::DexReader;
+use crate::error::DexError;
+
+/// Number of code units of an instruction shown before it is cut
+const SHOWN_CODE_UNITS: usize = 8;
+
+/// Dump all the DEX files of an APK or a DEX file
+pub fn dump_from_file(filepath: &str) -> Result<String, DexError> {
+    let options = ParseOptions {
+        checksum: IntegrityPolicy::Warn,
+        signature: IntegrityPolicy::Skip,
+        ..Default::default()
+    };
+
+    let mut output = format!("Processing '{filepath}'...\n");
+    for (idx, dex_reader) in DexReader::build_from_file(filepath)?.into_iter().enumerate() {
+        // Parse from a second reader borrowing the same bytes, so that both stay available
+        let dex = DexFile::build_with_options(DexReader::from_slice(dex_reader.raw())?, &options)?;
+
+        let location = match idx {
+            0 => filepath.to_string(),
+            _ => format!("{filepath}!classes{}.dex", idx + 1),
+        };
+        let version = String::from_utf8_lossy(&dex.header.version);
+        writeln!(output, "Opened '{location}', DEX version '{version}'").unwrap();
+        output.push_str(&dump(&dex_reader, &dex));
+    }
+
+    Ok(output)
+}
+
+/// Dump a DEX file
+///
+/// The reader must be over the bytes the `DexFile` was parsed from.
+pub fn dump(dex_reader: &DexReader, dex: &DexFile) -> String {
+    let dumper = Dumper {
+        raw: dex_reader.bytes.get_ref(),
+        dex,
+    };
+
+    let mut output = String::new();
+    dumper.file_header(&mut output, &dex.header);
+    for (idx, class) in dex.classes.items.iter().enumerate() {
+        dumper.class_def(&mut output, idx, class);
+        dumper.class(&mut output, idx, class);
+    }
+
+    output
+}
+
+/// Writer of the dump of a DEX file
+struct Dumper<'a> {
+    raw: &'a [u8],
+    dex: &'a DexFile,
+}
+
+impl Dumper<'_> {
+    fn file_header(&self, out: &mut String, header: &DexHeader) {
+        let signature = &header.signature;
+
+        out.push_str("DEX file header:\n");
+        writeln!(out, "magic               : '{}'", asciify(self.raw.get(..8).unwrap_or_default())).unwrap();
+        writeln!(out, "checksum            : {:08x}", header.checksum).unwrap();
+        writeln!(out, "signature           : {:02x}{:02x}...{:02x}{:02x}",
+                 signature[0], signature[1], signature[18], signature[19]).unwrap();
+        writeln!(out, "file_size           : {}", header.file_size as i32).unwrap();
+        writeln!(out, "header_size         : {}", header.header_size as i32).unwrap();
+        writeln!(out, "link_size           : {}", header.link_size as i32).unwrap();
+        write_offset(out, "link_off", header.link_off);
+        writeln!(out, "string_ids_size     : {}", header.string_ids_size as i32).unwrap();
+        write_offset(out, "string_ids_off", header.string_ids_off);
+        writeln!(out, "type_ids_size       : {}", header.type_ids_size as i32).unwrap();
+        write_offset(out, "type_ids_off", header.type_ids_off);
+        writeln!(out, "proto_ids_size      : {}", header.proto_ids_size as i32).unwrap();
+        write_offset(out, "proto_ids_off", header.proto_ids_off);
+        writeln!(out, "field_ids_size      : {}", header.fields_ids_size as i32).unwrap();
+        write_offset(out, "field_ids_off", header.fields_ids_off);
+        writeln!(out, "method_ids_size     : {}", header.method_ids_size as i32).unwrap();
+        write_offset(out, "method_ids_off", header.method_ids_off);
+        writeln!(out, "class_defs_size     : {}", header.class_defs_size as i32).unwrap();
+        write_offset(out, "class_defs_off", header.class_defs_off);
+        writeln!(out, "data_size           : {}", header.data_size as i32).unwrap();
+        write_offset(out, "data_off", header.data_off);
+        out.push('\n');
+    }
+
+    fn class_def(&self, out: &mut String, idx: usize, class: &ClassDefItem) {
+        let flags = class.get_raw_access_flags();
+
+        writeln!(out, "Class #{idx} header:").unwrap();
+        // Type indices are 16-bit values, followed by padding
+        writeln!(out, "class_idx           : {}", class.get_class_idx() as u16).unwrap();
+        writeln!(out, "access_flags        : {} (0x{:04x})", flags as i32, flags).unwrap();
+        writeln!(out, "superclass_idx      : {}", class.get_superclass_idx() as u16).unwrap();
+        write_offset(out, "interfaces_off", class.get_interfaces_off());
+        writeln!(out, "source_file_idx     : {}", class.get_source_file_idx() as i32).unwrap();
+        write_offset(out, "annotations_off", class.get_annotations_off());
+        write_offset(out, "class_data_off", class.get_class_data_off());
+        writeln!(out, "static_fields_size  : {}", class.get_static_fields().len()).unwrap();
+        writeln!(out, "instance_fields_size: {}", class.get_instance_fields().len()).unwrap();
+        writeln!(out, "direct_methods_size : {}", class.get_direct_methods().len()).unwrap();
+        writeln!(out, "virtual_methods_size: {}", class.get_virtual_methods().len()).unwrap();
+        out.push('\n');
+    }
+
+    fn class(&self, out: &mut String, idx: usize, class: &ClassDefItem) {
+        let flags = class.get_raw_access_flags();
+
+        writeln!(out, "Class #{idx}            -").unwrap();
+        writeln!(out, "  Class descriptor  : '{}'", class.get_class_name()).unwrap();
+        writeln!(out, "  Access flags      : 0x{:04x} ({})", flags, access_string(flags, AccessFlagType::Class)).unwrap();
+        if let Some(superclass) = class.get_superclass_name() {
+            writeln!(out, "  Superclass        : '{superclass}'").unwrap();
+        }
+        out.push_str("  Interfaces        -\n");
+        for (idx, interface) in class.get_interfaces().iter().enumerate() {
+            writeln!(out, "    #{idx}              : '{interface}'").unwrap();
+        }
+
+        out.push_str("  Static fields     -\n");
+        let static_values = class.get_static_values();
+        for (idx, field) in class.get_static_fields().iter().enumerate() {
+            self.field(out, idx, field);
+            if let Some((_, value)) = static_values.get(idx) {
+                writeln!(out, "      value         : {}", self.encoded_value(value)).unwrap();
+            }
+        }
+        out.push_str("  Instance fields   -\n");
+        for (idx, field) in class.get_instance_fields().iter().enumerate() {
+            self.field(out, idx, field);
+        }
+        out.push_str("  Direct methods    -\n");
+        for (idx, method) in class.get_direct_methods().iter().enumerate() {
+            self.method(out, idx, method);
+        }
+        out.push_str("  Virtual methods   -\n");
+        for (idx, method) in class.get_virtual_methods().iter().enumerate() {
+            self.method(out, idx, method);
+        }
+
+        let source_file = class.get_source_file().map_or("unknown", String::as_str);
+        writeln!(out, "  source_file_idx   : {} ({source_file})", class.get_source_file_idx() as i32).unwrap();
+        out.push('\n');
+    }
+
+    fn field(&self, out: &mut String, idx: usize, field: &EncodedField) {
+        let (class, name, type_) = split_field(field.get_field());
+        let flags = field.get_raw_access_flags();
+
+        writeln!(out, "    #{idx}              : (in {class})").unwrap();
+        writeln!(out, "      name          : '{name}'").unwrap();
+        writeln!(out, "      type          : '{type_}'").unwrap();
+        writeln!(out, "      access        : 0x{:04x} ({})", flags, access_string(flags, AccessFlagType::Field)).unwrap();
+    }
+
+    fn method(&self, out: &mut String, idx: usize, method: &EncodedMethod) {
+        let (class, name, signature) = split_method(method.get_proto());
+        let flags = method.get_raw_access_flags();
+
+        writeln!(out, "    #{idx}              : (in {class})").unwrap();
+        writeln!(out, "      name          : '{name}'").unwrap();
+        writeln!(out, "      type          : '{signature}'").unwrap();
+        writeln!(out, "      access        : 0x{:04x} ({})", flags, access_string(flags, AccessFlagType::Method)).unwrap();
+        match &method.code_item {
+            None => out.push_str("      code          : (none)\n"),
+            Some(code) => {
+                out.push_str("      code          -\n");
+                self.code(out, method, code);
+            },
+        }
+        out.push('\n');
+    }
+
+    fn code(&self, out: &mut String, method: &EncodedMethod, code: &CodeItem) {
+        writeln!(out, "      registers     : {}", code.registers_size()).unwrap();
+        writeln!(out, "      ins           : {}", code.ins_size()).unwrap();
+        writeln!(out, "      outs          : {}", code.outs_size()).unwrap();
+        writeln!(out, "      insns size    : {} 16-bit code units", code.insns_size() as i32).unwrap();
+
+        self.bytecode(out, method, code);
+
+        // Try blocks
+        let tries = code.tries();
+        if tries.is_empty() {
+            out.push_str("      catches       : (none)\n");
+        } else {
+            writeln!(out, "      catches       : {}", tries.len()).unwrap();
+            for try_item in tries {
+                let start = try_item.start_addr();
+                let end = start.wrapping_add(try_item.insn_count().into());
+                writeln!(out, "        0x{start:04x} - 0x{end:04x}").unwrap();
+
+                let Some(handler) = code.handlers().iter().find(|handler| handler.offset() == try_item.handler_off()) else {
+                    continue;
+                };
+                for pair in handler.handlers() {
+                    writeln!(out, "          {} -> 0x{:04x}", pair.decoded_type(), pair.addr()).unwrap();
+                }
+                if let Some(addr) = handler.catch_all_addr() {
+                    writeln!(out, "          <any> -> 0x{addr:04x}").unwrap();
+                }
+            }
+        }
+
+        // Debug information
+        out.push_str("      positions     : \n");
+        if let Some(debug_info) = code.debug_info() {
+            for position in debug_info.positions() {
+                writeln!(out, "        0x{:04x} line={}", position.address, position.line as i32).unwrap();
+            }
+        }
+        out.push_str("      locals        : \n");
+        if let Some(debug_info) = code.debug_info() {
+            let (class, _, _) = split_method(method.get_proto());
+            let is_static = method.get_raw_access_flags() & 0x8 != 0;
+            let parameters = parameters(method.get_proto());
+
+            let locals = debug_info.locals(code,
+                                           (!is_static).then_some(class),
+                                           &parameters,
+                                           &self.dex.strings,
+                                           &self.dex.types);
+            for local in locals {
+                writeln!(out, "        0x{:04x} - 0x{:04x} reg={} {} {} {}",
+                         local.start_address,
+                         local.end_address,
+                         local.register,
+                         local.name.as_deref().unwrap_or("(null)"),
+                         local.descriptor.as_deref().unwrap_or("(null)"),
+                         local.signature.as_deref().unwrap_or("")).unwrap();
+            }
+        }
+    }
+
+    fn bytecode(&self, out: &mut String, method: &EncodedMethod, code: &CodeItem) {
+        let (class, name, signature) = split_method(method.get_proto());
+        let code_off = code.offset();
+
+        writeln!(out, "{code_off:06x}:                                        |[{code_off:06x}] {}.{name}:{signature}",
+                 descriptor_to_dot(class)).unwrap();
+
+        let Some(insns) = &code.insns else {
+            return;
+        };
+        let insns_off = code_off as usize + CODE_ITEM_HEADER_SIZE;
+        let mut address = 0;
+        for inst in insns {
+            let width = inst.length();
+            if width == 0 {
+                break;
+            }
+
+            // Raw code units, in file order
+            write!(out, "{:06x}:", insns_off + address * 2).unwrap();
+            for idx in 0..SHOWN_CODE_UNITS {
+                let unit = read_u16_at(self.raw, insns_off + (address + idx) * 2).unwrap_or_default();
+                match idx {
+                    _ if idx >= width => out.push_str("     "),
+                    7 => out.push_str(" ... "),
+                    _ => write!(out, " {:02x}{:02x}", unit & 0xff, unit >> 8).unwrap(),
+                }
+            }
+
+            let label = match inst {
+                Instructions::PackedSwitchPayload(_) => format!("packed-switch-data ({width} units)"),
+                Instructions::SparseSwitchPayload(_) => format!("sparse-switch-data ({width} units)"),
+                Instructions::FillArrayDataPayload(_) => format!("array-data ({width} units)"),
+                _ if inst.opcode() == OpCode::NOP => "nop // spacer".to_string(),
+                _ => format!("{}{}", inst.opcode().mnemonic(), self.operands(inst, address as u32)),
+            };
+            writeln!(out, "|{address:04x}: {label}").unwrap();
+
+            address += width;
+        }
+    }
+
+    /// Format the operands of an instruction, as `dexdump` does
+    fn operands(&self, inst: &Instructions, address: u32) -> String {
+        let units = inst.bytes();
+        let unit = |idx: usize| units.get(idx).copied().unwrap_or_default();
+        let (u0, u1, u2) = (unit(0), unit(1), unit(2));
+        let (a4, b4, a8) = ((u0 >> 8) & 0xf, u0 >> 12, u0 >> 8);
+        let wide = |low: u16, high: u16| u32::from(low) | (u32::from(high) << 16);
+        let branch = |offset: i32| {
+            let sign = if offset < 0 { '-' } else { '+' };
+            format!("{:04x} // {sign}{:04x}", address.wrapping_add_signed(offset), offset.unsigned_abs())
+        };
+
+        match inst {
+            Instructions::Instruction10x(_) => String::new(),
+            Instructions::Instruction12x(_) => format!(" v{a4}, v{b4}"),
+            Instructions::Instruction11n(_) => {
+                let value = ((u0 as i16) >> 12) as i32;
+                format!(" v{a4}, #int {value} // #{:x}", value as u8)
+            },
+            Instructions::Instruction11x(_) => format!(" v{a8}"),
+            Instructions::Instruction10t(_) => format!(" {}", branch(i32::from(a8 as u8 as i8))),
+            Instructions::Instruction20t(_) => format!(" {}", branch(i32::from(u1 as i16))),
+            Instructions::Instruction22x(_) => format!(" v{a8}, v{u1}"),
+            Instructions::Instruction21t(_) => format!(" v{a8}, {}", branch(i32::from(u1 as i16))),
+            Instructions::Instruction21s(_) => format!(" v{a8}, #int {} // #{u1:x}", u1 as i16),
+            Instructions::Instruction21h(_) => match inst.opcode() {
+                OpCode::CONST_HIGH16 => format!(" v{a8}, #int {} // #{u1:x}", (u32::from(u1) << 16) as i32),
+                _ => format!(" v{a8}, #long {} // #{u1:x}", (u64::from(u1) << 48) as i64),
+            },
+            Instructions::Instruction21c(_) => format!(" v{a8}, {}", self.index(inst.opcode(), u1.into(), 0, 4)),
+            Instructions::Instruction31c(_) => format!(" v{a8}, {}", self.index(inst.opcode(), wide(u1, u2), 0, 8)),
+            Instructions::Instruction23x(_) => format!(" v{a8}, v{}, v{}", u1 & 0xff, u1 >> 8),
+            Instructions::Instruction22b(_) => {
+                let value = (u1 >> 8) as u8;
+                format!(" v{a8}, v{}, #int {} // #{value:02x}", u1 & 0xff, value as i8)
+            },
+            Instructions::Instruction22t(_) => format!(" v{a4}, v{b4}, {}", branch(i32::from(u1 as i16))),
+            Instructions::Instruction22s(_) => format!(" v{a4}, v{b4}, #int {} // #{u1:04x}", u1 as i16),
+            Instructions::Instruction22c(_) => format!(" v{a4}, v{b4}, {}", self.index(inst.opcode(), u1.into(), 0, 4)),
+            Instructions::Instruction30t(_) => format!(" #{:08x}", wide(u1, u2)),
+            Instructions::Instruction31i(_) => {
+                let value = wide(u1, u2);
+                format!(" v{a8}, #float {} // #{value:08x}", format_g(f32::from_bits(value).into()))
+            },
+            Instructions::Instruction31t(_) => {
+                let offset = wide(u1, u2);
+                format!(" v{a8}, {:08x} // +{offset:08x}", address.wrapping_add(offset))
+            },
+            Instructions::Instruction32x(_) => format!(" v{u1}, v{u2}"),
+            Instructions::Instruction35c(_) | Instructions::Instruction45cc(_) => {
+                let registers = [u2 & 0xf, (u2 >> 4) & 0xf, (u2 >> 8) & 0xf, u2 >> 12, a4];
+                let registers = registers.iter()
+                                         .take(b4.into())
+                                         .map(|register| format!("v{register}"))
+                                         .collect::<Vec<String>>();
+                format!(" {{{}}}, {}", registers.join(", "), self.index(inst.opcode(), u1.into(), unit(3), 4))
+            },
+            Instructions::Instruction3rc(_) | Instructions::Instruction4rcc(_) => {
+                let registers = (0..a8).map(|idx| format!("v{}", u32::from(u2) + u32::from(idx)))
+                                       .collect::<Vec<String>>();
+                format!(" {{{}}}, {}", registers.join(", "), self.index(inst.opcode(), u1.into(), unit(3), 4))
+            },
+            Instructions::Instruction51l(_) => {
+                let value = u64::from(wide(u1, u2)) | (u64::from(wide(unit(3), unit(4))) << 32);
+                format!(" v{a8}, #double {} // #{value:016x}", format_g(f64::from_bits(value)))
+            },
+            // Optimized formats are not supported by `dexdump`
+            _ => " ???".to_string(),
+        }
+    }
+
+    /// Format the item referenced by an instruction
+    fn index(&self, opcode: OpCode, idx: u32, proto_idx: u16, width: usize) -> String {
+        let dex = self.dex;
+        let method = |idx: u32| dex.methods.items.get(idx as usize).map(|method| {
+            let (class, name, signature) = split_method(method);
+            format!("{class}.{name}:{signature}")
+        });
+        let proto = |idx: u32| dex.protos.items.get(idx as usize).map(|proto| proto.replace(' ', ""));
+
+        match opcode {
+            OpCode::CONST_STRING | OpCode::CONST_STRING_JUMBO => match dex.strings.strings.get(idx as usize) {
+                Some(string) => format!("\"{}\" // string@{idx:0width$x}", escape(string)),
+                None => format!("<string?> // string@{idx:0width$x}"),
+            },
+            OpCode::CONST_CLASS | OpCode::CHECK_CAST | OpCode::INSTANCE_OF | OpCode::NEW_INSTANCE |
+            OpCode::NEW_ARRAY | OpCode::FILLED_NEW_ARRAY | OpCode::FILLED_NEW_ARRAY_RANGE => {
+                let type_ = dex.types.items.get(idx as usize).map_or("<type?>", String::as_str);
+                format!("{type_} // type@{idx:0width$x}")
+            },
+            OpCode::INVOKE_POLYMORPHIC | OpCode::INVOKE_POLYMORPHIC_RANGE => {
+                let method = method(idx).unwrap_or("<method?>".to_string());
+                let proto = proto(proto_idx.into()).unwrap_or("<proto?>".to_string());
+                format!("{method}, {proto} // method@{idx:0width$x}, proto@{proto_idx:0width$x}")
+            },
+            OpCode::INVOKE_CUSTOM | OpCode::INVOKE_CUSTOM_RANGE => format!("call_site@{idx:0width$x}"),
+            OpCode::CONST_METHOD_HANDLE => format!("method_handle@{idx:0width$x}"),
+            OpCode::CONST_METHOD_TYPE => {
+                let proto = proto(idx).unwrap_or("<?>".to_string());
+                format!("{proto} // proto@{idx:0width$x}")
+            },
+            _ if format!("{opcode:?}").starts_with("INVOKE_") => {
+                let method = method(idx).unwrap_or("<method?>".to_string());
+                format!("{method} // method@{idx:0width$x}")
+            },
+            _ => match dex.fields.items.get(idx as usize) {
+                Some(field) => {
+                    let (class, name, type_) = split_field(field);
+                    format!("{class}.{name}:{type_} // field@{idx:0width$x}")
+                },
+                None => format!("<field?> // field@{idx:0width$x}"),
+            },
+        }
+    }
+
+    /// Format a static value, as `dexdump` does
+    fn encoded_value(&self, value: &EncodedValue) -> String {
+        match value {
+            EncodedValue::Byte(value) => value.to_string(),
+            EncodedValue::Short(value) => value.to_string(),
+            EncodedValue::Char(value) => value.to_string(),
+            EncodedValue::Int(value) => value.to_string(),
+            EncodedValue::Long(value) => value.to_string(),
+            EncodedValue::Float(value) => format_g((*value).into()),
+            EncodedValue::Double(value) => format_g(*value),
+            EncodedValue::MethodType(idx) => self.dex.protos.items.get(*idx as usize)
+                                                                  .map_or("<?>".to_string(), |proto| proto.replace(' ', "")),
+            EncodedValue::MethodHandle(idx) => format!("method_handle@{idx}"),
+            EncodedValue::String(value) => format!("\"{}\"", escape(value)),
+            EncodedValue::Type(value) => value.to_string(),
+            EncodedValue::Field(value) | EncodedValue::Enum(value) => split_field(value).1.to_string(),
+            EncodedValue::Method(value) => split_method(value).1.to_string(),
+            EncodedValue::Array(values) => {
+                let mut output = "{".to_string();
+                for value in values {
+                    output.push(' ');
+                    output.push_str(&self.encoded_value(value));
+                }
+                output.push_str(" }");
+                output
+            },
+            EncodedValue::Annotation(annotation) => {
+                let mut output = annotation.type_.clone();
+                for (name, value) in annotation.elements.iter() {
+                    write!(output, " {name}={}", self.encoded_value(value)).unwrap();
+                }
+                output
+            },
+            EncodedValue::Null => "null".to_string(),
+            EncodedValue::Boolean(value) => value.to_string(),
+        }
+    }
+}
+
+/// Write an offset of the header in decimal and in hex
+fn write_offset(out: &mut String, name: &str, offset: u32) {
+    writeln!(out, "{name:<20}: {} (0x{offset:06x})", offset as i32).unwrap();
+}
+
+/// Make the magic bytes printable
+fn asciify(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| match byte {
+        b'\0' => "\\0".to_string(),
+        b'\n' => "\\n".to_string(),
+        0x01..0x20 => ".".to_string(),
+        0x80.. => "?".to_string(),
+        _ => char::from(byte).to_string(),
+    }).collect()
+}
+
+/// Escape a string for a Java-like string literal
+fn escape(string: &str) -> String {
+    let mut escaped = String::with_capacity(string.len());
+    for c in string.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c < ' ' || c == '\x7f' => write!(escaped, "\\u{:04x}", u32::from(c)).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Get the access flags in text, in the order of their bits
+///
+/// Bits which are not valid for the item are shown as `?`, except the ones which `dexdump` still
+/// names (used by old runtimes).
+fn access_string(raw: u32, for_type: AccessFlagType) -> String {
+    let mut names = Vec::new();
+    for bit in (0..18).map(|shift| 1u32 << shift).filter(|bit| raw & bit != 0) {
+        let (flags, _) = AccessFlag::parse_with_invalid(bit, for_type);
+        let name = match (flags.first(), for_type, bit) {
+            (Some(flag), _, _) => format!("{flag:?}").trim_start_matches("ACC_").to_string(),
+            (None, AccessFlagType::Class, 0x10000) => "VERIFIED".to_string(),
+            (None, AccessFlagType::Class, 0x20000) => "OPTIMIZED".to_string(),
+            (None, AccessFlagType::Method, 0x8000) => "MIRANDA".to_string(),
+            (None, _, _) => "?".to_string(),
+        };
+        names.push(name);
+    }
+
+    names.join(" ")
+}
+
+/// Convert a type descriptor to the name of the type (e.g. `Ljava/lang/String;` to
+/// `java.lang.String`)
+fn descriptor_to_dot(descriptor: &str) -> String {
+    let mut name = descriptor;
+    let mut depth = 0;
+    while name.len() > 1 && let Some(element) = name.strip_prefix('[') {
+        name = element;
+        depth += 1;
+    }
+
+    let mut dotted = match name {
+        "B" => "byte".to_string(),
+        "C" => "char".to_string(),
+        "D" => "double".to_string(),
+        "F" => "float".to_string(),
+        "I" => "int".to_string(),
+        "J" => "long".to_string(),
+        "S" => "short".to_string(),
+        "V" => "void".to_string(),
+        "Z" => "boolean".to_string(),
+        _ if name.len() == 1 => "UNKNOWN".to_string(),
+        _ => name.strip_prefix('L')
+                 .and_then(|name| name.strip_suffix(';'))
+                 .unwrap_or(name)
+                 .replace('/', "."),
+    };
+    dotted.push_str(&"[]".repeat(depth));
+
+    dotted
+}
+
+/// Split a method (`class->name(parameters)return`) into its class, name, and signature
+fn split_method(method: &str) -> (&str, &str, String) {
+    let (class, rest) = method.split_once("->").unwrap_or(("", method));
+    let (name, signature) = rest.find('(').map_or((rest, ""), |idx| rest.split_at(idx));
+
+    (class, name, signature.replace(' ', ""))
+}
+
+/// Get the descriptors of the parameters of a method
+fn parameters(method: &str) -> Vec<&str> {
+    let parameters = method.split_once("->")
+                           .and_then(|(_, rest)| rest.split_once('('))
+                           .and_then(|(_, rest)| rest.split_once(')'))
+                           .map_or("", |(parameters, _)| parameters);
+
+    parameters.split(' ').filter(|parameter| !parameter.is_empty()).collect()
+}
+
+/// Split a field (`class->name:type`) into its class, name, and type
+fn split_field(field: &str) -> (&str, &str, &str) {
+    let (class, rest) = field.split_once("->").unwrap_or(("", field));
+    let (name, type_) = rest.split_once(':').unwrap_or((rest, ""));
+
+    (class, name, type_)
+}
+
+/// Format a floating point value like the `%g` format of `printf`
+fn format_g(value: f64) -> String {
+    if value.is_nan() {
+        return if value.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if value.is_infinite() {
+        return if value < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    if value == 0.0 {
+        return if value.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+
+    let trim = |digits: &str| match digits.contains('.') {
+        true => digits.trim_end_matches('0').trim_end_matches('.').to_string(),
+        false => digits.to_string(),
+    };
+
+    // Six significant digits, the exponent being the one after rounding
+    let scientific = format!("{value:.5e}");
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent = exponent.parse::<i32>().unwrap_or_default();
+    if (-4..6).contains(&exponent) {
+        trim(&format!("{value:.*}", (5 - exponent) as usize))
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{sign}{:02}", trim(mantissa), exponent.unsigned_abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ fake_dex_with_methods, FakeMethod };
+
+    #[test]
+    fn test_dump() {
+        // const/4 v0, #-1; const-string v1, "La;"; invoke-direct {v0, v1}, La;-><init>()V;
+        // if-eqz v0, -3; return-void
+        let raw = fake_dex_with_methods(&["La;"], &[], &[
+            FakeMethod { class: "La;", name: "<init>", access_flags: 0x10001,
+                         code: Some((2, 1, 2, vec![0xf012, 0x011a, 0x0001, 0x2070, 0x0000, 0x0010,
+                                                   0x0038, 0xfffd, 0x000e])) },
+            FakeMethod { class: "La;", name: "run", access_flags: 0x8108, code: None },
+        ]);
+        let dex = DexFile::build(DexReader::build(raw.clone()).unwrap()).unwrap();
+        let output = dump(&DexReader::build(raw.clone()).unwrap(), &dex);
+        let code_off = dex.classes.items[0].get_direct_methods()[0].get_code_off() as usize;
+        let class_data_off = dex.classes.items[0].get_class_data_off();
+
+        let header = output.split("\n\n").next().unwrap();
+        assert!(header.starts_with("DEX file header:\nmagic               : 'dex\\n035\\0'\n"));
+        assert!(header.contains(&format!("file_size           : {}\n", raw.len())));
+        assert!(header.contains("string_ids_off      : 112 (0x000070)\n"));
+        assert!(header.contains("field_ids_size      : 0\n"));
+
+        let expected = format!("\
+Class #0 header:
+class_idx           : 0
+access_flags        : 1 (0x0001)
+superclass_idx      : 65535
+interfaces_off      : 0 (0x000000)
+source_file_idx     : -1
+annotations_off     : 0 (0x000000)
+class_data_off      : {class_data_off} (0x{class_data_off:06x})
+static_fields_size  : 0
+instance_fields_size: 0
+direct_methods_size : 2
+virtual_methods_size: 0
+
+Class #0            -
+  Class descriptor  : 'La;'
+  Access flags      : 0x0001 (PUBLIC)
+  Interfaces        -
+  Static fields     -
+  Instance fields   -
+  Direct methods    -
+    #0              : (in La;)
+      name          : '<init>'
+      type          : '()V'
+      access        : 0x10001 (PUBLIC CONSTRUCTOR)
+      code          -
+      registers     : 2
+      ins           : 1
+      outs          : 2
+      insns size    : 9 16-bit code units
+{code_off:06x}:                                        |[{code_off:06x}] a.<init>:()V
+{:06x}: 12f0                                   |0000: const/4 v0, #int -1 // #ff
+{:06x}: 1a01 0100                              |0001: const-string v1, \"La;\" // string@0001
+{:06x}: 7020 0000 1000                         |0003: invoke-direct {{v0, v1}}, La;.<init>:()V // method@0000
+{:06x}: 3800 fdff                              |0006: if-eqz v0, 0003 // -0003
+{:06x}: 0e00                                   |0008: return-void
+      catches       : (none)
+      positions     :\x20
+      locals        :\x20
+
+    #1              : (in La;)
+      name          : 'run'
+      type          : '()V'
+      access        : 0x8108 (STATIC NATIVE MIRANDA)
+      code          : (none)
+
+  Virtual methods   -
+  source_file_idx   : -1 (unknown)
+
+", code_off + 16, code_off + 18, code_off + 22, code_off + 28, code_off + 32);
+        assert_eq!(&output[header.len() + 2..], expected);
+    }
+
+    #[test]
+    fn test_access_string() {
+        assert_eq!(access_string(0x0011, AccessFlagType::Class), "PUBLIC FINAL");
+        assert_eq!(access_string(0x10401, AccessFlagType::Class), "PUBLIC ABSTRACT VERIFIED");
+        assert_eq!(access_string(0x20022, AccessFlagType::Method), "PRIVATE SYNCHRONIZED DECLARED_SYNCHRONIZED");
+        assert_eq!(access_string(0x4048, AccessFlagType::Field), "STATIC VOLATILE ENUM");
+        assert_eq!(access_string(0x0100, AccessFlagType::Field), "?");
+        assert_eq!(access_string(0, AccessFlagType::Field), "");
+    }
+
+    #[test]
+    fn test_descriptor_to_dot() {
+        assert_eq!(descriptor_to_dot("Ljava/lang/String;"), "java.lang.String");
+        assert_eq!(descriptor_to_dot("La$b;"), "a$b");
+        assert_eq!(descriptor_to_dot("[[I"), "int[][]");
+        assert_eq!(descriptor_to_dot("[La/b;"), "a.b[]");
+        assert_eq!(descriptor_to_dot("["), "UNKNOWN");
+        assert_eq!(descriptor_to_dot("[["), "UNKNOWN[]");
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split_method("La;->f(I Ljava/lang/String;)V"), ("La;", "f", "(ILjava/lang/String;)V".to_string()));
+        assert_eq!(parameters("La;->f(I Ljava/lang/String;)V"), vec!["I", "Ljava/lang/String;"]);
+        assert!(parameters("La;->f()V").is_empty());
+        assert_eq!(split_field("La;->count:I"), ("La;", "count", "I"));
+    }
+
+    #[test]
+    fn test_format_g() {
+        assert_eq!(format_g(1.0), "1");
+        assert_eq!(format_g(0.5), "0.5");
+        assert_eq!(format_g(-2.25), "-2.25");
+        assert_eq!(format_g(1.0 / 3.0), "0.333333");
+        assert_eq!(format_g(123456.0), "123456");
+        assert_eq!(format_g(1234567.0), "1.23457e+06");
+        assert_eq!(format_g(999999.5), "1e+06");
+        assert_eq!(format_g(0.0001), "0.0001");
+        assert_eq!(format_g(0.00001234), "1.234e-05");
+        assert_eq!(format_g(f64::from(f32::from_bits(0x3fc00000))), "1.5");
+        assert_eq!(format_g(f64::NEG_INFINITY), "-inf");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a\"b\\c\n\u{1}é"), "a\\\"b\\\\c\\n\\u0001é");
+        assert_eq!(asciify(b"dex\n035\0"), "dex\\n035\\0");
+    }
+}
@@ -0,0 +1,190 @@
+//! Diagnostics collected while parsing
+//!
+//! Anomalies which do not prevent parsing (invalid access flags, stale checksums, etc.) are
+//! recorded as diagnostics rather than only logged, so that callers can inspect them and decide
+//! what to do with them. They are stored in the `diagnostics` field of `DexFile`.
+
+use std::fmt;
+
+use crate::dex::map::MapItemType;
+
+/// Severity of a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Unusual but valid
+    Info,
+    /// Invalid, but the parser could work around it
+    Warning,
+    /// Invalid, and the parser could not go on
+    Error,
+}
+
+/// Kind of anomaly
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiagnosticCode {
+    /// The checksum of the header does not match the contents
+    InvalidChecksum,
+    /// The SHA-1 signature of the header does not match the contents
+    InvalidSignature,
+    /// Access flags contain bits which are not valid for the item
+    InvalidAccessFlags,
+    /// Bytecode uses an unused opcode
+    UnusedOpcode,
+    /// The name of a method cannot be retrieved from its prototype
+    InvalidMethodName,
+    /// The bytecode of a method cannot be decoded and was kept as raw code units
+    UndecodedCode,
+    /// A method cannot be parsed and was skipped
+    SkippedMethod,
+    /// A class cannot be parsed and was skipped
+    SkippedClass,
+    /// The header is inconsistent with the file
+    InvalidHeader,
+    /// The map list is invalid or inconsistent with the header
+    InvalidMapList,
+    /// A section or item is out of the file or of the data section
+    SectionOutOfBounds,
+    /// A section is not properly aligned
+    MisalignedSection,
+    /// An ID list is not sorted or contains duplicates
+    UnsortedIds,
+    /// An index or offset references a missing item
+    InvalidIndex,
+    /// Class data is malformed
+    InvalidClassData,
+    /// A code item is malformed
+    InvalidCodeItem,
+    /// A branch or switch target is outside of the method or inside an instruction
+    InvalidBranchTarget,
+    /// A register is out of the frame of the method or wide registers are inconsistent
+    InvalidRegister,
+    /// A payload is missing, misaligned, or referenced by the wrong instruction
+    InvalidPayload,
+    /// An instruction cannot appear at this point of the control flow
+    InvalidControlFlow,
+    /// A register does not hold a value of the type expected by an instruction
+    RegisterTypeMismatch,
+    /// An uninitialized instance is used before its constructor is called
+    UninitializedRegister,
+    /// A code item is used by several methods
+    SharedCodeItem,
+    /// Two code items overlap
+    OverlappingCodeItems,
+    /// A LEB128 value is encoded with more bytes than needed
+    OverlongLeb128,
+    /// Class data points into the header
+    ClassDataInHeader,
+    /// A class is defined more than once
+    DuplicateClass,
+    /// Access flags are valid on their own but cannot be combined
+    ConflictingAccessFlags,
+    /// Bytes follow the map list and the data section
+    TrailingData,
+    /// A payload is never referenced, or is reached by the execution flow
+    FakePayload,
+    /// The debug information of a method is malformed and was ignored
+    InvalidDebugInfo,
+    /// The initial values of the static fields of a class are malformed
+    InvalidStaticValues,
+    /// An annotation or a list of annotations is malformed
+    InvalidAnnotation,
+}
+
+/// An anomaly found while parsing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Severity of the anomaly
+    pub severity: Severity,
+    /// Kind of anomaly
+    pub code: DiagnosticCode,
+    /// Index of the DEX file, when several were merged
+    pub dex_index: usize,
+    /// Offset of the anomaly in the DEX file
+    pub offset: u64,
+    /// Section containing the anomaly, if known
+    pub section: Option<MapItemType>,
+    /// Class containing the anomaly, if any
+    pub class: Option<String>,
+    /// Method containing the anomaly, if any
+    pub method: Option<String>,
+    /// Human-readable description of the anomaly
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Create a new diagnostic, not attached to any class or method
+    pub fn new(severity: Severity,
+               code: DiagnosticCode,
+               offset: u64,
+               section: Option<MapItemType>,
+               message: String) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            dex_index: 0,
+            offset,
+            section,
+            class: None,
+            method: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} [{:?}] at 0x{:x}", self.severity, self.code, self.offset)?;
+        if let Some(section) = &self.section {
+            write!(f, " in {section:?}")?;
+        }
+        if let Some(method) = &self.method {
+            write!(f, " ({method})")?;
+        } else if let Some(class) = &self.class {
+            write!(f, " ({class})")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytes::read_u32_at;
+    use crate::dex::file::DexFile;
+    use crate::fixtures::fake_dex;
+    use crate::dex::integrity::{ fix_integrity, IntegrityPolicy };
+    use crate::dex::options::ParseOptions;
+    use crate::dex::reader::DexReader;
+
+    #[test]
+    fn test_diagnostics() {
+        let mut raw = fake_dex(&["La;", "Lb;"], &[]);
+
+        // Volatile flag (invalid for classes) on the second class
+        let class_defs_off = read_u32_at(&raw, 0x64).unwrap() as usize;
+        let flags_offset = class_defs_off + 32 + 4;
+        raw[flags_offset..flags_offset + 4].copy_from_slice(&0x41u32.to_le_bytes());
+        fix_integrity(&mut raw).unwrap();
+        raw[8] ^= 0xff;
+
+        let options = ParseOptions { checksum: IntegrityPolicy::Warn, signature: IntegrityPolicy::Strict, ..Default::default() };
+        let dex = DexFile::build_with_options(DexReader::build(raw).unwrap(), &options).unwrap();
+        assert_eq!(dex.diagnostics.len(), 2);
+
+        let checksum = &dex.diagnostics[0];
+        assert_eq!(checksum.code, DiagnosticCode::InvalidChecksum);
+        assert_eq!(checksum.section, Some(MapItemType::HeaderItem));
+
+        let flags = &dex.diagnostics[1];
+        assert_eq!(flags.code, DiagnosticCode::InvalidAccessFlags);
+        assert_eq!(flags.severity, Severity::Warning);
+        assert_eq!(flags.offset, flags_offset as u64);
+        assert_eq!(flags.class.as_deref(), Some("Lb;"));
+        assert!(flags.method.is_none());
+        assert_eq!(flags.to_string(),
+                   format!("Warning [InvalidAccessFlags] at 0x{flags_offset:x} in ClassDefItem (Lb;): ignoring invalid flags for class: 0x40"));
+
+        assert_eq!(dex.get_diagnostics(Severity::Warning).len(), 2);
+        assert!(dex.get_diagnostics(Severity::Error).is_empty());
+    }
+}
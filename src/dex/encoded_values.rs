@@ -0,0 +1,297 @@
+//! Encoded values
+//!
+//! Constant values embedded in a DEX file (initial values of static fields, annotation elements,
+//! arguments of call sites) use a compact variable-length encoding: a byte giving the type of the
+//! value and the number of bytes that follow, then the value itself.
+
+use crate::dex::reader::DexReader;
+use crate::dex::strings::DexStrings;
+use crate::dex::types::DexTypes;
+use crate::dex::fields::DexFields;
+use crate::dex::methods::DexMethods;
+use crate::error::DexError;
+
+/// Value types
+const VALUE_BYTE: u8 = 0x00;
+const VALUE_SHORT: u8 = 0x02;
+const VALUE_CHAR: u8 = 0x03;
+const VALUE_INT: u8 = 0x04;
+const VALUE_LONG: u8 = 0x06;
+const VALUE_FLOAT: u8 = 0x10;
+const VALUE_DOUBLE: u8 = 0x11;
+const VALUE_METHOD_TYPE: u8 = 0x15;
+const VALUE_METHOD_HANDLE: u8 = 0x16;
+const VALUE_STRING: u8 = 0x17;
+const VALUE_TYPE: u8 = 0x18;
+const VALUE_FIELD: u8 = 0x19;
+const VALUE_METHOD: u8 = 0x1a;
+const VALUE_ENUM: u8 = 0x1b;
+const VALUE_ARRAY: u8 = 0x1c;
+const VALUE_ANNOTATION: u8 = 0x1d;
+const VALUE_NULL: u8 = 0x1e;
+const VALUE_BOOLEAN: u8 = 0x1f;
+
+/// An annotation embedded in an encoded value
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedAnnotation {
+    /// Type of the annotation
+    pub type_: String,
+    /// Elements of the annotation, as (name, value) pairs
+    pub elements: Vec<(String, EncodedValue)>,
+}
+
+/// A decoded constant value
+#[derive(Debug, Clone, PartialEq)]
+pub enum EncodedValue {
+    Byte(i8),
+    Short(i16),
+    Char(u16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    /// Index into the list of prototypes
+    MethodType(u32),
+    /// Index into the list of method handles
+    MethodHandle(u32),
+    String(String),
+    Type(String),
+    Field(String),
+    Method(String),
+    Enum(String),
+    Array(Vec<EncodedValue>),
+    Annotation(EncodedAnnotation),
+    Null,
+    Boolean(bool),
+}
+
+/// Lists used to decode the indices found in encoded values
+pub struct EncodedValueContext<'a> {
+    pub strings: &'a DexStrings,
+    pub types: &'a DexTypes,
+    pub fields: &'a DexFields,
+    pub methods: &'a DexMethods,
+}
+
+impl EncodedValue {
+    /// Read an `encoded_array` from the reader
+    pub fn read_array(dex_reader: &mut DexReader,
+                      context: &EncodedValueContext) -> Result<Vec<Self>, DexError> {
+        EncodedValue::read_array_at_depth(dex_reader, context, 0)
+    }
+
+    /// Read an `encoded_value` from the reader
+    pub fn read(dex_reader: &mut DexReader,
+                context: &EncodedValueContext) -> Result<Self, DexError> {
+        EncodedValue::read_at_depth(dex_reader, context, 0)
+    }
+
+    /// Get the value as an integer, if it is one
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            EncodedValue::Byte(value)  => Some(*value as i64),
+            EncodedValue::Short(value) => Some(*value as i64),
+            EncodedValue::Char(value)  => Some(*value as i64),
+            EncodedValue::Int(value)   => Some(*value as i64),
+            EncodedValue::Long(value)  => Some(*value),
+            _ => None
+        }
+    }
+
+    fn read_array_at_depth(dex_reader: &mut DexReader,
+                           context: &EncodedValueContext,
+                           depth: usize) -> Result<Vec<Self>, DexError> {
+        let (size, _) = dex_reader.read_uleb128()?;
+
+        // Each value takes at least one byte
+        let mut values = Vec::with_capacity(dex_reader.check_count(size, 1)?);
+        for _ in 0..size {
+            values.push(EncodedValue::read_at_depth(dex_reader, context, depth + 1)?);
+        }
+
+        Ok(values)
+    }
+
+    fn read_annotation_at_depth(dex_reader: &mut DexReader,
+                                context: &EncodedValueContext,
+                                depth: usize) -> Result<EncodedAnnotation, DexError> {
+        let (type_idx, _) = dex_reader.read_uleb128()?;
+        let (size, _) = dex_reader.read_uleb128()?;
+
+        let mut elements = Vec::new();
+        for _ in 0..size {
+            let (name_idx, _) = dex_reader.read_uleb128()?;
+            let name = context.strings.strings.get(name_idx as usize)
+                                              .ok_or(DexError::InvalidStringIdx)?;
+            dex_reader.charge(name.len())?;
+            let value = EncodedValue::read_at_depth(dex_reader, context, depth + 1)?;
+            elements.push((name.to_string(), value));
+        }
+
+        Ok(EncodedAnnotation {
+            type_: context.types.items.get(type_idx as usize)
+                                      .ok_or(DexError::InvalidTypeIdx)?
+                                      .to_string(),
+            elements,
+        })
+    }
+
+    fn read_at_depth(dex_reader: &mut DexReader,
+                     context: &EncodedValueContext,
+                     depth: usize) -> Result<Self, DexError> {
+        if depth > dex_reader.limits.max_nesting_depth {
+            return Err(DexError::InvalidEncodedValue);
+        }
+
+        let header = dex_reader.read_u8()?;
+        let value_type = header & 0x1f;
+        let value_arg = (header >> 5) as usize;
+
+        let value = match value_type {
+            VALUE_BYTE   => EncodedValue::Byte(dex_reader.read_signed(value_arg, 1)? as i8),
+            VALUE_SHORT  => EncodedValue::Short(dex_reader.read_signed(value_arg, 2)? as i16),
+            VALUE_CHAR   => EncodedValue::Char(dex_reader.read_unsigned(value_arg, 2)? as u16),
+            VALUE_INT    => EncodedValue::Int(dex_reader.read_signed(value_arg, 4)? as i32),
+            VALUE_LONG   => EncodedValue::Long(dex_reader.read_signed(value_arg, 8)?),
+            VALUE_FLOAT  => {
+                let bits = dex_reader.read_right_zero_extended(value_arg, 4)?;
+                EncodedValue::Float(f32::from_bits((bits >> 32) as u32))
+            },
+            VALUE_DOUBLE => {
+                let bits = dex_reader.read_right_zero_extended(value_arg, 8)?;
+                EncodedValue::Double(f64::from_bits(bits))
+            },
+            VALUE_METHOD_TYPE   => EncodedValue::MethodType(dex_reader.read_unsigned(value_arg, 4)? as u32),
+            VALUE_METHOD_HANDLE => EncodedValue::MethodHandle(dex_reader.read_unsigned(value_arg, 4)? as u32),
+            VALUE_STRING => {
+                let idx = dex_reader.read_unsigned(value_arg, 4)? as usize;
+                EncodedValue::String(context.strings.strings.get(idx)
+                                                            .ok_or(DexError::InvalidStringIdx)?
+                                                            .to_string())
+            },
+            VALUE_TYPE => {
+                let idx = dex_reader.read_unsigned(value_arg, 4)? as usize;
+                EncodedValue::Type(context.types.items.get(idx)
+                                                      .ok_or(DexError::InvalidTypeIdx)?
+                                                      .to_string())
+            },
+            VALUE_FIELD | VALUE_ENUM => {
+                let idx = dex_reader.read_unsigned(value_arg, 4)? as usize;
+                let field = context.fields.items.get(idx)
+                                                .ok_or(DexError::InvalidFieldIdx)?
+                                                .to_string();
+                match value_type {
+                    VALUE_FIELD => EncodedValue::Field(field),
+                    _ => EncodedValue::Enum(field),
+                }
+            },
+            VALUE_METHOD => {
+                let idx = dex_reader.read_unsigned(value_arg, 4)? as usize;
+                EncodedValue::Method(context.methods.items.get(idx)
+                                                          .ok_or(DexError::InvalidMethodIdx)?
+                                                          .to_string())
+            },
+            VALUE_ARRAY => EncodedValue::Array(EncodedValue::read_array_at_depth(dex_reader, context, depth)?),
+            VALUE_ANNOTATION => EncodedValue::Annotation(EncodedValue::read_annotation_at_depth(dex_reader, context, depth)?),
+            VALUE_NULL => EncodedValue::Null,
+            VALUE_BOOLEAN => EncodedValue::Boolean(value_arg != 0),
+            _ => return Err(DexError::InvalidEncodedValue),
+        };
+
+        if let EncodedValue::String(decoded) | EncodedValue::Type(decoded) | EncodedValue::Field(decoded)
+               | EncodedValue::Method(decoded) | EncodedValue::Enum(decoded) = &value {
+            dex_reader.charge(decoded.len())?;
+        }
+
+        Ok(value)
+    }
+}
+
+impl DexReader<'_> {
+    /// Read the `value_arg + 1` bytes of an encoded value, little-endian
+    fn read_encoded_bytes(&mut self, value_arg: usize, max_size: usize) -> Result<(u64, usize), DexError> {
+        let size = value_arg + 1;
+        if size > max_size {
+            return Err(DexError::InvalidEncodedValue);
+        }
+
+        let mut value = 0u64;
+        for idx in 0..size {
+            value |= (self.read_u8()? as u64) << (8 * idx);
+        }
+
+        Ok((value, size))
+    }
+
+    /// Read a sign-extended encoded value
+    fn read_signed(&mut self, value_arg: usize, max_size: usize) -> Result<i64, DexError> {
+        let (value, size) = self.read_encoded_bytes(value_arg, max_size)?;
+        let shift = 64 - 8 * size;
+        Ok(((value << shift) as i64) >> shift)
+    }
+
+    /// Read a zero-extended encoded value
+    fn read_unsigned(&mut self, value_arg: usize, max_size: usize) -> Result<u64, DexError> {
+        Ok(self.read_encoded_bytes(value_arg, max_size)?.0)
+    }
+
+    /// Read an encoded value zero-extended to the right, i.e., the bytes read are the most
+    /// significant ones of a 64 bits value
+    fn read_right_zero_extended(&mut self, value_arg: usize, max_size: usize) -> Result<u64, DexError> {
+        let (value, size) = self.read_encoded_bytes(value_arg, max_size)?;
+        Ok(value << (64 - 8 * size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_values(bytes: &[u8]) -> Result<Vec<EncodedValue>, DexError> {
+        let mut raw = vec![0u8; 0x70];
+        raw[40..44].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        raw.extend_from_slice(bytes);
+
+        let mut reader = DexReader::build(raw).unwrap();
+        reader.bytes.set_position(0x70);
+
+        let context = EncodedValueContext {
+            strings: &DexStrings { strings: vec!["foo".to_string()] },
+            types: &DexTypes { items: vec!["I".to_string()] },
+            fields: &DexFields { items: Vec::new() },
+            methods: &DexMethods { items: Vec::new() },
+        };
+        EncodedValue::read_array(&mut reader, &context)
+    }
+
+    #[test]
+    fn test_read_array() {
+        let values = read_values(&[
+            0x07,
+            0x00, 0xff,                     // byte -1
+            0x44, 0x01, 0x00, 0x7f,         // int 0x7f0001 on 3 bytes
+            0x17, 0x00,                     // string "foo"
+            0x30, 0x80, 0x3f,               // float 1.0 on 2 bytes
+            0x3f,                           // boolean true
+            0x1e,                           // null
+            0x1c, 0x01, 0x02, 0x80,         // array of one short -128
+        ]).unwrap();
+
+        assert_eq!(values, vec![EncodedValue::Byte(-1),
+                                EncodedValue::Int(0x7f0001),
+                                EncodedValue::String("foo".to_string()),
+                                EncodedValue::Float(1.0),
+                                EncodedValue::Boolean(true),
+                                EncodedValue::Null,
+                                EncodedValue::Array(vec![EncodedValue::Short(-128)])]);
+        assert_eq!(values[1].as_int(), Some(0x7f0001));
+    }
+
+    #[test]
+    fn test_read_invalid() {
+        // Int on 5 bytes, unknown type, and string index out of bounds
+        assert!(read_values(&[0x01, 0x84, 0, 0, 0, 0, 0]).is_err());
+        assert!(read_values(&[0x01, 0x05]).is_err());
+        assert!(read_values(&[0x01, 0x17, 0x01]).is_err());
+    }
+}
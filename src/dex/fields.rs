@@ -8,7 +8,7 @@
 use std::io::{Seek, SeekFrom};
 use std::cmp::Ordering;
 
-use crate::error::DexError;
+use crate::error::{ DexError, ErrorContext };
 use crate::dex::reader::DexReader;
 use crate::dex::types::DexTypes;
 use crate::dex::strings::DexStrings;
@@ -66,7 +66,7 @@ impl DexFields {
                  strings_list: &DexStrings) -> Result<Self, DexError> {
         dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
 
-        let mut fields = Vec::new();
+        let mut fields = Vec::with_capacity(dex_reader.check_count(size, 8)?);
 
         for _ in 0..size {
             let class_idx = dex_reader.read_u16()?;
@@ -74,11 +74,12 @@ impl DexFields {
             let name_idx = dex_reader.read_u32()?;
 
             let mut decoded = String::new();
-            decoded.push_str(types_list.items.get(class_idx as usize).ok_or(DexError::InvalidTypeIdx)?);
+            decoded.push_str(types_list.items.get(class_idx as usize).ok_or(DexError::InvalidTypeIdx).at_index(class_idx.into())?);
             decoded.push_str("->");
-            decoded.push_str(strings_list.strings.get(name_idx as usize).ok_or(DexError::InvalidStringIdx)?);
+            decoded.push_str(strings_list.strings.get(name_idx as usize).ok_or(DexError::InvalidStringIdx).at_index(name_idx.into())?);
             decoded.push(':');
-            decoded.push_str(types_list.items.get(type_idx as usize).ok_or(DexError::InvalidTypeIdx)?);
+            decoded.push_str(types_list.items.get(type_idx as usize).ok_or(DexError::InvalidTypeIdx).at_index(type_idx.into())?);
+            dex_reader.charge(decoded.len())?;
 
             fields.push(FieldIdItem {
                 class_idx,
@@ -12,13 +12,16 @@ use log::info;
 
 use crate::dex::reader::DexReader;
 use crate::dex::header::DexHeader;
+use crate::dex::options::ParseOptions;
+use crate::dex::diagnostics::{ Diagnostic, Severity };
 use crate::dex::strings::DexStrings;
 use crate::dex::types::DexTypes;
 use crate::dex::protos::DexProtos;
 use crate::dex::fields::DexFields;
 use crate::dex::methods::DexMethods;
 use crate::dex::classes::{ DexClasses, ClassDefItem, EncodedMethod };
-use crate::error::DexError;
+use crate::dex::encoded_values::EncodedValueContext;
+use crate::error::{ DexError, ErrorContext, ErrorFrame };
 
 /// Representation of a DEX file
 #[derive(Debug)]
@@ -37,47 +40,81 @@ pub struct DexFile {
     pub methods: DexMethods,
     /// List of classes defined in the DEX file
     pub classes: DexClasses,
+    /// Anomalies found while parsing the DEX file(s)
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl DexFile {
     /// Parse a DEX file from the reader and create a `DexFile` object
-    pub fn build(mut dex_reader: DexReader) -> Result<Self, DexError> {
-        let dex_header = DexHeader::new(&mut dex_reader)?;
+    pub fn build(dex_reader: DexReader) -> Result<Self, DexError> {
+        DexFile::build_with_options(dex_reader, &ParseOptions::default())
+    }
+
+    /// Parse a DEX file from the reader with the given options and create a `DexFile` object
+    pub fn build_with_options(mut dex_reader: DexReader, options: &ParseOptions) -> Result<Self, DexError> {
+        let mut dex_file = DexFile::build_from_reader(&mut dex_reader, options, true)?;
+        dex_file.diagnostics = dex_reader.diagnostics;
+
+        Ok(dex_file)
+    }
 
-        let strings_list = DexStrings::build(&mut dex_reader,
+    /// Parse a DEX file from the reader, decoding the class data and code items only if
+    /// `decode_class_data` is set
+    ///
+    /// The diagnostics are left in the reader.
+    pub(crate) fn build_from_reader(dex_reader: &mut DexReader,
+                                    options: &ParseOptions,
+                                    decode_class_data: bool) -> Result<Self, DexError> {
+        dex_reader.recover = options.recover;
+        dex_reader.limits = options.limits.clone();
+        if dex_reader.bytes_len > options.limits.max_file_size {
+            return Err(DexError::ResourceLimitExceeded);
+        }
+        let dex_header = DexHeader::new_with_options(dex_reader, options)?;
+
+        let strings_list = DexStrings::build(dex_reader,
                                              dex_header.string_ids_off,
-                                             dex_header.string_ids_size)?;
+                                             dex_header.string_ids_size)
+                           .in_frame(ErrorFrame::at("string_ids", dex_header.string_ids_off.into()))?;
 
-        let type_ids_list = DexTypes::build(&mut dex_reader,
+        let type_ids_list = DexTypes::build(dex_reader,
                                             dex_header.type_ids_off,
                                             dex_header.type_ids_size,
-                                            &strings_list)?;
+                                            &strings_list)
+                            .in_frame(ErrorFrame::at("type_ids", dex_header.type_ids_off.into()))?;
 
-        let proto_ids_list = DexProtos::build(&mut dex_reader,
+        let proto_ids_list = DexProtos::build(dex_reader,
                                               dex_header.proto_ids_off,
                                               dex_header.proto_ids_size,
-                                              &type_ids_list)?;
+                                              &type_ids_list)
+                             .in_frame(ErrorFrame::at("proto_ids", dex_header.proto_ids_off.into()))?;
 
-        let field_ids_list = DexFields::build(&mut dex_reader,
+        let field_ids_list = DexFields::build(dex_reader,
                                               dex_header.fields_ids_off,
                                               dex_header.fields_ids_size,
                                               &type_ids_list,
-                                              &strings_list)?;
+                                              &strings_list)
+                             .in_frame(ErrorFrame::at("field_ids", dex_header.fields_ids_off.into()))?;
 
-        let method_ids_list = DexMethods::build(&mut dex_reader,
+        let method_ids_list = DexMethods::build(dex_reader,
                                                 dex_header.method_ids_off,
                                                 dex_header.method_ids_size,
                                                 &type_ids_list,
                                                 &proto_ids_list,
-                                                &strings_list)?;
+                                                &strings_list)
+                              .in_frame(ErrorFrame::at("method_ids", dex_header.method_ids_off.into()))?;
 
-        let class_defs_list = DexClasses::build(&mut dex_reader,
-                                                dex_header.class_defs_off,
-                                                dex_header.class_defs_size,
-                                                &field_ids_list,
-                                                &type_ids_list,
-                                                &strings_list,
-                                                &method_ids_list)?;
+        let context = EncodedValueContext {
+            strings: &strings_list,
+            types: &type_ids_list,
+            fields: &field_ids_list,
+            methods: &method_ids_list,
+        };
+        let class_defs_list = DexClasses::build_with_context(dex_reader,
+                                                             dex_header.class_defs_off,
+                                                             dex_header.class_defs_size,
+                                                             &context,
+                                                             decode_class_data)?;
 
         Ok(DexFile {
             header: dex_header,
@@ -87,6 +124,7 @@ impl DexFile {
             fields: field_ids_list,
             methods: method_ids_list,
             classes: class_defs_list,
+            diagnostics: Vec::new(),
         })
     }
 
@@ -95,16 +133,27 @@ impl DexFile {
     /// This function will create an intermediary `DexFile` object for each reader and then merge
     /// them into the final `DexFile`.
     pub fn merge(readers: Vec<DexReader>) -> Result<Self, DexError> {
+        DexFile::merge_with_options(readers, &ParseOptions::default())
+    }
+
+    /// Create a `DexFile` from a collection of `DexReader`, parsed with the given options
+    pub fn merge_with_options(readers: Vec<DexReader>, options: &ParseOptions) -> Result<Self, DexError> {
         let mut strings_list = Vec::new();
         let mut type_ids_list = Vec::new();
         let mut proto_ids_list = Vec::new();
         let mut field_ids_list = Vec::new();
         let mut method_ids_list = Vec::new();
         let mut class_defs_list = Vec::new();
+        let mut diagnostics = Vec::new();
 
         info!("start merging DEX files");
-        for reader in readers.into_iter() {
-            let current_dex_file = DexFile::build(reader)?;
+        for (dex_index, reader) in readers.into_iter().enumerate() {
+            let current_dex_file = DexFile::build_with_options(reader, options)?;
+
+            for mut diagnostic in current_dex_file.diagnostics.into_iter() {
+                diagnostic.dex_index = dex_index;
+                diagnostics.push(diagnostic);
+            }
 
             info!("  merging strings");
             for string in current_dex_file.strings.strings.into_iter() {
@@ -191,9 +240,17 @@ impl DexFile {
             fields: DexFields { items: field_ids_list },
             methods: DexMethods { items: method_ids_list },
             classes: DexClasses { items: class_defs_list },
+            diagnostics,
         })
     }
 
+    /// Get the diagnostics with at least the given severity
+    pub fn get_diagnostics(&self, min_severity: Severity) -> Vec<&Diagnostic> {
+        self.diagnostics.iter()
+                        .filter(|diagnostic| diagnostic.severity >= min_severity)
+                        .collect()
+    }
+
     /// Returns a vector containing the names of all the classes defined in the DEX file
     pub fn get_classes_names(&self) -> Vec<&String> {
         let mut class_names = Vec::new();
@@ -219,3 +276,114 @@ impl DexFile {
         Vec::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ fake_code_dex, fake_dex_file };
+
+    #[test]
+    fn test_build() {
+        let dex = fake_dex_file(&["Lb/B;", "La/A;"], &["Ljava/lang/Object;"]);
+        assert_eq!(dex.get_classes_names().len(), 2);
+        assert!(dex.get_class_def(&"La/A;".to_string()).is_some());
+        assert_eq!(dex.types.items, vec!["La/A;", "Lb/B;", "Ljava/lang/Object;"]);
+    }
+
+    #[test]
+    fn test_build_hostile_input() {
+        use crate::dex::{ code_verifier, dexdump, verifier };
+        use crate::dex::integrity::IntegrityPolicy;
+        use crate::dex::layout::DexLayout;
+        use crate::dex::options::ResourceLimits;
+
+        let limits = ResourceLimits { max_decoded_size: 1 << 16, ..Default::default() };
+        let parse = |raw: &[u8]| {
+            for recover in [false, true] {
+                let options = ParseOptions {
+                    checksum: IntegrityPolicy::Skip,
+                    signature: IntegrityPolicy::Skip,
+                    recover,
+                    limits: limits.clone(),
+                };
+                if let Ok(reader) = DexReader::build(raw.to_vec())
+                   && let Ok(dex) = DexFile::build_with_options(reader, &options) {
+                    code_verifier::verify_code(&dex);
+                    dexdump::dump(&DexReader::build(raw.to_vec()).unwrap(), &dex);
+                }
+            }
+            verifier::verify(raw);
+            if let Ok(layout) = DexLayout::build(raw) {
+                layout.render(raw);
+            }
+        };
+
+        let raw = fake_code_dex();
+        for len in 0..raw.len() {
+            parse(&raw[..len]);
+        }
+
+        // Reproducible pseudo-random corruptions (xorshift)
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..2000 {
+            let mut mutated = raw.clone();
+            for _ in 0..4 {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                mutated[(seed % raw.len() as u64) as usize] = (seed >> 32) as u8;
+            }
+            parse(&mutated);
+        }
+
+        // Sizes overflowing 32-bit arithmetic
+        let dex = DexFile::build(DexReader::build(raw.clone()).unwrap()).unwrap();
+        let class_def = dex.get_class_def(&"La;".to_string()).unwrap();
+        let class_data_off = class_def.get_class_data_off() as usize;
+        let code_off = class_def.get_direct_methods()[0].get_code_off() as usize;
+
+        let mut mutated = raw.clone();
+        for size_off in [class_data_off, class_data_off + 5] {
+            mutated[size_off..size_off + 5].copy_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x0f]);
+        }
+        parse(&mutated);
+
+        // One try block, with a handler of i32::MIN catch types
+        let mut mutated = raw;
+        mutated[code_off + 6..code_off + 8].copy_from_slice(&1u16.to_le_bytes());
+        let insns_size = u32::from_le_bytes(mutated[code_off + 12..code_off + 16].try_into().unwrap()) as usize;
+        let handlers_off = (code_off + 16 + 2 * insns_size).next_multiple_of(4) + 8;
+        mutated[handlers_off..handlers_off + 6].copy_from_slice(&[0x01, 0x80, 0x80, 0x80, 0x80, 0x78]);
+        parse(&mutated);
+    }
+
+    #[test]
+    fn test_build_resource_limits() {
+        use crate::dex::integrity::fix_integrity;
+        use crate::dex::options::ResourceLimits;
+
+        let build = |raw: Vec<u8>, limits: ResourceLimits| {
+            let options = ParseOptions { recover: true, limits, ..Default::default() };
+            DexFile::build_with_options(DexReader::build(raw).unwrap(), &options)
+        };
+        let is_limit = |result: Result<DexFile, DexError>| matches!(result.unwrap_err().root(), DexError::ResourceLimitExceeded);
+
+        let raw = fake_code_dex();
+        assert!(build(raw.clone(), ResourceLimits::default()).is_ok());
+        assert!(is_limit(build(raw.clone(), ResourceLimits { max_code_units: 8, ..Default::default() })));
+        assert!(is_limit(build(raw.clone(), ResourceLimits { max_decoded_size: 16, ..Default::default() })));
+        assert!(is_limit(build(raw.clone(), ResourceLimits { max_file_size: 16, ..Default::default() })));
+
+        // Huge counts fail before anything is allocated
+        let mut huge = raw.clone();
+        huge[0x38..0x3c].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        fix_integrity(&mut huge).unwrap();
+        assert!(is_limit(build(huge, ResourceLimits::default())));
+
+        let mut huge = raw;
+        huge[0x38..0x3c].copy_from_slice(&0x0010_0000u32.to_le_bytes());
+        fix_integrity(&mut huge).unwrap();
+        let err = build(huge, ResourceLimits::default()).unwrap_err();
+        assert!(matches!(err.root(), DexError::NoDataLeftError));
+    }
+}
@@ -3,13 +3,60 @@
 //! This module contains the representation of the header of a DEX file.
 //! The main use of this module is to load and parse each DEX file. When
 //! parsing a DEX file, the module will also verify the Adler32 checksum
-//! contained in the header.
+//! and the SHA-1 signature contained in the header, according to the
+//! integrity policies of the parse options.
 
 use std::io::Read;
 
 use crate::error::DexError;
 use crate::adler32;
 use crate::dex::reader::DexReader;
+use crate::dex::integrity::verify_signature;
+use crate::dex::options::ParseOptions;
+use crate::dex::diagnostics::{ Diagnostic, DiagnosticCode, Severity };
+use crate::dex::map::MapItemType;
+
+/// Magic bytes of a standard DEX file (the version follows)
+pub const DEX_FILE_MAGIC: [u8; 4] = *b"dex\n";
+/// Magic bytes of a compact DEX file (the version follows)
+pub const CDEX_FILE_MAGIC: [u8; 4] = *b"cdex";
+/// Size of the header of a DEX file
+pub const HEADER_SIZE: usize = 0x70;
+/// Endianness tag of little-endian DEX files
+pub const ENDIAN_CONSTANT: u32 = 0x12345678;
+
+/// Kind of DEX file, identified from its magic bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DexKind {
+    /// Standard DEX file
+    Dex,
+    /// Compact DEX file, as produced by ART for its VDEX files
+    CompactDex,
+}
+
+impl DexKind {
+    /// Identify the kind of DEX file from its first bytes
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        match bytes.get(0..4)? {
+            magic if magic == DEX_FILE_MAGIC => Some(DexKind::Dex),
+            magic if magic == CDEX_FILE_MAGIC => Some(DexKind::CompactDex),
+            _ => None
+        }
+    }
+}
+
+/// Get the DEX file starting at `offset` in a buffer, if there is one
+///
+/// The size of the DEX file is read from its header, so this can be used to extract DEX files
+/// embedded in larger containers.
+pub(crate) fn dex_slice_at(raw: &[u8], offset: usize) -> Option<(DexKind, &[u8])> {
+    let kind = DexKind::from_magic(raw.get(offset..)?)?;
+    let size_bytes = raw.get(offset + 32..offset + 36)?;
+    let file_size = u32::from_le_bytes([size_bytes[0], size_bytes[1], size_bytes[2], size_bytes[3]]);
+    let bytes = raw.get(offset..offset.checked_add(file_size as usize)?)?;
+
+    Some((kind, bytes))
+}
 
 /// Representation of the header of a DEX file
 #[derive(Debug)]
@@ -42,6 +89,12 @@ pub struct DexHeader {
 impl DexHeader {
     /// Reads from the given cursor and builds a `DexHeader`
     pub fn new(dex_cursor: &mut DexReader) -> Result<DexHeader, DexError> {
+        DexHeader::new_with_options(dex_cursor, &ParseOptions::default())
+    }
+
+    /// Reads from the given cursor and builds a `DexHeader`, checking its integrity according to
+    /// the given options
+    pub fn new_with_options(dex_cursor: &mut DexReader, options: &ParseOptions) -> Result<DexHeader, DexError> {
         // DEX version
         let mut magic = [0; 8];
         dex_cursor.bytes.read_exact(&mut magic)?;
@@ -51,10 +104,23 @@ impl DexHeader {
         version[2] = magic[6];
 
         let checksum = dex_cursor.read_u32()?;
-        adler32::verify_from_bytes(&dex_cursor.bytes, checksum)?;
+        if let Some(err) = options.checksum.check(|| adler32::verify_from_bytes(&dex_cursor.bytes, checksum))? {
+            dex_cursor.diagnostics.push(Diagnostic::new(Severity::Warning,
+                                                        DiagnosticCode::InvalidChecksum,
+                                                        8,
+                                                        Some(MapItemType::HeaderItem),
+                                                        err.to_string()));
+        }
 
         let mut signature = [0; 20];
         dex_cursor.bytes.read_exact(&mut signature)?;
+        if let Some(err) = options.signature.check(|| verify_signature(dex_cursor.bytes.get_ref(), &signature))? {
+            dex_cursor.diagnostics.push(Diagnostic::new(Severity::Warning,
+                                                        DiagnosticCode::InvalidSignature,
+                                                        12,
+                                                        Some(MapItemType::HeaderItem),
+                                                        err.to_string()));
+        }
 
         let file_size = dex_cursor.read_u32()?;
         let header_size = dex_cursor.read_u32()?;
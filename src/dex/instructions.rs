@@ -4,8 +4,12 @@
 //! special instructions such as `PackedSwitch` which have their payload
 //! at the end of the `CodeItem` of a method.
 
+use std::io::Read;
+
 use crate::dex::opcodes::OpCode;
 use crate::dex::reader::DexReader;
+use crate::dex::diagnostics::{ Diagnostic, DiagnosticCode, Severity };
+use crate::dex::map::MapItemType;
 use crate::error::DexError;
 
 /// `Instruction10t` instruction type
@@ -86,6 +90,24 @@ pub struct Instruction4rcc { opcode: OpCode, length: usize, bytes: [u16; 4] }
 /// `Instruction51l` instruction type
 #[derive(Debug, Clone)]
 pub struct Instruction51l  { opcode: OpCode, length: usize, bytes: [u16; 5] }
+/// `Instruction20bc` instruction type (ODEX only)
+#[derive(Debug, Clone)]
+pub struct Instruction20bc { opcode: OpCode, length: usize, bytes: [u16; 2] }
+/// `Instruction22cs` instruction type (ODEX only)
+#[derive(Debug, Clone)]
+pub struct Instruction22cs { opcode: OpCode, length: usize, bytes: [u16; 2] }
+/// `Instruction35mi` instruction type (ODEX only)
+#[derive(Debug, Clone)]
+pub struct Instruction35mi { opcode: OpCode, length: usize, bytes: [u16; 3] }
+/// `Instruction3rmi` instruction type (ODEX only)
+#[derive(Debug, Clone)]
+pub struct Instruction3rmi { opcode: OpCode, length: usize, bytes: [u16; 3] }
+/// `Instruction35ms` instruction type (ODEX only)
+#[derive(Debug, Clone)]
+pub struct Instruction35ms { opcode: OpCode, length: usize, bytes: [u16; 3] }
+/// `Instruction3rms` instruction type (ODEX only)
+#[derive(Debug, Clone)]
+pub struct Instruction3rms { opcode: OpCode, length: usize, bytes: [u16; 3] }
 /// `PackedSwitchPayload` instruction type
 #[derive(Debug, Clone)]
 pub struct PackedSwitchPayload {
@@ -111,6 +133,53 @@ pub struct FillArrayDataPayload {
     data: Vec<u8>
 }
 
+impl Instruction20bc {
+    /// Kind of verification error to throw (`VerifyError` value)
+    pub fn error_kind(&self) -> u8 {
+        (self.bytes[0] >> 8) as u8
+    }
+
+    /// Index of the class, field, or method which failed verification
+    pub fn reference_idx(&self) -> u16 {
+        self.bytes[1]
+    }
+}
+
+impl Instruction22cs {
+    /// Byte offset of the field in the object instance
+    pub fn field_offset(&self) -> u16 {
+        self.bytes[1]
+    }
+}
+
+impl Instruction35mi {
+    /// Index of the inlined method in the VM inline table
+    pub fn inline_index(&self) -> u16 {
+        self.bytes[1]
+    }
+}
+
+impl Instruction3rmi {
+    /// Index of the inlined method in the VM inline table
+    pub fn inline_index(&self) -> u16 {
+        self.bytes[1]
+    }
+}
+
+impl Instruction35ms {
+    /// Index of the method in the vtable of the receiver class
+    pub fn vtable_index(&self) -> u16 {
+        self.bytes[1]
+    }
+}
+
+impl Instruction3rms {
+    /// Index of the method in the vtable of the receiver class
+    pub fn vtable_index(&self) -> u16 {
+        self.bytes[1]
+    }
+}
+
 impl PackedSwitchPayload {
     /// Create a `PackedSwitchPayload` instruction from the reader
     fn build(reader: &mut DexReader) -> Result<Self, DexError> {
@@ -130,15 +199,18 @@ impl PackedSwitchPayload {
         })
     }
 
-    fn get_size(&self) -> usize {
+    /// Number of entries in the table
+    pub fn get_size(&self) -> usize {
         self.size as usize
     }
 
-    fn get_first_key(&self) -> i32 {
+    /// First (and lowest) switch case value
+    pub fn get_first_key(&self) -> i32 {
         self.first_key
     }
 
-    fn get_targets(&self) -> &[i32] {
+    /// Branch targets, relative to the address of the switch instruction
+    pub fn get_targets(&self) -> &[i32] {
         &self.targets
     }
 
@@ -187,15 +259,18 @@ impl SparseSwitchPayload {
         })
     }
 
-    fn get_size(&self) -> usize {
+    /// Number of entries in the table
+    pub fn get_size(&self) -> usize {
         self.size as usize
     }
 
-    fn get_keys(&self) -> &[i32] {
+    /// Sorted list of switch case values
+    pub fn get_keys(&self) -> &[i32] {
         &self.keys
     }
 
-    fn get_targets(&self) -> &[i32] {
+    /// Branch targets, relative to the address of the switch instruction
+    pub fn get_targets(&self) -> &[i32] {
         &self.targets
     }
 
@@ -227,12 +302,12 @@ impl FillArrayDataPayload {
         let element_width = reader.read_u16()?;
         let size = reader.read_u32()?;
 
-        let mut data = Vec::with_capacity((size * element_width as u32) as usize);
-        for _ in 0..size {
-            for _ in 0..element_width {
-                data.push(reader.read_u8()?);
-            }
+        let data_size = u64::from(size) * u64::from(element_width);
+        if data_size > reader.remaining() {
+            return Err(DexError::NoDataLeftError);
         }
+        let mut data = vec![0; data_size as usize];
+        reader.bytes.read_exact(&mut data)?;
 
         Ok(FillArrayDataPayload {
             opcode: OpCode::FILL_ARRAY_DATA_PAYLOAD,
@@ -242,20 +317,23 @@ impl FillArrayDataPayload {
         })
     }
 
-    fn get_element_width(&self) -> u16 {
+    /// Number of bytes in each element
+    pub fn get_element_width(&self) -> u16 {
         self.element_width
     }
 
-    fn get_size(&self) -> u32 {
+    /// Number of elements in the table
+    pub fn get_size(&self) -> u32 {
         self.size
     }
 
-    fn get_data(&self) -> &[u8] {
+    /// Raw data of the table
+    pub fn get_data(&self) -> &[u8] {
         &self.data
     }
 
     fn length(&self) -> usize {
-        ((self.size * self.element_width as u32) / 2 + 4) as usize
+        (self.size as usize * self.element_width as usize).div_ceil(2) + 4
     }
 
     fn opcode(&self) -> OpCode {
@@ -305,6 +383,12 @@ pub enum Instructions {
     Instruction45cc(Instruction45cc),
     Instruction4rcc(Instruction4rcc),
     Instruction51l(Instruction51l),
+    Instruction20bc(Instruction20bc),
+    Instruction22cs(Instruction22cs),
+    Instruction35mi(Instruction35mi),
+    Instruction3rmi(Instruction3rmi),
+    Instruction35ms(Instruction35ms),
+    Instruction3rms(Instruction3rms),
     PackedSwitchPayload(PackedSwitchPayload),
     SparseSwitchPayload(SparseSwitchPayload),
     FillArrayDataPayload(FillArrayDataPayload),
@@ -341,6 +425,12 @@ impl Instructions {
             Instructions::Instruction45cc(inst) => inst.length,
             Instructions::Instruction4rcc(inst) => inst.length,
             Instructions::Instruction51l(inst) => inst.length,
+            Instructions::Instruction20bc(inst) => inst.length,
+            Instructions::Instruction22cs(inst) => inst.length,
+            Instructions::Instruction35mi(inst) => inst.length,
+            Instructions::Instruction3rmi(inst) => inst.length,
+            Instructions::Instruction35ms(inst) => inst.length,
+            Instructions::Instruction3rms(inst) => inst.length,
             Instructions::PackedSwitchPayload(inst) => inst.length(),
             Instructions::SparseSwitchPayload(inst) => inst.length(),
             Instructions::FillArrayDataPayload(inst) => inst.length(),
@@ -376,6 +466,12 @@ impl Instructions {
             Instructions::Instruction45cc(inst) => inst.opcode,
             Instructions::Instruction4rcc(inst) => inst.opcode,
             Instructions::Instruction51l(inst) => inst.opcode,
+            Instructions::Instruction20bc(inst) => inst.opcode,
+            Instructions::Instruction22cs(inst) => inst.opcode,
+            Instructions::Instruction35mi(inst) => inst.opcode,
+            Instructions::Instruction3rmi(inst) => inst.opcode,
+            Instructions::Instruction35ms(inst) => inst.opcode,
+            Instructions::Instruction3rms(inst) => inst.opcode,
             Instructions::PackedSwitchPayload(inst) => inst.opcode,
             Instructions::SparseSwitchPayload(inst) => inst.opcode,
             Instructions::FillArrayDataPayload(inst) => inst.opcode,
@@ -411,6 +507,12 @@ impl Instructions {
             Instructions::Instruction45cc(inst) => &inst.bytes,
             Instructions::Instruction4rcc(inst) => &inst.bytes,
             Instructions::Instruction51l(inst) => &inst.bytes,
+            Instructions::Instruction20bc(inst) => &inst.bytes,
+            Instructions::Instruction22cs(inst) => &inst.bytes,
+            Instructions::Instruction35mi(inst) => &inst.bytes,
+            Instructions::Instruction3rmi(inst) => &inst.bytes,
+            Instructions::Instruction35ms(inst) => &inst.bytes,
+            Instructions::Instruction3rms(inst) => &inst.bytes,
             Instructions::PackedSwitchPayload(_) => &[],  // FIXME
             Instructions::SparseSwitchPayload(_) => &[],  // FIXME
             Instructions::FillArrayDataPayload(_) => &[],  // FIXME
@@ -424,7 +526,10 @@ impl Instructions {
 pub fn parse_instruction(reader: &mut DexReader, container: &mut Vec<Instructions>) -> Result<usize, DexError> {
     let raw_opcode = reader.read_u16()?;
 
-    let opcode = match OpCode::parse((raw_opcode & 0xff).try_into().unwrap()) {
+    // Optimized DEX files reuse some opcode values for their own instructions
+    let parse_opcode = if reader.quickened { OpCode::parse_quickened } else { OpCode::parse };
+
+    let opcode = match parse_opcode((raw_opcode & 0xff) as u8) {
         // Deal with the special cases of fill-array-data-payload,
         // packed-switch-payload, and sparse-switch-payload
         Some(OpCode::NOP) => match raw_opcode >> 8 {
@@ -434,7 +539,18 @@ pub fn parse_instruction(reader: &mut DexReader, container: &mut Vec<Instruction
             _    => OpCode::NOP
         },
         Some(code) => code,
-        None => return Err(DexError::InvalidOpCode)
+        None => {
+            // The diagnostic is only useful if parsing goes on: otherwise the error is enough
+            if reader.recover {
+                let offset = reader.bytes.position() - 2;
+                reader.diagnostics.push(Diagnostic::new(Severity::Error,
+                                                        DiagnosticCode::UnusedOpcode,
+                                                        offset,
+                                                        Some(MapItemType::CodeItem),
+                                                        format!("use of unused opcode 0x{:02x}", raw_opcode & 0xff)));
+            }
+            return Err(DexError::InvalidOpCode);
+        }
     };
 
     match opcode {
@@ -449,7 +565,8 @@ pub fn parse_instruction(reader: &mut DexReader, container: &mut Vec<Instruction
             Ok(1)
         },
 
-        OpCode::NOP | OpCode::RETURN_VOID => {
+        OpCode::NOP | OpCode::RETURN_VOID
+            | OpCode::BREAKPOINT | OpCode::RETURN_VOID_BARRIER => {
             let mut bytes = [0u16; 1];
             bytes[0] = raw_opcode;
             container.push(Instructions::Instruction10x(Instruction10x{
@@ -549,6 +666,9 @@ pub fn parse_instruction(reader: &mut DexReader, container: &mut Vec<Instruction
             | OpCode::SPUT_BYTE           | OpCode::SPUT_CHAR
             | OpCode::SPUT                | OpCode::SPUT_OBJECT
             | OpCode::SPUT_SHORT          | OpCode::SPUT_WIDE
+            | OpCode::SGET_VOLATILE       | OpCode::SPUT_VOLATILE
+            | OpCode::SGET_WIDE_VOLATILE  | OpCode::SPUT_WIDE_VOLATILE
+            | OpCode::SGET_OBJECT_VOLATILE | OpCode::SPUT_OBJECT_VOLATILE
             => {
                 let mut bytes = [0u16; 2];
                 bytes[0] = raw_opcode;
@@ -628,6 +748,9 @@ pub fn parse_instruction(reader: &mut DexReader, container: &mut Vec<Instruction
             | OpCode::IPUT_CHAR    | OpCode::IPUT
             | OpCode::IPUT_OBJECT  | OpCode::IPUT_SHORT
             | OpCode::IPUT_WIDE    | OpCode::NEW_ARRAY
+            | OpCode::IGET_VOLATILE        | OpCode::IPUT_VOLATILE
+            | OpCode::IGET_WIDE_VOLATILE   | OpCode::IPUT_WIDE_VOLATILE
+            | OpCode::IGET_OBJECT_VOLATILE | OpCode::IPUT_OBJECT_VOLATILE
             => {
                 let mut bytes = [0u16; 2];
                 bytes[0] = raw_opcode;
@@ -812,6 +935,7 @@ pub fn parse_instruction(reader: &mut DexReader, container: &mut Vec<Instruction
             | OpCode::INVOKE_DIRECT_RANGE | OpCode::INVOKE_INTERFACE_RANGE
             | OpCode::INVOKE_STATIC_RANGE | OpCode::INVOKE_SUPER_RANGE
             | OpCode::INVOKE_VIRTUAL_RANGE
+            | OpCode::INVOKE_OBJECT_INIT_RANGE
             => {
                 let mut bytes = [0u16; 3];
                 bytes[0] = raw_opcode;
@@ -868,6 +992,90 @@ pub fn parse_instruction(reader: &mut DexReader, container: &mut Vec<Instruction
             Ok(5)
         },
 
+        OpCode::THROW_VERIFICATION_ERROR
+            => {
+                let mut bytes = [0u16; 2];
+                bytes[0] = raw_opcode;
+                bytes[1] = reader.read_u16()?;
+                container.push(Instructions::Instruction20bc(Instruction20bc {
+                    opcode,
+                    bytes,
+                    length: 2
+                }));
+                Ok(2)
+            },
+
+        OpCode::IGET_QUICK            | OpCode::IGET_WIDE_QUICK
+            | OpCode::IGET_OBJECT_QUICK | OpCode::IPUT_QUICK
+            | OpCode::IPUT_WIDE_QUICK   | OpCode::IPUT_OBJECT_QUICK
+            => {
+                let mut bytes = [0u16; 2];
+                bytes[0] = raw_opcode;
+                bytes[1] = reader.read_u16()?;
+                container.push(Instructions::Instruction22cs(Instruction22cs {
+                    opcode,
+                    bytes,
+                    length: 2
+                }));
+                Ok(2)
+            },
+
+        OpCode::EXECUTE_INLINE
+            => {
+                let mut bytes = [0u16; 3];
+                bytes[0] = raw_opcode;
+                bytes[1] = reader.read_u16()?;
+                bytes[2] = reader.read_u16()?;
+                container.push(Instructions::Instruction35mi(Instruction35mi {
+                    opcode,
+                    bytes,
+                    length: 3
+                }));
+                Ok(3)
+            },
+
+        OpCode::EXECUTE_INLINE_RANGE
+            => {
+                let mut bytes = [0u16; 3];
+                bytes[0] = raw_opcode;
+                bytes[1] = reader.read_u16()?;
+                bytes[2] = reader.read_u16()?;
+                container.push(Instructions::Instruction3rmi(Instruction3rmi {
+                    opcode,
+                    bytes,
+                    length: 3
+                }));
+                Ok(3)
+            },
+
+        OpCode::INVOKE_VIRTUAL_QUICK | OpCode::INVOKE_SUPER_QUICK
+            => {
+                let mut bytes = [0u16; 3];
+                bytes[0] = raw_opcode;
+                bytes[1] = reader.read_u16()?;
+                bytes[2] = reader.read_u16()?;
+                container.push(Instructions::Instruction35ms(Instruction35ms {
+                    opcode,
+                    bytes,
+                    length: 3
+                }));
+                Ok(3)
+            },
+
+        OpCode::INVOKE_VIRTUAL_QUICK_RANGE | OpCode::INVOKE_SUPER_QUICK_RANGE
+            => {
+                let mut bytes = [0u16; 3];
+                bytes[0] = raw_opcode;
+                bytes[1] = reader.read_u16()?;
+                bytes[2] = reader.read_u16()?;
+                container.push(Instructions::Instruction3rms(Instruction3rms {
+                    opcode,
+                    bytes,
+                    length: 3
+                }));
+                Ok(3)
+            },
+
         OpCode::PACKED_SWITCH_PAYLOAD => {
             let inst = PackedSwitchPayload::build(reader)?;
             let len = inst.length();
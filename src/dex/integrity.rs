@@ -0,0 +1,126 @@
+//! Integrity checks of DEX files
+//!
+//! The header of a DEX file holds two integrity values: an Adler-32 checksum of everything after
+//! the checksum field, and a SHA-1 signature of everything after the signature field. Modified,
+//! repaired, or dumped DEX files often have stale values, so how a mismatch is handled is left to
+//! the caller (see `ParseOptions`).
+
+use log::warn;
+use sha1::{ Digest, Sha1 };
+
+use crate::adler32;
+use crate::error::DexError;
+
+/// Offset of the checksum in the header
+const CHECKSUM_OFFSET: usize = 8;
+/// Offset of the signature in the header
+const SIGNATURE_OFFSET: usize = 12;
+/// Size of the signature
+const SIGNATURE_SIZE: usize = 20;
+
+/// How to handle an integrity value which does not match the contents of the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegrityPolicy {
+    /// Fail to parse the file
+    Strict,
+    /// Log a warning and parse the file
+    Warn,
+    /// Do not compute the value
+    Skip,
+}
+
+impl IntegrityPolicy {
+    /// Run the check according to the policy
+    ///
+    /// Returns the error which was let through with the `Warn` policy, if any.
+    pub(crate) fn check<F>(&self, check: F) -> Result<Option<DexError>, DexError>
+    where F: FnOnce() -> Result<bool, DexError> {
+        match self {
+            IntegrityPolicy::Skip => Ok(None),
+            IntegrityPolicy::Strict => check().map(|_| None),
+            IntegrityPolicy::Warn => match check() {
+                Ok(_) => Ok(None),
+                Err(err) => {
+                    warn!("{err}");
+                    Ok(Some(err))
+                }
+            }
+        }
+    }
+}
+
+/// Compute the SHA-1 signature of a DEX file
+pub fn compute_signature(bytes: &[u8]) -> [u8; 20] {
+    let mut signature = [0; SIGNATURE_SIZE];
+    if let Some(signed) = bytes.get(SIGNATURE_OFFSET + SIGNATURE_SIZE..) {
+        signature.copy_from_slice(&Sha1::digest(signed));
+    }
+    signature
+}
+
+/// Verify the SHA-1 signature of a DEX file
+pub fn verify_signature(bytes: &[u8], signature: &[u8; 20]) -> Result<bool, DexError> {
+    if compute_signature(bytes) == *signature {
+        Ok(true)
+    } else {
+        Err(DexError::InvalidSignatureError)
+    }
+}
+
+/// Rewrite the signature and the checksum of a DEX file to match its contents
+///
+/// The signature is updated first, as the checksum covers it.
+pub fn fix_integrity(bytes: &mut [u8]) -> Result<(), DexError> {
+    if bytes.len() < SIGNATURE_OFFSET + SIGNATURE_SIZE {
+        return Err(DexError::DexHeaderTooShortError);
+    }
+
+    let signature = compute_signature(bytes);
+    bytes[SIGNATURE_OFFSET..SIGNATURE_OFFSET + SIGNATURE_SIZE].copy_from_slice(&signature);
+
+    let checksum = adler32::compute(bytes);
+    bytes[CHECKSUM_OFFSET..SIGNATURE_OFFSET].copy_from_slice(&checksum.to_le_bytes());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::file::DexFile;
+    use crate::fixtures::fake_dex;
+    use crate::dex::options::ParseOptions;
+    use crate::dex::reader::DexReader;
+
+    #[test]
+    fn test_fix_integrity() {
+        let mut raw = fake_dex(&["La;"], &[]);
+        assert!(verify_signature(&raw, &[0; 20]).is_err());
+
+        fix_integrity(&mut raw).unwrap();
+        let signature = raw[12..32].try_into().unwrap();
+        assert!(verify_signature(&raw, &signature).unwrap());
+        assert_eq!(raw[8..12], adler32::compute(&raw).to_le_bytes());
+
+        assert!(fix_integrity(&mut [0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_policies() {
+        // Valid checksum, zeroed signature
+        let raw = fake_dex(&["La;"], &[]);
+        let strict = ParseOptions { checksum: IntegrityPolicy::Strict, signature: IntegrityPolicy::Strict, ..Default::default() };
+        let result = DexFile::build_with_options(DexReader::build(raw.clone()).unwrap(), &strict);
+        assert!(matches!(result, Err(DexError::InvalidSignatureError)));
+        assert!(DexFile::build(DexReader::build(raw.clone()).unwrap()).is_ok());
+
+        // Invalid checksum
+        let mut corrupted = raw.clone();
+        corrupted[8] ^= 0xff;
+        assert!(DexFile::build(DexReader::build(corrupted.clone()).unwrap()).is_err());
+        for policy in [IntegrityPolicy::Warn, IntegrityPolicy::Skip] {
+            let options = ParseOptions { checksum: policy, signature: policy, ..Default::default() };
+            assert!(DexFile::build_with_options(DexReader::build(corrupted.clone()).unwrap(), &options).is_ok());
+        }
+    }
+}
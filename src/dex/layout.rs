@@ -0,0 +1,729 @@
+//! Byte-level layout of a DEX file
+//!
+//! The layout maps every byte of a (little-endian) DEX file to the structure it belongs to: the
+//! fields of the header, the items of the ID lists, and the items of the data section, as
+//! described by the map list. Code items are split into their header, bytecode, payloads, tries,
+//! and handlers. Each structure is annotated with its owner (string, type, class, method, etc.)
+//! when it can be found.
+//!
+//! Bytes which are not covered by any structure (other than alignment padding) are reported, as
+//! they often hide payloads.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+
+use crate::bytes::{ align_up, read_sleb128_at, read_u16_at, read_u32_at, read_uleb128_at, slice_at };
+use crate::dex::debug_info::DebugInfo;
+use crate::dex::header::{ DexHeader, HEADER_SIZE };
+use crate::dex::instructions::{ parse_instruction, Instructions };
+use crate::dex::integrity::IntegrityPolicy;
+use crate::dex::map::{ MapItemType, MapList };
+use crate::dex::opcodes::OpCode;
+use crate::dex::options::{ ParseOptions, ResourceLimits };
+use crate::dex::reader::DexReader;
+use crate::dex::verifier::{ alignment, encoded_item_end };
+use crate::dex::view::DexView;
+use crate::mutf8::decode_utf16;
+use crate::error::DexError;
+
+/// Fields of the header: name, offset, and size
+const HEADER_FIELDS: [(&str, usize, usize); 23] = [
+    ("magic", 0x00, 8),
+    ("checksum", 0x08, 4),
+    ("signature", 0x0c, 20),
+    ("file_size", 0x20, 4),
+    ("header_size", 0x24, 4),
+    ("endian_tag", 0x28, 4),
+    ("link_size", 0x2c, 4),
+    ("link_off", 0x30, 4),
+    ("map_off", 0x34, 4),
+    ("string_ids_size", 0x38, 4),
+    ("string_ids_off", 0x3c, 4),
+    ("type_ids_size", 0x40, 4),
+    ("type_ids_off", 0x44, 4),
+    ("proto_ids_size", 0x48, 4),
+    ("proto_ids_off", 0x4c, 4),
+    ("field_ids_size", 0x50, 4),
+    ("field_ids_off", 0x54, 4),
+    ("method_ids_size", 0x58, 4),
+    ("method_ids_off", 0x5c, 4),
+    ("class_defs_size", 0x60, 4),
+    ("class_defs_off", 0x64, 4),
+    ("data_size", 0x68, 4),
+    ("data_off", 0x6c, 4),
+];
+/// Number of bytes per line of the hexdump
+const HEXDUMP_WIDTH: usize = 16;
+
+/// Kind of structure covering a range of bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LayoutKind {
+    /// A field of the header
+    HeaderField(&'static str),
+    /// An item described by the map list
+    Item(MapItemType),
+    /// The link section
+    LinkData,
+    /// The fields of a code item preceding the bytecode
+    CodeItemHeader,
+    /// Instructions of a code item
+    Bytecode,
+    /// A payload of a code item (switch or array data)
+    Payload(OpCode),
+    /// The try blocks of a code item
+    Tries,
+    /// The catch handlers of a code item
+    CatchHandlers,
+    /// Zeros aligning the next structure
+    Padding,
+}
+
+impl fmt::Display for LayoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutKind::HeaderField(name) => write!(f, "HeaderItem.{name}"),
+            LayoutKind::Item(kind) => write!(f, "{kind:?}"),
+            LayoutKind::LinkData => write!(f, "LinkData"),
+            LayoutKind::CodeItemHeader => write!(f, "CodeItem.header"),
+            LayoutKind::Bytecode => write!(f, "CodeItem.insns"),
+            LayoutKind::Payload(opcode) => write!(f, "CodeItem.{opcode:?}"),
+            LayoutKind::Tries => write!(f, "CodeItem.tries"),
+            LayoutKind::CatchHandlers => write!(f, "CodeItem.handlers"),
+            LayoutKind::Padding => write!(f, "Padding"),
+        }
+    }
+}
+
+/// A range of bytes covered by a structure
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutEntry {
+    /// Offset of the structure in the file
+    pub offset: usize,
+    /// Size of the structure, in bytes
+    pub length: usize,
+    /// Kind of structure
+    pub kind: LayoutKind,
+    /// Item owning the structure (string, type, class, method, etc.), if known
+    pub owner: Option<String>,
+}
+
+impl fmt::Display for LayoutEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(owner) = &self.owner {
+            write!(f, " ({owner})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Layout of a DEX file
+#[derive(Debug)]
+pub struct DexLayout {
+    /// Structures of the file, sorted by offset
+    pub entries: Vec<LayoutEntry>,
+    /// Size of the file, in bytes
+    pub file_size: usize,
+}
+
+impl DexLayout {
+    /// Compute the layout of a DEX file with the default resource limits
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        DexLayout::build_with_options(raw, &ParseOptions::default())
+    }
+
+    /// Compute the layout of a DEX file with the resource limits of the given options
+    ///
+    /// Sections are walked item by item, following the map list: a section stops at the first
+    /// item which cannot be read, and the rest of its bytes are left uncovered. The checksum and
+    /// the signature are not checked, since corrupted files are the ones worth looking at.
+    pub fn build_with_options(raw: &[u8], options: &ParseOptions) -> Result<Self, DexError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(DexError::DexHeaderTooShortError);
+        }
+        let map_list = MapList::build(raw, read_u32_at(raw, 0x34)? as usize)?;
+
+        let options = ParseOptions {
+            checksum: IntegrityPolicy::Skip,
+            signature: IntegrityPolicy::Skip,
+            ..options.clone()
+        };
+        let view = DexView::new_with_options(raw, &options).ok();
+        let mut builder = LayoutBuilder {
+            raw,
+            limits: &options.limits,
+            names: Names::new(raw, view.as_ref()),
+            owners: HashMap::new(),
+            entries: Vec::new(),
+            reader: DexReader::from_slice(raw).ok().map(|mut reader| {
+                reader.limits = options.limits.clone();
+                reader
+            }),
+        };
+        builder.collect_owners(&map_list);
+
+        for item in map_list.items.iter() {
+            let mut pos = item.offset as usize;
+            for idx in 0..item.size {
+                // Structures do not overlap in valid files: more entries than bytes means that the
+                // map list describes the same bytes over and over
+                if builder.entries.len() > raw.len() {
+                    break;
+                }
+                pos = align_up(pos, alignment(item.kind));
+                match builder.walk_item(item.kind, idx, pos) {
+                    Ok(end) => pos = end,
+                    Err(_) => break,
+                }
+            }
+        }
+
+        let link_size = read_u32_at(raw, 0x2c)? as usize;
+        let link_off = read_u32_at(raw, 0x30)? as usize;
+        if link_size != 0 && slice_at(raw, link_off, link_size).is_ok() {
+            builder.push(link_off, link_size, LayoutKind::LinkData, None);
+        }
+
+        let mut layout = DexLayout { entries: builder.entries, file_size: raw.len() };
+        layout.entries.sort_by_key(|entry| entry.offset);
+        layout.add_padding(raw);
+
+        Ok(layout)
+    }
+
+    /// Ranges of bytes (offset and length) which are not covered by any structure
+    pub fn uncovered(&self) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        let mut covered = 0;
+
+        for entry in self.entries.iter() {
+            if entry.offset > covered {
+                ranges.push((covered, entry.offset - covered));
+            }
+            covered = covered.max(entry.offset + entry.length);
+        }
+        if covered < self.file_size {
+            ranges.push((covered, self.file_size - covered));
+        }
+
+        ranges
+    }
+
+    /// Mark the small runs of zeros which align the next structure as padding
+    fn add_padding(&mut self, raw: &[u8]) {
+        let padding = self.uncovered()
+                          .into_iter()
+                          .filter(|&(offset, length)| length < 4
+                                                      && (offset + length).is_multiple_of(4)
+                                                      && offset + length < self.file_size
+                                                      && raw[offset..offset + length].iter().all(|&byte| byte == 0))
+                          .collect::<Vec<(usize, usize)>>();
+        if padding.is_empty() {
+            return;
+        }
+
+        for (offset, length) in padding {
+            self.entries.push(LayoutEntry { offset, length, kind: LayoutKind::Padding, owner: None });
+        }
+        self.entries.sort_by_key(|entry| entry.offset);
+    }
+
+    /// Render the layout as an annotated hexdump of the given file
+    ///
+    /// Each structure starts on a new line, annotated with its kind and owner. Uncovered bytes are
+    /// dumped as well, and flagged.
+    pub fn render(&self, raw: &[u8]) -> String {
+        let mut regions = self.entries.iter()
+                                      .map(|entry| (entry.offset, entry.length, entry.to_string()))
+                                      .collect::<Vec<(usize, usize, String)>>();
+        regions.extend(self.uncovered()
+                           .into_iter()
+                           .map(|(offset, length)| (offset, length, "!! not covered by any structure".to_string())));
+        regions.sort_by_key(|(offset, _, _)| *offset);
+
+        let mut output = String::new();
+        for (offset, length, label) in regions {
+            let bytes = raw.get(offset..offset + length).unwrap_or(&[]);
+            for (row, chunk) in bytes.chunks(HEXDUMP_WIDTH).enumerate() {
+                let hex = chunk.iter()
+                               .map(|byte| format!("{byte:02x}"))
+                               .collect::<Vec<String>>()
+                               .join(" ");
+                let line = format!("{:08x}: {hex:<47} | {}",
+                                   offset + row * HEXDUMP_WIDTH,
+                                   if row == 0 { label.as_str() } else { "" });
+                let _ = writeln!(output, "{}", line.trim_end());
+            }
+        }
+
+        output
+    }
+}
+
+/// State of the computation of a layout
+struct LayoutBuilder<'a> {
+    raw: &'a [u8],
+    limits: &'a ResourceLimits,
+    names: Names<'a>,
+    /// Owners of the items of the data section, by offset
+    owners: HashMap<usize, String>,
+    entries: Vec<LayoutEntry>,
+    /// Reader used to decode the bytecode and the debug info, if the endianness tag is valid
+    reader: Option<DexReader<'a>>,
+}
+
+impl LayoutBuilder<'_> {
+    /// Record a structure
+    fn push(&mut self, offset: usize, length: usize, kind: LayoutKind, owner: Option<String>) {
+        self.entries.push(LayoutEntry { offset, length, kind, owner });
+    }
+
+    /// Record the owner of the item at the given offset, unless it already has one
+    fn own(&mut self, offset: usize, owner: &str) {
+        if offset != 0 {
+            self.owners.entry(offset).or_insert_with(|| owner.to_string());
+        }
+    }
+
+    /// Find the owners of the items of the data section, from the items referencing them
+    fn collect_owners(&mut self, map_list: &MapList) {
+        let names = self.names;
+
+        for idx in 0..names.string_ids.0 {
+            let Some(item) = names.item(names.string_ids, idx, 4) else { break };
+            let owner = names.string(idx).unwrap_or_default();
+            self.own(read_u32_at(self.raw, item).unwrap_or(0) as usize, &owner);
+        }
+        for idx in 0..names.proto_ids.0 {
+            let Some(item) = names.item(names.proto_ids, idx, 12) else { break };
+            let owner = names.proto(idx).unwrap_or_default();
+            self.own(read_u32_at(self.raw, item + 8).unwrap_or(0) as usize, &owner);
+        }
+        if let Some(call_sites) = map_list.get(MapItemType::CallSiteIdItem) {
+            for idx in 0..call_sites.size {
+                let Ok(offset) = read_u32_at(self.raw, call_sites.offset as usize + 4 * idx as usize) else { break };
+                self.own(offset as usize, &format!("call_site@{idx}"));
+            }
+        }
+
+        for idx in 0..names.class_defs.0 {
+            let Some(item) = names.item(names.class_defs, idx, 32) else { break };
+            let class_idx = read_u32_at(self.raw, item).unwrap_or(u32::MAX);
+            let class = names.type_name(class_idx).unwrap_or_else(|| format!("class@{idx}"));
+            let offset = |field: usize| read_u32_at(self.raw, item + field).unwrap_or(0) as usize;
+            let (interfaces_off, annotations_off, class_data_off, static_values_off) = (offset(12), offset(20), offset(24), offset(28));
+
+            self.own(interfaces_off, &class);
+            self.own(static_values_off, &class);
+            self.own(class_data_off, &class);
+            if class_data_off != 0 {
+                let _ = self.collect_class_data(class_data_off);
+            }
+            self.own(annotations_off, &class);
+            if annotations_off != 0 {
+                let _ = self.collect_annotations(annotations_off, &class);
+            }
+        }
+    }
+
+    /// Find the owners of the code items and debug info of a class
+    fn collect_class_data(&mut self, offset: usize) -> Result<(), DexError> {
+        let mut pos = offset;
+        let mut sizes = [0; 4];
+        for size in sizes.iter_mut() {
+            *size = read_uleb128_at(self.raw, &mut pos)?;
+        }
+        for _ in 0..u64::from(sizes[0]) + u64::from(sizes[1]) {
+            read_uleb128_at(self.raw, &mut pos)?;
+            read_uleb128_at(self.raw, &mut pos)?;
+        }
+
+        for size in [sizes[2], sizes[3]] {
+            let mut method_idx = 0u32;
+            for _ in 0..size {
+                method_idx = method_idx.wrapping_add(read_uleb128_at(self.raw, &mut pos)?);
+                read_uleb128_at(self.raw, &mut pos)?;
+                let code_off = read_uleb128_at(self.raw, &mut pos)? as usize;
+                if code_off != 0 {
+                    let method = self.names.method(method_idx).unwrap_or_else(|| format!("method@{method_idx}"));
+                    self.own(code_off, &method);
+                    self.own(read_u32_at(self.raw, code_off + 8).unwrap_or(0) as usize, &method);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find the owners of the annotations of a class
+    fn collect_annotations(&mut self, offset: usize, class: &str) -> Result<(), DexError> {
+        self.own_annotation_set(read_u32_at(self.raw, offset)? as usize, class);
+        let fields_size = read_u32_at(self.raw, offset + 4)? as usize;
+        let methods_size = read_u32_at(self.raw, offset + 8)? as usize;
+        let params_size = read_u32_at(self.raw, offset + 12)? as usize;
+
+        let mut pos = offset + 16;
+        for list in 0..3 {
+            let size = [fields_size, methods_size, params_size][list];
+            for _ in 0..size {
+                let idx = read_u32_at(self.raw, pos)?;
+                let annotations_off = read_u32_at(self.raw, pos + 4)? as usize;
+                pos += 8;
+
+                let owner = if list == 0 { self.names.field(idx) } else { self.names.method(idx) };
+                let owner = owner.unwrap_or_else(|| format!("{}@{idx}", if list == 0 { "field" } else { "method" }));
+                if list < 2 {
+                    self.own_annotation_set(annotations_off, &owner);
+                    continue;
+                }
+
+                // Parameter annotations are a list of annotation sets
+                self.own(annotations_off, &owner);
+                let refs_size = read_u32_at(self.raw, annotations_off)? as usize;
+                for ref_idx in 0..refs_size {
+                    let set_off = read_u32_at(self.raw, annotations_off + 4 + 4 * ref_idx)? as usize;
+                    self.own_annotation_set(set_off, &owner);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record the owner of an annotation set and its annotations
+    fn own_annotation_set(&mut self, offset: usize, owner: &str) {
+        if offset == 0 {
+            return;
+        }
+        self.own(offset, owner);
+        let size = read_u32_at(self.raw, offset).unwrap_or(0) as usize;
+        for idx in 0..size {
+            let Ok(annotation_off) = read_u32_at(self.raw, offset + 4 + 4 * idx) else { break };
+            self.own(annotation_off as usize, owner);
+        }
+    }
+
+    /// Record the item of the given type at the given position, and return its end
+    fn walk_item(&mut self, kind: MapItemType, idx: u32, pos: usize) -> Result<usize, DexError> {
+        match kind {
+            MapItemType::HeaderItem => {
+                slice_at(self.raw, pos, HEADER_SIZE)?;
+                for (name, offset, size) in HEADER_FIELDS {
+                    self.push(pos + offset, size, LayoutKind::HeaderField(name), None);
+                }
+                Ok(pos + HEADER_SIZE)
+            },
+            MapItemType::CodeItem => self.walk_code_item(pos),
+            _ => {
+                let end = self.item_end(kind, pos)?;
+                slice_at(self.raw, pos, end - pos)?;
+
+                let names = self.names;
+                let owner = match kind {
+                    MapItemType::StringIdItem => names.string(idx),
+                    MapItemType::TypeIdItem => names.type_name(idx),
+                    MapItemType::ProtoIdItem => names.proto(idx),
+                    MapItemType::FieldIdItem => names.field(idx),
+                    MapItemType::MethodIdItem => names.method(idx),
+                    MapItemType::ClassDefItem => names.type_name(read_u32_at(self.raw, pos)?),
+                    _ => self.owners.get(&pos).cloned(),
+                };
+                self.push(pos, end - pos, LayoutKind::Item(kind), owner);
+                Ok(end)
+            },
+        }
+    }
+
+    /// Find the end of the item of the given type at the given position
+    fn item_end(&mut self, kind: MapItemType, pos: usize) -> Result<usize, DexError> {
+        let raw = self.raw;
+        let count = |offset: usize| read_u32_at(raw, offset).map(|count| count as usize);
+
+        let end = match kind {
+            MapItemType::StringIdItem | MapItemType::TypeIdItem | MapItemType::CallSiteIdItem => pos + 4,
+            MapItemType::FieldIdItem | MapItemType::MethodIdItem | MapItemType::MethodHandleItem => pos + 8,
+            MapItemType::ProtoIdItem => pos + 12,
+            MapItemType::ClassDefItem => pos + 32,
+            MapItemType::MapList => pos + 4 + 12 * count(pos)?,
+            MapItemType::TypeList => pos + 4 + 2 * count(pos)?,
+            MapItemType::AnnotationSetRefList | MapItemType::AnnotationSetItem => pos + 4 + 4 * count(pos)?,
+            MapItemType::AnnotationsDirectoryItem => pos + 16 + 8 * (count(pos + 4)? + count(pos + 8)? + count(pos + 12)?),
+            MapItemType::HiddenapiClassDataItem => pos + count(pos)?.max(4),
+            MapItemType::StringDataItem => {
+                let mut end = pos;
+                read_uleb128_at(raw, &mut end)?;
+                let len = raw.get(end..)
+                             .and_then(|tail| tail.iter().position(|&byte| byte == 0))
+                             .ok_or(DexError::NoDataLeftError)?;
+                end + len + 1
+            },
+            MapItemType::ClassDataItem => {
+                let mut end = pos;
+                let mut sizes = [0; 4];
+                for size in sizes.iter_mut() {
+                    *size = read_uleb128_at(raw, &mut end)?;
+                }
+                for (list, size) in sizes.into_iter().enumerate() {
+                    for _ in 0..u64::from(size) * if list < 2 { 2 } else { 3 } {
+                        read_uleb128_at(raw, &mut end)?;
+                    }
+                }
+                end
+            },
+            MapItemType::DebugInfoItem => {
+                let reader = self.reader.as_mut().ok_or(DexError::NoDataLeftError)?;
+                DebugInfo::build(reader, u32::try_from(pos).map_err(|_| DexError::InvalidSectionBounds)?)?;
+                reader.bytes.position() as usize
+            },
+            MapItemType::AnnotationItem | MapItemType::EncodedArrayItem => {
+                encoded_item_end(raw, kind, pos, self.limits).ok_or(DexError::InvalidEncodedValue)?
+            },
+            MapItemType::HeaderItem | MapItemType::CodeItem => unreachable!("handled by walk_item"),
+        };
+
+        Ok(end)
+    }
+
+    /// Record the parts of the code item at the given position, and return its end
+    fn walk_code_item(&mut self, pos: usize) -> Result<usize, DexError> {
+        let raw = self.raw;
+        let owner = self.owners.get(&pos).cloned();
+        let tries_size = read_u16_at(raw, pos + 6)? as usize;
+        let insns_size = read_u32_at(raw, pos + 12)? as usize;
+        let insns_off = pos + 16;
+        slice_at(raw, insns_off, 2 * insns_size)?;
+        let insns_end = insns_off + 2 * insns_size;
+
+        self.push(pos, 16, LayoutKind::CodeItemHeader, owner.clone());
+        self.walk_bytecode(insns_off, insns_size, &owner);
+        if tries_size == 0 {
+            return Ok(insns_end);
+        }
+
+        let tries_off = align_up(insns_end, 4);
+        slice_at(raw, tries_off, 8 * tries_size)?;
+        self.push(tries_off, 8 * tries_size, LayoutKind::Tries, owner.clone());
+
+        let handlers_off = tries_off + 8 * tries_size;
+        let mut end = handlers_off;
+        for _ in 0..read_uleb128_at(raw, &mut end)? {
+            let size = read_sleb128_at(raw, &mut end)?;
+            for _ in 0..2 * u64::from(size.unsigned_abs()) {
+                read_uleb128_at(raw, &mut end)?;
+            }
+            if size <= 0 {
+                read_uleb128_at(raw, &mut end)?;
+            }
+        }
+        self.push(handlers_off, end - handlers_off, LayoutKind::CatchHandlers, owner);
+
+        Ok(end)
+    }
+
+    /// Record the bytecode of a code item, splitting out the payloads
+    fn walk_bytecode(&mut self, offset: usize, insns_size: usize, owner: &Option<String>) {
+        let end = offset + 2 * insns_size;
+        let mut payloads = Vec::new();
+
+        if let Some(reader) = self.reader.as_mut() {
+            reader.bytes.set_position(offset as u64);
+            let mut addr = 0;
+            while addr < insns_size {
+                let mut insns = Vec::new();
+                let Some(inst) = parse_instruction(reader, &mut insns).ok().and_then(|_| insns.pop()) else {
+                    break;
+                };
+                let length = inst.length();
+                if length == 0 || addr + length > insns_size {
+                    break;
+                }
+                if matches!(inst, Instructions::PackedSwitchPayload(_)
+                                | Instructions::SparseSwitchPayload(_)
+                                | Instructions::FillArrayDataPayload(_)) {
+                    payloads.push((offset + 2 * addr, 2 * length, inst.opcode()));
+                }
+                addr += length;
+            }
+        }
+
+        // Instructions between the payloads
+        let mut start = offset;
+        for (payload_off, length, opcode) in payloads {
+            if payload_off > start {
+                self.push(start, payload_off - start, LayoutKind::Bytecode, owner.clone());
+            }
+            self.push(payload_off, length, LayoutKind::Payload(opcode), owner.clone());
+            start = payload_off + length;
+        }
+        if start < end {
+            self.push(start, end - start, LayoutKind::Bytecode, owner.clone());
+        }
+    }
+}
+
+/// Names of the items of the ID lists
+///
+/// Strings and types are resolved through a `DexView`; the prototypes, fields and methods are
+/// formatted from them.
+#[derive(Clone, Copy)]
+struct Names<'a> {
+    raw: &'a [u8],
+    /// View of the file, if its header can be read
+    view: Option<&'a DexView<'a>>,
+    /// Size and offset of each ID list
+    string_ids: (u32, usize),
+    type_ids: (u32, usize),
+    proto_ids: (u32, usize),
+    field_ids: (u32, usize),
+    method_ids: (u32, usize),
+    class_defs: (u32, usize),
+}
+
+impl<'a> Names<'a> {
+    fn new(raw: &'a [u8], view: Option<&'a DexView<'a>>) -> Self {
+        let header = view.map(|view| view.header());
+        let section = |ids: fn(&DexHeader) -> (u32, u32)| header.map(ids)
+                                                                 .map_or((0, 0), |(size, offset)| (size, offset as usize));
+        Names {
+            raw,
+            view,
+            string_ids: section(|header| (header.string_ids_size, header.string_ids_off)),
+            type_ids: section(|header| (header.type_ids_size, header.type_ids_off)),
+            proto_ids: section(|header| (header.proto_ids_size, header.proto_ids_off)),
+            field_ids: section(|header| (header.fields_ids_size, header.fields_ids_off)),
+            method_ids: section(|header| (header.method_ids_size, header.method_ids_off)),
+            class_defs: section(|header| (header.class_defs_size, header.class_defs_off)),
+        }
+    }
+
+    /// Offset of an item of an ID list, if it is within the list and the file
+    fn item(&self, (size, offset): (u32, usize), idx: u32, item_size: usize) -> Option<usize> {
+        let item = offset + idx as usize * item_size;
+        (idx < size && slice_at(self.raw, item, item_size).is_ok()).then_some(item)
+    }
+
+    fn string(&self, idx: u32) -> Option<String> {
+        let string = self.view?.string(idx).ok()?;
+        decode_utf16(string.as_bytes()).map(|units| String::from_utf16_lossy(&units))
+    }
+
+    fn type_name(&self, idx: u32) -> Option<String> {
+        let descriptor = self.view?.type_descriptor(idx).ok()?;
+        decode_utf16(descriptor.as_bytes()).map(|units| String::from_utf16_lossy(&units))
+    }
+
+    fn proto(&self, idx: u32) -> Option<String> {
+        let item = self.item(self.proto_ids, idx, 12)?;
+        let return_type = self.type_name(read_u32_at(self.raw, item + 4).ok()?)?;
+        let params_off = read_u32_at(self.raw, item + 8).ok()? as usize;
+
+        let mut params = Vec::new();
+        if params_off != 0 {
+            let size = read_u32_at(self.raw, params_off).ok()? as usize;
+            for param in 0..size {
+                let type_idx = read_u16_at(self.raw, params_off + 4 + 2 * param).ok()?;
+                params.push(self.type_name(type_idx.into())?);
+            }
+        }
+
+        Some(format!("({}){return_type}", params.join(" ")))
+    }
+
+    fn field(&self, idx: u32) -> Option<String> {
+        let item = self.item(self.field_ids, idx, 8)?;
+        let class = self.type_name(read_u16_at(self.raw, item).ok()?.into())?;
+        let field_type = self.type_name(read_u16_at(self.raw, item + 2).ok()?.into())?;
+        let name = self.string(read_u32_at(self.raw, item + 4).ok()?)?;
+        Some(format!("{class}->{name}:{field_type}"))
+    }
+
+    fn method(&self, idx: u32) -> Option<String> {
+        let item = self.item(self.method_ids, idx, 8)?;
+        let class = self.type_name(read_u16_at(self.raw, item).ok()?.into())?;
+        let proto = self.proto(read_u16_at(self.raw, item + 2).ok()?.into())?;
+        let name = self.string(read_u32_at(self.raw, item + 4).ok()?)?;
+        Some(format!("{class}->{name}{proto}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ append_data_items, fake_code_dex, fake_dex };
+
+    #[test]
+    fn test_build() {
+        let raw = fake_code_dex();
+        let layout = DexLayout::build(&raw).unwrap();
+        assert!(layout.uncovered().is_empty());
+
+        let header_fields = layout.entries.iter()
+                                          .filter(|entry| matches!(entry.kind, LayoutKind::HeaderField(_)))
+                                          .count();
+        assert_eq!(header_fields, 23);
+        assert_eq!(layout.entries[0], LayoutEntry { offset: 0, length: 8, kind: LayoutKind::HeaderField("magic"), owner: None });
+
+        let payload = layout.entries.iter()
+                                    .find(|entry| matches!(entry.kind, LayoutKind::Payload(_)))
+                                    .unwrap();
+        assert_eq!(payload.kind, LayoutKind::Payload(OpCode::PACKED_SWITCH_PAYLOAD));
+        assert_eq!(payload.length, 12);
+        assert_eq!(payload.owner.as_deref(), Some("La;->switch()V"));
+
+        let owners = |kind: MapItemType| layout.entries.iter()
+                                                       .filter(|entry| entry.kind == LayoutKind::Item(kind))
+                                                       .map(|entry| entry.owner.clone().unwrap())
+                                                       .collect::<Vec<String>>();
+        assert_eq!(owners(MapItemType::StringDataItem), vec!["La;", "Lb;", "V", "[B", "fill", "run", "switch"]);
+        assert_eq!(owners(MapItemType::MethodIdItem), vec!["La;->fill()V", "La;->switch()V", "Lb;->run()V"]);
+        assert_eq!(owners(MapItemType::ClassDataItem), vec!["La;", "Lb;"]);
+        assert!(layout.entries.iter().any(|entry| entry.kind == LayoutKind::Padding));
+    }
+
+    #[test]
+    fn test_uncovered() {
+        let mut raw = fake_dex(&["La;"], &[]);
+        let file_size = raw.len();
+        raw.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef, 0x00]);
+
+        let layout = DexLayout::build(&raw).unwrap();
+        assert_eq!(layout.uncovered(), vec![(file_size, 5)]);
+        assert!(DexLayout::build(&raw[..0x40]).is_err());
+    }
+
+    #[test]
+    fn test_build_with_options() {
+        // An empty array nested in an array, and debug info with a single special opcode
+        let mut raw = fake_dex(&["La;"], &[]);
+        let offsets = append_data_items(&mut raw, &[(0x2005, &[0x01, 0x1c, 0x00]), (0x2003, &[0x01, 0x00, 0x0e, 0x00])]);
+
+        let layout = DexLayout::build(&raw).unwrap();
+        assert!(layout.uncovered().is_empty());
+        let item = |offset: usize| layout.entries.iter().find(|entry| entry.offset == offset).unwrap();
+        assert_eq!((item(offsets[0]).kind, item(offsets[0]).length), (LayoutKind::Item(MapItemType::EncodedArrayItem), 3));
+        assert_eq!((item(offsets[1]).kind, item(offsets[1]).length), (LayoutKind::Item(MapItemType::DebugInfoItem), 4));
+
+        let options = ParseOptions {
+            limits: ResourceLimits { max_nesting_depth: 0, ..Default::default() },
+            ..Default::default()
+        };
+        let layout = DexLayout::build_with_options(&raw, &options).unwrap();
+        // The alignment of the debug info is no longer padding once the array is left uncovered
+        assert_eq!(layout.uncovered(), vec![(offsets[0], offsets[1] - offsets[0])]);
+    }
+
+    #[test]
+    fn test_render() {
+        let mut raw = fake_code_dex();
+        raw.extend_from_slice(&[0xde, 0xad]);
+
+        let dump = DexLayout::build(&raw).unwrap().render(&raw);
+        let lines = dump.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "00000000: 64 65 78 0a 30 33 35 00                         | HeaderItem.magic");
+        assert_eq!(lines[2], "0000000c: 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 | HeaderItem.signature");
+        assert_eq!(lines[3], format!("0000001c: {:<47} |", "00 00 00 00"));
+        assert!(lines.iter().any(|line| line.ends_with("| CodeItem.PACKED_SWITCH_PAYLOAD (La;->switch()V)")));
+        assert_eq!(*lines.last().unwrap(), format!("{:08x}: de ad                                           | !! not covered by any structure", raw.len() - 2));
+    }
+}
@@ -0,0 +1,206 @@
+//! Lazily decoded DEX file
+//!
+//! Building a `DexFile` decodes the class data and the code of every method, which is wasted work
+//! when only the classes names or the bytecode of a few methods are needed. A `LazyDexFile` parses
+//! the header, the IDs sections, and the class definitions up front, but only decodes the class
+//! data of a class, and the code item of a method, when it is first accessed. Decoded items are
+//! cached, so each one is decoded at most once.
+
+use std::collections::HashMap;
+
+use crate::dex::reader::DexReader;
+use crate::dex::header::DexHeader;
+use crate::dex::options::ParseOptions;
+use crate::dex::diagnostics::{ Diagnostic, Severity };
+use crate::dex::strings::DexStrings;
+use crate::dex::types::DexTypes;
+use crate::dex::protos::DexProtos;
+use crate::dex::fields::DexFields;
+use crate::dex::methods::DexMethods;
+use crate::dex::classes::{ DexClasses, ClassDefItem, ClassDataItem };
+use crate::dex::code_item::CodeItem;
+use crate::dex::encoded_values::EncodedValueContext;
+use crate::dex::file::DexFile;
+use crate::error::{ DexError, ErrorContext, ErrorFrame };
+
+/// Representation of a DEX file whose class data and code items are decoded on demand
+///
+/// The class definitions are parsed without their class data: their fields and methods must be
+/// retrieved with `get_class_data`. The offsets of the class data and code items are kept in the
+/// class definitions and methods.
+#[derive(Debug)]
+pub struct LazyDexFile<'a> {
+    /// Header of the file
+    pub header: DexHeader,
+    /// List of strings defined in the DEX file
+    pub strings: DexStrings,
+    /// List of types defined in the DEX file
+    pub types: DexTypes,
+    /// List of prototypes defined in the DEX file
+    pub protos: DexProtos,
+    /// List of class fields defined in the DEX file
+    pub fields: DexFields,
+    /// List of methods defined in the DEX file
+    pub methods: DexMethods,
+    /// List of classes defined in the DEX file, without their class data
+    pub classes: DexClasses,
+    /// Class data decoded so far, by index of the class
+    class_data: HashMap<usize, Option<ClassDataItem>>,
+    /// Code items decoded so far, by offset
+    code_items: HashMap<u32, CodeItem>,
+    dex_reader: DexReader<'a>,
+}
+
+impl<'a> LazyDexFile<'a> {
+    /// Parse the IDs sections and class definitions of a DEX file from the reader
+    pub fn build(dex_reader: DexReader<'a>) -> Result<Self, DexError> {
+        LazyDexFile::build_with_options(dex_reader, &ParseOptions::default())
+    }
+
+    /// Parse the IDs sections and class definitions of a DEX file from the reader with the given
+    /// options
+    pub fn build_with_options(mut dex_reader: DexReader<'a>, options: &ParseOptions) -> Result<Self, DexError> {
+        let dex_file = DexFile::build_from_reader(&mut dex_reader, options, false)?;
+
+        Ok(LazyDexFile {
+            header: dex_file.header,
+            strings: dex_file.strings,
+            types: dex_file.types,
+            protos: dex_file.protos,
+            fields: dex_file.fields,
+            methods: dex_file.methods,
+            classes: dex_file.classes,
+            class_data: HashMap::new(),
+            code_items: HashMap::new(),
+            dex_reader,
+        })
+    }
+
+    /// Get the anomalies found so far, with at least the given severity
+    ///
+    /// Anomalies in the class data and code items are only found once they are decoded.
+    pub fn get_diagnostics(&self, min_severity: Severity) -> Vec<&Diagnostic> {
+        self.dex_reader.diagnostics.iter()
+                                   .filter(|diagnostic| diagnostic.severity >= min_severity)
+                                   .collect()
+    }
+
+    /// Get the names of all the classes
+    pub fn get_classes_names(&self) -> Vec<&String> {
+        self.classes.items.iter().map(|class| class.get_class_name()).collect()
+    }
+
+    /// Get a class definition from the class name, if it exists
+    pub fn get_class_def(&self, class_name: &String) -> Option<&ClassDefItem> {
+        self.classes.get_class_def(class_name)
+    }
+
+    /// Get the class data of a class, decoding it on first access
+    ///
+    /// Returns `None` if the class does not exist or has no class data. The code items of the
+    /// methods are not decoded: use `get_code_item` with the offset of their code.
+    pub fn get_class_data(&mut self, class_name: &String) -> Result<Option<&ClassDataItem>, DexError> {
+        let Some(idx) = self.classes.items.iter().position(|class| class.get_class_name() == class_name) else {
+            return Ok(None);
+        };
+
+        if !self.class_data.contains_key(&idx) {
+            let context = EncodedValueContext {
+                strings: &self.strings,
+                types: &self.types,
+                fields: &self.fields,
+                methods: &self.methods,
+            };
+            let class_data = DexClasses::build_class_data(&mut self.dex_reader, &self.classes.items[idx], &context)?;
+            self.class_data.insert(idx, class_data);
+        }
+
+        Ok(self.class_data[&idx].as_ref())
+    }
+
+    /// Get the code item at the given offset, decoding it on first access
+    pub fn get_code_item(&mut self, code_off: u32) -> Result<&CodeItem, DexError> {
+        if !self.code_items.contains_key(&code_off) {
+            let code_item = CodeItem::build(&mut self.dex_reader, code_off, &self.types)
+                                .in_frame(ErrorFrame::at("code_item", code_off.into()))?;
+            self.code_items.insert(code_off, code_item);
+        }
+
+        Ok(&self.code_items[&code_off])
+    }
+
+    /// Get the code item of a method from the class and method names, decoding them on first
+    /// access
+    ///
+    /// Returns `None` if the method does not exist or has no code.
+    pub fn get_method_code(&mut self, class_name: &String, method_name: &String) -> Result<Option<&CodeItem>, DexError> {
+        let code_off = self.get_class_data(class_name)?
+                           .and_then(|class_data| class_data.get_methods()
+                                                            .into_iter()
+                                                            .find(|method| method.get_method_name() == method_name))
+                           .map(|method| method.get_code_off());
+
+        match code_off {
+            Some(code_off) if code_off != 0 => self.get_code_item(code_off).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::diagnostics::DiagnosticCode;
+    use crate::fixtures::{ fake_dex_with_methods, FakeMethod };
+
+    #[test]
+    fn test_build() {
+        let methods = [
+            FakeMethod { class: "La;", name: "f", access_flags: 0x9, code: Some((1, 0, 0, vec![0x0012, 0x000e])) },
+            FakeMethod { class: "La;", name: "g", access_flags: 0x109, code: None },
+            FakeMethod { class: "Lb;", name: "bad", access_flags: 0x9, code: Some((0, 0, 0, vec![0x003e])) },
+        ];
+        let raw = fake_dex_with_methods(&["La;", "Lb;", "Lc;"], &[], &methods);
+        // The invalid bytecode is not decoded unless it is accessed
+        assert!(DexFile::build(DexReader::build(raw.clone()).unwrap()).is_err());
+        let mut dex = LazyDexFile::build(DexReader::from_slice(&raw).unwrap()).unwrap();
+        assert_eq!(dex.get_classes_names(), vec!["La;", "Lb;", "Lc;"]);
+
+        let class_def = dex.get_class_def(&"La;".to_string()).unwrap();
+        assert_ne!(class_def.get_class_data_off(), 0);
+        assert!(class_def.get_methods().is_empty());
+
+        let class_data = dex.get_class_data(&"La;".to_string()).unwrap().unwrap();
+        let methods = class_data.get_methods();
+        assert_eq!(methods.len(), 2);
+        assert!(methods.iter().all(|method| method.code_item.is_none()));
+        let code_off = methods[0].get_code_off();
+        assert_ne!(code_off, 0);
+        assert_eq!(methods[1].get_code_off(), 0);
+
+        let code_item = dex.get_code_item(code_off).unwrap();
+        assert_eq!(code_item.insns.as_ref().unwrap().len(), 2);
+        assert!(dex.code_items.contains_key(&code_off));
+
+        assert_eq!(dex.get_method_code(&"La;".to_string(), &"f".to_string()).unwrap().unwrap().insns_size(), 2);
+        assert!(dex.get_method_code(&"La;".to_string(), &"g".to_string()).unwrap().is_none());
+        assert!(dex.get_class_data(&"Lc;".to_string()).unwrap().is_none());
+        assert!(dex.get_class_data(&"Ld;".to_string()).unwrap().is_none());
+
+        let err = dex.get_method_code(&"Lb;".to_string(), &"bad".to_string()).unwrap_err();
+        assert!(matches!(err.root(), DexError::InvalidOpCode));
+        assert_eq!(err.location().unwrap().frames[0].name, "code_item");
+        // The error is enough when not recovering
+        assert!(dex.get_diagnostics(Severity::Error).is_empty());
+
+        let options = ParseOptions { recover: true, ..ParseOptions::default() };
+        let mut dex = LazyDexFile::build_with_options(DexReader::from_slice(&raw).unwrap(), &options).unwrap();
+        let code_item = dex.get_method_code(&"Lb;".to_string(), &"bad".to_string()).unwrap().unwrap();
+        assert!(code_item.insns.is_none());
+        let codes = dex.get_diagnostics(Severity::Error)
+                       .iter()
+                       .map(|diagnostic| diagnostic.code)
+                       .collect::<Vec<DiagnosticCode>>();
+        assert_eq!(codes, vec![DiagnosticCode::UnusedOpcode, DiagnosticCode::UndecodedCode]);
+    }
+}
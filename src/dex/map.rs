@@ -0,0 +1,138 @@
+//! Map list of a DEX file
+//!
+//! The map list, pointed to by the `map_off` field of the header, describes every section of a DEX
+//! file: its item type, number of items, and offset. It duplicates the sizes and offsets of the
+//! header, which makes it useful to validate or rebuild damaged headers.
+
+use crate::bytes::{ read_u16_at, read_u32_at };
+use crate::error::DexError;
+
+/// Size of an item of the map list
+pub const MAP_ITEM_SIZE: usize = 12;
+
+/// Types of the items described in the map list
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MapItemType {
+    HeaderItem,
+    StringIdItem,
+    TypeIdItem,
+    ProtoIdItem,
+    FieldIdItem,
+    MethodIdItem,
+    ClassDefItem,
+    CallSiteIdItem,
+    MethodHandleItem,
+    MapList,
+    TypeList,
+    AnnotationSetRefList,
+    AnnotationSetItem,
+    ClassDataItem,
+    CodeItem,
+    StringDataItem,
+    DebugInfoItem,
+    AnnotationItem,
+    EncodedArrayItem,
+    AnnotationsDirectoryItem,
+    HiddenapiClassDataItem,
+}
+
+impl MapItemType {
+    /// Parse the type code of a map item
+    pub fn parse(value: u16) -> Option<Self> {
+        match value {
+            0x0000 => Some(MapItemType::HeaderItem),
+            0x0001 => Some(MapItemType::StringIdItem),
+            0x0002 => Some(MapItemType::TypeIdItem),
+            0x0003 => Some(MapItemType::ProtoIdItem),
+            0x0004 => Some(MapItemType::FieldIdItem),
+            0x0005 => Some(MapItemType::MethodIdItem),
+            0x0006 => Some(MapItemType::ClassDefItem),
+            0x0007 => Some(MapItemType::CallSiteIdItem),
+            0x0008 => Some(MapItemType::MethodHandleItem),
+            0x1000 => Some(MapItemType::MapList),
+            0x1001 => Some(MapItemType::TypeList),
+            0x1002 => Some(MapItemType::AnnotationSetRefList),
+            0x1003 => Some(MapItemType::AnnotationSetItem),
+            0x2000 => Some(MapItemType::ClassDataItem),
+            0x2001 => Some(MapItemType::CodeItem),
+            0x2002 => Some(MapItemType::StringDataItem),
+            0x2003 => Some(MapItemType::DebugInfoItem),
+            0x2004 => Some(MapItemType::AnnotationItem),
+            0x2005 => Some(MapItemType::EncodedArrayItem),
+            0x2006 => Some(MapItemType::AnnotationsDirectoryItem),
+            0xf000 => Some(MapItemType::HiddenapiClassDataItem),
+            _ => None
+        }
+    }
+}
+
+/// An entry of the map list
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapItem {
+    /// Type of the items
+    pub kind: MapItemType,
+    /// Number of items
+    pub size: u32,
+    /// Offset of the first item from the start of the file
+    pub offset: u32,
+}
+
+/// Map list of a DEX file
+#[derive(Debug, Default)]
+pub struct MapList {
+    /// Entries of the map list, in the order of the file
+    pub items: Vec<MapItem>,
+}
+
+impl MapList {
+    /// Parse the map list at the given offset of a (little-endian) DEX file
+    pub fn build(raw: &[u8], offset: usize) -> Result<Self, DexError> {
+        if !offset.is_multiple_of(4) {
+            return Err(DexError::InvalidMapList);
+        }
+
+        let size = read_u32_at(raw, offset).map_err(|_| DexError::InvalidMapList)? as usize;
+        if size > (raw.len() - offset) / MAP_ITEM_SIZE {
+            return Err(DexError::InvalidMapList);
+        }
+
+        let mut items = Vec::with_capacity(size);
+        for idx in 0..size {
+            let item_offset = offset + 4 + idx * MAP_ITEM_SIZE;
+            let kind = read_u16_at(raw, item_offset)?;
+            items.push(MapItem {
+                kind: MapItemType::parse(kind).ok_or(DexError::InvalidMapList)?,
+                size: read_u32_at(raw, item_offset + 4)?,
+                offset: read_u32_at(raw, item_offset + 8)?,
+            });
+        }
+
+        Ok(MapList { items })
+    }
+
+    /// Get the entry for the given type of items
+    pub fn get(&self, kind: MapItemType) -> Option<&MapItem> {
+        self.items.iter().find(|item| item.kind == kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fake_dex;
+
+    #[test]
+    fn test_build() {
+        let raw = fake_dex(&["La;"], &["Lb;"]);
+        let map_off = read_u32_at(&raw, 0x34).unwrap() as usize;
+        let map = MapList::build(&raw, map_off).unwrap();
+
+        assert_eq!(map.items[0], MapItem { kind: MapItemType::HeaderItem, size: 1, offset: 0 });
+        assert_eq!(map.get(MapItemType::TypeIdItem).unwrap().size, 2);
+        assert_eq!(map.get(MapItemType::MapList).unwrap().offset as usize, map_off);
+        assert!(map.get(MapItemType::CodeItem).is_none());
+
+        assert!(MapList::build(&raw, map_off + 2).is_err());
+        assert!(MapList::build(&raw, raw.len() - 4).is_err());
+    }
+}
@@ -9,7 +9,7 @@
 use std::io::{Seek, SeekFrom};
 use std::cmp::Ordering;
 
-use crate::error::DexError;
+use crate::error::{ DexError, ErrorContext };
 use crate::dex::reader::DexReader;
 use crate::dex::types::DexTypes;
 use crate::dex::protos::DexProtos;
@@ -58,7 +58,7 @@ impl DexMethods {
                  strings_list: &DexStrings) -> Result<Self, DexError> {
         dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
 
-        let mut methods = Vec::new();
+        let mut methods = Vec::with_capacity(dex_reader.check_count(size, 8)?);
 
         for _ in 0..size {
             let class_idx = dex_reader.read_u16()?;
@@ -66,10 +66,11 @@ impl DexMethods {
             let name_idx = dex_reader.read_u32()?;
 
             let mut decoded = String::new();
-            decoded.push_str(types_list.items.get(class_idx as usize).ok_or(DexError::InvalidTypeIdx)?);
+            decoded.push_str(types_list.items.get(class_idx as usize).ok_or(DexError::InvalidTypeIdx).at_index(class_idx.into())?);
             decoded.push_str("->");
-            decoded.push_str(strings_list.strings.get(name_idx as usize).ok_or(DexError::InvalidStringIdx)?);
-            decoded.push_str(protos_list.items.get(proto_idx as usize).ok_or(DexError::InvalidTypeIdx)?); 
+            decoded.push_str(strings_list.strings.get(name_idx as usize).ok_or(DexError::InvalidStringIdx).at_index(name_idx.into())?);
+            decoded.push_str(protos_list.items.get(proto_idx as usize).ok_or(DexError::InvalidTypeIdx).at_index(proto_idx.into())?); 
+            dex_reader.charge(decoded.len())?;
 
             methods.push(MethodIdItem {
                 class_idx,
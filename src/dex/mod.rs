@@ -11,3 +11,19 @@ pub mod types;
 pub mod protos;
 pub mod fields;
 pub mod code_item;
+pub mod encoded_values;
+pub mod odex;
+pub mod map;
+pub mod carving;
+pub mod repair;
+pub mod integrity;
+pub mod options;
+pub mod diagnostics;
+pub mod verifier;
+pub mod code_verifier;
+pub mod anomalies;
+pub mod layout;
+pub mod debug_info;
+pub mod dexdump;
+pub mod view;
+pub mod lazy;
@@ -0,0 +1,323 @@
+//! Optimized DEX files
+//!
+//! Before ART, Dalvik optimized the DEX files of an app at install time with `dexopt` and stored
+//! the result in `.odex` files (or in the Dalvik cache). An ODEX file starts with its own header,
+//! followed by the optimized DEX file, the list of libraries it was optimized against, and some
+//! auxiliary data (class lookup table, register maps).
+//!
+//! The optimized DEX file has a regular DEX header, but its bytecode contains instructions only
+//! Dalvik understands: accesses to volatile fields, field accesses by offset (`iget-quick`),
+//! calls through the vtable (`invoke-virtual-quick`), inlined calls (`execute-inline`), etc. The
+//! reader returned by `OdexFile::dex_reader` is configured to decode them.
+
+use crate::bytes::{ slice_at, read_u32_at, read_c_string_at, align_up };
+use crate::dex::reader::DexReader;
+use crate::error::DexError;
+
+/// Magic bytes at the start of an ODEX file (the version follows)
+pub const ODEX_FILE_MAGIC: [u8; 4] = *b"dey\n";
+/// Size of the ODEX header
+const ODEX_HEADER_SIZE: usize = 40;
+/// Size of the SHA-1 signature of each dependency
+const DEPENDENCY_SIGNATURE_SIZE: usize = 20;
+/// Type of the chunk ending the optimized data
+const END_CHUNK: u32 = 0x41454e44;
+
+/// Representation of the header of an ODEX file
+#[derive(Debug)]
+pub struct OdexHeader {
+    /// Version of the ODEX format (`036` for all Dalvik releases since Android 2.0)
+    pub version: [u8; 3],
+    /// Offset of the optimized DEX file
+    pub dex_offset: u32,
+    /// Size of the optimized DEX file
+    pub dex_length: u32,
+    /// Offset of the dependencies table
+    pub deps_offset: u32,
+    /// Size of the dependencies table
+    pub deps_length: u32,
+    /// Offset of the optimized data chunks
+    pub opt_offset: u32,
+    /// Size of the optimized data chunks
+    pub opt_length: u32,
+    /// Optimization flags (`DEX_OPT_FLAG_*` values)
+    pub flags: u32,
+    /// Adler-32 checksum of the dependencies and optimized data
+    pub checksum: u32,
+}
+
+/// A library the optimized DEX file depends on
+#[derive(Debug, Clone, PartialEq)]
+pub struct OdexDependency {
+    /// Path of the library (e.g., `/system/framework/core.jar`)
+    pub name: String,
+    /// SHA-1 signature of the library's DEX file at optimization time
+    pub signature: [u8; 20],
+}
+
+/// Dependencies table of an ODEX file
+///
+/// The optimizations are only valid against the exact libraries listed here: field offsets and
+/// vtable indices refer to their classes.
+#[derive(Debug)]
+pub struct OdexDependencies {
+    /// Modification time of the source file
+    pub mod_when: u32,
+    /// CRC32 of the source file
+    pub crc: u32,
+    /// Version of the Dalvik VM which optimized the file
+    pub vm_build: u32,
+    /// Libraries the DEX file was optimized against
+    pub dependencies: Vec<OdexDependency>,
+}
+
+/// A chunk of optimized data (class lookup table, register maps, etc.)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OdexChunk {
+    /// Type of the chunk, as four ASCII characters (e.g., `CLKP`, `RMAP`)
+    pub kind: u32,
+    /// Offset of the chunk data in the ODEX file
+    pub offset: u32,
+    /// Size of the chunk data
+    pub size: u32,
+}
+
+/// Representation of an ODEX file
+#[derive(Debug)]
+pub struct OdexFile {
+    /// Header of the file
+    pub header: OdexHeader,
+    /// Dependencies table
+    pub dependencies: OdexDependencies,
+    /// Chunks of optimized data
+    pub chunks: Vec<OdexChunk>,
+    /// Raw bytes of the optimized DEX file
+    pub dex: Vec<u8>,
+}
+
+impl OdexFile {
+    /// Open the ODEX file at the given path and parse it
+    pub fn build_from_file(filepath: &str) -> Result<Self, DexError> {
+        let raw = std::fs::read(filepath)?;
+        OdexFile::build(&raw)
+    }
+
+    /// Parse an ODEX file from its raw bytes
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        let magic = slice_at(raw, 0, 8)?;
+        if magic[0..4] != ODEX_FILE_MAGIC {
+            return Err(DexError::InvalidOdexMagic);
+        }
+        if !matches!(&magic[4..8], b"035\0" | b"036\0") {
+            return Err(DexError::UnsupportedOdexVersion);
+        }
+        if raw.len() < ODEX_HEADER_SIZE {
+            return Err(DexError::DexHeaderTooShortError);
+        }
+
+        let header = OdexHeader {
+            version:     [magic[4], magic[5], magic[6]],
+            dex_offset:  read_u32_at(raw, 8)?,
+            dex_length:  read_u32_at(raw, 12)?,
+            deps_offset: read_u32_at(raw, 16)?,
+            deps_length: read_u32_at(raw, 20)?,
+            opt_offset:  read_u32_at(raw, 24)?,
+            opt_length:  read_u32_at(raw, 28)?,
+            flags:       read_u32_at(raw, 32)?,
+            checksum:    read_u32_at(raw, 36)?,
+        };
+
+        let dex = slice_at(raw, header.dex_offset as usize, header.dex_length as usize)?.to_vec();
+        let deps = slice_at(raw, header.deps_offset as usize, header.deps_length as usize)?;
+        let dependencies = OdexFile::parse_dependencies(deps)?;
+        let opt = slice_at(raw, header.opt_offset as usize, header.opt_length as usize)?;
+        let chunks = OdexFile::parse_chunks(opt, header.opt_offset)?;
+
+        Ok(OdexFile {
+            header,
+            dependencies,
+            chunks,
+            dex,
+        })
+    }
+
+    /// Parse the dependencies table
+    fn parse_dependencies(deps: &[u8]) -> Result<OdexDependencies, DexError> {
+        let mod_when = read_u32_at(deps, 0)?;
+        let crc      = read_u32_at(deps, 4)?;
+        let vm_build = read_u32_at(deps, 8)?;
+        let count    = read_u32_at(deps, 12)?;
+
+        let mut dependencies = Vec::new();
+        let mut offset = 16;
+        for _ in 0..count {
+            // The length includes the NUL terminator
+            let name_len = read_u32_at(deps, offset)? as usize;
+            slice_at(deps, offset + 4, name_len)?;
+            let name = read_c_string_at(deps, offset + 4)?;
+            offset += 4 + name_len;
+
+            let mut signature = [0u8; DEPENDENCY_SIGNATURE_SIZE];
+            signature.copy_from_slice(slice_at(deps, offset, DEPENDENCY_SIGNATURE_SIZE)?);
+            offset += DEPENDENCY_SIGNATURE_SIZE;
+
+            dependencies.push(OdexDependency { name, signature });
+        }
+
+        Ok(OdexDependencies {
+            mod_when,
+            crc,
+            vm_build,
+            dependencies,
+        })
+    }
+
+    /// Parse the list of optimized data chunks
+    ///
+    /// Each chunk starts with its type and size, and is padded to 8 bytes. The list ends with an
+    /// `AEND` chunk.
+    fn parse_chunks(opt: &[u8], opt_offset: u32) -> Result<Vec<OdexChunk>, DexError> {
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+
+        while offset < opt.len() {
+            let kind = read_u32_at(opt, offset)?;
+            if kind == END_CHUNK {
+                break;
+            }
+
+            let size = read_u32_at(opt, offset + 4)?;
+            slice_at(opt, offset + 8, size as usize)?;
+            chunks.push(OdexChunk {
+                kind,
+                offset: opt_offset + offset as u32 + 8,
+                size,
+            });
+
+            offset = align_up(offset + 8 + size as usize, 8);
+        }
+
+        Ok(chunks)
+    }
+
+    /// Create a reader for the optimized DEX file
+    ///
+    /// The reader decodes the optimized opcodes, which are not valid in regular DEX files.
+    pub fn dex_reader(&self) -> Result<DexReader<'static>, DexError> {
+        let mut reader = DexReader::build(self.dex.clone())?;
+        reader.quickened = true;
+        Ok(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fake_dex_header;
+    use crate::dex::instructions::{ parse_instruction, Instructions };
+    use crate::dex::opcodes::OpCode;
+
+    #[test]
+    fn test_build_invalid_magic() {
+        let raw = [0u8; ODEX_HEADER_SIZE];
+        assert_eq!(OdexFile::build(&raw).unwrap_err().to_string(), "invalid ODEX magic");
+
+        let mut raw = [0u8; ODEX_HEADER_SIZE];
+        raw[0..8].copy_from_slice(b"dey\n099\0");
+        assert_eq!(OdexFile::build(&raw).unwrap_err().to_string(), "unsupported ODEX version");
+    }
+
+    #[test]
+    fn test_build() {
+        let dex = fake_dex_header(0x70);
+
+        let mut deps = Vec::new();
+        deps.extend_from_slice(&0x1234u32.to_le_bytes());       // mod_when
+        deps.extend_from_slice(&0x5678u32.to_le_bytes());       // crc
+        deps.extend_from_slice(&27u32.to_le_bytes());           // vm_build
+        deps.extend_from_slice(&1u32.to_le_bytes());            // one dependency
+        deps.extend_from_slice(&10u32.to_le_bytes());
+        deps.extend_from_slice(b"core.jar\0\0");
+        deps.extend_from_slice(&[0xaa; 20]);
+
+        let mut opt = Vec::new();
+        opt.extend_from_slice(b"PKLC");
+        opt.extend_from_slice(&4u32.to_le_bytes());
+        opt.extend_from_slice(&[1, 2, 3, 4, 0, 0, 0, 0]);       // data + padding
+        opt.extend_from_slice(&END_CHUNK.to_le_bytes());
+        opt.extend_from_slice(&0u32.to_le_bytes());
+
+        let dex_off = ODEX_HEADER_SIZE;
+        let deps_off = dex_off + dex.len();
+        let opt_off = align_up(deps_off + deps.len(), 8);
+
+        let mut raw = Vec::new();
+        raw.extend_from_slice(b"dey\n036\0");
+        for value in [dex_off, dex.len(), deps_off, deps.len(), opt_off, opt.len(), 0, 0] {
+            raw.extend_from_slice(&(value as u32).to_le_bytes());
+        }
+        raw.extend_from_slice(&dex);
+        raw.extend_from_slice(&deps);
+        raw.resize(opt_off, 0);
+        raw.extend_from_slice(&opt);
+
+        let odex = OdexFile::build(&raw).unwrap();
+        assert_eq!(&odex.header.version, b"036");
+        assert_eq!(odex.dex, dex);
+        assert_eq!(odex.dependencies.vm_build, 27);
+        assert_eq!(odex.dependencies.dependencies, vec![OdexDependency {
+            name: "core.jar".to_string(),
+            signature: [0xaa; 20],
+        }]);
+        assert_eq!(odex.chunks, vec![OdexChunk {
+            kind: u32::from_le_bytes(*b"PKLC"),
+            offset: opt_off as u32 + 8,
+            size: 4,
+        }]);
+        assert!(odex.dex_reader().unwrap().quickened);
+    }
+
+    #[test]
+    fn test_parse_quickened_instructions() {
+        let mut raw = fake_dex_header(0x70);
+        let code: [u16; 9] = [
+            0x21f2, 0x0008,                 // iget-quick v1, v2, [obj+0x8]
+            0x20f8, 0x0011, 0x0010,         // invoke-virtual-quick {v0, v1}, vtable@0x11
+            0x03ef, 0x0004, 0x0000,         // execute-inline/range {v0..v2}, inline@0x4
+            0x00f1,                         // return-void-barrier
+        ];
+        for unit in code {
+            raw.extend_from_slice(&unit.to_le_bytes());
+        }
+
+        let mut reader = DexReader::build(raw.clone()).unwrap();
+        reader.bytes.set_position(0x70);
+        let mut insns = Vec::new();
+        assert!(parse_instruction(&mut reader, &mut insns).is_err());
+
+        let mut reader = DexReader::build(raw).unwrap();
+        reader.quickened = true;
+        reader.bytes.set_position(0x70);
+        let mut insns = Vec::new();
+        while reader.bytes.position() < reader.bytes_len {
+            parse_instruction(&mut reader, &mut insns).unwrap();
+        }
+
+        let opcodes = insns.iter().map(|inst| inst.opcode()).collect::<Vec<OpCode>>();
+        assert_eq!(opcodes, vec![OpCode::IGET_QUICK,
+                                 OpCode::INVOKE_VIRTUAL_QUICK,
+                                 OpCode::EXECUTE_INLINE_RANGE,
+                                 OpCode::RETURN_VOID_BARRIER]);
+
+        match (&insns[0], &insns[1], &insns[2]) {
+            (Instructions::Instruction22cs(iget),
+             Instructions::Instruction35ms(invoke),
+             Instructions::Instruction3rmi(inline)) => {
+                assert_eq!(iget.field_offset(), 0x8);
+                assert_eq!(invoke.vtable_index(), 0x11);
+                assert_eq!(inline.inline_index(), 0x4);
+            },
+            _ => panic!("unexpected instruction formats"),
+        }
+    }
+}
@@ -236,6 +236,36 @@ pub enum OpCode {
     PACKED_SWITCH_PAYLOAD,
     SPARSE_SWITCH_PAYLOAD,
     FILL_ARRAY_DATA_PAYLOAD,
+
+    // Optimized opcodes, only found in ODEX files
+    IGET_VOLATILE,
+    IPUT_VOLATILE,
+    SGET_VOLATILE,
+    SPUT_VOLATILE,
+    IGET_OBJECT_VOLATILE,
+    IGET_WIDE_VOLATILE,
+    IPUT_WIDE_VOLATILE,
+    SGET_WIDE_VOLATILE,
+    SPUT_WIDE_VOLATILE,
+    BREAKPOINT,
+    THROW_VERIFICATION_ERROR,
+    EXECUTE_INLINE,
+    EXECUTE_INLINE_RANGE,
+    INVOKE_OBJECT_INIT_RANGE,
+    RETURN_VOID_BARRIER,
+    IGET_QUICK,
+    IGET_WIDE_QUICK,
+    IGET_OBJECT_QUICK,
+    IPUT_QUICK,
+    IPUT_WIDE_QUICK,
+    IPUT_OBJECT_QUICK,
+    INVOKE_VIRTUAL_QUICK,
+    INVOKE_VIRTUAL_QUICK_RANGE,
+    INVOKE_SUPER_QUICK,
+    INVOKE_SUPER_QUICK_RANGE,
+    IPUT_OBJECT_VOLATILE,
+    SGET_OBJECT_VOLATILE,
+    SPUT_OBJECT_VOLATILE,
 }
 
 
@@ -497,4 +527,80 @@ impl OpCode {
             0xff => Some(OpCode::CONST_METHOD_TYPE),          // Instruction 21c,
         }
     }
+
+    /// Converts an `u8` into an `OpCode`, decoding the optimized opcodes of ODEX files
+    ///
+    /// `dexopt` rewrites some instructions in place once the classes they reference are
+    /// resolved. These optimized opcodes reuse values which are either unused (`0xe3` to `0xf9`)
+    /// or only valid in recent DEX versions (`0xfa` to `0xfe`) in regular DEX files.
+    pub fn parse_quickened(value: u8) -> Option<Self> {
+        match value {
+            0xe3 => Some(OpCode::IGET_VOLATILE),              // Instruction 22c
+            0xe4 => Some(OpCode::IPUT_VOLATILE),              // Instruction 22c
+            0xe5 => Some(OpCode::SGET_VOLATILE),              // Instruction 21c
+            0xe6 => Some(OpCode::SPUT_VOLATILE),              // Instruction 21c
+            0xe7 => Some(OpCode::IGET_OBJECT_VOLATILE),       // Instruction 22c
+            0xe8 => Some(OpCode::IGET_WIDE_VOLATILE),         // Instruction 22c
+            0xe9 => Some(OpCode::IPUT_WIDE_VOLATILE),         // Instruction 22c
+            0xea => Some(OpCode::SGET_WIDE_VOLATILE),         // Instruction 21c
+            0xeb => Some(OpCode::SPUT_WIDE_VOLATILE),         // Instruction 21c
+            0xec => Some(OpCode::BREAKPOINT),                 // Instruction 10x
+            0xed => Some(OpCode::THROW_VERIFICATION_ERROR),   // Instruction 20bc
+            0xee => Some(OpCode::EXECUTE_INLINE),             // Instruction 35mi
+            0xef => Some(OpCode::EXECUTE_INLINE_RANGE),       // Instruction 3rmi
+            0xf0 => Some(OpCode::INVOKE_OBJECT_INIT_RANGE),   // Instruction 3rc
+            0xf1 => Some(OpCode::RETURN_VOID_BARRIER),        // Instruction 10x
+            0xf2 => Some(OpCode::IGET_QUICK),                 // Instruction 22cs
+            0xf3 => Some(OpCode::IGET_WIDE_QUICK),            // Instruction 22cs
+            0xf4 => Some(OpCode::IGET_OBJECT_QUICK),          // Instruction 22cs
+            0xf5 => Some(OpCode::IPUT_QUICK),                 // Instruction 22cs
+            0xf6 => Some(OpCode::IPUT_WIDE_QUICK),            // Instruction 22cs
+            0xf7 => Some(OpCode::IPUT_OBJECT_QUICK),          // Instruction 22cs
+            0xf8 => Some(OpCode::INVOKE_VIRTUAL_QUICK),       // Instruction 35ms
+            0xf9 => Some(OpCode::INVOKE_VIRTUAL_QUICK_RANGE), // Instruction 3rms
+            0xfa => Some(OpCode::INVOKE_SUPER_QUICK),         // Instruction 35ms
+            0xfb => Some(OpCode::INVOKE_SUPER_QUICK_RANGE),   // Instruction 3rms
+            0xfc => Some(OpCode::IPUT_OBJECT_VOLATILE),       // Instruction 22c
+            0xfd => Some(OpCode::SGET_OBJECT_VOLATILE),       // Instruction 21c
+            0xfe => Some(OpCode::SPUT_OBJECT_VOLATILE),       // Instruction 21c
+
+            // Unused
+            0xff => {
+                warn!("use of unused opcode {}", value);
+                None
+            },
+
+            _ => OpCode::parse(value),
+        }
+    }
+
+    /// Get the mnemonic of the opcode, as used by the Dalvik tools (e.g. `const/4`)
+    pub fn mnemonic(&self) -> String {
+        const SUFFIXES: [&str; 10] = ["4", "16", "32", "from16", "high16", "2addr",
+                                      "lit8", "lit16", "range", "jumbo"];
+
+        let name = format!("{self:?}").to_lowercase().replace('_', "-");
+        match name.rsplit_once('-') {
+            Some((prefix, suffix)) if SUFFIXES.contains(&suffix) => format!("{prefix}/{suffix}"),
+            _ => name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mnemonic() {
+        assert_eq!(OpCode::NOP.mnemonic(), "nop");
+        assert_eq!(OpCode::CONST_4.mnemonic(), "const/4");
+        assert_eq!(OpCode::MOVE_WIDE_FROM16.mnemonic(), "move-wide/from16");
+        assert_eq!(OpCode::CONST_WIDE_HIGH16.mnemonic(), "const-wide/high16");
+        assert_eq!(OpCode::CONST_STRING_JUMBO.mnemonic(), "const-string/jumbo");
+        assert_eq!(OpCode::ADD_INT_2ADDR.mnemonic(), "add-int/2addr");
+        assert_eq!(OpCode::RSUB_INT_LIT8.mnemonic(), "rsub-int/lit8");
+        assert_eq!(OpCode::INVOKE_VIRTUAL_QUICK_RANGE.mnemonic(), "invoke-virtual-quick/range");
+        assert_eq!(OpCode::MOVE_RESULT_OBJECT.mnemonic(), "move-result-object");
+    }
 }
@@ -0,0 +1,69 @@
+//! Options of the DEX parser
+
+use crate::dex::integrity::IntegrityPolicy;
+
+/// Options controlling how DEX files are parsed
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// How to handle a checksum which does not match the contents
+    pub checksum: IntegrityPolicy,
+    /// How to handle a SHA-1 signature which does not match the contents
+    pub signature: IntegrityPolicy,
+    /// Skip the classes and methods which cannot be parsed instead of failing, and keep the code
+    /// which cannot be decoded as raw code units
+    ///
+    /// Everything skipped is reported in the diagnostics of the `DexFile`.
+    pub recover: bool,
+    /// Bounds on the resources used to parse a file
+    pub limits: ResourceLimits,
+}
+
+/// Bounds on the resources used to parse a DEX file
+///
+/// Counts and sizes read from the file are checked against these limits before anything is
+/// allocated, so that a crafted file cannot exhaust memory. Exceeding a limit fails the parse with
+/// `DexError::ResourceLimitExceeded`, even in recovery mode.
+#[derive(Debug, Clone)]
+pub struct ResourceLimits {
+    /// Maximum size of the file, in bytes
+    pub max_file_size: u64,
+    /// Maximum number of items in a list (IDs section, class definitions, class members, catch
+    /// handlers)
+    pub max_items: u32,
+    /// Maximum number of code units in the bytecode of a method
+    pub max_code_units: u32,
+    /// Maximum nesting depth of encoded arrays and annotations
+    pub max_nesting_depth: usize,
+    /// Maximum number of bytes decoded from the file (strings, identifiers, bytecode)
+    ///
+    /// Items can be referenced many times, e.g. class data shared by several classes, so this
+    /// bounds the total amount of work and memory spent on a file.
+    pub max_decoded_size: u64,
+}
+
+impl Default for ResourceLimits {
+    /// Limits well above what the DEX files produced by Android toolchains need
+    fn default() -> Self {
+        ResourceLimits {
+            max_file_size: 1 << 30,
+            max_items: 1 << 24,
+            max_code_units: 1 << 20,
+            max_nesting_depth: 64,
+            max_decoded_size: 1 << 30,
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    /// Fail on invalid checksums and malformed items, and do not check signatures
+    ///
+    /// Checking the signature hashes the whole file, so it has to be enabled explicitly.
+    fn default() -> Self {
+        ParseOptions {
+            checksum: IntegrityPolicy::Strict,
+            signature: IntegrityPolicy::Skip,
+            recover: false,
+            limits: ResourceLimits::default(),
+        }
+    }
+}
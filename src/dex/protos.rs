@@ -7,7 +7,7 @@ use std::cmp::Ordering;
 
 use crate::dex::reader::DexReader;
 use crate::dex::types::DexTypes;
-use crate::error::DexError;
+use crate::error::{ DexError, ErrorContext };
 
 /// Internal representation of a prototype index
 #[derive(Debug)]
@@ -46,7 +46,7 @@ impl DexProtos {
                  types_list: &DexTypes) -> Result<Self, DexError> {
         dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
 
-        let mut protos = Vec::new();
+        let mut protos = Vec::with_capacity(dex_reader.check_count(size, 12)?);
 
         for _ in 0..size {
             let shorty_idx = dex_reader.read_u32()?;
@@ -68,11 +68,12 @@ impl DexProtos {
 
                 proto.push('(');
                 let params_size = dex_reader.read_u32()?;
+                dex_reader.check_count(params_size, 2)?;
                 for idx in 0..params_size {
                     let offset = dex_reader.read_u16()?;
                     parameters_off_list.push(offset);
 
-                    proto.push_str(types_list.items.get(offset as usize).ok_or(DexError::InvalidTypeIdx)?);
+                    proto.push_str(types_list.items.get(offset as usize).ok_or(DexError::InvalidTypeIdx).at_index(offset.into())?);
                     if idx < params_size - 1 {
                         proto.push(' ');
                     }
@@ -82,7 +83,8 @@ impl DexProtos {
                 // Go back to the previous position
                 dex_reader.bytes.seek(SeekFrom::Start(current_pos))?;
             }
-            proto.push_str(types_list.items.get(return_type_idx as usize).ok_or(DexError::InvalidTypeIdx)?);
+            proto.push_str(types_list.items.get(return_type_idx as usize).ok_or(DexError::InvalidTypeIdx).at_index(return_type_idx.into())?);
+            dex_reader.charge(proto.len())?;
 
             protos.push(ProtoIdItem {
                 shorty_idx,
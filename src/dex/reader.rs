@@ -2,12 +2,23 @@
 //!
 //! This module defines all the methods to read bytes from the DEX file while respecint ght
 //! endianess which can change from one DEX file to another.
+//!
+//! A reader either owns the bytes of the DEX file or borrows them, e.g. from a memory-mapped
+//! file, in which case the DEX file is never copied. DEX files stored uncompressed in an APK are
+//! borrowed from the mapping of the whole archive. Note that parsing a `DexFile` from a borrowed
+//! reader still copies the decoded strings and bytecode: use `DexView` to access them without
+//! copies.
 
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{ Read, Cursor, Seek, SeekFrom };
-use zip::ZipArchive;
+use std::io::{ Read, Cursor, Seek };
+use memmap2::Mmap;
+use zip::{ CompressionMethod, ZipArchive };
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 
+use crate::dex::diagnostics::Diagnostic;
+use crate::dex::header::DexKind;
+use crate::dex::options::ResourceLimits;
 use crate::error::DexError;
 
 /// Little-endian DEX file
@@ -24,38 +35,72 @@ pub enum DexEndianness {
 
 /// A reader for a DEX file
 #[derive(Debug)]
-pub struct DexReader {
-    /// A cursor over the bytes of the DEX file
-    pub bytes: Cursor<Vec<u8>>,
+pub struct DexReader<'a> {
+    /// A cursor over the bytes of the DEX file, owned or borrowed
+    pub bytes: Cursor<Cow<'a, [u8]>>,
     /// Number of bytes in the DEX file
     pub bytes_len: u64,
     /// Endianness of the DEX file
     pub endianness: DexEndianness,
+    /// Whether the bytecode contains the optimized opcodes of ODEX files
+    pub quickened: bool,
+    /// Anomalies found so far while parsing
+    pub diagnostics: Vec<Diagnostic>,
+    /// Whether to skip the classes and methods which cannot be parsed instead of failing
+    pub recover: bool,
+    /// Bounds on the resources used to parse the file
+    pub limits: ResourceLimits,
+    /// Number of bytes decoded so far, checked against `limits.max_decoded_size`
+    decoded_size: u64,
+}
+
+/// Map a file in memory, to create readers borrowing its bytes with `build_from_mmap`
+///
+/// The file must not be modified while it is mapped.
+pub fn map_file(filepath: &str) -> Result<Mmap, DexError> {
+    let file = File::open(filepath)?;
+    // SAFETY: the mapping is read-only, and the caller must not modify the file while it is
+    // mapped (as documented above)
+    let map = unsafe { Mmap::map(&file)? };
+
+    Ok(map)
 }
 
-impl DexReader {
+/// Names of the DEX files of an archive, in loading order: `classes.dex`, `classes2.dex`, etc.
+/// then the other DEX files by name
+fn dex_entries_names<R: Read + Seek>(zip_file: &ZipArchive<R>) -> Vec<String> {
+    let mut names = zip_file.file_names()
+                            .filter(|name| name.ends_with(".dex"))
+                            .map(|name| name.to_string())
+                            .collect::<Vec<String>>();
+
+    names.sort_by_cached_key(|name| {
+        let index = match name.strip_prefix("classes").and_then(|name| name.strip_suffix(".dex")) {
+            Some("") => Some(1),
+            Some(index) => index.parse::<u32>().ok().filter(|&index| index > 1),
+            None => None,
+        };
+        (index.is_none(), index, name.clone())
+    });
+
+    names
+}
+
+impl DexReader<'static> {
     /// Open the file at the given path and create reader(s)
     ///
     /// Each APK can contain multiple DEX files. This function extracts them all, create a reader
     /// from each, and returns a vector of readers.
-    pub fn build_from_file(filepath: &str) -> Result<Vec<DexReader>, DexError> {
-        let raw_file = File::open(filepath)
-            .unwrap_or_else(|err| panic!("could not open input file: {err}"));
-        let mut zip_file = ZipArchive::new(raw_file)
-            .unwrap_or_else(|err| panic!("could not create ZipArchive object: {err}"));
-
-        let dex_entries_names = zip_file.file_names()
-                                        .filter(|name| name.ends_with(".dex"))
-                                        .map(|name| name.to_string())
-                                        .collect::<Vec<String>>();
+    pub fn build_from_file(filepath: &str) -> Result<Vec<DexReader<'static>>, DexError> {
+        let raw_file = File::open(filepath)?;
+        let mut zip_file = ZipArchive::new(raw_file).map_err(|_| DexError::InvalidZipArchive)?;
+        let dex_entries_names = dex_entries_names(&zip_file);
 
         let mut readers = Vec::new();
         for entry in dex_entries_names.iter() {
-            let mut dex_entry = zip_file.by_name(entry)
-                                        .unwrap_or_else(|_| panic!("cannot find classes.dex in the APK"));
+            let mut dex_entry = zip_file.by_name(entry).map_err(|_| DexError::InvalidZipArchive)?;
             let mut raw_dex = Vec::new();
-            dex_entry.read_to_end(&mut raw_dex)
-                     .unwrap_or_else(|err| panic!("Could not read input file: {err}"));
+            dex_entry.read_to_end(&mut raw_dex)?;
             let reader = DexReader::build(raw_dex)?;
             readers.push(reader);
         }
@@ -63,21 +108,83 @@ impl DexReader {
         Ok(readers)
     }
 
-    /// Read a DEX file and create a reader from it
+    /// Read a DEX file and create a reader owning its bytes
     pub fn build(raw_dex: Vec<u8>) -> Result<Self, DexError> {
-        let endianness = DexReader::check_endianness(&raw_dex)?;
+        DexReader::from_cow(Cow::Owned(raw_dex))
+    }
+}
+
+impl<'a> DexReader<'a> {
+    /// Create a reader borrowing the bytes of a DEX file, without copying them
+    pub fn from_slice(raw_dex: &'a [u8]) -> Result<Self, DexError> {
+        DexReader::from_cow(Cow::Borrowed(raw_dex))
+    }
+
+    /// Create reader(s) borrowing the bytes of a memory-mapped APK or DEX file
+    ///
+    /// DEX files stored uncompressed in an APK are borrowed from the mapping, while compressed
+    /// ones have to be inflated and are owned by their reader.
+    pub fn build_from_mmap(raw: &'a [u8]) -> Result<Vec<Self>, DexError> {
+        if DexKind::from_magic(raw) == Some(DexKind::Dex) {
+            return Ok(vec![DexReader::from_slice(raw)?]);
+        }
 
-        let mut bytes = Cursor::new(raw_dex);
-        let bytes_len = bytes.seek(SeekFrom::End(0))?;
-        bytes.rewind()?;
+        let mut zip_file = ZipArchive::new(Cursor::new(raw)).map_err(|_| DexError::InvalidZipArchive)?;
+        let dex_entries_names = dex_entries_names(&zip_file);
+
+        let mut readers = Vec::new();
+        for entry in dex_entries_names.iter() {
+            let mut dex_entry = zip_file.by_name(entry).map_err(|_| DexError::InvalidZipArchive)?;
+            let reader = match dex_entry.compression() {
+                CompressionMethod::Stored => {
+                    let start = usize::try_from(dex_entry.data_start()).map_err(|_| DexError::InvalidZipArchive)?;
+                    let size = usize::try_from(dex_entry.size()).map_err(|_| DexError::InvalidZipArchive)?;
+                    let raw_dex = start.checked_add(size)
+                                       .and_then(|end| raw.get(start..end))
+                                       .ok_or(DexError::InvalidZipArchive)?;
+                    DexReader::from_slice(raw_dex)?
+                },
+                _ => {
+                    let mut raw_dex = Vec::new();
+                    dex_entry.read_to_end(&mut raw_dex)?;
+                    DexReader::build(raw_dex)?
+                },
+            };
+            readers.push(reader);
+        }
+
+        Ok(readers)
+    }
+
+    fn from_cow(raw_dex: Cow<'a, [u8]>) -> Result<Self, DexError> {
+        let endianness = DexReader::check_endianness(&raw_dex)?;
+        let bytes_len = raw_dex.len() as u64;
 
         Ok(DexReader {
-            bytes,
+            bytes: Cursor::new(raw_dex),
             bytes_len,
-            endianness
+            endianness,
+            quickened: false,
+            diagnostics: Vec::new(),
+            recover: false,
+            limits: ResourceLimits::default(),
+            decoded_size: 0,
         })
     }
 
+    /// Bytes of the DEX file
+    pub fn raw(&self) -> &[u8] {
+        self.bytes.get_ref()
+    }
+
+    /// Bytes of the DEX file, if they are borrowed rather than owned by the reader
+    pub fn borrowed(&self) -> Option<&'a [u8]> {
+        match self.bytes.get_ref() {
+            Cow::Borrowed(raw) => Some(raw),
+            Cow::Owned(_) => None,
+        }
+    }
+
     /// Check the endianness of a DEX file
     pub fn check_endianness(bytes: &[u8]) -> Result<DexEndianness, DexError> {
         // Cannot use self here as we need to know the endianness before anything else
@@ -86,10 +193,7 @@ impl DexReader {
             return Err(DexError::DexHeaderTooShortError);
         }
 
-        let endian_tag = &bytes[40..44];
-
-        // try_into to convert the slice into an array
-        match endian_tag.try_into().unwrap() {
+        match [bytes[40], bytes[41], bytes[42], bytes[43]] {
             ENDIAN_CONSTANT => Ok(DexEndianness::BigEndian),
             REVERSE_ENDIAN_CONSTANT => Ok(DexEndianness::LittleEndian),
             _ => Err(DexError::InvalidEndianessTag)
@@ -99,13 +203,45 @@ impl DexReader {
     /// Check if the cursor is on an even-numbered bytecode offsets
     /// and, if not, consume data until it is
     pub fn align_cursor(&mut self) -> Result<(), DexError> {
-        while self.bytes.position() % 2 != 0 {
+        while !self.bytes.position().is_multiple_of(2) {
             let _ = self.read_u8()?;
         }
 
         Ok(())
     }
 
+    /// Number of bytes left after the current position
+    pub fn remaining(&self) -> u64 {
+        self.bytes_len.saturating_sub(self.bytes.position())
+    }
+
+    /// Check that a list of `count` items of at least `item_size` bytes each can start at the
+    /// current position, and return the capacity to reserve for it
+    ///
+    /// The list must fit in the rest of the file and within the limits of the reader, so that
+    /// counts read from the file never lead to unbounded allocations.
+    pub fn check_count(&self, count: u32, item_size: u64) -> Result<usize, DexError> {
+        if count > self.limits.max_items {
+            return Err(DexError::ResourceLimitExceeded);
+        }
+        if u64::from(count) * item_size > self.remaining() {
+            return Err(DexError::NoDataLeftError);
+        }
+
+        Ok(count as usize)
+    }
+
+    /// Account for `size` bytes decoded from the file, failing once the total exceeds the
+    /// `max_decoded_size` limit
+    pub fn charge(&mut self, size: usize) -> Result<(), DexError> {
+        self.decoded_size = self.decoded_size.saturating_add(size as u64);
+        if self.decoded_size > self.limits.max_decoded_size {
+            return Err(DexError::ResourceLimitExceeded);
+        }
+
+        Ok(())
+    }
+
     /// Read an unsigned 8 bits integer from the reader
     pub fn read_u8(&mut self) -> Result<u8, DexError> {
         if self.bytes.position() >= self.bytes_len {
@@ -117,7 +253,7 @@ impl DexReader {
 
     /// Read an unsigned 16 bits integer from the reader
     pub fn read_u16(&mut self) -> Result<u16, DexError> {
-        if self.bytes.position() > self.bytes_len - 2 {
+        if self.remaining() < 2 {
             return Err(DexError::NoDataLeftError);
         }
 
@@ -129,7 +265,7 @@ impl DexReader {
 
     /// Read an unsigned 32 bits integer from the reader
     pub fn read_u32(&mut self) -> Result<u32, DexError> {
-        if self.bytes.position() > self.bytes_len - 4 {
+        if self.remaining() < 4 {
             return Err(DexError::NoDataLeftError);
         }
 
@@ -141,7 +277,7 @@ impl DexReader {
 
     /// Read a signed 32 bits integer from the reader
     pub fn read_i32(&mut self) -> Result<i32, DexError> {
-        if self.bytes.position() > self.bytes_len - 4 {
+        if self.remaining() < 4 {
             return Err(DexError::NoDataLeftError);
         }
 
@@ -201,7 +337,7 @@ impl DexReader {
         }
 
         let mut result = result as i32;
-        if (byte & 0b0100_0000) == 0b0100_0000 {
+        if shift < 32 && (byte & 0b0100_0000) == 0b0100_0000 {
             /* sign extend */
             result |= -(1 << shift);
         }
@@ -221,6 +357,9 @@ impl DexReader {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::{ Seek, SeekFrom, Write };
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
 
     const DEX_DATA: [u8; 50] = [
         0x64, 0x65, 0x78, 0x0a, 0x30, 0x33, 0x35, 0x00, 0x00, 0x00,  // DEX magic
@@ -235,6 +374,52 @@ mod tests {
         let dex_reader = DexReader::build(DEX_DATA.to_vec()).unwrap();
         assert_eq!(dex_reader.bytes_len, DEX_DATA.len() as u64);
         assert_eq!(dex_reader.endianness, DexEndianness::LittleEndian);
+        assert!(dex_reader.borrowed().is_none());
+    }
+
+    #[test]
+    fn test_from_slice() {
+        let dex_reader = DexReader::from_slice(&DEX_DATA).unwrap();
+        assert_eq!(dex_reader.bytes_len, DEX_DATA.len() as u64);
+        assert_eq!(dex_reader.borrowed().unwrap().as_ptr(), DEX_DATA.as_ptr());
+        assert_eq!(dex_reader.raw(), &DEX_DATA);
+    }
+
+    #[test]
+    fn test_build_from_mmap() {
+        let readers = DexReader::build_from_mmap(&DEX_DATA).unwrap();
+        assert_eq!(readers.len(), 1);
+        assert!(readers[0].borrowed().is_some());
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer.start_file("classes.dex", FileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+        writer.write_all(&DEX_DATA).unwrap();
+        writer.start_file("classes2.dex", FileOptions::default().compression_method(CompressionMethod::Deflated)).unwrap();
+        writer.write_all(&DEX_DATA).unwrap();
+        let apk = writer.finish().unwrap().into_inner();
+
+        let readers = DexReader::build_from_mmap(&apk).unwrap();
+        assert_eq!(readers.len(), 2);
+        // Stored entries are borrowed from the archive, deflated ones are inflated
+        let (stored, deflated): (Vec<&DexReader>, Vec<&DexReader>) = readers.iter().partition(|reader| reader.borrowed().is_some());
+        let stored = stored[0].borrowed().unwrap();
+        assert!(apk.as_ptr_range().contains(&stored.as_ptr()));
+        assert_eq!(stored, &DEX_DATA);
+        assert_eq!(deflated[0].raw(), &DEX_DATA);
+
+        assert!(matches!(DexReader::build_from_mmap(b"not a dex file"), Err(DexError::InvalidZipArchive)));
+    }
+
+    #[test]
+    fn test_dex_entries_names() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for name in ["classes10.dex", "assets/extra.dex", "classes2.dex", "classes.dex", "classes1.dex", "res/icon.png"] {
+            writer.start_file(name, FileOptions::default()).unwrap();
+        }
+        let zip_file = ZipArchive::new(writer.finish().unwrap()).unwrap();
+
+        assert_eq!(dex_entries_names(&zip_file),
+                   vec!["classes.dex", "classes2.dex", "classes10.dex", "assets/extra.dex", "classes1.dex"]);
     }
 
     #[test]
@@ -0,0 +1,221 @@
+//! Reconstruction of damaged DEX headers
+//!
+//! Unpackers and memory dumps often leave DEX files with a wiped or corrupted header while the
+//! rest of the file is intact. Every field of the header can be recovered from the map list: the
+//! sizes and offsets of the ID sections are listed there, the data section starts with the first
+//! data item, and the checksum and signature can be recomputed.
+//!
+//! If the `map_off` field of the header does not point to a valid map list, the file is scanned
+//! for one, starting from the end where compilers put it.
+
+use log::warn;
+
+use crate::bytes::{ read_u16_at, read_u32_at };
+use crate::dex::header::{ HEADER_SIZE, ENDIAN_CONSTANT };
+use crate::dex::integrity::fix_integrity;
+use crate::dex::verifier::is_data_item;
+use crate::dex::map::{ MapItemType, MapList };
+use crate::error::DexError;
+
+/// Offset of the `map_off` field in the header
+const MAP_OFF_OFFSET: usize = 0x34;
+
+/// Rebuild the header of a DEX file from its map list
+///
+/// Returns the repaired DEX file, truncated to its actual size. The version of the magic is kept
+/// if it is valid, and otherwise inferred from the sections present in the map list.
+pub fn repair_header(raw: &[u8]) -> Result<Vec<u8>, DexError> {
+    if raw.len() < HEADER_SIZE {
+        return Err(DexError::DexHeaderTooShortError);
+    }
+
+    let (map_off, map) = match read_u32_at(raw, MAP_OFF_OFFSET).ok()
+                                                                .and_then(|offset| check_map_list(raw, offset as usize)) {
+        Some(map_off) => (map_off, MapList::build(raw, map_off)?),
+        None => {
+            warn!("invalid map offset in header, scanning for the map list");
+            let map_off = find_map_list(raw).ok_or(DexError::InvalidMapList)?;
+            (map_off, MapList::build(raw, map_off)?)
+        }
+    };
+
+    // The map list is the last item of the file, unless the compiler decided otherwise
+    let map_end = map_off + 4 + 12 * map.items.len();
+    let file_size = if map.items.iter().all(|item| item.offset as usize <= map_off) {
+        map_end
+    } else {
+        raw.len()
+    };
+    let data_off = map.items.iter()
+                            .filter(|item| is_data_item(item.kind))
+                            .map(|item| item.offset as usize)
+                            .min()
+                            .unwrap_or(map_off);
+
+    let section = |kind| map.get(kind).map_or((0, 0), |item| (item.size, item.offset));
+    let sections = [section(MapItemType::StringIdItem),
+                    section(MapItemType::TypeIdItem),
+                    section(MapItemType::ProtoIdItem),
+                    section(MapItemType::FieldIdItem),
+                    section(MapItemType::MethodIdItem),
+                    section(MapItemType::ClassDefItem),
+                    ((file_size - data_off) as u32, data_off as u32)];
+
+    let mut dex = raw[..file_size].to_vec();
+    let magic = &raw[..8];
+    if magic.starts_with(b"dex\n0") && magic[5].is_ascii_digit() && magic[6].is_ascii_digit() && magic[7] == 0 {
+        dex[..8].copy_from_slice(magic);
+    } else {
+        dex[..8].copy_from_slice(infer_magic(&map));
+    }
+
+    let mut header = Vec::with_capacity(HEADER_SIZE - 32);
+    // No link section
+    for value in [file_size as u32, HEADER_SIZE as u32, ENDIAN_CONSTANT, 0, 0, map_off as u32] {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    for (size, offset) in sections {
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&offset.to_le_bytes());
+    }
+    dex[32..HEADER_SIZE].copy_from_slice(&header);
+
+    fix_integrity(&mut dex)?;
+
+    Ok(dex)
+}
+
+/// Check if the map list at the given offset is valid and describes the file it is in
+fn check_map_list(raw: &[u8], map_off: usize) -> Option<usize> {
+    if map_off < HEADER_SIZE {
+        return None;
+    }
+    let map = MapList::build(raw, map_off).ok()?;
+
+    let header_item = map.items.first()?;
+    let map_item = map.get(MapItemType::MapList)?;
+    if header_item.kind != MapItemType::HeaderItem || header_item.offset != 0 || map_item.offset as usize != map_off {
+        return None;
+    }
+    if map.items.iter().any(|item| item.offset as usize > raw.len()) {
+        return None;
+    }
+
+    Some(map_off)
+}
+
+/// Look for a valid map list, from the end of the file
+///
+/// Candidates are accepted on the same conditions as `check_map_list`, but parsing the map list at
+/// every offset would be quadratic in the size of the file. Instead, the file is walked once
+/// backwards to find, for every aligned offset, how many valid map items follow in a row and
+/// where the first one describing the map list is.
+fn find_map_list(raw: &[u8]) -> Option<usize> {
+    // Map items are 3 words long
+    let words = raw.len() / 4;
+    let mut valid_items = vec![0u32; words + 3];
+    let mut first_map_item = vec![None; words + 3];
+    for word in (0..words).rev() {
+        let offset = word * 4;
+        let kind = read_u16_at(raw, offset).ok().and_then(MapItemType::parse);
+        let item_offset = read_u32_at(raw, offset + 8).ok();
+        if kind.is_none() || item_offset.is_none_or(|item_offset| item_offset as usize > raw.len()) {
+            continue;
+        }
+
+        valid_items[word] = valid_items[word + 3] + 1;
+        first_map_item[word] = match kind {
+            Some(MapItemType::MapList) => Some(word),
+            _ => first_map_item[word + 3],
+        };
+    }
+
+    (HEADER_SIZE / 4..words).rev().map(|word| word * 4).find(|&map_off| {
+        let first_item = map_off / 4 + 1;
+        let Ok(size) = read_u32_at(raw, map_off) else {
+            return false;
+        };
+        size != 0
+            && size <= valid_items[first_item]
+            && read_u16_at(raw, map_off + 4).ok().and_then(MapItemType::parse) == Some(MapItemType::HeaderItem)
+            && read_u32_at(raw, map_off + 12).ok() == Some(0)
+            && first_map_item[first_item].is_some_and(|word| (word - first_item) / 3 < size as usize
+                                                             && read_u32_at(raw, word * 4 + 8).ok() == Some(map_off as u32))
+    })
+}
+
+/// Guess the DEX version from the sections introduced by each version
+fn infer_magic(map: &MapList) -> &'static [u8; 8] {
+    if map.get(MapItemType::HiddenapiClassDataItem).is_some() {
+        b"dex\n039\0"
+    } else if map.get(MapItemType::CallSiteIdItem).is_some() || map.get(MapItemType::MethodHandleItem).is_some() {
+        b"dex\n038\0"
+    } else {
+        b"dex\n035\0"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::file::DexFile;
+    use crate::dex::integrity::compute_signature;
+    use crate::fixtures::fake_dex;
+    use crate::dex::reader::DexReader;
+
+    #[test]
+    fn test_repair_header() {
+        let original = fake_dex(&["La;", "Lb;"], &["Ljava/lang/Object;"]);
+
+        // Header wiped, with trailing garbage from the dump
+        let mut damaged = original.clone();
+        damaged[..HEADER_SIZE].fill(0);
+        damaged.extend_from_slice(&[0xff; 13]);
+
+        let repaired = repair_header(&damaged).unwrap();
+        assert_eq!(repaired.len(), original.len());
+        assert_eq!(repaired[..8], original[..8]);
+        assert_eq!(repaired[32..], original[32..]);
+        assert_eq!(repaired[12..32], compute_signature(&original));
+
+        let dex = DexFile::build(DexReader::build(repaired).unwrap()).unwrap();
+        assert_eq!(dex.get_classes_names().len(), 2);
+        assert_eq!(dex.types.items.len(), 3);
+    }
+
+    #[test]
+    fn test_repair_header_keeps_valid_map_offset() {
+        let original = fake_dex(&["La;"], &[]);
+
+        // Only the sizes are corrupted
+        let mut damaged = original.clone();
+        damaged[0x38..0x68].fill(0xee);
+        damaged[..8].copy_from_slice(b"dex\n037\0");
+
+        let repaired = repair_header(&damaged).unwrap();
+        assert_eq!(&repaired[..8], b"dex\n037\0");
+        assert_eq!(repaired[32..], original[32..]);
+
+        assert!(repair_header(&[0; HEADER_SIZE * 2]).is_err());
+    }
+
+    #[test]
+    fn test_find_map_list() {
+        let raw = fake_dex(&["La;", "Lb;"], &[]);
+        let map_off = read_u32_at(&raw, MAP_OFF_OFFSET).unwrap() as usize;
+        let scan = |raw: &[u8]| (HEADER_SIZE..raw.len()).rev()
+                                                      .filter(|offset| offset.is_multiple_of(4))
+                                                      .find_map(|offset| check_map_list(raw, offset));
+
+        // Same result as checking every offset, with a decoy map list describing another one
+        let mut with_decoy = raw.clone();
+        with_decoy.extend_from_slice(&raw[map_off..]);
+        assert_eq!(find_map_list(&raw), Some(map_off));
+        assert_eq!(find_map_list(&with_decoy), scan(&with_decoy));
+        assert_eq!(find_map_list(&with_decoy), Some(map_off));
+
+        // Every offset looks like the start of a long map list
+        let hostile = 0x00010001u32.to_le_bytes().repeat(0x10000);
+        assert_eq!(find_map_list(&hostile), None);
+    }
+}
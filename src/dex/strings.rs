@@ -49,7 +49,7 @@ impl DexStrings {
         // Move to start of map list
         dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
 
-        let mut strings = Vec::new();
+        let mut strings = Vec::with_capacity(dex_reader.check_count(size, 4)?);
 
         for _ in 0..size {
             let string_offset = dex_reader.read_u32()?;
@@ -59,9 +59,11 @@ impl DexStrings {
 
             let (utf16_size, _) = dex_reader.read_uleb128()?;
             if utf16_size > 0 {
-                let mut raw_string = Vec::with_capacity(utf16_size as usize);
+                // Each UTF-16 code unit takes at least one byte
+                let mut raw_string = Vec::with_capacity(u64::from(utf16_size).min(dex_reader.remaining()) as usize);
                 dex_reader.bytes.read_until(0, &mut raw_string)?;
                 raw_string.pop();
+                dex_reader.charge(raw_string.len())?;
 
                 // TODO: `mutf8::decode()` has some issues which leads to
                 // string ordering issues. For now we use `String::from_utf8_lossy()`
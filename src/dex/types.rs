@@ -7,7 +7,7 @@ use std::io::{Seek, SeekFrom};
 
 use crate::dex::reader::DexReader;
 use crate::dex::strings::DexStrings;
-use crate::error::DexError;
+use crate::error::{ DexError, ErrorContext };
 
 /// Internal representation of a DEX type item
 #[derive(Debug)]
@@ -30,17 +30,18 @@ impl DexTypes {
                  strings_list: &DexStrings) -> Result<Self, DexError> {
         dex_reader.bytes.seek(SeekFrom::Start(offset.into()))?;
 
-        let mut types = Vec::new();
+        let mut types = Vec::with_capacity(dex_reader.check_count(size, 4)?);
 
         for _ in 0..size {
             let offset = dex_reader.read_u32()?;
-            let str_type = strings_list.strings.get(offset as usize).ok_or(DexError::InvalidStringIdx)?;
+            let str_type = strings_list.strings.get(offset as usize).ok_or(DexError::InvalidStringIdx).at_index(offset.into())?;
+            dex_reader.charge(str_type.len())?;
             types.push(DexTypeItem {
                 offset,
                 str_type: str_type.to_string(),
             });
         }
-        types.sort_by(|a, b| a.offset.cmp(&b.offset));
+        types.sort_by_key(|a| a.offset);
 
         let mut items = Vec::new();
         for dex_type in types.into_iter() {
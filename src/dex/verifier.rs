@@ -0,0 +1,1183 @@
+//! Structural verification of DEX files
+//!
+//! The parser only reads what it needs and trusts the rest of the file. The verifier walks the raw
+//! bytes of a DEX file and checks the constraints enforced by ART when loading it (see
+//! `DexFileVerifier` in the ART sources):
+//!
+//!   * the header is consistent with the file (magic, sizes, endianness, section bounds)
+//!   * the map list covers the sections of the header, in order, with the right alignment
+//!   * the items of the data section are in the section of their type in the map list
+//!   * the ID lists are sorted and unique, and only reference valid indices
+//!   * class definitions, class data, code items, static values, and annotations are well-formed,
+//!     and the encoded values they contain only reference valid indices
+//!
+//! Every violation is reported as a `Diagnostic` with the `Error` severity. The verifier does not
+//! stop at the first violation, but skips the checks which depend on a broken section.
+
+use std::collections::HashSet;
+
+use crate::bytes::{ read_sleb128_at, read_u16_at, read_u32_at, read_uleb128_at, slice_at };
+use crate::dex::classes::NO_INDEX;
+use crate::dex::diagnostics::{ Diagnostic, DiagnosticCode, Severity };
+use crate::dex::header::{ HEADER_SIZE, ENDIAN_CONSTANT };
+use crate::dex::map::{ MapItemType, MapList };
+use crate::dex::options::{ ParseOptions, ResourceLimits };
+use crate::error::DexError;
+use crate::mutf8::decode_utf16;
+
+/// Versions of the DEX format supported by ART
+const KNOWN_VERSIONS: [&[u8; 3]; 7] = [b"035", b"036", b"037", b"038", b"039", b"040", b"041"];
+/// Access flags of the methods without code
+const ACC_NATIVE_OR_ABSTRACT: u32 = 0x100 | 0x400;
+
+/// A section of the file, as described by the header
+#[derive(Debug, Clone, Copy, Default)]
+struct Section {
+    size: usize,
+    offset: usize,
+    /// Whether the section is within the bounds of the file
+    valid: bool,
+}
+
+/// Verify the DEX file at the given path
+pub fn verify_from_file(filepath: &str) -> Result<Vec<Diagnostic>, DexError> {
+    let raw = std::fs::read(filepath)?;
+    Ok(verify(&raw))
+}
+
+/// Verify the structure of a DEX file and return the list of violations
+pub fn verify(raw: &[u8]) -> Vec<Diagnostic> {
+    verify_with_options(raw, &ParseOptions::default())
+}
+
+/// Verify the structure of a DEX file with the given options and return the list of violations
+///
+/// Only the resource limits are used: the verifier checks the whole file whatever the integrity
+/// policies and the recovery mode.
+pub fn verify_with_options(raw: &[u8], options: &ParseOptions) -> Vec<Diagnostic> {
+    let mut verifier = Verifier::new(raw, &options.limits);
+    if verifier.check_header() {
+        verifier.check_map_list();
+        verifier.check_string_ids();
+        verifier.check_type_ids();
+        verifier.check_proto_ids();
+        verifier.check_field_ids();
+        verifier.check_method_ids();
+        verifier.check_class_defs();
+    }
+
+    verifier.violations
+}
+
+/// State of the verification of a file
+struct Verifier<'a> {
+    raw: &'a [u8],
+    limits: &'a ResourceLimits,
+    violations: Vec<Diagnostic>,
+    string_ids: Section,
+    type_ids: Section,
+    proto_ids: Section,
+    field_ids: Section,
+    method_ids: Section,
+    class_defs: Section,
+    data: Section,
+    /// Number of method handles, from the map list
+    method_handles_size: usize,
+    /// Bounds of the section of each type of items in the map list, if it can be parsed
+    map_sections: Option<Vec<(MapItemType, usize, usize)>>,
+    /// Offsets of the items referenced from several places which were already checked
+    checked_items: HashSet<usize>,
+}
+
+/// Find the end of the annotation or encoded array item at the given position
+///
+/// Only the structure of the values is checked, not the indices they hold. Returns `None` if the
+/// item cannot be read.
+pub(crate) fn encoded_item_end(raw: &[u8], kind: MapItemType, pos: usize, limits: &ResourceLimits) -> Option<usize> {
+    let mut verifier = Verifier::new(raw, limits);
+    let mut end = pos;
+    let readable = match kind {
+        MapItemType::AnnotationItem => {
+            end += 1;
+            verifier.check_encoded_annotation(&mut end, kind, 0)
+        },
+        MapItemType::EncodedArrayItem => verifier.check_encoded_array(&mut end, kind, 0),
+        _ => false,
+    };
+
+    readable.then_some(end)
+}
+
+impl<'a> Verifier<'a> {
+    /// Create a verifier for the given file, before any section is known
+    fn new(raw: &'a [u8], limits: &'a ResourceLimits) -> Self {
+        Verifier {
+            raw,
+            limits,
+            violations: Vec::new(),
+            string_ids: Section::default(),
+            type_ids: Section::default(),
+            proto_ids: Section::default(),
+            field_ids: Section::default(),
+            method_ids: Section::default(),
+            class_defs: Section::default(),
+            data: Section::default(),
+            method_handles_size: 0,
+            map_sections: None,
+            checked_items: HashSet::new(),
+        }
+    }
+
+    /// Record a violation
+    fn report(&mut self, code: DiagnosticCode, offset: usize, section: MapItemType, message: String) {
+        self.violations.push(Diagnostic::new(Severity::Error, code, offset as u64, Some(section), message));
+    }
+
+    /// Read a `u32` which is known to be within the file
+    fn u32_at(&self, offset: usize) -> u32 {
+        read_u32_at(self.raw, offset).unwrap_or(0)
+    }
+
+    /// Read a `u16` which is known to be within the file
+    fn u16_at(&self, offset: usize) -> u16 {
+        read_u16_at(self.raw, offset).unwrap_or(0)
+    }
+
+    /// Check if the given range is within the data section
+    fn in_data(&self, offset: usize, size: usize) -> bool {
+        self.data.valid
+            && offset >= self.data.offset
+            && offset.checked_add(size).is_some_and(|end| end <= self.data.offset + self.data.size)
+    }
+
+    /// Check that an item of the data section is in the section of its type in the map list
+    fn check_in_map(&mut self, kind: MapItemType, offset: usize) {
+        let Some(map_sections) = &self.map_sections else {
+            return;
+        };
+
+        let covered = map_sections.iter()
+                                  .find(|(section_kind, _, _)| *section_kind == kind)
+                                  .is_some_and(|&(_, start, end)| offset >= start && offset < end);
+        if !covered {
+            self.report(DiagnosticCode::InvalidMapList, offset, kind,
+                        format!("item at 0x{offset:x} not covered by the map list"));
+        }
+    }
+
+    /// Check the header and read the bounds of the sections
+    ///
+    /// Returns `false` if the file cannot be verified any further.
+    fn check_header(&mut self) -> bool {
+        if self.raw.len() < HEADER_SIZE {
+            self.report(DiagnosticCode::InvalidHeader, 0, MapItemType::HeaderItem,
+                        format!("file too short for a header: {} bytes", self.raw.len()));
+            return false;
+        }
+
+        let magic = &self.raw[..8];
+        let version = [magic[4], magic[5], magic[6]];
+        if &magic[..4] != b"dex\n" || magic[7] != 0 || !KNOWN_VERSIONS.contains(&&version) {
+            self.report(DiagnosticCode::InvalidHeader, 0, MapItemType::HeaderItem,
+                        format!("invalid magic {magic:02x?}"));
+        }
+
+        let endian_tag = self.u32_at(0x28);
+        if endian_tag != ENDIAN_CONSTANT {
+            self.report(DiagnosticCode::InvalidHeader, 0x28, MapItemType::HeaderItem,
+                        format!("unsupported endianness tag 0x{endian_tag:08x}"));
+            return false;
+        }
+
+        let file_size = self.u32_at(0x20) as usize;
+        if file_size != self.raw.len() {
+            self.report(DiagnosticCode::InvalidHeader, 0x20, MapItemType::HeaderItem,
+                        format!("file size {file_size} does not match actual size {}", self.raw.len()));
+        }
+        let header_size = self.u32_at(0x24) as usize;
+        if header_size != HEADER_SIZE {
+            self.report(DiagnosticCode::InvalidHeader, 0x24, MapItemType::HeaderItem,
+                        format!("invalid header size 0x{header_size:x}"));
+        }
+
+        let link_size = self.u32_at(0x2c) as usize;
+        let link_off = self.u32_at(0x30) as usize;
+        if (link_size == 0) != (link_off == 0) || slice_at(self.raw, link_off, link_size).is_err() {
+            self.report(DiagnosticCode::SectionOutOfBounds, 0x2c, MapItemType::HeaderItem,
+                        format!("invalid link section: size {link_size}, offset 0x{link_off:x}"));
+        }
+
+        self.data = self.check_section(0x68, 1, 1, MapItemType::HeaderItem);
+        self.string_ids = self.check_section(0x38, 4, 4, MapItemType::StringIdItem);
+        self.type_ids = self.check_section(0x40, 4, 4, MapItemType::TypeIdItem);
+        self.proto_ids = self.check_section(0x48, 12, 4, MapItemType::ProtoIdItem);
+        self.field_ids = self.check_section(0x50, 8, 4, MapItemType::FieldIdItem);
+        self.method_ids = self.check_section(0x58, 8, 4, MapItemType::MethodIdItem);
+        self.class_defs = self.check_section(0x60, 32, 4, MapItemType::ClassDefItem);
+
+        if self.type_ids.size > 0xffff {
+            self.report(DiagnosticCode::InvalidHeader, 0x40, MapItemType::TypeIdItem,
+                        format!("too many type IDs: {}", self.type_ids.size));
+        }
+        if self.proto_ids.size > 0xffff {
+            self.report(DiagnosticCode::InvalidHeader, 0x48, MapItemType::ProtoIdItem,
+                        format!("too many prototype IDs: {}", self.proto_ids.size));
+        }
+
+        true
+    }
+
+    /// Check the bounds and alignment of the section described at the given offset of the header
+    fn check_section(&mut self, field: usize, item_size: usize, alignment: usize, kind: MapItemType) -> Section {
+        let size = self.u32_at(field) as usize;
+        let offset = self.u32_at(field + 4) as usize;
+        let mut section = Section { size, offset, valid: true };
+
+        if size == 0 {
+            if offset != 0 {
+                self.report(DiagnosticCode::InvalidHeader, field + 4, kind,
+                            format!("empty section with non-zero offset 0x{offset:x}"));
+            }
+            return section;
+        }
+
+        if offset < HEADER_SIZE || size.checked_mul(item_size).is_none_or(|len| slice_at(self.raw, offset, len).is_err()) {
+            self.report(DiagnosticCode::SectionOutOfBounds, field, kind,
+                        format!("section of {size} items at 0x{offset:x} out of the file"));
+            section.valid = false;
+        }
+        if !offset.is_multiple_of(alignment) {
+            self.report(DiagnosticCode::MisalignedSection, field + 4, kind,
+                        format!("section at 0x{offset:x} not aligned on {alignment} bytes"));
+        }
+
+        section
+    }
+
+    /// Check that the map list is valid and consistent with the header
+    fn check_map_list(&mut self) {
+        let map_off = self.u32_at(0x34) as usize;
+        if !self.in_data(map_off, 4) {
+            self.report(DiagnosticCode::InvalidMapList, 0x34, MapItemType::MapList,
+                        format!("map list at 0x{map_off:x} out of the data section"));
+        }
+        let map = match MapList::build(self.raw, map_off) {
+            Ok(map) => map,
+            Err(_) => {
+                self.report(DiagnosticCode::InvalidMapList, map_off, MapItemType::MapList,
+                            "cannot parse map list".to_string());
+                return;
+            }
+        };
+
+        let mut previous_offset = None;
+        for (idx, item) in map.items.iter().enumerate() {
+            let item_offset = map_off + 4 + 12 * idx;
+            let offset = item.offset as usize;
+
+            if map.items[..idx].iter().any(|other| other.kind == item.kind) {
+                self.report(DiagnosticCode::InvalidMapList, item_offset, MapItemType::MapList,
+                            format!("duplicate map item for {:?}", item.kind));
+            }
+            if previous_offset.is_some_and(|previous| offset <= previous) {
+                self.report(DiagnosticCode::InvalidMapList, item_offset, MapItemType::MapList,
+                            format!("map item for {:?} at 0x{offset:x} out of order", item.kind));
+            }
+            previous_offset = Some(offset);
+
+            if offset >= self.raw.len() {
+                self.report(DiagnosticCode::SectionOutOfBounds, item_offset, item.kind,
+                            format!("section at 0x{offset:x} out of the file"));
+            }
+            if !offset.is_multiple_of(alignment(item.kind)) {
+                self.report(DiagnosticCode::MisalignedSection, item_offset, item.kind,
+                            format!("section at 0x{offset:x} not aligned on {} bytes", alignment(item.kind)));
+            }
+            if is_data_item(item.kind) && !self.in_data(offset, 1) {
+                self.report(DiagnosticCode::SectionOutOfBounds, item_offset, item.kind,
+                            format!("section at 0x{offset:x} out of the data section"));
+            }
+        }
+
+        // Each section ends where the next one starts. Only the first item of each type is kept,
+        // as `MapList::get` does.
+        let mut offsets = map.items.iter().map(|item| item.offset as usize).collect::<Vec<usize>>();
+        offsets.sort_unstable();
+        let mut map_sections: Vec<(MapItemType, usize, usize)> = Vec::new();
+        for item in map.items.iter() {
+            if map_sections.iter().any(|(kind, _, _)| *kind == item.kind) {
+                continue;
+            }
+            let start = item.offset as usize;
+            let end = offsets.get(offsets.partition_point(|&offset| offset <= start))
+                             .copied()
+                             .unwrap_or(self.raw.len());
+            map_sections.push((item.kind, start, end));
+        }
+        self.map_sections = Some(map_sections);
+        self.method_handles_size = map.get(MapItemType::MethodHandleItem).map_or(0, |item| item.size as usize);
+
+        match map.items.first() {
+            Some(item) if item.kind == MapItemType::HeaderItem && item.offset == 0 && item.size == 1 => (),
+            _ => self.report(DiagnosticCode::InvalidMapList, map_off, MapItemType::MapList,
+                             "map list does not start with the header".to_string()),
+        }
+        if map.get(MapItemType::MapList).is_none_or(|item| item.offset as usize != map_off) {
+            self.report(DiagnosticCode::InvalidMapList, map_off, MapItemType::MapList,
+                        "map list does not describe itself".to_string());
+        }
+
+        // The ID sections of the header must be in the map list, with the same bounds
+        let sections = [(MapItemType::StringIdItem, self.string_ids),
+                        (MapItemType::TypeIdItem, self.type_ids),
+                        (MapItemType::ProtoIdItem, self.proto_ids),
+                        (MapItemType::FieldIdItem, self.field_ids),
+                        (MapItemType::MethodIdItem, self.method_ids),
+                        (MapItemType::ClassDefItem, self.class_defs)];
+        for (kind, section) in sections {
+            let (size, offset) = map.get(kind).map_or((0, 0), |item| (item.size as usize, item.offset as usize));
+            if size != section.size || (size != 0 && offset != section.offset) {
+                self.report(DiagnosticCode::InvalidMapList, map_off, kind,
+                            format!("map list does not match header: {size} items at 0x{offset:x}, \
+                                     expected {} items at 0x{:x}", section.size, section.offset));
+            }
+        }
+    }
+
+    /// Check that strings are in the data section, well-formed, sorted, and unique
+    fn check_string_ids(&mut self) {
+        if !self.string_ids.valid {
+            return;
+        }
+
+        let mut previous: Option<Vec<u16>> = None;
+        for idx in 0..self.string_ids.size {
+            let id_offset = self.string_ids.offset + 4 * idx;
+            let data_offset = self.u32_at(id_offset) as usize;
+
+            let string = match self.read_string(data_offset) {
+                Some(string) => string,
+                None => {
+                    self.report(DiagnosticCode::InvalidIndex, id_offset, MapItemType::StringIdItem,
+                                format!("invalid string data at 0x{data_offset:x} for string {idx}"));
+                    previous = None;
+                    continue;
+                }
+            };
+
+            self.check_in_map(MapItemType::StringDataItem, data_offset);
+            if previous.as_ref().is_some_and(|previous| string <= *previous) {
+                self.report(DiagnosticCode::UnsortedIds, id_offset, MapItemType::StringIdItem,
+                            format!("string {idx} out of order or duplicated"));
+            }
+            previous = Some(string);
+        }
+    }
+
+    /// Read the string data at the given offset as UTF-16 code units
+    fn read_string(&self, offset: usize) -> Option<Vec<u16>> {
+        if !self.in_data(offset, 1) {
+            return None;
+        }
+
+        let mut pos = offset;
+        let utf16_size = read_uleb128_at(self.raw, &mut pos).ok()? as usize;
+        let len = self.raw.get(pos..)?.iter().position(|&byte| byte == 0)?;
+        if !self.in_data(pos, len + 1) {
+            return None;
+        }
+
+        let string = decode_utf16(&self.raw[pos..pos + len])?;
+        (string.len() == utf16_size).then_some(string)
+    }
+
+    /// Check that types reference strings, and are sorted and unique
+    fn check_type_ids(&mut self) {
+        if !self.type_ids.valid {
+            return;
+        }
+
+        let mut previous = None;
+        for idx in 0..self.type_ids.size {
+            let id_offset = self.type_ids.offset + 4 * idx;
+            let descriptor_idx = self.u32_at(id_offset);
+
+            if descriptor_idx as usize >= self.string_ids.size {
+                self.report(DiagnosticCode::InvalidIndex, id_offset, MapItemType::TypeIdItem,
+                            format!("invalid string index {descriptor_idx} for type {idx}"));
+            }
+            if previous.is_some_and(|previous| descriptor_idx <= previous) {
+                self.report(DiagnosticCode::UnsortedIds, id_offset, MapItemType::TypeIdItem,
+                            format!("type {idx} out of order or duplicated"));
+            }
+            previous = Some(descriptor_idx);
+        }
+    }
+
+    /// Check that prototypes reference valid strings and types, and are sorted and unique
+    fn check_proto_ids(&mut self) {
+        if !self.proto_ids.valid {
+            return;
+        }
+
+        let mut previous: Option<(u32, Vec<u16>)> = None;
+        for idx in 0..self.proto_ids.size {
+            let id_offset = self.proto_ids.offset + 12 * idx;
+            let shorty_idx = self.u32_at(id_offset);
+            let return_type_idx = self.u32_at(id_offset + 4);
+            let parameters_off = self.u32_at(id_offset + 8) as usize;
+
+            if shorty_idx as usize >= self.string_ids.size {
+                self.report(DiagnosticCode::InvalidIndex, id_offset, MapItemType::ProtoIdItem,
+                            format!("invalid string index {shorty_idx} for prototype {idx}"));
+            }
+            if return_type_idx as usize >= self.type_ids.size {
+                self.report(DiagnosticCode::InvalidIndex, id_offset + 4, MapItemType::ProtoIdItem,
+                            format!("invalid type index {return_type_idx} for prototype {idx}"));
+            }
+            let parameters = match parameters_off {
+                0 => Vec::new(),
+                _ => self.check_type_list(parameters_off, id_offset + 8, MapItemType::ProtoIdItem).unwrap_or_default(),
+            };
+
+            let key = (return_type_idx, parameters);
+            if previous.as_ref().is_some_and(|previous| key <= *previous) {
+                self.report(DiagnosticCode::UnsortedIds, id_offset, MapItemType::ProtoIdItem,
+                            format!("prototype {idx} out of order or duplicated"));
+            }
+            previous = Some(key);
+        }
+    }
+
+    /// Check a type list and return its type indices
+    fn check_type_list(&mut self, offset: usize, referrer: usize, section: MapItemType) -> Option<Vec<u16>> {
+        let size = read_u32_at(self.raw, offset).ok().map(|size| size as usize);
+        let Some(size) = size.filter(|&size| offset.is_multiple_of(4) && self.in_data(offset, 4 + 2 * size)) else {
+            self.report(DiagnosticCode::SectionOutOfBounds, referrer, section,
+                        format!("invalid type list at 0x{offset:x}"));
+            return None;
+        };
+        self.check_in_map(MapItemType::TypeList, offset);
+
+        let types = (0..size).map(|idx| self.u16_at(offset + 4 + 2 * idx)).collect::<Vec<u16>>();
+        for &type_idx in types.iter() {
+            if type_idx as usize >= self.type_ids.size {
+                self.report(DiagnosticCode::InvalidIndex, offset, MapItemType::TypeList,
+                            format!("invalid type index {type_idx} in type list"));
+            }
+        }
+
+        Some(types)
+    }
+
+    /// Check that fields reference valid strings and types, and are sorted and unique
+    fn check_field_ids(&mut self) {
+        if !self.field_ids.valid {
+            return;
+        }
+
+        let mut previous = None;
+        for idx in 0..self.field_ids.size {
+            let id_offset = self.field_ids.offset + 8 * idx;
+            let class_idx = self.u16_at(id_offset);
+            let type_idx = self.u16_at(id_offset + 2);
+            let name_idx = self.u32_at(id_offset + 4);
+
+            self.check_type_idx(class_idx.into(), id_offset, MapItemType::FieldIdItem);
+            self.check_type_idx(type_idx.into(), id_offset + 2, MapItemType::FieldIdItem);
+            if name_idx as usize >= self.string_ids.size {
+                self.report(DiagnosticCode::InvalidIndex, id_offset + 4, MapItemType::FieldIdItem,
+                            format!("invalid string index {name_idx} for field {idx}"));
+            }
+
+            let key = (class_idx, name_idx, type_idx);
+            if previous.is_some_and(|previous| key <= previous) {
+                self.report(DiagnosticCode::UnsortedIds, id_offset, MapItemType::FieldIdItem,
+                            format!("field {idx} out of order or duplicated"));
+            }
+            previous = Some(key);
+        }
+    }
+
+    /// Check that methods reference valid strings, types, and prototypes, and are sorted and unique
+    fn check_method_ids(&mut self) {
+        if !self.method_ids.valid {
+            return;
+        }
+
+        let mut previous = None;
+        for idx in 0..self.method_ids.size {
+            let id_offset = self.method_ids.offset + 8 * idx;
+            let class_idx = self.u16_at(id_offset);
+            let proto_idx = self.u16_at(id_offset + 2);
+            let name_idx = self.u32_at(id_offset + 4);
+
+            self.check_type_idx(class_idx.into(), id_offset, MapItemType::MethodIdItem);
+            if proto_idx as usize >= self.proto_ids.size {
+                self.report(DiagnosticCode::InvalidIndex, id_offset + 2, MapItemType::MethodIdItem,
+                            format!("invalid prototype index {proto_idx} for method {idx}"));
+            }
+            if name_idx as usize >= self.string_ids.size {
+                self.report(DiagnosticCode::InvalidIndex, id_offset + 4, MapItemType::MethodIdItem,
+                            format!("invalid string index {name_idx} for method {idx}"));
+            }
+
+            let key = (class_idx, name_idx, proto_idx);
+            if previous.is_some_and(|previous| key <= previous) {
+                self.report(DiagnosticCode::UnsortedIds, id_offset, MapItemType::MethodIdItem,
+                            format!("method {idx} out of order or duplicated"));
+            }
+            previous = Some(key);
+        }
+    }
+
+    /// Check that a type index is valid
+    fn check_type_idx(&mut self, type_idx: u32, offset: usize, section: MapItemType) {
+        if type_idx as usize >= self.type_ids.size {
+            self.report(DiagnosticCode::InvalidIndex, offset, section,
+                        format!("invalid type index {type_idx}"));
+        }
+    }
+
+    /// Check the class definitions, along with their class data and code
+    fn check_class_defs(&mut self) {
+        if !self.class_defs.valid {
+            return;
+        }
+
+        let mut defined = HashSet::new();
+        for idx in 0..self.class_defs.size {
+            let def_offset = self.class_defs.offset + 32 * idx;
+            let class_idx = self.u32_at(def_offset);
+            let superclass_idx = self.u32_at(def_offset + 8);
+            let interfaces_off = self.u32_at(def_offset + 12) as usize;
+            let source_file_idx = self.u32_at(def_offset + 16);
+            let annotations_off = self.u32_at(def_offset + 20) as usize;
+            let class_data_off = self.u32_at(def_offset + 24) as usize;
+            let static_values_off = self.u32_at(def_offset + 28) as usize;
+            let first_violation = self.violations.len();
+
+            self.check_type_idx(class_idx, def_offset, MapItemType::ClassDefItem);
+            if !defined.insert(class_idx) {
+                self.report(DiagnosticCode::DuplicateClass, def_offset, MapItemType::ClassDefItem,
+                            format!("class {idx} defined more than once"));
+            }
+
+            if superclass_idx != NO_INDEX {
+                self.check_type_idx(superclass_idx, def_offset + 8, MapItemType::ClassDefItem);
+                if superclass_idx == class_idx {
+                    self.report(DiagnosticCode::InvalidIndex, def_offset + 8, MapItemType::ClassDefItem,
+                                format!("class {idx} is its own superclass"));
+                }
+            }
+            if interfaces_off != 0 {
+                self.check_type_list(interfaces_off, def_offset + 12, MapItemType::ClassDefItem);
+            }
+            if source_file_idx != NO_INDEX && source_file_idx as usize >= self.string_ids.size {
+                self.report(DiagnosticCode::InvalidIndex, def_offset + 16, MapItemType::ClassDefItem,
+                            format!("invalid string index {source_file_idx} for source file"));
+            }
+            for (field, offset) in [(20, annotations_off), (28, static_values_off)] {
+                if offset != 0 && !self.in_data(offset, 1) {
+                    self.report(DiagnosticCode::SectionOutOfBounds, def_offset + field, MapItemType::ClassDefItem,
+                                format!("offset 0x{offset:x} out of the data section"));
+                }
+            }
+            if annotations_off != 0 && self.in_data(annotations_off, 1) {
+                self.check_annotations_directory(annotations_off, def_offset + 20);
+            }
+            if static_values_off != 0 && self.in_data(static_values_off, 1) {
+                self.check_static_values(static_values_off);
+            }
+            if class_data_off != 0 {
+                if self.in_data(class_data_off, 1) {
+                    self.check_in_map(MapItemType::ClassDataItem, class_data_off);
+                    self.check_class_data(class_data_off, class_idx);
+                } else {
+                    self.report(DiagnosticCode::SectionOutOfBounds, def_offset + 24, MapItemType::ClassDefItem,
+                                format!("class data at 0x{class_data_off:x} out of the data section"));
+                }
+            }
+
+            // Name the class in the violations, when possible
+            let class = (class_idx as usize) < self.type_ids.size && self.type_ids.valid;
+            let class = class.then(|| self.u32_at(self.type_ids.offset + 4 * class_idx as usize))
+                             .filter(|&string_idx| (string_idx as usize) < self.string_ids.size && self.string_ids.valid)
+                             .and_then(|string_idx| self.read_string(self.u32_at(self.string_ids.offset + 4 * string_idx as usize) as usize))
+                             .map(|string| String::from_utf16_lossy(&string));
+            for violation in self.violations[first_violation..].iter_mut() {
+                violation.class = class.clone();
+            }
+        }
+    }
+
+    /// Check the class data at the given offset
+    fn check_class_data(&mut self, offset: usize, class_idx: u32) {
+        let mut pos = offset;
+        let sizes = (0..4).map(|_| read_uleb128_at(self.raw, &mut pos))
+                          .collect::<Result<Vec<u32>, DexError>>();
+        let sizes = match sizes {
+            Ok(sizes) => sizes,
+            Err(_) => {
+                self.report(DiagnosticCode::InvalidClassData, offset, MapItemType::ClassDataItem,
+                            "cannot read class data header".to_string());
+                return;
+            }
+        };
+
+        for (list, &size) in sizes.iter().enumerate() {
+            let is_method = list >= 2;
+            let mut member_idx = 0u32;
+
+            for position in 0..size {
+                let member_offset = pos;
+                let entry = if is_method {
+                    (0..3).map(|_| read_uleb128_at(self.raw, &mut pos)).collect::<Result<Vec<u32>, DexError>>()
+                } else {
+                    (0..2).map(|_| read_uleb128_at(self.raw, &mut pos)).collect::<Result<Vec<u32>, DexError>>()
+                };
+                let entry = match entry {
+                    Ok(entry) if self.in_data(member_offset, pos - member_offset) => entry,
+                    _ => {
+                        self.report(DiagnosticCode::InvalidClassData, member_offset, MapItemType::ClassDataItem,
+                                    "truncated class data".to_string());
+                        return;
+                    }
+                };
+
+                if position > 0 && entry[0] == 0 {
+                    self.report(DiagnosticCode::UnsortedIds, member_offset, MapItemType::ClassDataItem,
+                                "duplicated member in class data".to_string());
+                }
+                member_idx = member_idx.wrapping_add(entry[0]);
+
+                let (ids, item_offset, kind) = if is_method {
+                    (self.method_ids, 8, "method")
+                } else {
+                    (self.field_ids, 8, "field")
+                };
+                if member_idx as usize >= ids.size {
+                    self.report(DiagnosticCode::InvalidIndex, member_offset, MapItemType::ClassDataItem,
+                                format!("invalid {kind} index {member_idx}"));
+                    continue;
+                }
+                if ids.valid && u32::from(self.u16_at(ids.offset + item_offset * member_idx as usize)) != class_idx {
+                    self.report(DiagnosticCode::InvalidClassData, member_offset, MapItemType::ClassDataItem,
+                                format!("{kind} {member_idx} belongs to another class"));
+                }
+
+                if is_method {
+                    let access_flags = entry[1];
+                    let code_off = entry[2] as usize;
+                    let has_code = access_flags & ACC_NATIVE_OR_ABSTRACT == 0;
+                    if has_code != (code_off != 0) {
+                        self.report(DiagnosticCode::InvalidClassData, member_offset, MapItemType::ClassDataItem,
+                                    format!("method {member_idx} has flags 0x{access_flags:x} and code at 0x{code_off:x}"));
+                    } else if code_off != 0 {
+                        self.check_code_item(code_off, member_offset);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check the code item at the given offset
+    fn check_code_item(&mut self, offset: usize, referrer: usize) {
+        let report = |verifier: &mut Self, at: usize, message: String| {
+            verifier.report(DiagnosticCode::InvalidCodeItem, at, MapItemType::CodeItem, message);
+        };
+
+        if !offset.is_multiple_of(4) || !self.in_data(offset, 16) {
+            report(self, referrer, format!("invalid code item at 0x{offset:x}"));
+            return;
+        }
+        self.check_in_map(MapItemType::CodeItem, offset);
+
+        let registers_size = self.u16_at(offset);
+        let ins_size = self.u16_at(offset + 2);
+        let tries_size = self.u16_at(offset + 6) as usize;
+        let insns_size = self.u32_at(offset + 12) as usize;
+        if ins_size > registers_size {
+            report(self, offset, format!("{ins_size} ins for {registers_size} registers"));
+        }
+
+        let insns_end = insns_size.checked_mul(2).and_then(|len| (offset + 16).checked_add(len));
+        let Some(insns_end) = insns_end.filter(|&end| self.in_data(offset, end - offset)) else {
+            report(self, offset, format!("bytecode of {insns_size} code units out of the data section"));
+            return;
+        };
+        if tries_size == 0 {
+            return;
+        }
+
+        // Padding to align the tries
+        let tries_off = if insns_size % 2 == 1 { insns_end + 2 } else { insns_end };
+        let handlers_off = tries_off + 8 * tries_size;
+        if !self.in_data(tries_off, 8 * tries_size + 1) {
+            report(self, offset, format!("{tries_size} tries out of the data section"));
+            return;
+        }
+
+        // Handlers, indexed by their offset from the start of the list
+        let mut pos = handlers_off;
+        let Ok(handlers_count) = read_uleb128_at(self.raw, &mut pos) else {
+            report(self, handlers_off, "cannot read handlers".to_string());
+            return;
+        };
+        let mut handler_offsets = Vec::new();
+        for _ in 0..handlers_count {
+            handler_offsets.push(pos - handlers_off);
+            let Ok(size) = read_sleb128_at(self.raw, &mut pos) else {
+                report(self, pos, "cannot read handler".to_string());
+                return;
+            };
+
+            let pairs = size.unsigned_abs().min(0xffff);
+            for _ in 0..pairs {
+                let handler_offset = pos;
+                let (Ok(type_idx), Ok(addr)) = (read_uleb128_at(self.raw, &mut pos), read_uleb128_at(self.raw, &mut pos)) else {
+                    report(self, handler_offset, "truncated handler".to_string());
+                    return;
+                };
+                self.check_type_idx(type_idx, handler_offset, MapItemType::CodeItem);
+                if addr as usize >= insns_size {
+                    report(self, handler_offset, format!("handler address 0x{addr:x} out of the bytecode"));
+                }
+            }
+            if size <= 0 {
+                let handler_offset = pos;
+                match read_uleb128_at(self.raw, &mut pos) {
+                    Ok(addr) if (addr as usize) < insns_size => (),
+                    _ => report(self, handler_offset, "invalid catch-all handler".to_string()),
+                }
+            }
+        }
+        if !self.in_data(handlers_off, pos - handlers_off) {
+            report(self, handlers_off, "handlers out of the data section".to_string());
+        }
+
+        let mut previous_end = 0;
+        for idx in 0..tries_size {
+            let try_offset = tries_off + 8 * idx;
+            let start_addr = self.u32_at(try_offset) as usize;
+            let insn_count = self.u16_at(try_offset + 4) as usize;
+            let handler_off = self.u16_at(try_offset + 6) as usize;
+
+            if start_addr < previous_end || start_addr + insn_count > insns_size {
+                report(self, try_offset, format!("invalid try range 0x{start_addr:x}+{insn_count}"));
+            }
+            if !handler_offsets.contains(&handler_off) {
+                report(self, try_offset, format!("invalid handler offset 0x{handler_off:x}"));
+            }
+            previous_end = start_addr + insn_count;
+        }
+    }
+
+    /// Check the initial values of the static fields of a class
+    fn check_static_values(&mut self, offset: usize) {
+        if !self.checked_items.insert(offset) {
+            return;
+        }
+        self.check_in_map(MapItemType::EncodedArrayItem, offset);
+
+        let mut pos = offset;
+        if !self.check_encoded_array(&mut pos, MapItemType::EncodedArrayItem, 0) || !self.in_data(offset, pos - offset) {
+            self.report(DiagnosticCode::InvalidStaticValues, offset, MapItemType::EncodedArrayItem,
+                        "invalid static values".to_string());
+        }
+    }
+
+    /// Check the annotations directory of a class, along with the annotations it references
+    fn check_annotations_directory(&mut self, offset: usize, referrer: usize) {
+        if !self.checked_items.insert(offset) {
+            return;
+        }
+
+        let kind = MapItemType::AnnotationsDirectoryItem;
+        if !offset.is_multiple_of(4) || !self.in_data(offset, 16) {
+            self.report(DiagnosticCode::SectionOutOfBounds, referrer, kind,
+                        format!("invalid annotations directory at 0x{offset:x}"));
+            return;
+        }
+        self.check_in_map(kind, offset);
+
+        let class_annotations_off = self.u32_at(offset) as usize;
+        let fields_size = self.u32_at(offset + 4) as usize;
+        let methods_size = self.u32_at(offset + 8) as usize;
+        let parameters_size = self.u32_at(offset + 12) as usize;
+        let entries = fields_size + methods_size + parameters_size;
+        if !self.in_data(offset + 16, 8 * entries) {
+            self.report(DiagnosticCode::SectionOutOfBounds, offset, kind,
+                        format!("{entries} annotated members out of the data section"));
+            return;
+        }
+
+        if class_annotations_off != 0 {
+            self.check_annotation_set(class_annotations_off, offset);
+        }
+        for idx in 0..entries {
+            let entry_offset = offset + 16 + 8 * idx;
+            let member_idx = self.u32_at(entry_offset);
+            let annotations_off = self.u32_at(entry_offset + 4) as usize;
+
+            let (ids, member) = if idx < fields_size {
+                (self.field_ids, "field")
+            } else {
+                (self.method_ids, "method")
+            };
+            if member_idx as usize >= ids.size {
+                self.report(DiagnosticCode::InvalidIndex, entry_offset, kind,
+                            format!("invalid {member} index {member_idx} in annotations directory"));
+            }
+
+            if idx < fields_size + methods_size {
+                self.check_annotation_set(annotations_off, entry_offset + 4);
+            } else {
+                self.check_annotation_set_ref_list(annotations_off, entry_offset + 4);
+            }
+        }
+    }
+
+    /// Check the annotations of the parameters of a method
+    fn check_annotation_set_ref_list(&mut self, offset: usize, referrer: usize) {
+        if !self.checked_items.insert(offset) {
+            return;
+        }
+
+        let kind = MapItemType::AnnotationSetRefList;
+        let size = read_u32_at(self.raw, offset).ok().map(|size| size as usize);
+        let Some(size) = size.filter(|&size| offset.is_multiple_of(4) && self.in_data(offset, 4 + 4 * size)) else {
+            self.report(DiagnosticCode::SectionOutOfBounds, referrer, kind,
+                        format!("invalid annotation set list at 0x{offset:x}"));
+            return;
+        };
+        self.check_in_map(kind, offset);
+
+        for idx in 0..size {
+            let annotations_off = self.u32_at(offset + 4 + 4 * idx) as usize;
+            // Parameters without annotations have no set
+            if annotations_off != 0 {
+                self.check_annotation_set(annotations_off, offset + 4 + 4 * idx);
+            }
+        }
+    }
+
+    /// Check a set of annotations, along with its annotations
+    fn check_annotation_set(&mut self, offset: usize, referrer: usize) {
+        if !self.checked_items.insert(offset) {
+            return;
+        }
+
+        let kind = MapItemType::AnnotationSetItem;
+        let size = read_u32_at(self.raw, offset).ok().map(|size| size as usize);
+        let Some(size) = size.filter(|&size| offset.is_multiple_of(4) && self.in_data(offset, 4 + 4 * size)) else {
+            self.report(DiagnosticCode::SectionOutOfBounds, referrer, kind,
+                        format!("invalid annotation set at 0x{offset:x}"));
+            return;
+        };
+        self.check_in_map(kind, offset);
+
+        for idx in 0..size {
+            let annotation_off = self.u32_at(offset + 4 + 4 * idx) as usize;
+            self.check_annotation(annotation_off, offset + 4 + 4 * idx);
+        }
+    }
+
+    /// Check an annotation, and the indices of its type, elements, and values
+    fn check_annotation(&mut self, offset: usize, referrer: usize) {
+        if !self.checked_items.insert(offset) {
+            return;
+        }
+
+        let kind = MapItemType::AnnotationItem;
+        if !self.in_data(offset, 1) {
+            self.report(DiagnosticCode::SectionOutOfBounds, referrer, kind,
+                        format!("annotation at 0x{offset:x} out of the data section"));
+            return;
+        }
+        self.check_in_map(kind, offset);
+
+        let visibility = self.raw[offset];
+        if visibility > 2 {
+            self.report(DiagnosticCode::InvalidAnnotation, offset, kind,
+                        format!("invalid visibility 0x{visibility:x}"));
+        }
+        let mut pos = offset + 1;
+        if !self.check_encoded_annotation(&mut pos, kind, 0) || !self.in_data(offset, pos - offset) {
+            self.report(DiagnosticCode::InvalidAnnotation, offset, kind,
+                        "cannot read annotation".to_string());
+        }
+    }
+
+    /// Check the encoded array at `pos` and move `pos` after it
+    ///
+    /// Returns `false` if the array cannot be read. Invalid indices are reported, but do not
+    /// prevent reading the rest of the array.
+    fn check_encoded_array(&mut self, pos: &mut usize, section: MapItemType, depth: usize) -> bool {
+        if depth > self.limits.max_nesting_depth {
+            return false;
+        }
+        let Ok(size) = read_uleb128_at(self.raw, pos) else {
+            return false;
+        };
+
+        (0..size).all(|_| self.check_encoded_value(pos, section, depth))
+    }
+
+    /// Check the encoded annotation at `pos` and move `pos` after it
+    fn check_encoded_annotation(&mut self, pos: &mut usize, section: MapItemType, depth: usize) -> bool {
+        if depth > self.limits.max_nesting_depth {
+            return false;
+        }
+        let annotation_offset = *pos;
+        let (Ok(type_idx), Ok(size)) = (read_uleb128_at(self.raw, pos), read_uleb128_at(self.raw, pos)) else {
+            return false;
+        };
+        self.check_type_idx(type_idx, annotation_offset, section);
+
+        for _ in 0..size {
+            let element_offset = *pos;
+            let Ok(name_idx) = read_uleb128_at(self.raw, pos) else {
+                return false;
+            };
+            if name_idx as usize >= self.string_ids.size {
+                self.report(DiagnosticCode::InvalidIndex, element_offset, section,
+                            format!("invalid string index {name_idx} for annotation element"));
+            }
+            if !self.check_encoded_value(pos, section, depth) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Check the encoded value at `pos` and move `pos` after it
+    fn check_encoded_value(&mut self, pos: &mut usize, section: MapItemType, depth: usize) -> bool {
+        let value_offset = *pos;
+        let Some(&header) = self.raw.get(value_offset) else {
+            return false;
+        };
+        *pos += 1;
+
+        let value_type = header & 0x1f;
+        let size = usize::from(header >> 5) + 1;
+        // Maximum size of the value, and list referenced by its index, if any
+        let (max_size, ids) = match value_type {
+            0x1c => return self.check_encoded_array(pos, section, depth + 1),
+            0x1d => return self.check_encoded_annotation(pos, section, depth + 1),
+            0x1e | 0x1f => return true,
+            0x00 => (1, None),
+            0x02 | 0x03 => (2, None),
+            0x04 | 0x10 => (4, None),
+            0x06 | 0x11 => (8, None),
+            0x15 => (4, Some((self.proto_ids.size, "prototype"))),
+            0x16 => (4, Some((self.method_handles_size, "method handle"))),
+            0x17 => (4, Some((self.string_ids.size, "string"))),
+            0x18 => (4, Some((self.type_ids.size, "type"))),
+            0x19 | 0x1b => (4, Some((self.field_ids.size, "field"))),
+            0x1a => (4, Some((self.method_ids.size, "method"))),
+            _ => return false,
+        };
+        let Some(bytes) = self.raw.get(*pos..*pos + size).filter(|_| size <= max_size) else {
+            return false;
+        };
+        *pos += size;
+
+        if let Some((ids_size, kind)) = ids {
+            let idx = bytes.iter().rev().fold(0usize, |idx, &byte| idx << 8 | usize::from(byte));
+            if idx >= ids_size {
+                self.report(DiagnosticCode::InvalidIndex, value_offset, section,
+                            format!("invalid {kind} index {idx} in encoded value"));
+            }
+        }
+
+        true
+    }
+}
+
+/// Required alignment of the items of the given type
+pub(crate) fn alignment(kind: MapItemType) -> usize {
+    match kind {
+        MapItemType::ClassDataItem
+        | MapItemType::StringDataItem
+        | MapItemType::DebugInfoItem
+        | MapItemType::AnnotationItem
+        | MapItemType::EncodedArrayItem => 1,
+        _ => 4,
+    }
+}
+
+/// Check if items of the given type are stored in the data section
+pub(crate) fn is_data_item(kind: MapItemType) -> bool {
+    !matches!(kind, MapItemType::HeaderItem
+                    | MapItemType::StringIdItem
+                    | MapItemType::TypeIdItem
+                    | MapItemType::ProtoIdItem
+                    | MapItemType::FieldIdItem
+                    | MapItemType::MethodIdItem
+                    | MapItemType::ClassDefItem
+                    | MapItemType::CallSiteIdItem
+                    | MapItemType::MethodHandleItem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ append_data_items, fake_code_dex, fake_dex };
+
+    fn codes(raw: &[u8]) -> Vec<DiagnosticCode> {
+        verify(raw).iter().map(|violation| violation.code).collect()
+    }
+
+    #[test]
+    fn test_verify_valid() {
+        assert!(verify(&fake_dex(&["La;", "Lb;"], &[])).is_empty());
+        assert!(verify(&fake_code_dex()).is_empty());
+    }
+
+    #[test]
+    fn test_verify_header() {
+        assert_eq!(codes(&[0; 16]), vec![DiagnosticCode::InvalidHeader]);
+
+        let mut raw = fake_dex(&["La;"], &[]);
+        raw[..8].copy_from_slice(b"dex\n099\0");
+        raw.extend_from_slice(&[0; 4]);
+        assert_eq!(codes(&raw), vec![DiagnosticCode::InvalidHeader, DiagnosticCode::InvalidHeader]);
+
+        // Type IDs out of the file, and no longer matching the map list
+        let mut raw = fake_dex(&["La;"], &[]);
+        raw[0x44..0x48].copy_from_slice(&0x10000u32.to_le_bytes());
+        assert_eq!(codes(&raw), vec![DiagnosticCode::SectionOutOfBounds, DiagnosticCode::InvalidMapList]);
+    }
+
+    #[test]
+    fn test_verify_ids() {
+        // Swap the two type IDs
+        let mut raw = fake_dex(&["La;", "Lb;"], &[]);
+        let type_ids_off = read_u32_at(&raw, 0x44).unwrap() as usize;
+        raw.copy_within(type_ids_off..type_ids_off + 4, type_ids_off + 4);
+        let violations = verify(&raw);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].code, DiagnosticCode::UnsortedIds);
+        assert_eq!(violations[0].offset as usize, type_ids_off + 4);
+
+        // Invalid superclass
+        let mut raw = fake_dex(&["La;"], &[]);
+        let class_defs_off = read_u32_at(&raw, 0x64).unwrap() as usize;
+        raw[class_defs_off + 8..class_defs_off + 12].copy_from_slice(&7u32.to_le_bytes());
+        let violations = verify(&raw);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].code, DiagnosticCode::InvalidIndex);
+        assert_eq!(violations[0].class.as_deref(), Some("La;"));
+
+        // The second class redefines the first one
+        let mut raw = fake_dex(&["La;", "Lb;"], &[]);
+        let class_defs_off = read_u32_at(&raw, 0x64).unwrap() as usize;
+        raw[class_defs_off + 32..class_defs_off + 36].copy_from_slice(&0u32.to_le_bytes());
+        let violations = verify(&raw);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].code, DiagnosticCode::DuplicateClass);
+        assert_eq!(violations[0].offset as usize, class_defs_off + 32);
+    }
+
+    #[test]
+    fn test_verify_class_data() {
+        let raw = fake_code_dex();
+        let map_off = read_u32_at(&raw, 0x34).unwrap() as usize;
+        let map = MapList::build(&raw, map_off).unwrap();
+        let code_off = map.get(MapItemType::CodeItem).unwrap().offset as usize;
+
+        // More ins than registers, and bytecode out of the file
+        let mut invalid = raw.clone();
+        invalid[code_off + 2] = 2;
+        invalid[code_off + 12..code_off + 16].copy_from_slice(&0x10000u32.to_le_bytes());
+        assert_eq!(codes(&invalid), vec![DiagnosticCode::InvalidCodeItem, DiagnosticCode::InvalidCodeItem]);
+
+        // Method without code, no longer native
+        let mut invalid = raw.clone();
+        let class_data_off = map.get(MapItemType::ClassDataItem).unwrap().offset as usize;
+        let run = class_data_off + invalid[class_data_off..].windows(4)
+                                                             .position(|entry| entry == [0x02, 0x89, 0x02, 0x00])
+                                                             .unwrap();
+        invalid[run + 2] = 0x00;
+        let violations = verify(&invalid);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].code, DiagnosticCode::InvalidClassData);
+        assert_eq!(violations[0].offset as usize, run);
+    }
+
+    #[test]
+    fn test_verify_map_coverage() {
+        let raw = fake_code_dex();
+        let map_off = read_u32_at(&raw, 0x34).unwrap() as usize;
+        let map = MapList::build(&raw, map_off).unwrap();
+        let code_off = map.get(MapItemType::CodeItem).unwrap().offset as usize;
+
+        // Describe the code items as debug info
+        let mut invalid = raw.clone();
+        let item = map.items.iter().position(|item| item.kind == MapItemType::CodeItem).unwrap();
+        invalid[map_off + 4 + 12 * item..map_off + 6 + 12 * item].copy_from_slice(&0x2003u16.to_le_bytes());
+        let violations = verify(&invalid);
+        assert_eq!(violations.len(), 2);
+        assert!(violations.iter().all(|violation| violation.code == DiagnosticCode::InvalidMapList));
+        assert!(violations.iter().any(|violation| violation.offset as usize == code_off));
+    }
+
+    #[test]
+    fn test_verify_encoded_values() {
+        let raw = fake_dex(&["La;"], &[]);
+        let class_defs_off = read_u32_at(&raw, 0x64).unwrap() as usize;
+        let with_items = |items: &[(u16, &[u8])]| {
+            let mut raw = raw.clone();
+            let offsets = append_data_items(&mut raw, items);
+            (raw, offsets)
+        };
+
+        // Static values: a valid string, then an invalid type
+        let values: &[u8] = &[0x02, 0x17, 0x00, 0x18, 0x09];
+        let (mut invalid, offsets) = with_items(&[(0x2005, values)]);
+        invalid[class_defs_off + 28..class_defs_off + 32].copy_from_slice(&(offsets[0] as u32).to_le_bytes());
+        let violations = verify(&invalid);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].code, DiagnosticCode::InvalidIndex);
+        assert_eq!(violations[0].offset as usize, offsets[0] + 3);
+        assert_eq!(violations[0].class.as_deref(), Some("La;"));
+
+        // Truncated static values, described as debug info
+        let (mut invalid, offsets) = with_items(&[(0x2003, &values[..2])]);
+        invalid[class_defs_off + 28..class_defs_off + 32].copy_from_slice(&(offsets[0] as u32).to_le_bytes());
+        assert_eq!(codes(&invalid), vec![DiagnosticCode::InvalidMapList, DiagnosticCode::InvalidStaticValues]);
+
+        // Annotation of the class with an invalid element name
+        let (mut invalid, offsets) = with_items(&[(0x2006, &[0; 16]), (0x1003, &[1, 0, 0, 0, 0, 0, 0, 0]),
+                                                  (0x2004, &[0x01, 0x00, 0x01, 0x07, 0x1e])]);
+        invalid[offsets[0]..offsets[0] + 4].copy_from_slice(&(offsets[1] as u32).to_le_bytes());
+        invalid[offsets[1] + 4..offsets[1] + 8].copy_from_slice(&(offsets[2] as u32).to_le_bytes());
+        invalid[class_defs_off + 20..class_defs_off + 24].copy_from_slice(&(offsets[0] as u32).to_le_bytes());
+        let violations = verify(&invalid);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].code, DiagnosticCode::InvalidIndex);
+        assert_eq!(violations[0].offset as usize, offsets[2] + 3);
+    }
+
+    #[test]
+    fn test_verify_nesting_limit() {
+        // Static values made of an empty array nested in an array
+        let mut raw = fake_dex(&["La;"], &[]);
+        let class_defs_off = read_u32_at(&raw, 0x64).unwrap() as usize;
+        let offsets = append_data_items(&mut raw, &[(0x2005, &[0x01, 0x1c, 0x00])]);
+        raw[class_defs_off + 28..class_defs_off + 32].copy_from_slice(&(offsets[0] as u32).to_le_bytes());
+        assert!(verify(&raw).is_empty());
+
+        let options = ParseOptions {
+            limits: ResourceLimits { max_nesting_depth: 0, ..Default::default() },
+            ..Default::default()
+        };
+        let codes = verify_with_options(&raw, &options).iter()
+                                                       .map(|violation| violation.code)
+                                                       .collect::<Vec<_>>();
+        assert_eq!(codes, vec![DiagnosticCode::InvalidStaticValues]);
+    }
+
+}
@@ -0,0 +1,253 @@
+//! Zero-copy view of a DEX file
+//!
+//! Parsing a `DexFile` decodes every string and every method up front, which is wasteful when
+//! only a few items are needed, e.g. when scanning a large corpus of memory-mapped files. A
+//! `DexView` borrows the bytes of the file instead: strings and code units are returned as
+//! slices of the input, and owned types are only created on demand.
+//!
+//! Only the view itself is zero-copy: `to_dex_file` builds a regular `DexFile`, whose `DexStrings`
+//! still own a copy of every string.
+
+use std::borrow::Cow;
+
+use crate::dex::code_item::CODE_ITEM_HEADER_SIZE;
+use crate::dex::file::DexFile;
+use crate::dex::header::DexHeader;
+use crate::dex::options::ParseOptions;
+use crate::bytes::{ read_u32_at, read_uleb128_at };
+use crate::dex::reader::{ DexEndianness, DexReader };
+use crate::error::DexError;
+
+/// A view of a DEX file borrowing its bytes
+#[derive(Debug)]
+pub struct DexView<'a> {
+    raw: &'a [u8],
+    endianness: DexEndianness,
+    header: DexHeader,
+}
+
+/// A string of a DEX file, borrowed from the string data section
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DexStr<'a> {
+    /// Size of the decoded string in UTF-16 code units
+    utf16_size: u32,
+    /// MUTF-8 bytes of the string, without the terminating null byte
+    bytes: &'a [u8],
+}
+
+/// The bytecode of a method, borrowed from its code item
+#[derive(Debug, Clone, Copy)]
+pub struct CodeUnits<'a> {
+    bytes: &'a [u8],
+    endianness: DexEndianness,
+}
+
+impl<'a> DexView<'a> {
+    /// Create a view of a DEX file, checking its header with the default options
+    pub fn new(raw: &'a [u8]) -> Result<Self, DexError> {
+        DexView::new_with_options(raw, &ParseOptions::default())
+    }
+
+    /// Create a view of a DEX file, checking its header with the given options
+    pub fn new_with_options(raw: &'a [u8], options: &ParseOptions) -> Result<Self, DexError> {
+        let mut dex_reader = DexReader::from_slice(raw)?;
+        let header = DexHeader::new_with_options(&mut dex_reader, options)?;
+
+        Ok(DexView {
+            raw,
+            endianness: dex_reader.endianness,
+            header,
+        })
+    }
+
+    /// Bytes of the DEX file
+    pub fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    /// Header of the DEX file
+    pub fn header(&self) -> &DexHeader {
+        &self.header
+    }
+
+    /// Number of strings in the DEX file
+    pub fn strings_count(&self) -> u32 {
+        self.header.string_ids_size
+    }
+
+    /// Get the string at the given index of the string IDs section
+    ///
+    /// Unlike `DexStrings`, strings are neither sorted nor deduplicated: the index is the one used
+    /// by the bytecode.
+    pub fn string(&self, idx: u32) -> Result<DexStr<'a>, DexError> {
+        if idx >= self.header.string_ids_size {
+            return Err(DexError::InvalidStringIdx);
+        }
+        let string_off = self.read_u32(self.header.string_ids_off as usize + idx as usize * 4)?;
+
+        let mut pos = string_off as usize;
+        let utf16_size = read_uleb128_at(self.raw, &mut pos)?;
+        let data = &self.raw[pos..];
+        let end = data.iter().position(|&byte| byte == 0).ok_or(DexError::NoDataLeftError)?;
+
+        Ok(DexStr {
+            utf16_size,
+            bytes: &data[..end],
+        })
+    }
+
+    /// Iterate over the strings of the DEX file, in the order of the string IDs section
+    pub fn strings(&self) -> impl Iterator<Item = Result<DexStr<'a>, DexError>> + '_ {
+        (0..self.header.string_ids_size).map(|idx| self.string(idx))
+    }
+
+    /// Get the descriptor of the type at the given index of the type IDs section
+    pub fn type_descriptor(&self, idx: u32) -> Result<DexStr<'a>, DexError> {
+        if idx >= self.header.type_ids_size {
+            return Err(DexError::InvalidTypeIdx);
+        }
+        let descriptor_idx = self.read_u32(self.header.type_ids_off as usize + idx as usize * 4)?;
+
+        self.string(descriptor_idx)
+    }
+
+    /// Get the bytecode of the code item at the given offset
+    pub fn code_units(&self, code_off: u32) -> Result<CodeUnits<'a>, DexError> {
+        let insns_size = self.read_u32(code_off as usize + 12)?;
+        let start = code_off as usize + CODE_ITEM_HEADER_SIZE;
+        let bytes = (insns_size as usize).checked_mul(2)
+                                         .and_then(|size| start.checked_add(size))
+                                         .and_then(|end| self.raw.get(start..end))
+                                         .ok_or(DexError::NoDataLeftError)?;
+
+        Ok(CodeUnits {
+            bytes,
+            endianness: self.endianness,
+        })
+    }
+
+    /// Parse the whole DEX file and create an owned `DexFile` object
+    pub fn to_dex_file(&self, options: &ParseOptions) -> Result<DexFile, DexError> {
+        DexFile::build_with_options(DexReader::from_slice(self.raw)?, options)
+    }
+
+    /// Read a `u32` in the endianness of the file
+    fn read_u32(&self, offset: usize) -> Result<u32, DexError> {
+        let value = read_u32_at(self.raw, offset)?;
+
+        Ok(match self.endianness {
+            DexEndianness::LittleEndian => value,
+            DexEndianness::BigEndian => value.swap_bytes(),
+        })
+    }
+}
+
+impl<'a> DexStr<'a> {
+    /// Size of the decoded string in UTF-16 code units
+    pub fn utf16_size(&self) -> u32 {
+        self.utf16_size
+    }
+
+    /// MUTF-8 bytes of the string
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Decode the string, borrowing it when it is valid UTF-8
+    ///
+    /// Invalid sequences are replaced, as `DexStrings` does.
+    pub fn to_str(&self) -> Cow<'a, str> {
+        String::from_utf8_lossy(self.bytes)
+    }
+}
+
+impl<'a> CodeUnits<'a> {
+    /// Number of code units
+    pub fn len(&self) -> usize {
+        self.bytes.len() / 2
+    }
+
+    /// Whether there is no code unit
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Raw bytes of the code units
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Get the code unit at the given index
+    pub fn get(&self, idx: usize) -> Option<u16> {
+        let start = idx.checked_mul(2)?;
+        let bytes = self.bytes.get(start..start.checked_add(2)?)?;
+        let bytes = [bytes[0], bytes[1]];
+
+        Some(match self.endianness {
+            DexEndianness::LittleEndian => u16::from_le_bytes(bytes),
+            DexEndianness::BigEndian => u16::from_be_bytes(bytes),
+        })
+    }
+
+    /// Iterate over the code units
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.len()).filter_map(|idx| self.get(idx))
+    }
+
+    /// Copy the code units into a vector
+    pub fn to_vec(&self) -> Vec<u16> {
+        self.iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{ fake_dex, fake_dex_with_methods, FakeMethod };
+
+    #[test]
+    fn test_strings() {
+        let raw = fake_dex(&["La/A;"], &["Ljava/lang/Object;"]);
+        let view = DexView::new(&raw).unwrap();
+        let dex = view.to_dex_file(&ParseOptions::default()).unwrap();
+
+        let strings = view.strings()
+                          .map(|string| string.unwrap().to_str().into_owned())
+                          .collect::<Vec<String>>();
+        assert_eq!(strings, dex.strings.strings);
+
+        let string = view.string(0).unwrap();
+        assert_eq!(string.as_bytes(), b"La/A;");
+        assert_eq!(string.utf16_size(), 5);
+        assert!(matches!(string.to_str(), Cow::Borrowed("La/A;")));
+        // The string is a slice of the input
+        assert!(raw.as_ptr_range().contains(&string.as_bytes().as_ptr()));
+
+        assert_eq!(view.type_descriptor(1).unwrap().to_str(), "Ljava/lang/Object;");
+        assert!(matches!(view.string(view.strings_count()), Err(DexError::InvalidStringIdx)));
+        assert!(matches!(view.type_descriptor(2), Err(DexError::InvalidTypeIdx)));
+    }
+
+    #[test]
+    fn test_code_units() {
+        let raw = fake_dex_with_methods(&["La;"], &[], &[FakeMethod {
+            class: "La;",
+            name: "f",
+            access_flags: 0x9,
+            code: Some((1, 0, 0, vec![0x0012, 0x000e])),
+        }]);
+        let view = DexView::new(&raw).unwrap();
+        let dex = view.to_dex_file(&ParseOptions::default()).unwrap();
+        let class = dex.get_class_def(&"La;".to_string()).unwrap();
+        let code_off = class.get_direct_methods()[0].get_code_off();
+
+        let code_units = view.code_units(code_off).unwrap();
+        assert_eq!(code_units.len(), 2);
+        assert_eq!(code_units.get(1), Some(0x000e));
+        assert_eq!(code_units.get(2), None);
+        assert_eq!(code_units.get(usize::MAX), None);
+        assert_eq!(code_units.to_vec(), vec![0x0012, 0x000e]);
+
+        assert!(matches!(view.code_units(raw.len() as u32), Err(DexError::InvalidSectionBounds)));
+    }
+}
@@ -0,0 +1,536 @@
+//! Minimal ELF parser
+//!
+//! Compiled ART artifacts (`.oat`/`.odex` files) and native libraries shipped in APKs are ELF
+//! files. We only need a small subset of the format: the section and program headers, to map
+//! virtual addresses to file offsets, and the dynamic symbol table, to find exported symbols.
+//! Only little-endian files are supported, which covers all Android ABIs.
+
+use crate::bytes::{ slice_at, read_u16_at, read_u32_at, read_u64_at, read_c_string_at };
+use crate::error::DexError;
+
+/// Magic bytes at the start of an ELF file
+pub const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+/// Section type of the dynamic symbol table
+const SHT_DYNSYM: u32 = 11;
+/// Program header type of the dynamic section
+const PT_DYNAMIC: u32 = 2;
+/// Program header type of a loadable segment
+pub const PT_LOAD: u32 = 1;
+
+/// Dynamic section tags
+const DT_NULL: u64 = 0;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_STRSZ: u64 = 10;
+const DT_GNU_HASH: u64 = 0x6fff_fef5;
+
+/// Representation of a section header
+#[derive(Debug)]
+pub struct ElfSection {
+    /// Name of the section, empty if the file has no section names table
+    pub name: String,
+    /// Type of the section (`SHT_*` constant)
+    pub kind: u32,
+    /// Virtual address of the section once loaded
+    pub addr: u64,
+    /// Offset of the section in the file
+    pub offset: u64,
+    /// Size of the section in the file
+    pub size: u64,
+    /// Index of the associated section (e.g., the string table of a symbol table)
+    pub link: u32,
+}
+
+/// Representation of a program header
+#[derive(Debug)]
+pub struct ElfSegment {
+    /// Type of the segment (`PT_*` constant)
+    pub kind: u32,
+    /// Offset of the segment in the file
+    pub offset: u64,
+    /// Virtual address of the segment once loaded
+    pub vaddr: u64,
+    /// Size of the segment in the file
+    pub filesz: u64,
+    /// Size of the segment in memory
+    pub memsz: u64,
+}
+
+/// Representation of a symbol of the dynamic symbol table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElfSymbol {
+    /// Name of the symbol
+    pub name: String,
+    /// Value of the symbol, usually a virtual address
+    pub value: u64,
+    /// Size of the object referenced by the symbol
+    pub size: u64,
+    /// Symbol type (`STT_*` constant)
+    pub kind: u8,
+    /// Symbol binding (`STB_*` constant)
+    pub binding: u8,
+    /// Index of the section the symbol is defined in, zero for undefined symbols
+    pub section_idx: u16,
+}
+
+impl ElfSymbol {
+    /// Check if the symbol is defined in this file (as opposed to imported)
+    pub fn is_defined(&self) -> bool {
+        self.section_idx != 0
+    }
+}
+
+/// Representation of an ELF file
+#[derive(Debug)]
+pub struct ElfFile {
+    /// Whether the file uses the 64 bits format
+    pub is_64: bool,
+    /// Target architecture (`EM_*` constant)
+    pub machine: u16,
+    /// Section headers, possibly empty for stripped files
+    pub sections: Vec<ElfSection>,
+    /// Program headers
+    pub segments: Vec<ElfSegment>,
+    /// Symbols of the dynamic symbol table
+    pub dynamic_symbols: Vec<ElfSymbol>,
+}
+
+/// Location of a symbol table: offset of the table, number of symbols, and string table
+type SymbolTable<'a> = (usize, usize, &'a [u8]);
+
+/// Layout of the integer fields, which depends on the ELF class
+struct ElfLayout {
+    is_64: bool,
+}
+
+impl ElfLayout {
+    /// Read an address-sized field
+    fn read_addr(&self, raw: &[u8], offset: usize) -> Result<u64, DexError> {
+        if self.is_64 {
+            read_u64_at(raw, offset)
+        } else {
+            Ok(read_u32_at(raw, offset)? as u64)
+        }
+    }
+
+    /// Size of a symbol table entry
+    fn symbol_size(&self) -> usize {
+        if self.is_64 { 24 } else { 16 }
+    }
+
+    /// Read the symbol table entry at `offset`, resolving its name in `strtab`
+    fn read_symbol(&self, raw: &[u8], offset: usize, strtab: &[u8]) -> Result<ElfSymbol, DexError> {
+        slice_at(raw, offset, self.symbol_size())?;
+        let name_idx = read_u32_at(raw, offset)? as usize;
+        let (value, size, info, section_idx) = if self.is_64 {
+            (read_u64_at(raw, offset + 8)?,
+             read_u64_at(raw, offset + 16)?,
+             slice_at(raw, offset + 4, 1)?[0],
+             read_u16_at(raw, offset + 6)?)
+        } else {
+            (read_u32_at(raw, offset + 4)? as u64,
+             read_u32_at(raw, offset + 8)? as u64,
+             slice_at(raw, offset + 12, 1)?[0],
+             read_u16_at(raw, offset + 14)?)
+        };
+
+        Ok(ElfSymbol {
+            name: read_c_string_at(strtab, name_idx).unwrap_or_default(),
+            value,
+            size,
+            kind: info & 0x0f,
+            binding: info >> 4,
+            section_idx,
+        })
+    }
+}
+
+impl ElfFile {
+    /// Open the ELF file at the given path and parse it
+    pub fn build_from_file(filepath: &str) -> Result<Self, DexError> {
+        let raw = std::fs::read(filepath)?;
+        ElfFile::build(&raw)
+    }
+
+    /// Parse an ELF file from its raw bytes
+    pub fn build(raw: &[u8]) -> Result<Self, DexError> {
+        if slice_at(raw, 0, 4)? != ELF_MAGIC {
+            return Err(DexError::InvalidElfMagic);
+        }
+
+        // EI_CLASS: 1 for 32 bits, 2 for 64 bits; EI_DATA: 1 for little-endian
+        let ident = slice_at(raw, 4, 2)?;
+        let is_64 = match ident[0] {
+            1 => false,
+            2 => true,
+            _ => return Err(DexError::UnsupportedElfFile),
+        };
+        if ident[1] != 1 {
+            return Err(DexError::UnsupportedElfFile);
+        }
+        let layout = ElfLayout { is_64 };
+
+        let machine = read_u16_at(raw, 18)?;
+        let (phoff, shoff, fields_off) = if is_64 {
+            (read_u64_at(raw, 32)?, read_u64_at(raw, 40)?, 54)
+        } else {
+            (read_u32_at(raw, 28)? as u64, read_u32_at(raw, 32)? as u64, 42)
+        };
+        let phentsize = read_u16_at(raw, fields_off)? as usize;
+        let phnum     = read_u16_at(raw, fields_off + 2)? as usize;
+        let shentsize = read_u16_at(raw, fields_off + 4)? as usize;
+        let shnum     = read_u16_at(raw, fields_off + 6)? as usize;
+        let shstrndx  = read_u16_at(raw, fields_off + 8)? as usize;
+
+        let mut segments = Vec::new();
+        for idx in 0..phnum {
+            let offset = (phoff as usize).checked_add(idx * phentsize).ok_or(DexError::InvalidSectionBounds)?;
+            // Bound the whole entry first so the field offsets below cannot overflow
+            slice_at(raw, offset, if is_64 { 56 } else { 32 })?;
+            let segment = if is_64 {
+                ElfSegment {
+                    kind:   read_u32_at(raw, offset)?,
+                    offset: read_u64_at(raw, offset + 8)?,
+                    vaddr:  read_u64_at(raw, offset + 16)?,
+                    filesz: read_u64_at(raw, offset + 32)?,
+                    memsz:  read_u64_at(raw, offset + 40)?,
+                }
+            } else {
+                ElfSegment {
+                    kind:   read_u32_at(raw, offset)?,
+                    offset: read_u32_at(raw, offset + 4)? as u64,
+                    vaddr:  read_u32_at(raw, offset + 8)? as u64,
+                    filesz: read_u32_at(raw, offset + 16)? as u64,
+                    memsz:  read_u32_at(raw, offset + 20)? as u64,
+                }
+            };
+            segments.push(segment);
+        }
+
+        let mut sections = Vec::new();
+        let mut name_offsets = Vec::new();
+        for idx in 0..shnum {
+            let offset = (shoff as usize).checked_add(idx * shentsize).ok_or(DexError::InvalidSectionBounds)?;
+            slice_at(raw, offset, if is_64 { 64 } else { 40 })?;
+            name_offsets.push(read_u32_at(raw, offset)? as usize);
+            let kind = read_u32_at(raw, offset + 4)?;
+            let section = if is_64 {
+                ElfSection {
+                    name: String::new(),
+                    kind,
+                    addr:   read_u64_at(raw, offset + 16)?,
+                    offset: read_u64_at(raw, offset + 24)?,
+                    size:   read_u64_at(raw, offset + 32)?,
+                    link:   read_u32_at(raw, offset + 40)?,
+                }
+            } else {
+                ElfSection {
+                    name: String::new(),
+                    kind,
+                    addr:   read_u32_at(raw, offset + 12)? as u64,
+                    offset: read_u32_at(raw, offset + 16)? as u64,
+                    size:   read_u32_at(raw, offset + 20)? as u64,
+                    link:   read_u32_at(raw, offset + 24)?,
+                }
+            };
+            sections.push(section);
+        }
+
+        // Resolve section names if there is a section names table
+        if shstrndx != 0 && shstrndx < sections.len() {
+            let shstrtab = slice_at(raw,
+                                    sections[shstrndx].offset as usize,
+                                    sections[shstrndx].size as usize)?.to_vec();
+            for (section, name_off) in sections.iter_mut().zip(name_offsets) {
+                section.name = read_c_string_at(&shstrtab, name_off).unwrap_or_default();
+            }
+        }
+
+        let mut elf = ElfFile {
+            is_64,
+            machine,
+            sections,
+            segments,
+            dynamic_symbols: Vec::new(),
+        };
+        elf.dynamic_symbols = elf.read_dynamic_symbols(raw, &layout)?;
+
+        Ok(elf)
+    }
+
+    /// Read the dynamic symbol table
+    ///
+    /// We first look for the `SHT_DYNSYM` section. Files with stripped section headers still
+    /// need the dynamic section at runtime, so we fall back to it when there is no such section.
+    fn read_dynamic_symbols(&self, raw: &[u8], layout: &ElfLayout) -> Result<Vec<ElfSymbol>, DexError> {
+        let (symtab_off, symbols_count, strtab) =
+            match self.sections.iter().find(|section| section.kind == SHT_DYNSYM) {
+                Some(dynsym) => {
+                    let strtab = self.sections.get(dynsym.link as usize)
+                                              .ok_or(DexError::InvalidSectionBounds)?;
+                    let strtab = slice_at(raw, strtab.offset as usize, strtab.size as usize)?;
+                    (dynsym.offset as usize,
+                     dynsym.size as usize / layout.symbol_size(),
+                     strtab)
+                },
+                None => match self.read_dynamic_section(raw, layout)? {
+                    Some(found) => found,
+                    None => return Ok(Vec::new()),
+                }
+            };
+
+        let mut symbols = Vec::new();
+        for idx in 0..symbols_count {
+            let offset = idx.checked_mul(layout.symbol_size())
+                            .and_then(|offset| offset.checked_add(symtab_off))
+                            .ok_or(DexError::InvalidSectionBounds)?;
+            symbols.push(layout.read_symbol(raw, offset, strtab)?);
+        }
+
+        Ok(symbols)
+    }
+
+    /// Locate the dynamic symbol table and its string table using the dynamic section
+    fn read_dynamic_section<'a>(&self,
+                                raw: &'a [u8],
+                                layout: &ElfLayout) -> Result<Option<SymbolTable<'a>>, DexError> {
+        let Some(dynamic) = self.segments.iter().find(|segment| segment.kind == PT_DYNAMIC) else {
+            return Ok(None);
+        };
+
+        let entry_size = if layout.is_64 { 16 } else { 8 };
+        let mut tags = Vec::new();
+        for idx in 0..(dynamic.filesz as usize / entry_size) {
+            let offset = (dynamic.offset as usize).checked_add(idx * entry_size)
+                                                  .ok_or(DexError::InvalidSectionBounds)?;
+            let tag = layout.read_addr(raw, offset)?;
+            let value = layout.read_addr(raw, offset.checked_add(entry_size / 2)
+                                                    .ok_or(DexError::InvalidSectionBounds)?)?;
+            if tag == DT_NULL {
+                break;
+            }
+            tags.push((tag, value));
+        }
+        let get_tag = |wanted: u64| tags.iter().find(|(tag, _)| *tag == wanted).map(|(_, value)| *value);
+
+        let (Some(symtab), Some(strtab), Some(strsz)) = (get_tag(DT_SYMTAB), get_tag(DT_STRTAB), get_tag(DT_STRSZ)) else {
+            return Ok(None);
+        };
+        let symtab_off = self.virtual_address_to_offset(symtab).ok_or(DexError::InvalidSectionBounds)? as usize;
+        let strtab_off = self.virtual_address_to_offset(strtab).ok_or(DexError::InvalidSectionBounds)? as usize;
+        let strtab = slice_at(raw, strtab_off, strsz as usize)?;
+
+        // The number of symbols is not stored, but can be deduced from the hash tables
+        let symbols_count = if let Some(hash) = get_tag(DT_HASH) {
+            let hash_off = self.virtual_address_to_offset(hash).ok_or(DexError::InvalidSectionBounds)? as usize;
+            read_u32_at(raw, hash_off.checked_add(4).ok_or(DexError::InvalidSectionBounds)?)? as usize
+        } else if let Some(gnu_hash) = get_tag(DT_GNU_HASH) {
+            let hash_off = self.virtual_address_to_offset(gnu_hash).ok_or(DexError::InvalidSectionBounds)? as usize;
+            ElfFile::count_gnu_hash_symbols(raw, hash_off, layout)?
+        } else {
+            0
+        };
+
+        Ok(Some((symtab_off, symbols_count, strtab)))
+    }
+
+    /// Compute the number of symbols covered by a GNU hash table
+    fn count_gnu_hash_symbols(raw: &[u8], offset: usize, layout: &ElfLayout) -> Result<usize, DexError> {
+        // Every offset comes from the file: an overflow means the table is out of bounds
+        let at = |base: usize, delta: usize| base.checked_add(delta).ok_or(DexError::InvalidSectionBounds);
+
+        let nbuckets = read_u32_at(raw, offset)? as usize;
+        let symoffset = read_u32_at(raw, at(offset, 4)?)? as usize;
+        let bloom_size = read_u32_at(raw, at(offset, 8)?)? as usize;
+        let word_size = if layout.is_64 { 8 } else { 4 };
+
+        let buckets_off = at(offset, 16 + bloom_size * word_size)?;
+        let mut last_symbol = 0;
+        for idx in 0..nbuckets {
+            last_symbol = last_symbol.max(read_u32_at(raw, at(buckets_off, idx * 4)?)? as usize);
+        }
+        if last_symbol < symoffset {
+            return Ok(symoffset);
+        }
+
+        // Walk the chain of the last bucket until the end marker (lowest bit set)
+        let chains_off = at(buckets_off, nbuckets * 4)?;
+        loop {
+            let hash = read_u32_at(raw, at(chains_off, (last_symbol - symoffset) * 4)?)?;
+            last_symbol += 1;
+            if hash & 1 == 1 {
+                break;
+            }
+        }
+
+        Ok(last_symbol)
+    }
+
+    /// Get a dynamic symbol by name
+    pub fn get_dynamic_symbol(&self, name: &str) -> Option<&ElfSymbol> {
+        self.dynamic_symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Convert a virtual address into an offset in the file using the loadable segments
+    pub fn virtual_address_to_offset(&self, vaddr: u64) -> Option<u64> {
+        self.segments.iter()
+                     .filter(|segment| segment.kind == PT_LOAD)
+                     .find(|segment| vaddr >= segment.vaddr && vaddr - segment.vaddr < segment.filesz)
+                     .and_then(|segment| (vaddr - segment.vaddr).checked_add(segment.offset))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Build a minimal 64 bits ELF file with a dynamic symbol table and a payload
+    ///
+    /// The whole file is mapped at address 0 so symbol values are also file offsets. Symbols are
+    /// given as (name, offset in the payload, size) and are made relative to the start of the
+    /// file.
+    pub(crate) fn fake_elf(symbols: &[(&str, u64, u64)], payload: &[u8]) -> Vec<u8> {
+        let mut strtab = vec![0u8];
+        let mut name_offsets = Vec::new();
+        for (name, _, _) in symbols {
+            name_offsets.push(strtab.len() as u32);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+        }
+
+        let phoff = 64;
+        let strtab_off = phoff + 56;
+        let symtab_off = (strtab_off + strtab.len()).div_ceil(8) * 8;
+        let symtab_size = (symbols.len() + 1) * 24;
+        let payload_off = symtab_off + symtab_size;
+        let shoff = (payload_off + payload.len()).div_ceil(8) * 8;
+
+        let mut symtab = vec![0u8; 24];
+        for ((_, value, size), name_off) in symbols.iter().zip(name_offsets) {
+            symtab.extend_from_slice(&name_off.to_le_bytes());
+            symtab.push(0x11);                                       // global object
+            symtab.push(0);
+            symtab.extend_from_slice(&3u16.to_le_bytes());           // defined in the payload
+            symtab.extend_from_slice(&(payload_off as u64 + value).to_le_bytes());
+            symtab.extend_from_slice(&size.to_le_bytes());
+        }
+
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&ELF_MAGIC);
+        raw.extend_from_slice(&[2, 1, 1, 0]);
+        raw.resize(16, 0);
+        raw.extend_from_slice(&3u16.to_le_bytes());                  // ET_DYN
+        raw.extend_from_slice(&183u16.to_le_bytes());                // EM_AARCH64
+        raw.extend_from_slice(&1u32.to_le_bytes());
+        raw.extend_from_slice(&0u64.to_le_bytes());                  // entry
+        raw.extend_from_slice(&(phoff as u64).to_le_bytes());
+        raw.extend_from_slice(&(shoff as u64).to_le_bytes());
+        raw.extend_from_slice(&0u32.to_le_bytes());                  // flags
+        raw.extend_from_slice(&64u16.to_le_bytes());
+        raw.extend_from_slice(&56u16.to_le_bytes());
+        raw.extend_from_slice(&1u16.to_le_bytes());
+        raw.extend_from_slice(&64u16.to_le_bytes());
+        raw.extend_from_slice(&4u16.to_le_bytes());
+        raw.extend_from_slice(&0u16.to_le_bytes());                  // no section names
+
+        let file_size = (shoff + 4 * 64) as u64;
+        raw.extend_from_slice(&PT_LOAD.to_le_bytes());
+        raw.extend_from_slice(&5u32.to_le_bytes());
+        raw.extend_from_slice(&0u64.to_le_bytes());
+        raw.extend_from_slice(&0u64.to_le_bytes());
+        raw.extend_from_slice(&0u64.to_le_bytes());
+        raw.extend_from_slice(&file_size.to_le_bytes());
+        raw.extend_from_slice(&file_size.to_le_bytes());
+        raw.extend_from_slice(&0x1000u64.to_le_bytes());
+
+        raw.extend_from_slice(&strtab);
+        raw.resize(symtab_off, 0);
+        raw.extend_from_slice(&symtab);
+        raw.extend_from_slice(payload);
+        raw.resize(shoff, 0);
+
+        let section = |kind: u32, offset: usize, size: usize, link: u32, entsize: u64| {
+            let mut header = Vec::new();
+            header.extend_from_slice(&0u32.to_le_bytes());
+            header.extend_from_slice(&kind.to_le_bytes());
+            header.extend_from_slice(&2u64.to_le_bytes());
+            header.extend_from_slice(&(offset as u64).to_le_bytes());
+            header.extend_from_slice(&(offset as u64).to_le_bytes());
+            header.extend_from_slice(&(size as u64).to_le_bytes());
+            header.extend_from_slice(&link.to_le_bytes());
+            header.extend_from_slice(&1u32.to_le_bytes());
+            header.extend_from_slice(&8u64.to_le_bytes());
+            header.extend_from_slice(&entsize.to_le_bytes());
+            header
+        };
+        raw.extend_from_slice(&[0u8; 64]);
+        raw.extend_from_slice(&section(3, strtab_off, strtab.len(), 0, 0));
+        raw.extend_from_slice(&section(SHT_DYNSYM, symtab_off, symtab_size, 1, 24));
+        raw.extend_from_slice(&section(1, payload_off, payload.len(), 0, 0));
+
+        raw
+    }
+
+    #[test]
+    fn test_build() {
+        let raw = fake_elf(&[("oatdata", 0, 16), ("Java_Foo_bar", 8, 8)], &[0xaa; 16]);
+        let elf = ElfFile::build(&raw).unwrap();
+
+        assert!(elf.is_64);
+        assert_eq!(elf.machine, 183);
+        assert_eq!(elf.sections.len(), 4);
+        assert_eq!(elf.dynamic_symbols.len(), 3);
+
+        let symbol = elf.get_dynamic_symbol("Java_Foo_bar").unwrap();
+        assert!(symbol.is_defined());
+        assert_eq!(symbol.size, 8);
+
+        let offset = elf.virtual_address_to_offset(symbol.value).unwrap() as usize;
+        assert_eq!(raw[offset], 0xaa);
+        assert!(elf.get_dynamic_symbol("oatexec").is_none());
+    }
+
+    #[test]
+    fn test_virtual_address_to_offset_overflow() {
+        let raw = fake_elf(&[("oatdata", 0, 16)], &[0xaa; 16]);
+        let mut elf = ElfFile::build(&raw).unwrap();
+        let segment = elf.segments.iter_mut().find(|segment| segment.kind == PT_LOAD).unwrap();
+        segment.vaddr = u64::MAX - 4;
+        segment.filesz = 16;
+        segment.offset = u64::MAX;
+
+        assert_eq!(elf.virtual_address_to_offset(u64::MAX - 4), Some(u64::MAX));
+        assert_eq!(elf.virtual_address_to_offset(u64::MAX), None);
+        assert_eq!(elf.virtual_address_to_offset(0), None);
+    }
+
+    #[test]
+    fn test_read_dynamic_section_overflow() {
+        let mut raw = fake_elf(&[("oatdata", 0, 16)], &[0xaa; 16]);
+        let mut elf = ElfFile::build(&raw).unwrap();
+
+        // The hash table is mapped right before the end of the address space
+        let dynamic_off = raw.len() as u64;
+        for (tag, value) in [(DT_SYMTAB, 0u64), (DT_STRTAB, 0), (DT_STRSZ, 1), (DT_HASH, 0x100000), (DT_NULL, 0)] {
+            raw.extend_from_slice(&tag.to_le_bytes());
+            raw.extend_from_slice(&value.to_le_bytes());
+        }
+        elf.segments.push(ElfSegment { kind: PT_DYNAMIC, offset: dynamic_off, vaddr: 0, filesz: 80, memsz: 80 });
+        elf.segments.push(ElfSegment { kind: PT_LOAD, offset: u64::MAX - 2, vaddr: 0x100000, filesz: 16, memsz: 16 });
+
+        let layout = ElfLayout { is_64: true };
+        assert!(elf.read_dynamic_section(&raw, &layout).unwrap_err().is(&DexError::InvalidSectionBounds));
+        assert!(ElfFile::count_gnu_hash_symbols(&raw, usize::MAX - 4, &layout).unwrap_err()
+                                                                           .is(&DexError::InvalidSectionBounds));
+    }
+
+    #[test]
+    fn test_build_invalid() {
+        assert_eq!(ElfFile::build(b"\x7fELG\x02\x01").unwrap_err().to_string(),
+                   "invalid ELF magic");
+        assert_eq!(ElfFile::build(b"\x7fELF\x02\x02").unwrap_err().to_string(),
+                   "unsupported ELF class or endianness");
+    }
+}
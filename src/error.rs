@@ -1,15 +1,30 @@
 //! Collection of error types for DEX files parsing
 //!
 //! We rely on `thiserror` for the heavy lifting
+//!
+//! Errors raised while parsing a DEX file are wrapped in `DexError::Located`, which records where
+//! the parser was when the error happened: the chain of sections and items being parsed, with
+//! their offsets, the offending index, and the enclosing class and method. Use `DexError::root`
+//! to get the underlying error.
+//!
+//! Code matching on the error directly, e.g. `matches!(err, DexError::InvalidTypeIdx)`, no longer
+//! matches located errors: match on `err.root()` instead, or use `DexError::is`.
 
+use std::fmt;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 /// All errors that can be returned by the parser
+///
+/// Parse errors are usually wrapped in `Located`: use `root` or `is` to check which error
+/// happened.
 pub enum DexError {
     /// The checksum of the header does not match the one in the DEX header
     #[error("computed checksum does not match one in header")]
     InvalidChecksumError,
+    /// The SHA-1 signature of the header does not match the contents of the file
+    #[error("computed signature does not match one in header")]
+    InvalidSignatureError,
     /// The header of the file is too short to be a valid DEX header
     #[error("DEX header too short")]
     DexHeaderTooShortError,
@@ -46,4 +61,269 @@ pub enum DexError {
     /// Encountered an invalid or unused opcode
     #[error("cannot parse instruction opcode")]
     InvalidOpCode,
+    /// Encountered an encoded value with an invalid type or size
+    #[error("cannot decode encoded value")]
+    InvalidEncodedValue,
+    /// The file does not start with the VDEX magic
+    #[error("invalid VDEX magic")]
+    InvalidVdexMagic,
+    /// The VDEX version is not supported by the parser
+    #[error("unsupported VDEX version")]
+    UnsupportedVdexVersion,
+    /// A section of a container file points outside of the file
+    #[error("section offset or size out of bounds")]
+    InvalidSectionBounds,
+    /// The file does not start with the ELF magic
+    #[error("invalid ELF magic")]
+    InvalidElfMagic,
+    /// The ELF file is big-endian or uses an unknown class
+    #[error("unsupported ELF class or endianness")]
+    UnsupportedElfFile,
+    /// A required symbol is missing from the ELF dynamic symbols
+    #[error("cannot find symbol in ELF file")]
+    MissingElfSymbol,
+    /// The OAT data does not start with the OAT magic
+    #[error("invalid OAT magic")]
+    InvalidOatMagic,
+    /// The table of DEX files of the OAT file cannot be located or decoded
+    #[error("cannot decode OAT DEX files table")]
+    InvalidOatDexFilesTable,
+    /// The file does not start with the ODEX magic
+    #[error("invalid ODEX magic")]
+    InvalidOdexMagic,
+    /// The ODEX version is not supported by the parser
+    #[error("unsupported ODEX version")]
+    UnsupportedOdexVersion,
+    /// The file is not a valid ZIP archive
+    #[error("invalid ZIP archive")]
+    InvalidZipArchive,
+    /// A binary XML or resource table chunk is malformed
+    #[error("invalid resource chunk")]
+    InvalidResourceChunk,
+    /// A string pool of a binary XML or resource table file is malformed
+    #[error("invalid string pool")]
+    InvalidStringPool,
+    /// The APK does not contain a valid `AndroidManifest.xml`
+    #[error("cannot find Android manifest")]
+    MissingManifest,
+    /// The APK does not contain a `resources.arsc` file
+    #[error("cannot find resource table")]
+    MissingResourceTable,
+    /// A certificate or signature is not valid DER
+    #[error("invalid DER encoding")]
+    InvalidDerEncoding,
+    /// The APK Signing Block or one of its signature scheme blocks is malformed
+    #[error("invalid APK Signing Block")]
+    InvalidApkSigningBlock,
+    /// The file does not start with an ART profile or profile metadata magic
+    #[error("invalid ART profile magic")]
+    InvalidProfileMagic,
+    /// The ART profile version is not supported by the parser
+    #[error("unsupported ART profile version")]
+    UnsupportedProfileVersion,
+    /// The compressed body of an ART profile cannot be inflated or decoded
+    #[error("invalid ART profile data")]
+    InvalidProfileData,
+    /// The archive does not contain an ART profile
+    #[error("cannot find ART profile")]
+    MissingProfile,
+    /// The checksum recorded in the profile does not match the DEX file
+    #[error("profile checksum does not match the DEX file")]
+    ProfileChecksumMismatch,
+    /// The signing key or its certificate cannot be decoded, or they do not match
+    #[error("invalid signing key")]
+    InvalidSigningKey,
+    /// The map list of a DEX file is malformed
+    #[error("invalid map list")]
+    InvalidMapList,
+    /// The input exceeds one of the resource limits of the parser
+    #[error("resource limit exceeded")]
+    ResourceLimitExceeded,
+    /// An error, along with where it happened in the DEX file
+    #[error("{location}: {source}")]
+    Located {
+        location: Box<ErrorLocation>,
+        source: Box<DexError>,
+    },
+}
+
+impl DexError {
+    /// Get the underlying error, without its location
+    pub fn root(&self) -> &DexError {
+        match self {
+            DexError::Located { source, .. } => source.root(),
+            err => err,
+        }
+    }
+
+    /// Check whether the underlying error is of the same kind as the given one
+    ///
+    /// Only the variants are compared, e.g. `err.is(&DexError::InvalidTypeIdx)`.
+    pub fn is(&self, other: &DexError) -> bool {
+        std::mem::discriminant(self.root()) == std::mem::discriminant(other.root())
+    }
+
+    /// Get where the error happened, if known
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        match self {
+            DexError::Located { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// Update the location of the error, wrapping it if it does not have one yet
+    fn locate<F>(self, update: F) -> Self
+    where F: FnOnce(&mut ErrorLocation) {
+        match self {
+            DexError::Located { mut location, source } => {
+                update(&mut location);
+                DexError::Located { location, source }
+            },
+            err => {
+                let mut location = Box::default();
+                update(&mut location);
+                DexError::Located { location, source: Box::new(err) }
+            }
+        }
+    }
+}
+
+/// A section or item being parsed, e.g., `direct_methods[3]` or `code_item@0x1a2c`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorFrame {
+    /// Name of the section or item
+    pub name: &'static str,
+    /// Index of the item in its section
+    pub index: Option<usize>,
+    /// Offset of the section or item in the file
+    pub offset: Option<u64>,
+}
+
+impl ErrorFrame {
+    /// A section or item at the given offset
+    pub fn at(name: &'static str, offset: u64) -> Self {
+        ErrorFrame { name, index: None, offset: Some(offset) }
+    }
+
+    /// An item of a list, at the given offset
+    pub fn item(name: &'static str, index: usize, offset: u64) -> Self {
+        ErrorFrame { name, index: Some(index), offset: Some(offset) }
+    }
+}
+
+impl fmt::Display for ErrorFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(index) = self.index {
+            write!(f, "[{index}]")?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, "@0x{offset:x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Where an error happened in a DEX file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorLocation {
+    /// Sections and items being parsed, from the outermost to the innermost
+    pub frames: Vec<ErrorFrame>,
+    /// Offending index, for errors on invalid indexes
+    pub index: Option<u64>,
+    /// Class being parsed
+    pub class: Option<String>,
+    /// Method being parsed
+    pub method: Option<String>,
+}
+
+impl ErrorLocation {
+    /// Offset of the innermost item with a known offset
+    pub fn offset(&self) -> Option<u64> {
+        self.frames.iter().rev().find_map(|frame| frame.offset)
+    }
+}
+
+impl fmt::Display for ErrorLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.frames.iter()
+                              .map(|frame| frame.to_string())
+                              .collect::<Vec<String>>();
+        write!(f, "{}", path.join(" -> "))?;
+        if let Some(method) = &self.method {
+            write!(f, " in {method}")?;
+        } else if let Some(class) = &self.class {
+            write!(f, " in {class}")?;
+        }
+        if let Some(index) = self.index {
+            write!(f, " (index {index})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Attach location information to the errors of results
+pub(crate) trait ErrorContext<T> {
+    /// Record that the error happened while parsing the given section or item
+    fn in_frame(self, frame: ErrorFrame) -> Result<T, DexError>;
+    /// Record the class being parsed
+    fn in_class(self, class: &str) -> Result<T, DexError>;
+    /// Record the method being parsed
+    fn in_method(self, method: &str) -> Result<T, DexError>;
+    /// Record the offending index
+    fn at_index(self, index: u64) -> Result<T, DexError>;
+}
+
+impl<T> ErrorContext<T> for Result<T, DexError> {
+    fn in_frame(self, frame: ErrorFrame) -> Result<T, DexError> {
+        self.map_err(|err| err.locate(|location| location.frames.insert(0, frame)))
+    }
+
+    fn in_class(self, class: &str) -> Result<T, DexError> {
+        self.map_err(|err| err.locate(|location| {
+            location.class.get_or_insert_with(|| class.to_string());
+        }))
+    }
+
+    fn in_method(self, method: &str) -> Result<T, DexError> {
+        self.map_err(|err| err.locate(|location| {
+            location.method.get_or_insert_with(|| method.to_string());
+        }))
+    }
+
+    fn at_index(self, index: u64) -> Result<T, DexError> {
+        self.map_err(|err| err.locate(|location| location.index = Some(index)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_located_error() {
+        let result: Result<(), DexError> = Err(DexError::InvalidTypeIdx);
+        let err = result.at_index(70000)
+                        .in_frame(ErrorFrame::at("code_item", 0x1a2c))
+                        .in_method("La;->foo()V")
+                        .in_frame(ErrorFrame::item("direct_methods", 3, 0x1a00))
+                        .in_frame(ErrorFrame { name: "class_data", index: None, offset: None })
+                        .in_class("La;")
+                        .in_frame(ErrorFrame::item("class_defs", 12, 0x200))
+                        .unwrap_err();
+
+        assert!(matches!(err.root(), DexError::InvalidTypeIdx));
+        assert!(!matches!(err, DexError::InvalidTypeIdx));
+        assert!(err.is(&DexError::InvalidTypeIdx));
+        assert!(!err.is(&DexError::InvalidStringIdx));
+        let location = err.location().unwrap();
+        assert_eq!(location.offset(), Some(0x1a2c));
+        assert_eq!(location.class.as_deref(), Some("La;"));
+        assert_eq!(err.to_string(),
+                   "class_defs[12]@0x200 -> class_data -> direct_methods[3]@0x1a00 -> code_item@0x1a2c \
+                    in La;->foo()V (index 70000): cannot find element in types list");
+
+        assert!(matches!(DexError::InvalidMapList.root(), DexError::InvalidMapList));
+        assert!(DexError::InvalidMapList.location().is_none());
+    }
 }
@@ -0,0 +1,271 @@
+//! Fixtures shared by the tests of the crate
+//!
+//! DEX files are built from scratch, with a valid checksum and map list, so that tests can corrupt
+//! a single field and check how it is handled.
+
+use crate::bytes::read_u32_at;
+use crate::dex::classes::NO_INDEX;
+use crate::dex::file::DexFile;
+use crate::dex::header::{ HEADER_SIZE, ENDIAN_CONSTANT };
+use crate::dex::reader::DexReader;
+
+/// A method defined by `fake_dex_with_methods`, with a `()V` prototype
+pub(crate) struct FakeMethod<'a> {
+    pub class: &'a str,
+    pub name: &'a str,
+    pub access_flags: u32,
+    /// Registers, ins, and outs sizes, and code units of the bytecode
+    pub code: Option<(u16, u16, u16, Vec<u16>)>,
+}
+
+/// Build a DEX file defining the given classes and referencing the given types
+///
+/// Classes have no superclass, no members, and no class data. The map list describes the
+/// header, the IDs sections, and the string data.
+pub(crate) fn fake_dex(classes: &[&str], types: &[&str]) -> Vec<u8> {
+    fake_dex_with_methods(classes, types, &[])
+}
+
+/// Build a DEX file defining the given classes, with the given direct methods
+///
+/// The map list describes every section of the file.
+pub(crate) fn fake_dex_with_methods(classes: &[&str], types: &[&str], methods: &[FakeMethod]) -> Vec<u8> {
+    let mut descriptors = classes.iter().chain(types.iter()).copied().collect::<Vec<&str>>();
+    if !methods.is_empty() {
+        descriptors.push("V");
+    }
+    descriptors.sort();
+    descriptors.dedup();
+
+    let mut strings = descriptors.clone();
+    strings.extend(methods.iter().map(|method| method.name));
+    strings.sort();
+    strings.dedup();
+    let string_idx = |value: &str| strings.iter().position(|string| *string == value).unwrap();
+    let type_idx = |value: &str| descriptors.iter().position(|descriptor| *descriptor == value).unwrap();
+
+    let mut method_ids = (0..methods.len()).collect::<Vec<usize>>();
+    method_ids.sort_by_key(|&idx| (type_idx(methods[idx].class), string_idx(methods[idx].name)));
+    let protos_count = usize::from(!methods.is_empty());
+
+    let string_ids_off = HEADER_SIZE;
+    let type_ids_off = string_ids_off + 4 * strings.len();
+    let proto_ids_off = type_ids_off + 4 * descriptors.len();
+    let method_ids_off = proto_ids_off + 12 * protos_count;
+    let class_defs_off = method_ids_off + 8 * methods.len();
+    let data_off = class_defs_off + 32 * classes.len();
+
+    let mut ids = Vec::new();
+    let mut data = Vec::new();
+
+    // Code items
+    let mut code_offsets = vec![0; methods.len()];
+    for (idx, method) in methods.iter().enumerate() {
+        if let Some((registers, ins, outs, insns)) = &method.code {
+            while data.len() % 4 != 0 {
+                data.push(0);
+            }
+            code_offsets[idx] = data_off + data.len();
+            for value in [*registers, *ins, *outs, 0] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.extend_from_slice(&(insns.len() as u32).to_le_bytes());
+            for unit in insns {
+                data.extend_from_slice(&unit.to_le_bytes());
+            }
+        }
+    }
+    let code_items_count = code_offsets.iter().filter(|&&offset| offset != 0).count();
+    let code_items_off = code_offsets.iter().copied().find(|&offset| offset != 0).unwrap_or(0);
+
+    // Strings
+    let string_data_off = data_off + data.len();
+    for string in strings.iter() {
+        ids.extend_from_slice(&((data_off + data.len()) as u32).to_le_bytes());
+        data.push(string.len() as u8);
+        data.extend_from_slice(string.as_bytes());
+        data.push(0);
+    }
+
+    // Types, prototypes, and methods
+    for descriptor in descriptors.iter() {
+        ids.extend_from_slice(&(string_idx(descriptor) as u32).to_le_bytes());
+    }
+    if protos_count != 0 {
+        for value in [string_idx("V") as u32, type_idx("V") as u32, 0] {
+            ids.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    for &idx in method_ids.iter() {
+        ids.extend_from_slice(&(type_idx(methods[idx].class) as u16).to_le_bytes());
+        ids.extend_from_slice(&0u16.to_le_bytes());
+        ids.extend_from_slice(&(string_idx(methods[idx].name) as u32).to_le_bytes());
+    }
+
+    // Class data, only for classes with methods
+    let class_data_off = data_off + data.len();
+    let mut class_data_offsets = Vec::new();
+    for class in classes {
+        let class_methods = method_ids.iter()
+                                      .enumerate()
+                                      .filter(|(_, idx)| methods[**idx].class == *class)
+                                      .collect::<Vec<(usize, &usize)>>();
+        if class_methods.is_empty() {
+            class_data_offsets.push(0);
+            continue;
+        }
+
+        class_data_offsets.push(data_off + data.len());
+        for value in [0, 0, class_methods.len() as u32, 0] {
+            write_uleb128(&mut data, value);
+        }
+        let mut previous = 0;
+        for (method_idx, &idx) in class_methods {
+            write_uleb128(&mut data, (method_idx - previous) as u32);
+            write_uleb128(&mut data, methods[idx].access_flags);
+            write_uleb128(&mut data, code_offsets[idx] as u32);
+            previous = method_idx;
+        }
+    }
+    let class_data_count = class_data_offsets.iter().filter(|&&offset| offset != 0).count();
+
+    for (class, class_data) in classes.iter().zip(class_data_offsets) {
+        for value in [type_idx(class) as u32, 0x1, NO_INDEX, 0, NO_INDEX, 0, class_data as u32, 0] {
+            ids.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    while data.len() % 4 != 0 {
+        data.push(0);
+    }
+    let map_off = data_off + data.len();
+    let map_items = [(0x0000, 1, 0),
+                     (0x0001, strings.len(), string_ids_off),
+                     (0x0002, descriptors.len(), type_ids_off),
+                     (0x0003, protos_count, proto_ids_off),
+                     (0x0005, methods.len(), method_ids_off),
+                     (0x0006, classes.len(), class_defs_off),
+                     (0x2001, code_items_count, code_items_off),
+                     (0x2002, strings.len(), string_data_off),
+                     (0x2000, class_data_count, class_data_off),
+                     (0x1000, 1, map_off)];
+    let map_items = map_items.into_iter()
+                             .filter(|(_, size, _)| *size != 0)
+                             .collect::<Vec<(u16, usize, usize)>>();
+    data.extend_from_slice(&(map_items.len() as u32).to_le_bytes());
+    for (kind, size, offset) in map_items {
+        data.extend_from_slice(&(kind as u32).to_le_bytes());
+        data.extend_from_slice(&(size as u32).to_le_bytes());
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+    }
+
+    let file_size = data_off + data.len();
+    let section = |size: usize, offset: usize| if size == 0 { (0, 0) } else { (size, offset) };
+    let sizes = [(strings.len(), string_ids_off),
+                 (descriptors.len(), type_ids_off),
+                 section(protos_count, proto_ids_off),
+                 (0, 0),
+                 section(methods.len(), method_ids_off),
+                 (classes.len(), class_defs_off),
+                 (data.len(), data_off)];
+
+    let mut raw = b"dex\n035\0".to_vec();
+    raw.extend_from_slice(&[0; 24]);
+    // No link section
+    for value in [file_size, HEADER_SIZE, ENDIAN_CONSTANT as usize, 0, 0, map_off] {
+        raw.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+    for (size, offset) in sizes {
+        raw.extend_from_slice(&(size as u32).to_le_bytes());
+        raw.extend_from_slice(&(offset as u32).to_le_bytes());
+    }
+    raw.extend_from_slice(&ids);
+    raw.extend_from_slice(&data);
+
+    let checksum = crate::adler32::compute(&raw);
+    raw[8..12].copy_from_slice(&checksum.to_le_bytes());
+    raw
+}
+
+/// Append items to the data section of a file built by `fake_dex`, describing them in the
+/// map list, and return their offsets
+pub(crate) fn append_data_items(raw: &mut Vec<u8>, items: &[(u16, &[u8])]) -> Vec<usize> {
+    let map_off = read_u32_at(raw, 0x34).unwrap() as usize;
+    let map_size = read_u32_at(raw, map_off).unwrap() as usize;
+    raw[map_off..map_off + 4].copy_from_slice(&((map_size + items.len()) as u32).to_le_bytes());
+
+    let data_start = raw.len() + 12 * items.len();
+    let mut entries = Vec::new();
+    let mut data = Vec::new();
+    let mut offsets = Vec::new();
+    for (kind, bytes) in items {
+        while !(data_start + data.len()).is_multiple_of(4) {
+            data.push(0);
+        }
+        offsets.push(data_start + data.len());
+        for value in [u32::from(*kind), 1, (data_start + data.len()) as u32] {
+            entries.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(bytes);
+    }
+    raw.extend_from_slice(&entries);
+    raw.extend_from_slice(&data);
+
+    let data_off = read_u32_at(raw, 0x6c).unwrap() as usize;
+    let (file_size, data_size) = (raw.len() as u32, (raw.len() - data_off) as u32);
+    raw[0x20..0x24].copy_from_slice(&file_size.to_le_bytes());
+    raw[0x68..0x6c].copy_from_slice(&data_size.to_le_bytes());
+    offsets
+}
+
+/// Append an unsigned LEB128 value
+fn write_uleb128(data: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            data.push(byte);
+            break;
+        }
+        data.push(byte | 0x80);
+    }
+}
+
+/// Parse the DEX file built by `fake_dex`
+pub(crate) fn fake_dex_file(classes: &[&str], types: &[&str]) -> DexFile {
+    DexFile::build(DexReader::build(fake_dex(classes, types)).unwrap()).unwrap()
+}
+
+/// Parse the DEX file built by `fake_dex_with_methods`
+pub(crate) fn fake_dex_file_with_methods(classes: &[&str], types: &[&str], methods: &[FakeMethod]) -> DexFile {
+    DexFile::build(DexReader::build(fake_dex_with_methods(classes, types, methods)).unwrap()).unwrap()
+}
+
+/// Build a DEX file whose bytecode uses payloads
+///
+/// `La;` defines `switch()V`, with a packed switch, and `fill()V`, filling an array from a
+/// payload. `Lb;` defines the native method `run()V`.
+pub(crate) fn fake_code_dex() -> Vec<u8> {
+    // const/4 v0, #0; packed-switch v0, +5; return-void; nop; packed-switch-payload
+    let switch = vec![0x0012, 0x002b, 0x0005, 0x0000, 0x000e, 0x0000,
+                      0x0100, 0x0001, 0x0000, 0x0000, 0x0003, 0x0000];
+    // fill-array-data v0, +3; return-void; fill-array-data-payload (2 bytes)
+    let fill = vec![0x0026, 0x0003, 0x0000, 0x000e, 0x0300, 0x0001, 0x0002, 0x0000, 0x0201];
+    let methods = [
+        FakeMethod { class: "La;", name: "switch", access_flags: 0x9, code: Some((1, 0, 0, switch)) },
+        FakeMethod { class: "La;", name: "fill", access_flags: 0x9, code: Some((1, 0, 0, fill)) },
+        FakeMethod { class: "Lb;", name: "run", access_flags: 0x109, code: None },
+    ];
+    fake_dex_with_methods(&["La;", "Lb;"], &["[B"], &methods)
+}
+
+/// Build a minimal DEX file of the given size: only the magic, the file size and the endianness
+/// tag of the header are set
+pub(crate) fn fake_dex_header(size: u32) -> Vec<u8> {
+    let mut dex = vec![0u8; size as usize];
+    dex[0..8].copy_from_slice(b"dex\n035\0");
+    dex[32..36].copy_from_slice(&size.to_le_bytes());
+    dex[40..44].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+    dex
+}
@@ -1,14 +1,29 @@
 #![allow(dead_code)]
 
+use log::warn;
 use error::DexError;
 
 use crate::dex::reader::DexReader;
 use crate::dex::file::DexFile;
+use crate::dex::lazy::LazyDexFile;
 use crate::dex::instructions::Instructions;
+use crate::dex::odex::OdexFile;
+use crate::dex::carving::carve_from_file;
+use crate::dex::options::ParseOptions;
+use crate::apk::discovery::{ discover_from_file, DiscoveryOptions };
+use crate::art::vdex::VdexFile;
+use crate::art::oat::OatFile;
 
 pub mod dex;
+pub mod art;
+pub mod apk;
+pub mod elf;
 pub mod error;
-mod adler32;
+pub mod adler32;
+mod bytes;
+mod mutf8;
+#[cfg(test)]
+mod fixtures;
 
 /// Parse an APK and create a `DexFile` object from the embedded class(es) files
 pub fn parse(filepath: &str) -> Result<DexFile, DexError> {
@@ -16,6 +31,75 @@ pub fn parse(filepath: &str) -> Result<DexFile, DexError> {
     DexFile::merge(readers)
 }
 
+/// Parse an APK with the given options and create a `DexFile` object from the embedded class(es)
+/// files
+pub fn parse_with_options(filepath: &str, options: &ParseOptions) -> Result<DexFile, DexError> {
+    let readers = DexReader::build_from_file(filepath)?;
+    DexFile::merge_with_options(readers, options)
+}
+
+/// Open an APK and create a `LazyDexFile` object for each embedded DEX file
+///
+/// Only the IDs sections and the class definitions are parsed; the class data and code items are
+/// decoded on demand. DEX files are not merged, so that each one keeps its own offsets.
+pub fn parse_lazy(filepath: &str, options: &ParseOptions) -> Result<Vec<LazyDexFile<'static>>, DexError> {
+    DexReader::build_from_file(filepath)?.into_iter()
+                                         .map(|reader| LazyDexFile::build_with_options(reader, options))
+                                         .collect()
+}
+
+/// Parse an APK and create a `DexFile` object from all the DEX files found in it
+///
+/// Unlike `parse`, every entry is sniffed, including the ones in nested archives, so that DEX
+/// files hidden in assets or resources are also parsed. Compact DEX files are skipped, and so are
+/// the blobs which cannot be parsed, since a corrupted decoy must not hide the other DEX files.
+pub fn parse_discovered(filepath: &str, options: &DiscoveryOptions) -> Result<DexFile, DexError> {
+    let readers = discover_from_file(filepath, options)?
+                      .iter()
+                      .filter_map(|blob| match blob.dex_reader()? {
+                          Ok(reader) => Some(reader),
+                          Err(err) => {
+                              warn!("skipping blob {}: {err}", blob.path);
+                              None
+                          }
+                      })
+                      .collect();
+    DexFile::merge(readers)
+}
+
+/// Parse a VDEX file and create a `DexFile` object from the embedded DEX file(s)
+pub fn parse_vdex(filepath: &str) -> Result<DexFile, DexError> {
+    let vdex = VdexFile::build_from_file(filepath)?;
+    DexFile::merge(vdex.dex_readers()?)
+}
+
+/// Parse an OAT file and create a `DexFile` object from the DEX file(s) it was compiled from
+///
+/// DEX files which are not embedded in the OAT file are looked up in the companion VDEX file.
+pub fn parse_oat(filepath: &str) -> Result<DexFile, DexError> {
+    let oat = OatFile::build_from_file(filepath)?;
+    let readers = oat.dex_readers()?
+                     .into_iter()
+                     .map(|(_, reader)| reader)
+                     .collect();
+    DexFile::merge(readers)
+}
+
+/// Parse an ODEX file and create a `DexFile` object from the optimized DEX file
+pub fn parse_odex(filepath: &str) -> Result<DexFile, DexError> {
+    let odex = OdexFile::build_from_file(filepath)?;
+    DexFile::merge(vec![odex.dex_reader()?])
+}
+
+/// Carve the DEX files out of any file (e.g., a memory dump) and create a `DexFile` object from them
+pub fn parse_carved(filepath: &str) -> Result<DexFile, DexError> {
+    let readers = carve_from_file(filepath)?
+                      .iter()
+                      .map(|carved| carved.dex_reader())
+                      .collect::<Result<Vec<DexReader>, DexError>>()?;
+    DexFile::merge(readers)
+}
+
 /// Return the list of qualified method names from a `DexFile` object
 pub fn get_qualified_method_names(dex: &DexFile) -> Vec<String> {
     let mut methods = Vec::new();
@@ -37,12 +121,10 @@ pub fn get_qualified_method_names(dex: &DexFile) -> Vec<String> {
 pub fn get_bytecode_for_method(dex: &DexFile,
                                class_name: &String,
                                method_name: &String) -> Option<Vec<Instructions>> {
-    if let Some(class_def) = dex.get_class_def(class_name) {
-        if let Some(encoded_method) = class_def.get_encoded_method(method_name) {
-            if let Some(code_item) = &encoded_method.code_item {
-                return code_item.insns.clone();
-            }
-        }
+    if let Some(class_def) = dex.get_class_def(class_name)
+        && let Some(encoded_method) = class_def.get_encoded_method(method_name)
+        && let Some(code_item) = &encoded_method.code_item {
+        return code_item.insns.clone();
     }
 
     None
@@ -1,6 +1,6 @@
 use log::{error, debug};
 
-pub fn decode(raw: &Vec<u8>) -> Result<String, &'static str> {
+pub fn decode(raw: &[u8]) -> Result<String, &'static str> {
     let raw_len = raw.len();
     let mut decoded: Vec<u32> = Vec::new();
     let mut idx = 0;
@@ -29,7 +29,7 @@ pub fn decode(raw: &Vec<u8>) -> Result<String, &'static str> {
 
             let code_point = ((x & 0b0001_1111) << 6)
                             + (y & 0b0011_1111);
-            decoded.push(code_point as u32);
+            decoded.push(code_point);
 
             idx += 2;
             continue;
@@ -54,7 +54,7 @@ pub fn decode(raw: &Vec<u8>) -> Result<String, &'static str> {
                            + (z & 0b0011_1111);
 
             /* dealing with surrogate pairs */
-            if code_point >= 0xd800 && code_point <= 0xdbff {
+            if (0xd800..=0xdbff).contains(&code_point) {
                 if idx + 3 >= raw_len {
                     error!("[MUTF-8] error: truncated surrogate pair");
                     return Err("[MUTF-8] truncated surrogate pair");
@@ -69,7 +69,7 @@ pub fn decode(raw: &Vec<u8>) -> Result<String, &'static str> {
                                     + ((next_y & 0b0011_1111) << 6)
                                     + (next_z & 0b0011_1111);
 
-                if next_code_point >= 0xdc00 && next_code_point <= 0xdfff {
+                if (0xdc00..=0xdfff).contains(&next_code_point) {
                     let final_code_point = ((code_point - 0xd800) << 10
                                          | (next_code_point - 0xdc00)) + 0x10000;
                     decoded.push(final_code_point);
@@ -82,7 +82,7 @@ pub fn decode(raw: &Vec<u8>) -> Result<String, &'static str> {
                 }
             } else {
                 /* regular three-bytes code point */
-                decoded.push(code_point as u32);
+                decoded.push(code_point);
                 idx += 3;
                 continue;
             }
@@ -102,6 +102,36 @@ pub fn decode(raw: &Vec<u8>) -> Result<String, &'static str> {
     Ok(decoded_str)
 }
 
+/// Decode a MUTF-8 string into UTF-16 code units, without combining the surrogate pairs
+///
+/// DEX files store the length of the strings in code units, so this is what the validation of
+/// the strings needs.
+pub(crate) fn decode_utf16(bytes: &[u8]) -> Option<Vec<u16>> {
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let byte = bytes[pos] as u16;
+        let (unit, len) = match byte {
+            0x01..=0x7f => (byte, 1),
+            _ if byte & 0xe0 == 0xc0 => {
+                let second = *bytes.get(pos + 1)? as u16;
+                ((byte & 0x1f) << 6 | (second & 0x3f), 2)
+            },
+            _ if byte & 0xf0 == 0xe0 => {
+                let second = *bytes.get(pos + 1)? as u16;
+                let third = *bytes.get(pos + 2)? as u16;
+                ((byte & 0x0f) << 12 | (second & 0x3f) << 6 | (third & 0x3f), 3)
+            },
+            _ => return None,
+        };
+        units.push(unit);
+        pos += len;
+    }
+
+    Some(units)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -166,4 +196,13 @@ mod tests {
         assert_eq!(result.unwrap_err().to_string(),
                    "[MUTF-8] truncated surrogate pair");
     }
+
+    #[test]
+    fn test_decode_utf16() {
+        assert_eq!(decode_utf16(b"abc").unwrap(), vec![0x61, 0x62, 0x63]);
+        assert_eq!(decode_utf16(&[0xc0, 0x80]).unwrap(), vec![0]);
+        assert_eq!(decode_utf16(&[0xe2, 0x82, 0xac]).unwrap(), vec![0x20ac]);
+        assert!(decode_utf16(&[0xe2, 0x82]).is_none());
+        assert!(decode_utf16(&[0xff]).is_none());
+    }
 }